@@ -0,0 +1,151 @@
+use ff::{BitIterator, PrimeField};
+use models::plasma::circuit::change_pubkey::pub_key_hash_bits;
+use models::plasma::circuit::sig::TransactionSignature;
+use models::plasma::circuit::utils::le_bit_vector_into_field_element;
+use models::plasma::params as plasma_constants;
+use sapling_crypto::eddsa::{PrivateKey, PublicKey};
+use sapling_crypto::jubjub::{FixedGenerators, JubjubEngine};
+
+// This is a request to replace the public key of an account
+
+#[derive(Clone)]
+pub struct ChangePubKeyRequest<E: JubjubEngine> {
+    pub account: Option<E::Fr>,
+    pub new_pub_x: Option<E::Fr>,
+    pub new_pub_y: Option<E::Fr>,
+    // keep a nonce in request for ease of signing,
+    // the circuit takes the nonce from the leaf
+    pub nonce: Option<E::Fr>,
+    // deployment the request is signed for, the circuit checks it against its own
+    pub chain_id: Option<E::Fr>,
+    pub signature: Option<TransactionSignature<E>>,
+}
+
+impl<E: JubjubEngine> ChangePubKeyRequest<E> {
+    pub fn public_data_into_bits(&self) -> Vec<bool> {
+        // fields are
+        // - account
+        // - compressed new public key
+        let mut account: Vec<bool> = BitIterator::new(self.account.unwrap().into_repr()).collect();
+        account.reverse();
+        account.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+        // reverse again to have BE as in Ethereum native types
+        account.reverse();
+
+        let mut y_bits: Vec<bool> = BitIterator::new(self.new_pub_y.unwrap().into_repr()).collect();
+        y_bits.reverse();
+        y_bits.truncate(E::Fr::NUM_BITS as usize);
+        y_bits.resize(plasma_constants::FR_BIT_WIDTH - 1, false);
+
+        let mut x_bits: Vec<bool> = BitIterator::new(self.new_pub_x.unwrap().into_repr()).collect();
+        x_bits.reverse();
+        // push sign bit
+        y_bits.push(x_bits[0]);
+        // reverse again to have BE as in Ethereum native types
+        y_bits.reverse();
+
+        let mut packed: Vec<bool> = vec![];
+        packed.extend(account.into_iter());
+        packed.extend(y_bits.into_iter());
+
+        packed
+    }
+
+    // this function returns data to make a request signature
+    // in a format that is later used in zkSNARK
+    pub fn data_for_signature_into_bits(&self, params: &E::Params) -> Vec<bool> {
+        // fields are
+        // - account
+        // - nonce
+        // - hash of the new public key
+        // - chain_id
+
+        // LE account
+        let mut account: Vec<bool> = BitIterator::new(self.account.unwrap().into_repr()).collect();
+        account.reverse();
+        account.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+        // LE nonce
+        let mut nonce: Vec<bool> = BitIterator::new(self.nonce.unwrap().into_repr()).collect();
+        nonce.reverse();
+        nonce.truncate(plasma_constants::NONCE_BIT_WIDTH);
+
+        let new_pub_key_hash =
+            pub_key_hash_bits::<E>(&self.new_pub_x.unwrap(), &self.new_pub_y.unwrap(), params);
+        let mut chain_id: Vec<bool> =
+            BitIterator::new(self.chain_id.unwrap().into_repr()).collect();
+        chain_id.reverse();
+        chain_id.truncate(plasma_constants::CHAIN_ID_BIT_WIDTH);
+
+        let mut packed: Vec<bool> = vec![];
+
+        packed.extend(account.into_iter());
+        packed.extend(nonce.into_iter());
+        packed.extend(new_pub_key_hash.into_iter());
+        packed.extend(chain_id.into_iter());
+
+        packed
+    }
+
+    pub fn data_as_bytes(&self, params: &E::Params) -> Vec<u8> {
+        let raw_data: Vec<bool> = self.data_for_signature_into_bits(params);
+
+        let mut message_bytes: Vec<u8> = vec![];
+
+        let byte_chunks = raw_data.chunks(8);
+        for byte_chunk in byte_chunks {
+            let mut byte = 0u8;
+            for (i, bit) in byte_chunk.iter().enumerate() {
+                if *bit {
+                    byte |= 1 << i;
+                }
+            }
+            message_bytes.push(byte);
+        }
+
+        message_bytes
+    }
+
+    pub fn sign<R>(
+        &mut self,
+        private_key: &PrivateKey<E>,
+        p_g: FixedGenerators,
+        params: &E::Params,
+        rng: &mut R,
+    ) where
+        R: rand::Rng,
+    {
+        let message_bytes = self.data_as_bytes(params);
+
+        let max_message_len = plasma_constants::BALANCE_TREE_DEPTH
+            + plasma_constants::NONCE_BIT_WIDTH
+            + plasma_constants::NEW_PUBKEY_HASH_WIDTH
+            + plasma_constants::CHAIN_ID_BIT_WIDTH;
+
+        let signature =
+            private_key.sign_raw_message(&message_bytes, rng, p_g, params, max_message_len / 8);
+
+        let pk = PublicKey::from_private(&private_key, p_g, params);
+        let is_valid_signature = pk.verify_for_raw_message(
+            &message_bytes,
+            &signature.clone(),
+            p_g,
+            params,
+            max_message_len / 8,
+        );
+        if !is_valid_signature {
+            return;
+        }
+
+        let mut sigs_le_bits: Vec<bool> = BitIterator::new(signature.s.into_repr()).collect();
+        sigs_le_bits.reverse();
+
+        let sigs_converted = le_bit_vector_into_field_element(&sigs_le_bits);
+
+        let converted_signature = TransactionSignature {
+            r: signature.r,
+            s: sigs_converted,
+        };
+
+        self.signature = Some(converted_signature);
+    }
+}
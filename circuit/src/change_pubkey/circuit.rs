@@ -0,0 +1,813 @@
+use crate::change_pubkey::change_pubkey_request::ChangePubKeyRequest;
+use crate::leaf::{
+    account_leaf_hash, account_tree_node, make_leaf_content, public_key_leaf_bits, LeafWitness,
+};
+use bellman::{Circuit, ConstraintSystem, SynthesisError};
+use ff::{Field, PrimeField};
+use models::plasma::circuit::utils::{allocate_audit_path, append_packed_public_key};
+use models::plasma::params as plasma_constants;
+use sapling_crypto::circuit::baby_eddsa::EddsaSignature;
+use sapling_crypto::circuit::num::{AllocatedNum, Num};
+use sapling_crypto::circuit::{boolean, ecc, num, pedersen_hash, sha256, Assignment};
+use sapling_crypto::jubjub::{FixedGenerators, JubjubEngine, JubjubParams};
+
+#[derive(Clone)]
+pub struct ChangePubKeyWitness<E: JubjubEngine> {
+    pub leaf: LeafWitness<E>,
+    pub auth_path: Vec<Option<E::Fr>>,
+}
+
+/// This is an instance of the `ChangePubKey` circuit.
+pub struct ChangePubKey<'a, E: JubjubEngine> {
+    pub params: &'a E::Params,
+
+    // number of key changes per block
+    pub number_of_changes: usize,
+
+    /// Chain id of the deployment, it is fixed when the keys are generated
+    pub chain_id: E::Fr,
+
+    /// The old root of the tree
+    pub old_root: Option<E::Fr>,
+
+    /// The new root of the tree
+    pub new_root: Option<E::Fr>,
+
+    /// Final truncated rolling SHA256
+    pub public_data_commitment: Option<E::Fr>,
+
+    /// Block number
+    pub block_number: Option<E::Fr>,
+
+    /// Requests for this block
+    pub requests: Vec<(ChangePubKeyRequest<E>, ChangePubKeyWitness<E>)>,
+}
+
+impl<'a, E: JubjubEngine> Circuit<E> for ChangePubKey<'a, E> {
+    fn synthesize<CS: ConstraintSystem<E>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        // Check that requests are in a right quantity
+        assert!(self.number_of_changes == self.requests.len());
+
+        let old_root_value = self.old_root;
+        // Expose inputs and do the bits decomposition of hash
+        let mut old_root =
+            AllocatedNum::alloc(cs.namespace(|| "old root"), || Ok(*old_root_value.get()?))?;
+        old_root.inputize(cs.namespace(|| "old root input"))?;
+
+        let new_root_value = self.new_root;
+        let new_root =
+            AllocatedNum::alloc(cs.namespace(|| "new root"), || Ok(*new_root_value.get()?))?;
+        new_root.inputize(cs.namespace(|| "new root input"))?;
+
+        let rolling_hash_value = self.public_data_commitment;
+        let rolling_hash = AllocatedNum::alloc(cs.namespace(|| "rolling hash"), || {
+            Ok(*rolling_hash_value.get()?)
+        })?;
+        rolling_hash.inputize(cs.namespace(|| "rolling hash input"))?;
+
+        let mut public_data_vector: Vec<boolean::Boolean> = vec![];
+
+        let public_generator = self
+            .params
+            .generator(FixedGenerators::SpendingKeyGenerator)
+            .clone();
+        let generator = ecc::EdwardsPoint::witness(
+            cs.namespace(|| "allocate public generator"),
+            Some(public_generator),
+            self.params,
+        )?;
+
+        // Ok, now we need to update the old root by applying requests in sequence
+        let requests = self.requests.clone();
+
+        for (i, tx) in requests.into_iter().enumerate() {
+            let (request, witness) = tx;
+            let (intermediate_root, public_data) = apply_request(
+                cs.namespace(|| format!("applying request {}", i)),
+                old_root,
+                request,
+                witness,
+                self.chain_id,
+                self.params,
+                generator.clone(),
+            )?;
+            old_root = intermediate_root;
+            // flatten the public transaction data
+            public_data_vector.extend(public_data.into_iter());
+        }
+
+        // constraint the new hash to be equal to updated hash
+
+        cs.enforce(
+            || "enforce new root equal to recalculated one",
+            |lc| lc + new_root.get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc + old_root.get_variable(),
+        );
+
+        // Now it's time to pack the initial SHA256 hash due to Ethereum BE encoding
+        // and start rolling the hash
+
+        let mut initial_hash_data: Vec<boolean::Boolean> = vec![];
+
+        let block_number_allocated =
+            AllocatedNum::alloc(cs.namespace(|| "allocate block number"), || {
+                Ok(*self.block_number.get()?)
+            })?;
+
+        // make initial hash as sha256(uint256(block_number))
+        let mut block_number_bits = block_number_allocated
+            .into_bits_le(cs.namespace(|| "unpack block number for hashing"))?;
+
+        block_number_bits.resize(
+            plasma_constants::FR_BIT_WIDTH,
+            boolean::Boolean::Constant(false),
+        );
+        block_number_bits.reverse();
+        initial_hash_data.extend(block_number_bits.into_iter());
+
+        assert_eq!(initial_hash_data.len(), 256);
+
+        let mut hash_block = sha256::sha256(
+            cs.namespace(|| "initial rolling sha256"),
+            &initial_hash_data,
+        )?;
+
+        // now pack the public data and do the final hash
+
+        let mut pack_bits = vec![];
+        pack_bits.extend(hash_block);
+        pack_bits.extend(public_data_vector.into_iter());
+
+        hash_block = sha256::sha256(cs.namespace(|| "hash public data"), &pack_bits)?;
+
+        // now pack and enforce equality to the input
+
+        hash_block.reverse();
+        hash_block.truncate(E::Fr::CAPACITY as usize);
+
+        let mut packed_hash_lc = Num::<E>::zero();
+        let mut coeff = E::Fr::one();
+        for bit in hash_block {
+            packed_hash_lc = packed_hash_lc.add_bool_with_coeff(CS::one(), &bit, coeff);
+            coeff.double();
+        }
+
+        cs.enforce(
+            || "enforce external data hash equality",
+            |lc| lc + rolling_hash.get_variable(),
+            |lc| lc + CS::one(),
+            |_| packed_hash_lc.lc(E::Fr::one()),
+        );
+
+        Ok(())
+    }
+}
+
+/// Applies one request to the tree,
+/// outputs a new root
+fn apply_request<E, CS>(
+    mut cs: CS,
+    old_root: AllocatedNum<E>,
+    request: ChangePubKeyRequest<E>,
+    witness: ChangePubKeyWitness<E>,
+    chain_id: E::Fr,
+    params: &E::Params,
+    generator: ecc::EdwardsPoint<E>,
+) -> Result<(AllocatedNum<E>, Vec<boolean::Boolean>), SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    // balances are not changed, so the leaf is opened for token 0,
+    // any token gives the same balances root
+
+    let token_bits = vec![boolean::Boolean::Constant(false); plasma_constants::TOKEN_BIT_WIDTH];
+
+    let leaf = make_leaf_content(
+        cs.namespace(|| "create leaf"),
+        witness.clone().leaf,
+        &token_bits,
+        params,
+    )?;
+
+    // Compute the hash of the leaf
+    let leaf_hash = account_leaf_hash(
+        cs.namespace(|| "leaf content hash"),
+        &leaf.leaf_bits,
+        params,
+    )?;
+
+    // Constraint that "account" field in request is
+    // equal to the merkle proof path
+
+    let address_allocated = AllocatedNum::alloc(cs.namespace(|| "account address"), || {
+        Ok(*request.account.get()?)
+    })?;
+
+    let mut path_bits =
+        address_allocated.into_bits_le(cs.namespace(|| "address bit decomposition"))?;
+
+    path_bits.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+
+    let audit_path = allocate_audit_path(
+        cs.namespace(|| "allocate audit path"),
+        witness.clone().auth_path,
+    )?;
+
+    {
+        let mut cur = leaf_hash.clone();
+
+        // Ascend the merkle tree authentication path
+        for (i, direction_bit) in path_bits.clone().into_iter().enumerate() {
+            let cs = &mut cs.namespace(|| format!("merkle tree hash {}", i));
+
+            // "direction_bit" determines if the current subtree
+            // is the "right" leaf at this depth of the tree.
+
+            // Witness the authentication path element adjacent
+            // at this depth.
+            let path_element = &audit_path[i];
+
+            // Swap the two if the current subtree is on the right
+            let (xl, xr) = num::AllocatedNum::conditionally_reverse(
+                cs.namespace(|| "conditional reversal of preimage"),
+                &cur,
+                path_element,
+                &direction_bit,
+            )?;
+
+            // Compute the new subtree value
+            cur = account_tree_node(cs.namespace(|| "node hash"), &xl, &xr, i, params)?;
+        }
+
+        // enforce old root before update
+        cs.enforce(
+            || "enforce correct old root for leaf",
+            |lc| lc + cur.get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc + old_root.get_variable(),
+        );
+    }
+
+    // allocate the new public key and check that it's a point on the curve
+
+    let new_pub_x = AllocatedNum::alloc(cs.namespace(|| "allocate new public key x"), || {
+        Ok(*request.new_pub_x.get()?)
+    })?;
+
+    let new_pub_y = AllocatedNum::alloc(cs.namespace(|| "allocate new public key y"), || {
+        Ok(*request.new_pub_y.get()?)
+    })?;
+
+    ecc::EdwardsPoint::interpret(
+        cs.namespace(|| "new public key"),
+        &new_pub_x,
+        &new_pub_y,
+        params,
+    )?;
+
+    let mut new_pub_x_bit = new_pub_x.into_bits_le(cs.namespace(|| "new pub_x bits"))?;
+    // leave only the parity bit
+    new_pub_x_bit.truncate(1);
+
+    let mut new_pub_y_bits = new_pub_y.into_bits_le(cs.namespace(|| "new pub_y bits"))?;
+    new_pub_y_bits.resize(
+        plasma_constants::FR_BIT_WIDTH - 1,
+        boolean::Boolean::Constant(false),
+    );
+
+    let mut new_pub_key_bits = vec![];
+    append_packed_public_key(&mut new_pub_key_bits, new_pub_x_bit, new_pub_y_bits);
+
+    // the whole key does not fit into a signed message, so its hash is signed instead
+
+    let mut new_pub_key_hash_bits = pedersen_hash::pedersen_hash(
+        cs.namespace(|| "new public key hash"),
+        pedersen_hash::Personalization::NoteCommitment,
+        &new_pub_key_bits,
+        params,
+    )?
+    .get_x()
+    .into_bits_le(cs.namespace(|| "new public key hash bits"))?;
+
+    new_pub_key_hash_bits.truncate(plasma_constants::NEW_PUBKEY_HASH_WIDTH);
+
+    // the key is checked to be the one of the leaf and signs the change. An empty leaf has
+    // no key, zero key is not a curve point, so keys of new accounts are registered only
+    // by deposits and transfers
+
+    let (pub_x, pub_y) = leaf.public_key(cs.namespace(|| "check leaf public key"), params)?;
+
+    let mut message_bits: Vec<boolean::Boolean> = vec![];
+    message_bits.extend(path_bits.clone());
+    message_bits.extend(leaf.nonce_bits.clone());
+    message_bits.extend(new_pub_key_hash_bits);
+
+    let request_chain_id =
+        AllocatedNum::alloc(cs.namespace(|| "allocate request chain id"), || {
+            Ok(*request.chain_id.get()?)
+        })?;
+
+    // a change signed for another deployment is rejected
+    cs.enforce(
+        || "enforce chain id of the deployment",
+        |lc| lc + request_chain_id.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + (chain_id, CS::one()),
+    );
+
+    let mut chain_id_bits = request_chain_id.into_bits_le(cs.namespace(|| "chain id bits"))?;
+    chain_id_bits.truncate(plasma_constants::CHAIN_ID_BIT_WIDTH);
+    message_bits.extend(chain_id_bits);
+
+    let old_pk = ecc::EdwardsPoint::interpret(
+        cs.namespace(|| "current public key"),
+        &pub_x,
+        &pub_y,
+        params,
+    )?;
+
+    let signature_r_x = AllocatedNum::alloc(cs.namespace(|| "signature r_x witness"), || {
+        Ok(request.signature.get()?.r.into_xy().0)
+    })?;
+
+    let signature_r_y = AllocatedNum::alloc(cs.namespace(|| "signature r_y witness"), || {
+        Ok(request.signature.get()?.r.into_xy().1)
+    })?;
+
+    let signature_r = ecc::EdwardsPoint::interpret(
+        cs.namespace(|| "signature r as point"),
+        &signature_r_x,
+        &signature_r_y,
+        params,
+    )?;
+
+    let signature_s = AllocatedNum::alloc(cs.namespace(|| "signature s witness"), || {
+        Ok(request.signature.get()?.s)
+    })?;
+
+    let signature = EddsaSignature {
+        r: signature_r,
+        s: signature_s,
+        pk: old_pk,
+    };
+
+    let max_message_len = plasma_constants::BALANCE_TREE_DEPTH
+        + plasma_constants::NONCE_BIT_WIDTH
+        + plasma_constants::NEW_PUBKEY_HASH_WIDTH
+        + plasma_constants::CHAIN_ID_BIT_WIDTH;
+
+    signature.verify_raw_message_signature(
+        cs.namespace(|| "verify request signature"),
+        params,
+        &message_bits,
+        generator,
+        max_message_len,
+    )?;
+
+    // key change consumes a nonce, so the signature can not be replayed
+
+    let mut nonce_lc = Num::<E>::zero();
+    let mut coeff = E::Fr::one();
+    for bit in &leaf.nonce_bits {
+        nonce_lc = nonce_lc.add_bool_with_coeff(CS::one(), &bit, coeff);
+        coeff.double();
+    }
+
+    let nonce = AllocatedNum::alloc(cs.namespace(|| "nonce"), || {
+        Ok(*nonce_lc.get_value().get()?)
+    })?;
+
+    cs.enforce(
+        || "pack nonce",
+        |lc| lc + nonce.get_variable(),
+        |lc| lc + CS::one(),
+        |_| nonce_lc.lc(E::Fr::one()),
+    );
+
+    let new_nonce = AllocatedNum::alloc(cs.namespace(|| "new nonce"), || {
+        let mut new_nonce_value = *nonce.get_value().get()?;
+        new_nonce_value.add_assign(&E::Fr::one());
+
+        Ok(new_nonce_value)
+    })?;
+
+    // constraint no overflow
+    new_nonce.limit_number_of_bits(
+        cs.namespace(|| "limit number of bits for new nonce"),
+        plasma_constants::NONCE_BIT_WIDTH,
+    )?;
+
+    cs.enforce(
+        || "enforce nonce to increase",
+        |lc| lc + new_nonce.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + nonce.get_variable() + CS::one(),
+    );
+
+    let mut leaf_content = vec![];
+
+    // balances root is kept as is
+    leaf_content.extend(
+        leaf.leaf_bits[0..plasma_constants::FR_BIT_WIDTH]
+            .iter()
+            .cloned(),
+    );
+
+    let mut nonce_content = new_nonce.into_bits_le(cs.namespace(|| "updated nonce bits"))?;
+
+    nonce_content.truncate(plasma_constants::NONCE_BIT_WIDTH);
+    leaf_content.extend(nonce_content);
+
+    leaf_content.extend(public_key_leaf_bits(
+        cs.namespace(|| "new public key leaf bits"),
+        new_pub_key_bits.clone(),
+        params,
+    )?);
+
+    // cheque window and lock are kept as is
+    leaf_content.extend(leaf.state_bits.clone());
+
+    let updated_leaf_hash = account_leaf_hash(
+        cs.namespace(|| "updated leaf content hash"),
+        &leaf_content,
+        params,
+    )?;
+
+    let mut cur = updated_leaf_hash.clone();
+
+    // Ascend the merkle tree authentication path
+    for (i, direction_bit) in path_bits.clone().into_iter().enumerate() {
+        let cs = &mut cs.namespace(|| format!("update merkle tree hash {}", i));
+
+        // "direction_bit" determines if the current subtree
+        // is the "right" leaf at this depth of the tree.
+
+        // Witness the authentication path element adjacent
+        // at this depth.
+        let path_element = &audit_path[i];
+
+        // Swap the two if the current subtree is on the right
+        let (xl, xr) = num::AllocatedNum::conditionally_reverse(
+            cs.namespace(|| "conditional reversal of preimage"),
+            &cur,
+            path_element,
+            &direction_bit,
+        )?;
+
+        // Compute the new subtree value
+        cur = account_tree_node(cs.namespace(|| "node hash"), &xl, &xr, i, params)?;
+    }
+
+    // the last step - we expose public data for later commitment
+
+    // data packing should be BE
+    let mut public_data = vec![];
+    let mut path_bits_be = path_bits.clone();
+    path_bits_be.reverse();
+    public_data.extend(path_bits_be);
+    let mut new_pub_key_bits_be = new_pub_key_bits;
+    new_pub_key_bits_be.reverse();
+    public_data.extend(new_pub_key_bits_be);
+
+    assert_eq!(
+        public_data.len(),
+        plasma_constants::BALANCE_TREE_DEPTH + plasma_constants::FR_BIT_WIDTH
+    );
+
+    Ok((cur, public_data))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use log::debug;
+
+    use ff::PrimeFieldRepr;
+
+    use sapling_crypto::eddsa::{PrivateKey, PublicKey};
+
+    #[test]
+    fn test_change_pubkey_of_existing_leaf() {
+        use crate::CircuitAccountTree;
+        use ff::{BitIterator, Field};
+        use models::plasma::circuit::account::{leaf_pub_key_hash, CircuitAccount};
+        use models::plasma::circuit::utils::be_bit_vector_into_bytes;
+        use pairing::bn256::*;
+        use rand::{Rng, SeedableRng, XorShiftRng};
+        use sapling_crypto::alt_babyjubjub::AltJubjubBn256;
+        use sapling_crypto::circuit::test::*;
+
+        use crypto::digest::Digest;
+        use crypto::sha2::Sha256;
+        use hex;
+
+        let params = &AltJubjubBn256::new();
+        let p_g = FixedGenerators::SpendingKeyGenerator;
+
+        let rng = &mut XorShiftRng::from_seed([0x3dbe_6258, 0x8d31_3d76, 0x3237_db17, 0xe5bc_0654]);
+
+        let tree_depth = plasma_constants::BALANCE_TREE_DEPTH as u32;
+        let mut tree = CircuitAccountTree::new(tree_depth);
+
+        let capacity = tree.capacity();
+        assert_eq!(capacity, 1 << plasma_constants::BALANCE_TREE_DEPTH);
+
+        let old_sk = PrivateKey::<Bn256>(rng.gen());
+        let old_pk = PublicKey::from_private(&old_sk, p_g, params);
+        let (old_x, old_y) = old_pk.0.into_xy();
+
+        let new_sk = PrivateKey::<Bn256>(rng.gen());
+        let new_pk = PublicKey::from_private(&new_sk, p_g, params);
+        let (new_x, new_y) = new_pk.0.into_xy();
+
+        let mut leaf_number: u32 = rng.gen();
+        leaf_number %= capacity;
+
+        let token: u32 = 3;
+        let balance = Fr::from_str("1000").unwrap();
+        let nonce = Fr::from_str("7").unwrap();
+
+        let mut leaf = CircuitAccount::<Bn256> {
+            nonce,
+            pub_x: old_x,
+            pub_y: old_y,
+            ..Default::default()
+        };
+        leaf.set_balance(token, balance, params);
+
+        tree.insert(leaf_number, leaf.clone());
+
+        let initial_root = tree.root_hash();
+        debug!("Initial root = {}", initial_root);
+
+        let auth_path: Vec<Option<Fr>> = tree
+            .merkle_path(leaf_number)
+            .into_iter()
+            .map(|e| Some(e.0))
+            .collect();
+
+        let mut request: ChangePubKeyRequest<Bn256> = ChangePubKeyRequest {
+            account: Fr::from_str(&leaf_number.to_string()),
+            new_pub_x: Some(new_x),
+            new_pub_y: Some(new_y),
+            nonce: Some(nonce),
+            chain_id: Some(Fr::one()),
+            signature: None,
+        };
+
+        request.sign(&old_sk, p_g, params, rng);
+        assert!(request.signature.is_some());
+
+        // leaf is opened for token 0, the balance of which is zero
+        let leaf_witness = LeafWitness {
+            balance: Some(Fr::zero()),
+            balance_path: leaf.balance_path(0, params).into_iter().map(Some).collect(),
+            nonce: Some(nonce),
+            pub_x: Some(old_x),
+            pub_y: Some(old_y),
+            pub_key_hash: leaf_pub_key_hash(&old_x, &old_y),
+            cheque_bits: Some(Fr::zero()),
+            cheque_start: Some(Fr::zero()),
+            lock_commitment: Some(Fr::zero()),
+        };
+
+        let witness = ChangePubKeyWitness {
+            leaf: leaf_witness,
+            auth_path,
+        };
+
+        let mut updated_leaf = leaf.clone();
+        updated_leaf.nonce.add_assign(&Fr::one());
+        updated_leaf.pub_x = new_x;
+        updated_leaf.pub_y = new_y;
+
+        tree.insert(leaf_number, updated_leaf);
+
+        let new_root = tree.root_hash();
+
+        debug!("New root = {}", new_root);
+
+        assert_ne!(initial_root, new_root);
+
+        {
+            let mut cs = TestConstraintSystem::<Bn256>::new();
+
+            let mut public_data_initial_bits = Vec::new();
+
+            // these two are BE encodings because an iterator is BE. This is also an Ethereum standard behavior
+
+            let block_number_bits: Vec<bool> = BitIterator::new(Fr::one().into_repr()).collect();
+            for _ in 0..256 - block_number_bits.len() {
+                public_data_initial_bits.push(false);
+            }
+            public_data_initial_bits.extend(block_number_bits.into_iter());
+
+            assert_eq!(public_data_initial_bits.len(), 256);
+
+            let mut h = Sha256::new();
+
+            let bytes_to_hash = be_bit_vector_into_bytes(&public_data_initial_bits);
+
+            h.input(&bytes_to_hash);
+
+            let mut hash_result = [0u8; 32];
+            h.result(&mut hash_result[..]);
+
+            debug!("Initial hash hex {}", hex::encode(hash_result));
+
+            let packed_transaction_data = request.public_data_into_bits();
+
+            let packed_transaction_data_bytes = be_bit_vector_into_bytes(&packed_transaction_data);
+
+            let mut next_round_hash_bytes = vec![];
+            next_round_hash_bytes.extend(hash_result.iter());
+            next_round_hash_bytes.extend(packed_transaction_data_bytes);
+
+            h = Sha256::new();
+            h.input(&next_round_hash_bytes);
+            hash_result = [0u8; 32];
+            h.result(&mut hash_result[..]);
+
+            debug!("Final hash as hex {}", hex::encode(hash_result));
+
+            hash_result[0] &= 0x1f; // temporary solution
+
+            let mut repr = Fr::zero().into_repr();
+            repr.read_be(&hash_result[..])
+                .expect("pack hash as field element");
+
+            let public_data_commitment = Fr::from_repr(repr).unwrap();
+
+            let instance = ChangePubKey {
+                params,
+                number_of_changes: 1,
+                chain_id: Fr::one(),
+                old_root: Some(initial_root),
+                new_root: Some(new_root),
+                public_data_commitment: Some(public_data_commitment),
+                block_number: Some(Fr::one()),
+                requests: vec![(request, witness)],
+            };
+
+            instance.synthesize(&mut cs).unwrap();
+
+            debug!("{}", cs.num_constraints());
+
+            assert_eq!(cs.num_inputs(), 4);
+
+            let err = cs.which_is_unsatisfied();
+            if err.is_some() {
+                panic!("ERROR satisfying in {}", err.unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_self_signed_key_of_empty_leaf_is_refused() {
+        use crate::CircuitAccountTree;
+        use ff::{BitIterator, Field};
+        use models::plasma::circuit::account::{leaf_pub_key_hash, CircuitAccount};
+        use models::plasma::circuit::utils::be_bit_vector_into_bytes;
+        use pairing::bn256::*;
+        use rand::{Rng, SeedableRng, XorShiftRng};
+        use sapling_crypto::alt_babyjubjub::AltJubjubBn256;
+        use sapling_crypto::circuit::test::*;
+
+        use crypto::digest::Digest;
+        use crypto::sha2::Sha256;
+
+        let params = &AltJubjubBn256::new();
+        let p_g = FixedGenerators::SpendingKeyGenerator;
+
+        let rng = &mut XorShiftRng::from_seed([0x3dbe_6258, 0x8d31_3d76, 0x3237_db17, 0xe5bc_0654]);
+
+        let tree_depth = plasma_constants::BALANCE_TREE_DEPTH as u32;
+        let mut tree = CircuitAccountTree::new(tree_depth);
+
+        let capacity = tree.capacity();
+
+        let new_sk = PrivateKey::<Bn256>(rng.gen());
+        let new_pk = PublicKey::from_private(&new_sk, p_g, params);
+        let (new_x, new_y) = new_pk.0.into_xy();
+
+        let mut leaf_number: u32 = rng.gen();
+        leaf_number %= capacity;
+
+        let initial_root = tree.root_hash();
+
+        let auth_path: Vec<Option<Fr>> = tree
+            .merkle_path(leaf_number)
+            .into_iter()
+            .map(|e| Some(e.0))
+            .collect();
+
+        // anyone could squat an empty leaf if its key was signed by the new key itself
+        let mut request: ChangePubKeyRequest<Bn256> = ChangePubKeyRequest {
+            account: Fr::from_str(&leaf_number.to_string()),
+            new_pub_x: Some(new_x),
+            new_pub_y: Some(new_y),
+            nonce: Some(Fr::zero()),
+            chain_id: Some(Fr::one()),
+            signature: None,
+        };
+
+        request.sign(&new_sk, p_g, params, rng);
+        assert!(request.signature.is_some());
+
+        let leaf_witness = LeafWitness {
+            balance: Some(Fr::zero()),
+            balance_path: CircuitAccount::<Bn256>::default()
+                .balance_path(0, params)
+                .into_iter()
+                .map(Some)
+                .collect(),
+            nonce: Some(Fr::zero()),
+            pub_x: Some(Fr::zero()),
+            pub_y: Some(Fr::zero()),
+            pub_key_hash: leaf_pub_key_hash(&Fr::zero(), &Fr::zero()),
+            cheque_bits: Some(Fr::zero()),
+            cheque_start: Some(Fr::zero()),
+            lock_commitment: Some(Fr::zero()),
+        };
+
+        let witness = ChangePubKeyWitness {
+            leaf: leaf_witness,
+            auth_path,
+        };
+
+        let registered_leaf = CircuitAccount::<Bn256> {
+            nonce: Fr::one(),
+            pub_x: new_x,
+            pub_y: new_y,
+            ..Default::default()
+        };
+
+        tree.insert(leaf_number, registered_leaf);
+
+        let new_root = tree.root_hash();
+
+        assert_ne!(initial_root, new_root);
+
+        {
+            let mut cs = TestConstraintSystem::<Bn256>::new();
+
+            let mut public_data_initial_bits = Vec::new();
+
+            let block_number_bits: Vec<bool> = BitIterator::new(Fr::one().into_repr()).collect();
+            for _ in 0..256 - block_number_bits.len() {
+                public_data_initial_bits.push(false);
+            }
+            public_data_initial_bits.extend(block_number_bits.into_iter());
+
+            let mut h = Sha256::new();
+
+            let bytes_to_hash = be_bit_vector_into_bytes(&public_data_initial_bits);
+
+            h.input(&bytes_to_hash);
+
+            let mut hash_result = [0u8; 32];
+            h.result(&mut hash_result[..]);
+
+            let packed_transaction_data_bytes =
+                be_bit_vector_into_bytes(&request.public_data_into_bits());
+
+            let mut next_round_hash_bytes = vec![];
+            next_round_hash_bytes.extend(hash_result.iter());
+            next_round_hash_bytes.extend(packed_transaction_data_bytes);
+
+            h = Sha256::new();
+            h.input(&next_round_hash_bytes);
+            hash_result = [0u8; 32];
+            h.result(&mut hash_result[..]);
+
+            hash_result[0] &= 0x1f; // temporary solution
+
+            let mut repr = Fr::zero().into_repr();
+            repr.read_be(&hash_result[..])
+                .expect("pack hash as field element");
+
+            let public_data_commitment = Fr::from_repr(repr).unwrap();
+
+            let instance = ChangePubKey {
+                params,
+                number_of_changes: 1,
+                chain_id: Fr::one(),
+                old_root: Some(initial_root),
+                new_root: Some(new_root),
+                public_data_commitment: Some(public_data_commitment),
+                block_number: Some(Fr::one()),
+                requests: vec![(request, witness)],
+            };
+
+            // zero key of the empty leaf is not a curve point and signs nothing
+            let refused = instance.synthesize(&mut cs).is_err() || !cs.is_satisfied();
+            assert!(
+                refused,
+                "key of an empty leaf registered by a self-signed change"
+            );
+        }
+    }
+}
@@ -0,0 +1,2 @@
+pub mod change_pubkey_request;
+pub mod circuit;
@@ -1,5 +1,6 @@
 use bellman::{Circuit, ConstraintSystem, SynthesisError};
 use ff::{Field, PrimeField};
+use models::plasma::params as plasma_constants;
 use sapling_crypto::circuit::num::{AllocatedNum, Num};
 use sapling_crypto::circuit::polynomial_lookup::{do_the_lookup, generate_powers};
 use sapling_crypto::circuit::{boolean, Assignment};
@@ -167,291 +168,320 @@ fn generate_start_adjustment_polynomial<E: JubjubEngine>(
 
 impl<'a, E: JubjubEngine> Circuit<E> for BitSet<'a, E> {
     fn synthesize<CS: ConstraintSystem<E>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
-        let bitfield_length = 128u128;
-        let shift_length = 64u128;
-        // let log_shift_length = 6;
-
-        // distance in range [0, 127] is without shift
-        // distance [128, 191] is with shift
-        let max_valid_distance = bitfield_length + shift_length - 1u128;
-        let lookup_polynomial_length = bitfield_length * 2;
-        let lookup_argument_bit_width = 8;
-
         let (bit_number, witness) = self.action;
         let current_bits_fe =
             AllocatedNum::alloc(cs.namespace(|| "allocate bits witness"), || {
                 Ok(*witness.bits.get()?)
             })?;
 
-        // current bits is in the field and is of predefined bit length
-        current_bits_fe.limit_number_of_bits(
-            cs.namespace(|| "limit number of bits of the bitfield"),
-            bitfield_length as usize,
-        )?;
-
         let start = AllocatedNum::alloc(cs.namespace(|| "allocate window start witness"), || {
             Ok(*witness.start.get()?)
         })?;
 
-        let two_inverted = E::Fr::from_str("2").unwrap().inverse().unwrap();
-
-        let current_bits = current_bits_fe.into_bits_le(cs.namespace(|| "get current bits"))?;
-
         let bit_number = AllocatedNum::alloc(cs.namespace(|| "allocate bit number"), || {
             Ok(*bit_number.number.get()?)
         })?;
 
-        start.limit_number_of_bits(cs.namespace(|| "limit start as 2^32"), 32)?;
-
-        let distance = AllocatedNum::alloc(cs.namespace(|| "allocate distance"), || {
-            let mut num = *bit_number.get_value().get()?;
-            let start = *start.get_value().get()?;
-            num.sub_assign(&start);
-
-            Ok(num)
-        })?;
-
-        cs.enforce(
-            || "enforce distance calculation",
-            |lc| lc + distance.get_variable(),
-            |lc| lc + CS::one(),
-            |lc| lc + bit_number.get_variable() - start.get_variable(),
-        );
-
-        // distance must be smaller than 128*2 as a first limit for further use of polynomial tricks
-        distance.limit_number_of_bits(
-            cs.namespace(|| "limit distance as 2^8"),
-            lookup_argument_bit_width,
+        set_bit(
+            cs.namespace(|| "set bit"),
+            &current_bits_fe,
+            &start,
+            &bit_number,
         )?;
 
-        let distance_powers = generate_powers(
-            cs.namespace(|| "generate powers of distance variable"),
-            &distance,
-            lookup_polynomial_length as usize,
-        )?;
-
-        assert_eq!(distance_powers.len(), lookup_polynomial_length as usize);
-
-        let bit_lookup_coeffs =
-            generate_bit_lookup_polynomial::<E>(0u128, bitfield_length, max_valid_distance);
-        assert_eq!(
-            bit_lookup_coeffs.len(),
-            (bitfield_length + shift_length) as usize
-        );
-
-        let mask_lookup_coeffs =
-            generate_shift_bitmask_polynomial::<E>(0u128, bitfield_length, max_valid_distance);
-        assert_eq!(
-            mask_lookup_coeffs.len(),
-            (bitfield_length + shift_length) as usize
-        );
-
-        let valid_distance_lookup_coeffs = generate_correctness_polynomial::<E>(
-            0u128,
-            bitfield_length + shift_length - 1u128,
-            (1u128 << lookup_argument_bit_width) - 1u128,
-        );
-        assert_eq!(
-            valid_distance_lookup_coeffs.len(),
-            lookup_polynomial_length as usize
-        );
-
-        let start_adjustment_lookup_coeffs =
-            generate_start_adjustment_polynomial::<E>(0u128, bitfield_length, max_valid_distance);
-        assert_eq!(
-            start_adjustment_lookup_coeffs.len(),
-            (bitfield_length + shift_length) as usize
-        );
-
-        let is_valid = do_the_lookup(
-            cs.namespace(|| "lookup distance validity"),
-            &valid_distance_lookup_coeffs,
-            &distance_powers,
-        )?;
-
-        let mask_fe = do_the_lookup(
-            cs.namespace(|| "lookup shift mask"),
-            &mask_lookup_coeffs,
-            &distance_powers[0..((bitfield_length + shift_length) as usize)],
-        )?;
+        Ok(())
+    }
+}
 
-        let bit_position_fe = do_the_lookup(
-            cs.namespace(|| "lookup bit position"),
-            &bit_lookup_coeffs,
-            &distance_powers[0..((bitfield_length + shift_length) as usize)],
+/// Sets the bit of `bit_number` in the window, the bit must not be set yet.
+/// A bit past the end of the window moves the window forward, so that it becomes the last bit.
+/// Returns new bits and start of the window
+pub fn set_bit<E, CS>(
+    mut cs: CS,
+    current_bits_fe: &AllocatedNum<E>,
+    start: &AllocatedNum<E>,
+    bit_number: &AllocatedNum<E>,
+) -> Result<(AllocatedNum<E>, AllocatedNum<E>), SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    let bitfield_length = plasma_constants::CHEQUE_WINDOW_BIT_WIDTH as u128;
+    let shift_length = plasma_constants::CHEQUE_WINDOW_SHIFT as u128;
+    // let log_shift_length = 6;
+
+    // distance in range [0, bitfield_length) is without shift
+    // distance [bitfield_length, bitfield_length + shift_length) is with shift
+    let max_valid_distance = bitfield_length + shift_length - 1u128;
+    let lookup_polynomial_length = bitfield_length * 2;
+    // the distance is limited to the domain of the lookup polynomials
+    let lookup_argument_bit_width = lookup_polynomial_length.trailing_zeros() as usize;
+
+    // current bits is in the field and is of predefined bit length
+    current_bits_fe.limit_number_of_bits(
+        cs.namespace(|| "limit number of bits of the bitfield"),
+        bitfield_length as usize,
+    )?;
+
+    let two_inverted = E::Fr::from_str("2").unwrap().inverse().unwrap();
+
+    let current_bits = current_bits_fe.into_bits_le(cs.namespace(|| "get current bits"))?;
+
+    start.limit_number_of_bits(
+        cs.namespace(|| "limit start as 2^32"),
+        plasma_constants::CHEQUE_SERIAL_BIT_WIDTH,
+    )?;
+
+    let distance = AllocatedNum::alloc(cs.namespace(|| "allocate distance"), || {
+        let mut num = *bit_number.get_value().get()?;
+        let start = *start.get_value().get()?;
+        num.sub_assign(&start);
+
+        Ok(num)
+    })?;
+
+    cs.enforce(
+        || "enforce distance calculation",
+        |lc| lc + distance.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + bit_number.get_variable() - start.get_variable(),
+    );
+
+    // distance must be smaller than bitfield_length * 2 as a first limit for the polynomial tricks
+    distance.limit_number_of_bits(
+        cs.namespace(|| "limit distance bits"),
+        lookup_argument_bit_width,
+    )?;
+
+    let distance_powers = generate_powers(
+        cs.namespace(|| "generate powers of distance variable"),
+        &distance,
+        lookup_polynomial_length as usize,
+    )?;
+
+    assert_eq!(distance_powers.len(), lookup_polynomial_length as usize);
+
+    let bit_lookup_coeffs =
+        generate_bit_lookup_polynomial::<E>(0u128, bitfield_length, max_valid_distance);
+    assert_eq!(
+        bit_lookup_coeffs.len(),
+        (bitfield_length + shift_length) as usize
+    );
+
+    let mask_lookup_coeffs =
+        generate_shift_bitmask_polynomial::<E>(0u128, bitfield_length, max_valid_distance);
+    assert_eq!(
+        mask_lookup_coeffs.len(),
+        (bitfield_length + shift_length) as usize
+    );
+
+    let valid_distance_lookup_coeffs = generate_correctness_polynomial::<E>(
+        0u128,
+        bitfield_length + shift_length - 1u128,
+        (1u128 << lookup_argument_bit_width) - 1u128,
+    );
+    assert_eq!(
+        valid_distance_lookup_coeffs.len(),
+        lookup_polynomial_length as usize
+    );
+
+    let start_adjustment_lookup_coeffs =
+        generate_start_adjustment_polynomial::<E>(0u128, bitfield_length, max_valid_distance);
+    assert_eq!(
+        start_adjustment_lookup_coeffs.len(),
+        (bitfield_length + shift_length) as usize
+    );
+
+    let is_valid = do_the_lookup(
+        cs.namespace(|| "lookup distance validity"),
+        &valid_distance_lookup_coeffs,
+        &distance_powers,
+    )?;
+
+    let mask_fe = do_the_lookup(
+        cs.namespace(|| "lookup shift mask"),
+        &mask_lookup_coeffs,
+        &distance_powers[0..((bitfield_length + shift_length) as usize)],
+    )?;
+
+    let bit_position_fe = do_the_lookup(
+        cs.namespace(|| "lookup bit position"),
+        &bit_lookup_coeffs,
+        &distance_powers[0..((bitfield_length + shift_length) as usize)],
+    )?;
+
+    cs.enforce(
+        || "enforce distance is valid",
+        |lc| lc + is_valid.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + CS::one(),
+    );
+
+    let mask_bits = mask_fe.into_bits_le(cs.namespace(|| "bitshift mask bit decomposition"))?;
+
+    // current_bits.truncate(bitfield_length as usize);
+    // mask_bits.truncate(bitfield_length as usize);
+
+    let mut masked_bits: Vec<boolean::Boolean> = vec![];
+    assert_eq!(current_bits.len(), mask_bits.len());
+
+    // mask the field bits to further make a shift. This is basically bitfield mod 2^shift
+    for (i, (mask_bit, field_bit)) in mask_bits.iter().zip(current_bits.iter()).enumerate() {
+        let bit = boolean::Boolean::and(
+            cs.namespace(|| format!("mask the field bit {}", i)),
+            mask_bit,
+            field_bit,
         )?;
 
-        cs.enforce(
-            || "enforce distance is valid",
-            |lc| lc + is_valid.get_variable(),
-            |lc| lc + CS::one(),
-            |lc| lc + CS::one(),
-        );
-
-        let mask_bits = mask_fe.into_bits_le(cs.namespace(|| "bitshift mask bit decomposition"))?;
-
-        // current_bits.truncate(bitfield_length as usize);
-        // mask_bits.truncate(bitfield_length as usize);
-
-        let mut masked_bits: Vec<boolean::Boolean> = vec![];
-        assert_eq!(current_bits.len(), mask_bits.len());
-
-        // mask the field bits to further make a shift. This is basically bitfield mod 2^shift
-        for (i, (mask_bit, field_bit)) in mask_bits.iter().zip(current_bits.iter()).enumerate() {
-            let bit = boolean::Boolean::and(
-                cs.namespace(|| format!("mask the field bit {}", i)),
-                mask_bit,
-                field_bit,
-            )?;
-
-            masked_bits.push(bit);
-        }
-
-        // repack remainder before shifting
-        let mut masked_lc = Num::<E>::zero();
-        let mut coeff = E::Fr::one();
-        for bit in &masked_bits {
-            masked_lc = masked_lc.add_bool_with_coeff(CS::one(), &bit, coeff);
-            coeff.double();
-        }
-
-        let remainder = AllocatedNum::alloc(
-            cs.namespace(|| "allocate the remainder after bitmask"),
-            || Ok(*masked_lc.get_value().get()?),
-        )?;
+        masked_bits.push(bit);
+    }
 
-        cs.enforce(
-            || "pack the remainder after bitmasking",
-            |lc| lc + remainder.get_variable(),
-            |lc| lc + CS::one(),
-            |_| masked_lc.lc(E::Fr::one()),
-        );
+    // repack remainder before shifting
+    let mut masked_lc = Num::<E>::zero();
+    let mut coeff = E::Fr::one();
+    for bit in &masked_bits {
+        masked_lc = masked_lc.add_bool_with_coeff(CS::one(), &bit, coeff);
+        coeff.double();
+    }
 
-        let quotient = AllocatedNum::alloc(
-            cs.namespace(|| "allocate top field bits after masking"),
+    let remainder = AllocatedNum::alloc(
+        cs.namespace(|| "allocate the remainder after bitmask"),
+        || Ok(*masked_lc.get_value().get()?),
+    )?;
+
+    cs.enforce(
+        || "pack the remainder after bitmasking",
+        |lc| lc + remainder.get_variable(),
+        |lc| lc + CS::one(),
+        |_| masked_lc.lc(E::Fr::one()),
+    );
+
+    let quotient = AllocatedNum::alloc(
+        cs.namespace(|| "allocate top field bits after masking"),
+        || {
+            let mut initial = *current_bits_fe.get_value().get()?;
+            let masked = *remainder.get_value().get()?;
+            initial.sub_assign(&masked);
+
+            Ok(initial)
+        },
+    )?;
+
+    cs.enforce(
+        || "enforce top bits after masking",
+        |lc| lc + quotient.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + current_bits_fe.get_variable() - remainder.get_variable(),
+    );
+
+    let mut shifted_quotient = quotient.clone();
+
+    // do the bitshifting
+    for (i, mask_bit) in mask_bits.iter().enumerate() {
+        let multiplier = AllocatedNum::alloc(
+            cs.namespace(|| format!("allocate bitshift multiplier {}", i)),
             || {
-                let mut initial = *current_bits_fe.get_value().get()?;
-                let masked = *remainder.get_value().get()?;
-                initial.sub_assign(&masked);
-
-                Ok(initial)
+                let bval = *mask_bit.get_value().get()?;
+                if !bval {
+                    Ok(E::Fr::one())
+                } else {
+                    Ok(two_inverted)
+                }
             },
         )?;
 
-        cs.enforce(
-            || "enforce top bits after masking",
-            |lc| lc + quotient.get_variable(),
-            |lc| lc + CS::one(),
-            |lc| lc + current_bits_fe.get_variable() - remainder.get_variable(),
-        );
-
-        let mut shifted_quotient = quotient.clone();
-
-        // do the bitshifting
-        for (i, mask_bit) in mask_bits.iter().enumerate() {
-            let multiplier = AllocatedNum::alloc(
-                cs.namespace(|| format!("allocate bitshift multiplier {}", i)),
-                || {
-                    let bval = *mask_bit.get_value().get()?;
-                    if !bval {
-                        Ok(E::Fr::one())
-                    } else {
-                        Ok(two_inverted)
-                    }
-                },
-            )?;
-
-            // b*two_inv + (1 - b) * 1 = b(two_inv - i) - 1
-
-            let mut c = two_inverted;
-            c.sub_assign(&E::Fr::one());
-
-            cs.enforce(
-                || format!("enforce multiplier selection {}", i),
-                |lc| lc + multiplier.get_variable(),
-                |lc| lc + CS::one(),
-                |_| mask_bit.lc::<E>(CS::one(), c) + CS::one(),
-            );
-
-            shifted_quotient = shifted_quotient
-                .mul(cs.namespace(|| format!("do the shift {}", i)), &multiplier)?;
-        }
-
-        // decompose the resulting register state
-
-        let shifted_bits =
-            shifted_quotient.into_bits_le(cs.namespace(|| "get shifted register bits"))?;
-
-        let bit_position_bits =
-            bit_position_fe.into_bits_le(cs.namespace(|| "get bit of interest mask bits"))?;
-
-        for (i, (reg_bit, position_bit)) in shifted_bits
-            .iter()
-            .zip(bit_position_bits.iter())
-            .enumerate()
-        {
-            // enforce that bit is not set
-            // reg_bit * lookup_bit = 0
-            // reg_bit = 1, lookup_bit = 0 -> valid
-            // reg_bit = 0, lookup_bit = 0 -> valid
-            // reg_bit = 0, lookup_bit = 1 -> valid, bit is not set
-            // reg_bit = 1, lookup_bit = 1 -> invalid, bit is already set
-            cs.enforce(
-                || format!("enforce shifted register bit is not set, iteraction {}", i),
-                |_| reg_bit.lc::<E>(CS::one(), E::Fr::one()),
-                |_| position_bit.lc::<E>(CS::one(), E::Fr::one()),
-                |lc| lc,
-            );
-        }
-
-        // make a final register state
-
-        let new_register = AllocatedNum::alloc(cs.namespace(|| "allocate new register"), || {
-            let mut new_val = *shifted_quotient.get_value().get()?;
-            let position = *bit_position_fe.get_value().get()?;
-            new_val.add_assign(&position);
+        // b*two_inv + (1 - b) * 1 = b(two_inv - i) - 1
 
-            Ok(new_val)
-        })?;
+        let mut c = two_inverted;
+        c.sub_assign(&E::Fr::one());
 
         cs.enforce(
-            || "enforce new register",
-            |lc| lc + new_register.get_variable(),
+            || format!("enforce multiplier selection {}", i),
+            |lc| lc + multiplier.get_variable(),
             |lc| lc + CS::one(),
-            |lc| lc + shifted_quotient.get_variable() + bit_position_fe.get_variable(),
+            |_| mask_bit.lc::<E>(CS::one(), c) + CS::one(),
         );
 
-        let start_adjustment = do_the_lookup(
-            cs.namespace(|| "create start adjustment"),
-            &start_adjustment_lookup_coeffs,
-            &distance_powers[0..((bitfield_length + shift_length) as usize)],
-        )?;
-
-        // start_adjustment.limit_number_of_bits(
-        //     cs.namespace(|| "limit number of bits in the start change"),
-        //     log_shift_length + 1
-        // )?;
-
-        let new_start = AllocatedNum::alloc(cs.namespace(|| "allocate new start"), || {
-            let mut new_val = *start.get_value().get()?;
-            let shift = *start_adjustment.get_value().get()?;
-            new_val.add_assign(&shift);
-
-            Ok(new_val)
-        })?;
+        shifted_quotient =
+            shifted_quotient.mul(cs.namespace(|| format!("do the shift {}", i)), &multiplier)?;
+    }
 
+    // decompose the resulting register state
+
+    let shifted_bits =
+        shifted_quotient.into_bits_le(cs.namespace(|| "get shifted register bits"))?;
+
+    let bit_position_bits =
+        bit_position_fe.into_bits_le(cs.namespace(|| "get bit of interest mask bits"))?;
+
+    for (i, (reg_bit, position_bit)) in shifted_bits
+        .iter()
+        .zip(bit_position_bits.iter())
+        .enumerate()
+    {
+        // enforce that bit is not set
+        // reg_bit * lookup_bit = 0
+        // reg_bit = 1, lookup_bit = 0 -> valid
+        // reg_bit = 0, lookup_bit = 0 -> valid
+        // reg_bit = 0, lookup_bit = 1 -> valid, bit is not set
+        // reg_bit = 1, lookup_bit = 1 -> invalid, bit is already set
         cs.enforce(
-            || "enforce new start",
-            |lc| lc + new_start.get_variable(),
-            |lc| lc + CS::one(),
-            |lc| lc + start.get_variable() + start_adjustment.get_variable(),
+            || format!("enforce shifted register bit is not set, iteraction {}", i),
+            |_| reg_bit.lc::<E>(CS::one(), E::Fr::one()),
+            |_| position_bit.lc::<E>(CS::one(), E::Fr::one()),
+            |lc| lc,
         );
-
-        new_start
-            .limit_number_of_bits(cs.namespace(|| "limit number of bits for a new start"), 32)?;
-
-        Ok(())
     }
+
+    // make a final register state
+
+    let new_register = AllocatedNum::alloc(cs.namespace(|| "allocate new register"), || {
+        let mut new_val = *shifted_quotient.get_value().get()?;
+        let position = *bit_position_fe.get_value().get()?;
+        new_val.add_assign(&position);
+
+        Ok(new_val)
+    })?;
+
+    cs.enforce(
+        || "enforce new register",
+        |lc| lc + new_register.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + shifted_quotient.get_variable() + bit_position_fe.get_variable(),
+    );
+
+    let start_adjustment = do_the_lookup(
+        cs.namespace(|| "create start adjustment"),
+        &start_adjustment_lookup_coeffs,
+        &distance_powers[0..((bitfield_length + shift_length) as usize)],
+    )?;
+
+    // start_adjustment.limit_number_of_bits(
+    //     cs.namespace(|| "limit number of bits in the start change"),
+    //     log_shift_length + 1
+    // )?;
+
+    let new_start = AllocatedNum::alloc(cs.namespace(|| "allocate new start"), || {
+        let mut new_val = *start.get_value().get()?;
+        let shift = *start_adjustment.get_value().get()?;
+        new_val.add_assign(&shift);
+
+        Ok(new_val)
+    })?;
+
+    cs.enforce(
+        || "enforce new start",
+        |lc| lc + new_start.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + start.get_variable() + start_adjustment.get_variable(),
+    );
+
+    new_start.limit_number_of_bits(
+        cs.namespace(|| "limit number of bits for a new start"),
+        plasma_constants::CHEQUE_SERIAL_BIT_WIDTH,
+    )?;
+
+    Ok((new_register, new_start))
 }
 
 #[cfg(test)]
@@ -0,0 +1,194 @@
+use ff::{BitIterator, PrimeField};
+use models::plasma::circuit::sig::TransactionSignature;
+use models::plasma::circuit::utils::le_bit_vector_into_field_element;
+use models::plasma::params as plasma_constants;
+use sapling_crypto::eddsa::{PrivateKey, PublicKey};
+use sapling_crypto::jubjub::{FixedGenerators, JubjubEngine};
+
+// This is a request to redeem a cheque signed by the payer
+
+#[derive(Clone)]
+pub struct ChequeRequest<E: JubjubEngine> {
+    pub from: Option<E::Fr>,
+    pub to: Option<E::Fr>,
+    pub token: Option<E::Fr>,
+    // packed amount
+    pub amount: Option<E::Fr>,
+    pub serial: Option<E::Fr>,
+    pub good_until_block: Option<E::Fr>,
+    // deployment the request is signed for, the circuit checks it against its own
+    pub chain_id: Option<E::Fr>,
+    pub signature: Option<TransactionSignature<E>>,
+}
+
+impl<E: JubjubEngine> ChequeRequest<E> {
+    pub fn public_data_into_bits(&self) -> Vec<bool> {
+        // fields are
+        // - from
+        // - to
+        // - token
+        // - amount
+        // - serial
+        let mut from: Vec<bool> = BitIterator::new(self.from.unwrap().into_repr()).collect();
+        from.reverse();
+        from.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+        // reverse again to have BE as in Ethereum native types
+        from.reverse();
+
+        let mut to: Vec<bool> = BitIterator::new(self.to.unwrap().into_repr()).collect();
+        to.reverse();
+        to.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+        to.reverse();
+
+        let mut token: Vec<bool> = BitIterator::new(self.token.unwrap().into_repr()).collect();
+        token.reverse();
+        token.truncate(plasma_constants::TOKEN_BIT_WIDTH);
+        token.reverse();
+
+        // packed amount is kept LE, the same as in transfers
+        let mut amount: Vec<bool> = BitIterator::new(self.amount.unwrap().into_repr()).collect();
+        amount.reverse();
+        amount.truncate(
+            plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH
+                + plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH,
+        );
+
+        let mut serial: Vec<bool> = BitIterator::new(self.serial.unwrap().into_repr()).collect();
+        serial.reverse();
+        serial.truncate(plasma_constants::CHEQUE_SERIAL_BIT_WIDTH);
+        serial.reverse();
+
+        let mut packed: Vec<bool> = vec![];
+        packed.extend(from.into_iter());
+        packed.extend(to.into_iter());
+        packed.extend(token.into_iter());
+        packed.extend(amount.into_iter());
+        packed.extend(serial.into_iter());
+
+        packed
+    }
+
+    // this function returns data to make a request signature
+    // in a format that is later used in zkSNARK
+    pub fn data_for_signature_into_bits(&self) -> Vec<bool> {
+        // fields are
+        // - from
+        // - to
+        // - token
+        // - amount
+        // - serial
+        // - good_until_block
+        // - chain_id
+        let mut from: Vec<bool> = BitIterator::new(self.from.unwrap().into_repr()).collect();
+        from.reverse();
+        from.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+
+        let mut to: Vec<bool> = BitIterator::new(self.to.unwrap().into_repr()).collect();
+        to.reverse();
+        to.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+
+        let mut token: Vec<bool> = BitIterator::new(self.token.unwrap().into_repr()).collect();
+        token.reverse();
+        token.truncate(plasma_constants::TOKEN_BIT_WIDTH);
+
+        let mut amount: Vec<bool> = BitIterator::new(self.amount.unwrap().into_repr()).collect();
+        amount.reverse();
+        amount.truncate(
+            plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH
+                + plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH,
+        );
+
+        let mut serial: Vec<bool> = BitIterator::new(self.serial.unwrap().into_repr()).collect();
+        serial.reverse();
+        serial.truncate(plasma_constants::CHEQUE_SERIAL_BIT_WIDTH);
+
+        let mut good_until_block: Vec<bool> =
+            BitIterator::new(self.good_until_block.unwrap().into_repr()).collect();
+        good_until_block.reverse();
+        good_until_block.truncate(plasma_constants::BLOCK_NUMBER_BIT_WIDTH);
+
+        let mut chain_id: Vec<bool> =
+            BitIterator::new(self.chain_id.unwrap().into_repr()).collect();
+        chain_id.reverse();
+        chain_id.truncate(plasma_constants::CHAIN_ID_BIT_WIDTH);
+
+        let mut packed: Vec<bool> = vec![];
+
+        packed.extend(from.into_iter());
+        packed.extend(to.into_iter());
+        packed.extend(token.into_iter());
+        packed.extend(amount.into_iter());
+        packed.extend(serial.into_iter());
+        packed.extend(good_until_block.into_iter());
+        packed.extend(chain_id.into_iter());
+
+        packed
+    }
+
+    pub fn data_as_bytes(&self) -> Vec<u8> {
+        let raw_data: Vec<bool> = self.data_for_signature_into_bits();
+
+        let mut message_bytes: Vec<u8> = vec![];
+
+        let byte_chunks = raw_data.chunks(8);
+        for byte_chunk in byte_chunks {
+            let mut byte = 0u8;
+            for (i, bit) in byte_chunk.iter().enumerate() {
+                if *bit {
+                    byte |= 1 << i;
+                }
+            }
+            message_bytes.push(byte);
+        }
+
+        message_bytes
+    }
+
+    pub fn sign<R>(
+        &mut self,
+        private_key: &PrivateKey<E>,
+        p_g: FixedGenerators,
+        params: &E::Params,
+        rng: &mut R,
+    ) where
+        R: rand::Rng,
+    {
+        let message_bytes = self.data_as_bytes();
+
+        let max_message_len = plasma_constants::BALANCE_TREE_DEPTH
+            + plasma_constants::BALANCE_TREE_DEPTH
+            + plasma_constants::TOKEN_BIT_WIDTH
+            + plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH
+            + plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH
+            + plasma_constants::CHEQUE_SERIAL_BIT_WIDTH
+            + plasma_constants::BLOCK_NUMBER_BIT_WIDTH
+            + plasma_constants::CHAIN_ID_BIT_WIDTH;
+
+        let signature =
+            private_key.sign_raw_message(&message_bytes, rng, p_g, params, max_message_len / 8);
+
+        let pk = PublicKey::from_private(&private_key, p_g, params);
+        let is_valid_signature = pk.verify_for_raw_message(
+            &message_bytes,
+            &signature.clone(),
+            p_g,
+            params,
+            max_message_len / 8,
+        );
+        if !is_valid_signature {
+            return;
+        }
+
+        let mut sigs_le_bits: Vec<bool> = BitIterator::new(signature.s.into_repr()).collect();
+        sigs_le_bits.reverse();
+
+        let sigs_converted = le_bit_vector_into_field_element(&sigs_le_bits);
+
+        let converted_signature = TransactionSignature {
+            r: signature.r,
+            s: sigs_converted,
+        };
+
+        self.signature = Some(converted_signature);
+    }
+}
@@ -0,0 +1,942 @@
+use crate::cheque::bitwindow::set_bit;
+use crate::cheque::cheque_request::ChequeRequest;
+use crate::leaf::{
+    account_leaf_hash, account_tree_node, balances_root_into_bits, calculate_balances_root,
+    leaf_state_bits, make_leaf_content, LeafWitness,
+};
+use bellman::{Circuit, ConstraintSystem, SynthesisError};
+use ff::{Field, PrimeField};
+use models::plasma::circuit::utils::allocate_audit_path;
+use models::plasma::params as plasma_constants;
+use sapling_crypto::circuit::baby_eddsa::EddsaSignature;
+use sapling_crypto::circuit::float_point::parse_with_exponent_le;
+use sapling_crypto::circuit::num::{AllocatedNum, Num};
+use sapling_crypto::circuit::{boolean, ecc, num, sha256, Assignment};
+use sapling_crypto::jubjub::{FixedGenerators, JubjubEngine, JubjubParams};
+
+#[derive(Clone)]
+pub struct ChequeWitness<E: JubjubEngine> {
+    pub leaf_from: LeafWitness<E>,
+    pub auth_path_from: Vec<Option<E::Fr>>,
+    // payee's leaf is opened in the tree where the payer's leaf is already updated
+    pub leaf_to: LeafWitness<E>,
+    pub auth_path_to: Vec<Option<E::Fr>>,
+}
+
+/// This is an instance of the `Cheque` circuit.
+pub struct Cheque<'a, E: JubjubEngine> {
+    pub params: &'a E::Params,
+
+    // number of cheques per block
+    pub number_of_cheques: usize,
+
+    /// Chain id of the deployment, it is fixed when the keys are generated
+    pub chain_id: E::Fr,
+
+    /// The old root of the tree
+    pub old_root: Option<E::Fr>,
+
+    /// The new root of the tree
+    pub new_root: Option<E::Fr>,
+
+    /// Final truncated rolling SHA256
+    pub public_data_commitment: Option<E::Fr>,
+
+    /// Block number
+    pub block_number: Option<E::Fr>,
+
+    /// Requests for this block
+    pub requests: Vec<(ChequeRequest<E>, ChequeWitness<E>)>,
+}
+
+impl<'a, E: JubjubEngine> Circuit<E> for Cheque<'a, E> {
+    fn synthesize<CS: ConstraintSystem<E>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        // Check that requests are in a right quantity
+        assert!(self.number_of_cheques == self.requests.len());
+
+        let old_root_value = self.old_root;
+        // Expose inputs and do the bits decomposition of hash
+        let mut old_root =
+            AllocatedNum::alloc(cs.namespace(|| "old root"), || Ok(*old_root_value.get()?))?;
+        old_root.inputize(cs.namespace(|| "old root input"))?;
+
+        let new_root_value = self.new_root;
+        let new_root =
+            AllocatedNum::alloc(cs.namespace(|| "new root"), || Ok(*new_root_value.get()?))?;
+        new_root.inputize(cs.namespace(|| "new root input"))?;
+
+        let rolling_hash_value = self.public_data_commitment;
+        let rolling_hash = AllocatedNum::alloc(cs.namespace(|| "rolling hash"), || {
+            Ok(*rolling_hash_value.get()?)
+        })?;
+        rolling_hash.inputize(cs.namespace(|| "rolling hash input"))?;
+
+        let mut block_numbers = vec![];
+
+        let mut public_data_vector: Vec<boolean::Boolean> = vec![];
+
+        let public_generator = self
+            .params
+            .generator(FixedGenerators::SpendingKeyGenerator)
+            .clone();
+        let generator = ecc::EdwardsPoint::witness(
+            cs.namespace(|| "allocate public generator"),
+            Some(public_generator),
+            self.params,
+        )?;
+
+        // Ok, now we need to update the old root by applying requests in sequence
+        let requests = self.requests.clone();
+
+        for (i, tx) in requests.into_iter().enumerate() {
+            let (request, witness) = tx;
+            let (intermediate_root, block_number, public_data) = apply_request(
+                cs.namespace(|| format!("applying request {}", i)),
+                old_root,
+                request,
+                witness,
+                self.chain_id,
+                self.params,
+                generator.clone(),
+            )?;
+            old_root = intermediate_root;
+            block_numbers.push(block_number);
+
+            // flatten the public transaction data
+            public_data_vector.extend(public_data.into_iter());
+        }
+
+        // constraint the new hash to be equal to updated hash
+
+        cs.enforce(
+            || "enforce new root equal to recalculated one",
+            |lc| lc + new_root.get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc + old_root.get_variable(),
+        );
+
+        // Then check that for every cheque in this block
+        // the parameter "good until" was greater or equal
+        // than the current block number
+
+        let block_number_allocated =
+            AllocatedNum::alloc(cs.namespace(|| "allocate block number"), || {
+                Ok(*self.block_number.get()?)
+            })?;
+
+        for (i, block_number_in_tx) in block_numbers.into_iter().enumerate() {
+            // first name a new value and constraint that it's a proper subtraction
+
+            let difference_allocated = AllocatedNum::alloc(
+                cs.namespace(|| format!("allocate block number difference {}", i)),
+                || {
+                    let mut difference = *block_number_in_tx.get_value().get()?;
+                    difference.sub_assign(self.block_number.get()?);
+
+                    Ok(difference)
+                },
+            )?;
+
+            // check for overflow
+
+            difference_allocated.limit_number_of_bits(
+                cs.namespace(|| format!("check for subtraction overflow {}", i)),
+                plasma_constants::BLOCK_NUMBER_BIT_WIDTH,
+            )?;
+
+            // enforce proper subtraction
+            cs.enforce(
+                || format!("enforce subtraction in block number calculation {}", i),
+                |lc| lc + difference_allocated.get_variable(),
+                |lc| lc + CS::one(),
+                |lc| lc + block_number_in_tx.get_variable() - block_number_allocated.get_variable(),
+            );
+        }
+
+        // Now it's time to pack the initial SHA256 hash due to Ethereum BE encoding
+        // and start rolling the hash
+
+        let mut initial_hash_data: Vec<boolean::Boolean> = vec![];
+
+        // make initial hash as sha256(uint256(block_number))
+        let mut block_number_bits = block_number_allocated
+            .into_bits_le(cs.namespace(|| "unpack block number for hashing"))?;
+
+        block_number_bits.resize(
+            plasma_constants::FR_BIT_WIDTH,
+            boolean::Boolean::Constant(false),
+        );
+        block_number_bits.reverse();
+        initial_hash_data.extend(block_number_bits.into_iter());
+
+        assert_eq!(initial_hash_data.len(), 256);
+
+        let mut hash_block = sha256::sha256(
+            cs.namespace(|| "initial rolling sha256"),
+            &initial_hash_data,
+        )?;
+
+        // now pack the public data and do the final hash
+
+        let mut pack_bits = vec![];
+        pack_bits.extend(hash_block);
+        pack_bits.extend(public_data_vector.into_iter());
+
+        hash_block = sha256::sha256(cs.namespace(|| "hash public data"), &pack_bits)?;
+
+        // now pack and enforce equality to the input
+
+        hash_block.reverse();
+        hash_block.truncate(E::Fr::CAPACITY as usize);
+
+        let mut packed_hash_lc = Num::<E>::zero();
+        let mut coeff = E::Fr::one();
+        for bit in hash_block {
+            packed_hash_lc = packed_hash_lc.add_bool_with_coeff(CS::one(), &bit, coeff);
+            coeff.double();
+        }
+
+        cs.enforce(
+            || "enforce external data hash equality",
+            |lc| lc + rolling_hash.get_variable(),
+            |lc| lc + CS::one(),
+            |_| packed_hash_lc.lc(E::Fr::one()),
+        );
+
+        Ok(())
+    }
+}
+
+/// Ascends the merkle tree authentication path from the leaf,
+/// outputs the root
+fn calculate_root<E, CS>(
+    mut cs: CS,
+    leaf_bits: &[boolean::Boolean],
+    path_bits: &[boolean::Boolean],
+    audit_path: &[AllocatedNum<E>],
+    params: &E::Params,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    let mut cur = account_leaf_hash(cs.namespace(|| "leaf content hash"), leaf_bits, params)?;
+
+    for (i, direction_bit) in path_bits.iter().enumerate() {
+        let cs = &mut cs.namespace(|| format!("merkle tree hash {}", i));
+
+        // Swap the two if the current subtree is on the right
+        let (xl, xr) = num::AllocatedNum::conditionally_reverse(
+            cs.namespace(|| "conditional reversal of preimage"),
+            &cur,
+            &audit_path[i],
+            direction_bit,
+        )?;
+
+        // Compute the new subtree value
+        cur = account_tree_node(cs.namespace(|| "node hash"), &xl, &xr, i, params)?;
+    }
+
+    Ok(cur)
+}
+
+/// Repacks a truncated bit decomposition into a number
+fn pack_bits_into_num<E, CS>(
+    mut cs: CS,
+    bits: &[boolean::Boolean],
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    let mut packed_lc = Num::<E>::zero();
+    let mut coeff = E::Fr::one();
+    for bit in bits {
+        packed_lc = packed_lc.add_bool_with_coeff(CS::one(), bit, coeff);
+        coeff.double();
+    }
+
+    let packed = AllocatedNum::alloc(cs.namespace(|| "allocate packed value"), || {
+        Ok(*packed_lc.get_value().get()?)
+    })?;
+
+    cs.enforce(
+        || "pack bits",
+        |lc| lc + packed.get_variable(),
+        |lc| lc + CS::one(),
+        |_| packed_lc.lc(E::Fr::one()),
+    );
+
+    Ok(packed)
+}
+
+/// Applies one request to the tree,
+/// outputs a new root
+#[allow(clippy::type_complexity)]
+fn apply_request<E, CS>(
+    mut cs: CS,
+    old_root: AllocatedNum<E>,
+    request: ChequeRequest<E>,
+    witness: ChequeWitness<E>,
+    chain_id: E::Fr,
+    params: &E::Params,
+    generator: ecc::EdwardsPoint<E>,
+) -> Result<(AllocatedNum<E>, AllocatedNum<E>, Vec<boolean::Boolean>), SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    // Payer and payee balances are of the same token
+
+    let token_allocated =
+        AllocatedNum::alloc(cs.namespace(|| "token"), || Ok(*request.token.get()?))?;
+
+    let mut token_bits =
+        token_allocated.into_bits_le(cs.namespace(|| "token bit decomposition"))?;
+
+    token_bits.truncate(plasma_constants::TOKEN_BIT_WIDTH);
+
+    let leaf_from = make_leaf_content(
+        cs.namespace(|| "create payer's leaf"),
+        witness.clone().leaf_from,
+        &token_bits,
+        params,
+    )?;
+
+    let from_address_allocated = AllocatedNum::alloc(cs.namespace(|| "payer address"), || {
+        Ok(*request.from.get()?)
+    })?;
+
+    let mut from_path_bits =
+        from_address_allocated.into_bits_le(cs.namespace(|| "payer address bit decomposition"))?;
+
+    from_path_bits.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+
+    let audit_path_from = allocate_audit_path(
+        cs.namespace(|| "allocate audit path for payer"),
+        witness.clone().auth_path_from,
+    )?;
+
+    let old_root_from = calculate_root(
+        cs.namespace(|| "payer's old root"),
+        &leaf_from.leaf_bits,
+        &from_path_bits,
+        &audit_path_from,
+        params,
+    )?;
+
+    // enforce old root before update
+    cs.enforce(
+        || "enforce correct old root for payer's leaf",
+        |lc| lc + old_root_from.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + old_root.get_variable(),
+    );
+
+    let to_address_allocated = AllocatedNum::alloc(cs.namespace(|| "payee address"), || {
+        Ok(*request.to.get()?)
+    })?;
+
+    let mut to_path_bits =
+        to_address_allocated.into_bits_le(cs.namespace(|| "payee address bit decomposition"))?;
+
+    to_path_bits.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+
+    // parse the rest of the cheque and check the payer's signature
+
+    let amount_encoded =
+        AllocatedNum::alloc(cs.namespace(|| "allocate encoded cheque amount"), || {
+            Ok(*request.amount.get()?)
+        })?;
+
+    let mut amount_bits = amount_encoded.into_bits_le(cs.namespace(|| "amount bits"))?;
+
+    amount_bits.truncate(
+        plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH + plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH,
+    );
+
+    let serial = AllocatedNum::alloc(cs.namespace(|| "allocate cheque serial"), || {
+        Ok(*request.serial.get()?)
+    })?;
+
+    // serial is used as a whole number in the window, so it must fit the signed bits
+    serial.limit_number_of_bits(
+        cs.namespace(|| "limit number of bits for cheque serial"),
+        plasma_constants::CHEQUE_SERIAL_BIT_WIDTH,
+    )?;
+
+    let mut serial_bits = serial.into_bits_le(cs.namespace(|| "serial bits"))?;
+
+    serial_bits.truncate(plasma_constants::CHEQUE_SERIAL_BIT_WIDTH);
+
+    let good_until_block =
+        AllocatedNum::alloc(cs.namespace(|| "allocate cheque good until block"), || {
+            Ok(*request.good_until_block.get()?)
+        })?;
+
+    let mut block_number_bits =
+        good_until_block.into_bits_le(cs.namespace(|| "block number bits"))?;
+
+    block_number_bits.truncate(plasma_constants::BLOCK_NUMBER_BIT_WIDTH);
+
+    let mut message_bits: Vec<boolean::Boolean> = vec![];
+    message_bits.extend(from_path_bits.clone());
+    message_bits.extend(to_path_bits.clone());
+    message_bits.extend(token_bits.clone());
+    message_bits.extend(amount_bits.clone());
+    message_bits.extend(serial_bits.clone());
+    message_bits.extend(block_number_bits);
+
+    let request_chain_id =
+        AllocatedNum::alloc(cs.namespace(|| "allocate request chain id"), || {
+            Ok(*request.chain_id.get()?)
+        })?;
+
+    // a cheque signed for another deployment is rejected
+    cs.enforce(
+        || "enforce chain id of the deployment",
+        |lc| lc + request_chain_id.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + (chain_id, CS::one()),
+    );
+
+    let mut chain_id_bits = request_chain_id.into_bits_le(cs.namespace(|| "chain id bits"))?;
+    chain_id_bits.truncate(plasma_constants::CHAIN_ID_BIT_WIDTH);
+    message_bits.extend(chain_id_bits);
+
+    let (pub_x, pub_y) = leaf_from.public_key(cs.namespace(|| "check payer public key"), params)?;
+
+    let payer_pk =
+        ecc::EdwardsPoint::interpret(cs.namespace(|| "payer public key"), &pub_x, &pub_y, params)?;
+
+    let signature_r_x = AllocatedNum::alloc(cs.namespace(|| "signature r_x witness"), || {
+        Ok(request.signature.get()?.r.into_xy().0)
+    })?;
+
+    let signature_r_y = AllocatedNum::alloc(cs.namespace(|| "signature r_y witness"), || {
+        Ok(request.signature.get()?.r.into_xy().1)
+    })?;
+
+    let signature_r = ecc::EdwardsPoint::interpret(
+        cs.namespace(|| "signature r as point"),
+        &signature_r_x,
+        &signature_r_y,
+        params,
+    )?;
+
+    let signature_s = AllocatedNum::alloc(cs.namespace(|| "signature s witness"), || {
+        Ok(request.signature.get()?.s)
+    })?;
+
+    let signature = EddsaSignature {
+        r: signature_r,
+        s: signature_s,
+        pk: payer_pk,
+    };
+
+    let max_message_len = plasma_constants::BALANCE_TREE_DEPTH
+        + plasma_constants::BALANCE_TREE_DEPTH
+        + plasma_constants::TOKEN_BIT_WIDTH
+        + plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH
+        + plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH
+        + plasma_constants::CHEQUE_SERIAL_BIT_WIDTH
+        + plasma_constants::BLOCK_NUMBER_BIT_WIDTH
+        + plasma_constants::CHAIN_ID_BIT_WIDTH;
+
+    signature.verify_raw_message_signature(
+        cs.namespace(|| "verify cheque signature"),
+        params,
+        &message_bits,
+        generator,
+        max_message_len,
+    )?;
+
+    let amount = parse_with_exponent_le(
+        cs.namespace(|| "parse amount"),
+        &amount_bits,
+        plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH,
+        plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH,
+        10,
+    )?;
+
+    // take the amount from the payer
+
+    let old_balance_from = pack_bits_into_num(
+        cs.namespace(|| "pack payer's old balance"),
+        &leaf_from.value_bits,
+    )?;
+
+    let new_balance_from = AllocatedNum::alloc(cs.namespace(|| "new balance from"), || {
+        let mut new_balance_value = *old_balance_from.get_value().get()?;
+        new_balance_value.sub_assign(amount.get_value().get()?);
+
+        Ok(new_balance_value)
+    })?;
+
+    // constraint no underflow
+    new_balance_from.limit_number_of_bits(
+        cs.namespace(|| "limit number of bits for new balance from"),
+        plasma_constants::BALANCE_BIT_WIDTH,
+    )?;
+
+    cs.enforce(
+        || "enforce new balance from",
+        |lc| lc + new_balance_from.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + old_balance_from.get_variable() - amount.get_variable(),
+    );
+
+    // mark the serial as redeemed, it must not be redeemed yet
+
+    let (new_cheque_bits, new_cheque_start) = set_bit(
+        cs.namespace(|| "redeem serial in the cheque window"),
+        &leaf_from.cheque_bits,
+        &leaf_from.cheque_start,
+        &serial,
+    )?;
+
+    let mut leaf_content = vec![];
+
+    let mut value_content =
+        new_balance_from.into_bits_le(cs.namespace(|| "payer's updated amount bits"))?;
+
+    value_content.truncate(plasma_constants::BALANCE_BIT_WIDTH);
+
+    let balances_root = calculate_balances_root(
+        cs.namespace(|| "payer's updated balances root"),
+        &value_content,
+        &token_bits,
+        &leaf_from.balance_path,
+        params,
+    )?;
+
+    leaf_content.extend(balances_root_into_bits(
+        cs.namespace(|| "payer's updated balances root bits"),
+        &balances_root,
+    )?);
+
+    // nonce and public key are kept as is
+    leaf_content.extend(leaf_from.nonce_bits.clone());
+    leaf_content.extend(leaf_from.pub_key_bits.clone());
+
+    let mut cheque_window_content =
+        new_cheque_bits.into_bits_le(cs.namespace(|| "payer's updated cheque bits"))?;
+    cheque_window_content.truncate(plasma_constants::CHEQUE_WINDOW_BIT_WIDTH);
+
+    let mut cheque_start_content =
+        new_cheque_start.into_bits_le(cs.namespace(|| "payer's updated cheque start bits"))?;
+    cheque_start_content.truncate(plasma_constants::CHEQUE_SERIAL_BIT_WIDTH);
+    cheque_window_content.extend(cheque_start_content);
+
+    leaf_content.extend(leaf_state_bits(
+        cs.namespace(|| "payer's updated leaf state bits"),
+        &cheque_window_content,
+        &leaf_from.lock_bits,
+        params,
+    )?);
+
+    assert_eq!(leaf_content.len(), leaf_from.leaf_bits.len());
+
+    let intermediate_root = calculate_root(
+        cs.namespace(|| "payer's updated root"),
+        &leaf_content,
+        &from_path_bits,
+        &audit_path_from,
+        params,
+    )?;
+
+    // now give the amount to the payee
+
+    let leaf_to = make_leaf_content(
+        cs.namespace(|| "create payee's leaf"),
+        witness.clone().leaf_to,
+        &token_bits,
+        params,
+    )?;
+
+    let audit_path_to = allocate_audit_path(
+        cs.namespace(|| "allocate audit path for payee"),
+        witness.clone().auth_path_to,
+    )?;
+
+    let old_root_to = calculate_root(
+        cs.namespace(|| "payee's old root"),
+        &leaf_to.leaf_bits,
+        &to_path_bits,
+        &audit_path_to,
+        params,
+    )?;
+
+    cs.enforce(
+        || "enforce correct intermediate root for payee's leaf",
+        |lc| lc + old_root_to.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + intermediate_root.get_variable(),
+    );
+
+    let old_balance_to = pack_bits_into_num(
+        cs.namespace(|| "pack payee's old balance"),
+        &leaf_to.value_bits,
+    )?;
+
+    let new_balance_to = AllocatedNum::alloc(cs.namespace(|| "new balance to"), || {
+        let mut new_balance_value = *old_balance_to.get_value().get()?;
+        new_balance_value.add_assign(amount.get_value().get()?);
+
+        Ok(new_balance_value)
+    })?;
+
+    // constraint no overflow
+    new_balance_to.limit_number_of_bits(
+        cs.namespace(|| "limit number of bits for new balance to"),
+        plasma_constants::BALANCE_BIT_WIDTH,
+    )?;
+
+    cs.enforce(
+        || "enforce new balance to",
+        |lc| lc + new_balance_to.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + old_balance_to.get_variable() + amount.get_variable(),
+    );
+
+    let mut leaf_content = vec![];
+
+    let mut value_content =
+        new_balance_to.into_bits_le(cs.namespace(|| "payee's updated amount bits"))?;
+
+    value_content.truncate(plasma_constants::BALANCE_BIT_WIDTH);
+
+    let balances_root = calculate_balances_root(
+        cs.namespace(|| "payee's updated balances root"),
+        &value_content,
+        &token_bits,
+        &leaf_to.balance_path,
+        params,
+    )?;
+
+    leaf_content.extend(balances_root_into_bits(
+        cs.namespace(|| "payee's updated balances root bits"),
+        &balances_root,
+    )?);
+
+    // everything else remains the same
+    leaf_content.extend(leaf_to.nonce_bits.clone());
+    leaf_content.extend(leaf_to.pub_key_bits.clone());
+    leaf_content.extend(leaf_to.state_bits.clone());
+
+    let new_root = calculate_root(
+        cs.namespace(|| "payee's updated root"),
+        &leaf_content,
+        &to_path_bits,
+        &audit_path_to,
+        params,
+    )?;
+
+    // the last step - we expose public data for later commitment
+
+    // convert to BE for further use in Ethereum
+    let mut from_path_be = from_path_bits;
+    from_path_be.reverse();
+
+    let mut to_path_be = to_path_bits;
+    to_path_be.reverse();
+
+    let mut token_be = token_bits;
+    token_be.reverse();
+
+    let mut serial_be = serial_bits;
+    serial_be.reverse();
+
+    let mut public_data = vec![];
+    public_data.extend(from_path_be);
+    public_data.extend(to_path_be);
+    public_data.extend(token_be);
+    public_data.extend(amount_bits);
+    public_data.extend(serial_be);
+
+    assert_eq!(
+        public_data.len(),
+        plasma_constants::BALANCE_TREE_DEPTH
+            + plasma_constants::BALANCE_TREE_DEPTH
+            + plasma_constants::TOKEN_BIT_WIDTH
+            + plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH
+            + plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH
+            + plasma_constants::CHEQUE_SERIAL_BIT_WIDTH
+    );
+
+    Ok((new_root, good_until_block, public_data))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use log::debug;
+
+    use crate::CircuitAccountTree;
+    use ff::{BitIterator, PrimeFieldRepr};
+    use models::plasma::account::ChequeWindow;
+    use models::plasma::circuit::account::{leaf_pub_key_hash, CircuitAccount};
+    use models::plasma::circuit::utils::{
+        be_bit_vector_into_bytes, le_bit_vector_into_field_element,
+    };
+    use pairing::bn256::*;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use sapling_crypto::alt_babyjubjub::AltJubjubBn256;
+    use sapling_crypto::circuit::float_point::convert_to_float;
+    use sapling_crypto::circuit::test::*;
+    use sapling_crypto::eddsa::{PrivateKey, PublicKey};
+
+    use crypto::digest::Digest;
+    use crypto::sha2::Sha256;
+
+    fn window_as_fr(window: &ChequeWindow) -> (Fr, Fr) {
+        (
+            Fr::from_str(&window.bits.to_string()).unwrap(),
+            Fr::from_str(&window.start.to_string()).unwrap(),
+        )
+    }
+
+    // redeems a cheque of 100 from a payer that holds 1000,
+    // returns the constraint system after synthesis
+    fn redeem_cheque(window: ChequeWindow, serial: u32) -> TestConstraintSystem<Bn256> {
+        let params = &AltJubjubBn256::new();
+        let p_g = FixedGenerators::SpendingKeyGenerator;
+
+        let rng = &mut XorShiftRng::from_seed([0x3dbe_6258, 0x8d31_3d76, 0x3237_db17, 0xe5bc_0654]);
+
+        let tree_depth = plasma_constants::BALANCE_TREE_DEPTH as u32;
+        let mut tree = CircuitAccountTree::new(tree_depth);
+
+        let capacity = tree.capacity();
+
+        let payer_sk = PrivateKey::<Bn256>(rng.gen());
+        let payer_pk = PublicKey::from_private(&payer_sk, p_g, params);
+        let (payer_x, payer_y) = payer_pk.0.into_xy();
+
+        let payee_sk = PrivateKey::<Bn256>(rng.gen());
+        let payee_pk = PublicKey::from_private(&payee_sk, p_g, params);
+        let (payee_x, payee_y) = payee_pk.0.into_xy();
+
+        let payer_leaf_number: u32 = rng.gen::<u32>() % capacity;
+        let mut payee_leaf_number: u32 = rng.gen::<u32>() % capacity;
+        if payee_leaf_number == payer_leaf_number {
+            payee_leaf_number = (payee_leaf_number + 1) % capacity;
+        }
+
+        let token: u32 = 2;
+
+        let (cheque_bits, cheque_start) = window_as_fr(&window);
+
+        let mut payer_leaf = CircuitAccount::<Bn256> {
+            nonce: Fr::from_str("3").unwrap(),
+            pub_x: payer_x,
+            pub_y: payer_y,
+            cheque_bits,
+            cheque_start,
+            ..Default::default()
+        };
+        payer_leaf.set_balance(token, Fr::from_str("1000").unwrap(), params);
+
+        let mut payee_leaf = CircuitAccount::<Bn256> {
+            pub_x: payee_x,
+            pub_y: payee_y,
+            ..Default::default()
+        };
+        payee_leaf.set_balance(token, Fr::from_str("20").unwrap(), params);
+
+        tree.insert(payer_leaf_number, payer_leaf.clone());
+        tree.insert(payee_leaf_number, payee_leaf.clone());
+
+        let initial_root = tree.root_hash();
+        debug!("Initial root = {}", initial_root);
+
+        let amount_bits = convert_to_float(
+            100,
+            plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH,
+            plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH,
+            10,
+        )
+        .unwrap();
+
+        let mut request: ChequeRequest<Bn256> = ChequeRequest {
+            from: Fr::from_str(&payer_leaf_number.to_string()),
+            to: Fr::from_str(&payee_leaf_number.to_string()),
+            token: Fr::from_str(&token.to_string()),
+            amount: Some(le_bit_vector_into_field_element(&amount_bits)),
+            serial: Fr::from_str(&serial.to_string()),
+            good_until_block: Some(Fr::one()),
+            chain_id: Some(Fr::one()),
+            signature: None,
+        };
+
+        request.sign(&payer_sk, p_g, params, rng);
+        assert!(request.signature.is_some());
+
+        let auth_path_from: Vec<Option<Fr>> = tree
+            .merkle_path(payer_leaf_number)
+            .into_iter()
+            .map(|e| Some(e.0))
+            .collect();
+
+        let leaf_from = LeafWitness {
+            balance: Some(payer_leaf.get_balance(token)),
+            balance_path: payer_leaf
+                .balance_path(token, params)
+                .into_iter()
+                .map(Some)
+                .collect(),
+            nonce: Some(payer_leaf.nonce),
+            pub_x: Some(payer_x),
+            pub_y: Some(payer_y),
+            pub_key_hash: leaf_pub_key_hash(&payer_x, &payer_y),
+            cheque_bits: Some(payer_leaf.cheque_bits),
+            cheque_start: Some(payer_leaf.cheque_start),
+            lock_commitment: Some(payer_leaf.lock_commitment),
+        };
+
+        // the window of an invalid redemption is kept, the circuit must not be satisfied anyway
+        let (new_cheque_bits, new_cheque_start) =
+            window_as_fr(&window.redeem(serial).unwrap_or(window));
+
+        let mut updated_payer_leaf = payer_leaf.clone();
+        updated_payer_leaf.set_balance(token, Fr::from_str("900").unwrap(), params);
+        updated_payer_leaf.cheque_bits = new_cheque_bits;
+        updated_payer_leaf.cheque_start = new_cheque_start;
+        tree.insert(payer_leaf_number, updated_payer_leaf);
+
+        let auth_path_to: Vec<Option<Fr>> = tree
+            .merkle_path(payee_leaf_number)
+            .into_iter()
+            .map(|e| Some(e.0))
+            .collect();
+
+        let leaf_to = LeafWitness {
+            balance: Some(payee_leaf.get_balance(token)),
+            balance_path: payee_leaf
+                .balance_path(token, params)
+                .into_iter()
+                .map(Some)
+                .collect(),
+            nonce: Some(payee_leaf.nonce),
+            pub_x: Some(payee_x),
+            pub_y: Some(payee_y),
+            pub_key_hash: leaf_pub_key_hash(&payee_x, &payee_y),
+            cheque_bits: Some(payee_leaf.cheque_bits),
+            cheque_start: Some(payee_leaf.cheque_start),
+            lock_commitment: Some(payee_leaf.lock_commitment),
+        };
+
+        let mut updated_payee_leaf = payee_leaf.clone();
+        updated_payee_leaf.set_balance(token, Fr::from_str("120").unwrap(), params);
+        tree.insert(payee_leaf_number, updated_payee_leaf);
+
+        let new_root = tree.root_hash();
+        debug!("New root = {}", new_root);
+
+        let witness = ChequeWitness {
+            leaf_from,
+            auth_path_from,
+            leaf_to,
+            auth_path_to,
+        };
+
+        let mut public_data_initial_bits = Vec::new();
+
+        // these two are BE encodings because an iterator is BE. This is also an Ethereum standard behavior
+
+        let block_number_bits: Vec<bool> = BitIterator::new(Fr::one().into_repr()).collect();
+        for _ in 0..256 - block_number_bits.len() {
+            public_data_initial_bits.push(false);
+        }
+        public_data_initial_bits.extend(block_number_bits.into_iter());
+
+        let mut h = Sha256::new();
+
+        let bytes_to_hash = be_bit_vector_into_bytes(&public_data_initial_bits);
+
+        h.input(&bytes_to_hash);
+
+        let mut hash_result = [0u8; 32];
+        h.result(&mut hash_result[..]);
+
+        let packed_transaction_data_bytes =
+            be_bit_vector_into_bytes(&request.public_data_into_bits());
+
+        let mut next_round_hash_bytes = vec![];
+        next_round_hash_bytes.extend(hash_result.iter());
+        next_round_hash_bytes.extend(packed_transaction_data_bytes);
+
+        h = Sha256::new();
+        h.input(&next_round_hash_bytes);
+        hash_result = [0u8; 32];
+        h.result(&mut hash_result[..]);
+
+        hash_result[0] &= 0x1f; // temporary solution
+
+        let mut repr = Fr::zero().into_repr();
+        repr.read_be(&hash_result[..])
+            .expect("pack hash as field element");
+
+        let public_data_commitment = Fr::from_repr(repr).unwrap();
+
+        let instance = Cheque {
+            params,
+            number_of_cheques: 1,
+            chain_id: Fr::one(),
+            old_root: Some(initial_root),
+            new_root: Some(new_root),
+            public_data_commitment: Some(public_data_commitment),
+            block_number: Some(Fr::one()),
+            requests: vec![(request, witness)],
+        };
+
+        let mut cs = TestConstraintSystem::<Bn256>::new();
+
+        instance.synthesize(&mut cs).unwrap();
+
+        debug!("{}", cs.num_constraints());
+
+        assert_eq!(cs.num_inputs(), 4);
+
+        cs
+    }
+
+    #[test]
+    fn test_redeem_cheque() {
+        let window = ChequeWindow {
+            start: 10,
+            bits: 0b1011,
+        };
+
+        // inside of the window
+        let cs = redeem_cheque(window, 12);
+        let err = cs.which_is_unsatisfied();
+        if err.is_some() {
+            panic!("ERROR satisfying in {}", err.unwrap());
+        }
+
+        // past the end of the window, the window is moved
+        let cs = redeem_cheque(window, 10 + 150);
+        let err = cs.which_is_unsatisfied();
+        if err.is_some() {
+            panic!("ERROR satisfying in {}", err.unwrap());
+        }
+    }
+
+    #[test]
+    fn test_redeem_cheque_twice() {
+        let window = ChequeWindow {
+            start: 10,
+            bits: 0b1011,
+        };
+
+        // serial 11 is already redeemed
+        let cs = redeem_cheque(window, 11);
+        assert!(cs.which_is_unsatisfied().is_some());
+
+        // serial is before the window
+        let cs = redeem_cheque(window, 9);
+        assert!(cs.which_is_unsatisfied().is_some());
+    }
+}
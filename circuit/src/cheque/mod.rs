@@ -1 +1,3 @@
 pub mod bitwindow;
+pub mod cheque_request;
+pub mod circuit;
@@ -1,11 +1,15 @@
 use crate::deposit::deposit_request::DepositRequest;
-use crate::leaf::{make_leaf_content, LeafWitness};
+use crate::leaf::{
+    account_leaf_hash, account_tree_node, balances_root_into_bits, calculate_balances_root,
+    leaf_bit_width, make_leaf_content, public_key_leaf_bits, LeafWitness,
+};
 use bellman::{Circuit, ConstraintSystem, SynthesisError};
 use ff::{Field, PrimeField};
+use models::plasma::circuit::account::empty_balance_hashes;
 use models::plasma::circuit::utils::{allocate_audit_path, append_packed_public_key};
 use models::plasma::params as plasma_constants;
 use sapling_crypto::circuit::num::{AllocatedNum, Num};
-use sapling_crypto::circuit::{boolean, ecc, num, pedersen_hash, sha256, Assignment};
+use sapling_crypto::circuit::{boolean, ecc, num, sha256, Assignment};
 use sapling_crypto::jubjub::JubjubEngine;
 
 #[derive(Clone)]
@@ -175,14 +179,27 @@ where
     E: JubjubEngine,
     CS: ConstraintSystem<E>,
 {
+    let token_allocated = AllocatedNum::alloc(cs.namespace(|| "deposit token"), || {
+        Ok(*request.token.get()?)
+    })?;
+
+    let mut token_bits =
+        token_allocated.into_bits_le(cs.namespace(|| "token bit decomposition"))?;
+
+    token_bits.truncate(plasma_constants::TOKEN_BIT_WIDTH);
+
     // Calculate leaf value commitment
 
-    let leaf = make_leaf_content(cs.namespace(|| "create leaf"), witness.clone().leaf)?;
+    let leaf = make_leaf_content(
+        cs.namespace(|| "create leaf"),
+        witness.clone().leaf,
+        &token_bits,
+        params,
+    )?;
 
     // Compute the hash of the from leaf
-    let mut leaf_hash = pedersen_hash::pedersen_hash(
+    let mut leaf_hash = account_leaf_hash(
         cs.namespace(|| "leaf content hash"),
-        pedersen_hash::Personalization::NoteCommitment,
         &leaf.leaf_bits,
         params,
     )?;
@@ -205,9 +222,7 @@ where
     )?;
 
     {
-        // This is an injective encoding, as cur is a
-        // point in the prime order subgroup.
-        let mut cur = leaf_hash.get_x().clone();
+        let mut cur = leaf_hash.clone();
 
         // Ascend the merkle tree authentication path
         for (i, direction_bit) in path_bits.clone().into_iter().enumerate() {
@@ -228,23 +243,8 @@ where
                 &direction_bit,
             )?;
 
-            // We don't need to be strict, because the function is
-            // collision-resistant. If the prover witnesses a congruency,
-            // they will be unable to find an authentication path in the
-            // tree with high probability.
-            let mut preimage = vec![];
-            preimage.extend(xl.into_bits_le(cs.namespace(|| "xl into bits"))?);
-            preimage.extend(xr.into_bits_le(cs.namespace(|| "xr into bits"))?);
-
             // Compute the new subtree value
-            cur = pedersen_hash::pedersen_hash(
-                cs.namespace(|| "computation of pedersen hash"),
-                pedersen_hash::Personalization::MerkleTree(i),
-                &preimage,
-                params,
-            )?
-            .get_x()
-            .clone(); // Injective encoding
+            cur = account_tree_node(cs.namespace(|| "node hash"), &xl, &xr, i, params)?;
         }
 
         // enforce old root before update
@@ -258,7 +258,7 @@ where
 
     // Initial leaf values are allocated, so we modify a leaf
 
-    // Leaf can be empty if and only if the nonce == 0 && balance == 0
+    // Leaf can be empty if and only if the nonce == 0 && there are no balances in any token
     // but we also check that pub_x and pub_y are zeroes.
     // External witness is used whether leaf is empty or not
 
@@ -268,10 +268,11 @@ where
     )?);
 
     // constraint it
-    // balance * leaf_is_empty == 0 -> balance == 0 || leaf_is_empty != 1
+    // (balances_root - empty_root) * leaf_is_empty == 0 -> balances_root == empty_root || leaf_is_empty != 1
+    let empty_balances_root = empty_balance_hashes::<E>(params)[plasma_constants::TOKEN_BIT_WIDTH];
     cs.enforce(
-        || "boolean constraint for balance is zero for empty leaf",
-        |lc| lc + leaf.value.get_variable(),
+        || "boolean constraint for balances are empty for empty leaf",
+        |lc| lc + leaf.balances_root.get_variable() - (empty_balances_root, CS::one()),
         |_| leaf_is_empty.lc(CS::one(), E::Fr::one()),
         |lc| lc,
     );
@@ -283,16 +284,40 @@ where
         |lc| lc,
     );
 
+    // the key is checked to be empty and then kept or replaced
+    let (pub_x, pub_y) = leaf.public_key(cs.namespace(|| "check leaf public key"), params)?;
+
     cs.enforce(
         || "boolean constraint for pub_x is zero for empty leaf",
-        |lc| lc + leaf.pub_x.get_variable(),
+        |lc| lc + pub_x.get_variable(),
         |_| leaf_is_empty.lc(CS::one(), E::Fr::one()),
         |lc| lc,
     );
 
     cs.enforce(
         || "boolean constraint for pub_y is zero for empty leaf",
-        |lc| lc + leaf.pub_y.get_variable(),
+        |lc| lc + pub_y.get_variable(),
+        |_| leaf_is_empty.lc(CS::one(), E::Fr::one()),
+        |lc| lc,
+    );
+
+    cs.enforce(
+        || "boolean constraint for cheque bits are zero for empty leaf",
+        |lc| lc + leaf.cheque_bits.get_variable(),
+        |_| leaf_is_empty.lc(CS::one(), E::Fr::one()),
+        |lc| lc,
+    );
+
+    cs.enforce(
+        || "boolean constraint for cheque start is zero for empty leaf",
+        |lc| lc + leaf.cheque_start.get_variable(),
+        |_| leaf_is_empty.lc(CS::one(), E::Fr::one()),
+        |lc| lc,
+    );
+
+    cs.enforce(
+        || "boolean constraint for lock commitment is zero for empty leaf",
+        |lc| lc + leaf.lock_commitment.get_variable(),
         |_| leaf_is_empty.lc(CS::one(), E::Fr::one()),
         |lc| lc,
     );
@@ -316,7 +341,7 @@ where
     let leaf_pk_x = num::AllocatedNum::conditionally_select(
         cs.namespace(|| "conditional select public key x"),
         &new_pk_x,
-        &leaf.pub_x,
+        &pub_x,
         &leaf_is_empty,
     )?;
 
@@ -324,7 +349,7 @@ where
     let leaf_pk_y = num::AllocatedNum::conditionally_select(
         cs.namespace(|| "conditional select public key y"),
         &new_pk_y,
-        &leaf.pub_y,
+        &pub_y,
         &leaf_is_empty,
     )?;
 
@@ -419,35 +444,49 @@ where
             new_balance.into_bits_le(cs.namespace(|| "from leaf updated amount bits"))?;
 
         value_content.truncate(plasma_constants::BALANCE_BIT_WIDTH);
-        leaf_content.extend(value_content.clone());
+
+        let balances_root = calculate_balances_root(
+            cs.namespace(|| "updated balances root"),
+            &value_content,
+            &token_bits,
+            &leaf.balance_path,
+            params,
+        )?;
+
+        leaf_content.extend(balances_root_into_bits(
+            cs.namespace(|| "updated balances root bits"),
+            &balances_root,
+        )?);
 
         leaf_content.extend(leaf.nonce_bits.clone());
 
         // update public keys
 
+        let mut pub_key_content_new = vec![];
         append_packed_public_key(
-            &mut leaf_content,
+            &mut pub_key_content_new,
             pub_x_content_new.clone(),
             pub_y_content_new.clone(),
         );
+        leaf_content.extend(public_key_leaf_bits(
+            cs.namespace(|| "updated public key leaf bits"),
+            pub_key_content_new,
+            params,
+        )?);
 
-        assert_eq!(
-            leaf_content.len(),
-            plasma_constants::BALANCE_BIT_WIDTH
-                + plasma_constants::NONCE_BIT_WIDTH
-                + plasma_constants::FR_BIT_WIDTH
-        );
+        leaf_content.extend(leaf.state_bits.clone());
+
+        assert_eq!(leaf_content.len(), leaf_bit_width());
 
         // Compute the hash of the from leaf
-        leaf_hash = pedersen_hash::pedersen_hash(
+        leaf_hash = account_leaf_hash(
             cs.namespace(|| "leaf content hash updated"),
-            pedersen_hash::Personalization::NoteCommitment,
             &leaf_content,
             params,
         )?;
     }
 
-    let mut cur = leaf_hash.get_x().clone();
+    let mut cur = leaf_hash.clone();
 
     // Ascend the merkle tree authentication path
     for (i, direction_bit) in path_bits.clone().into_iter().enumerate() {
@@ -468,23 +507,8 @@ where
             &direction_bit,
         )?;
 
-        // We don't need to be strict, because the function is
-        // collision-resistant. If the prover witnesses a congruency,
-        // they will be unable to find an authentication path in the
-        // tree with high probability.
-        let mut preimage = vec![];
-        preimage.extend(xl.into_bits_le(cs.namespace(|| "xl into bits"))?);
-        preimage.extend(xr.into_bits_le(cs.namespace(|| "xr into bits"))?);
-
         // Compute the new subtree value
-        cur = pedersen_hash::pedersen_hash(
-            cs.namespace(|| "computation of pedersen hash"),
-            pedersen_hash::Personalization::MerkleTree(i),
-            &preimage,
-            params,
-        )?
-        .get_x()
-        .clone(); // Injective encoding
+        cur = account_tree_node(cs.namespace(|| "node hash"), &xl, &xr, i, params)?;
     }
 
     // the last step - we expose public data for later commitment
@@ -494,6 +518,9 @@ where
     let mut path_bits = path_bits.clone();
     path_bits.reverse();
     public_data.extend(path_bits);
+    let mut token_bits_be = token_bits.clone();
+    token_bits_be.reverse();
+    public_data.extend(token_bits_be);
     let mut amount_bits_be = amount_bits.clone();
     amount_bits_be.reverse();
     public_data.extend(amount_bits_be);
@@ -510,6 +537,7 @@ where
     assert_eq!(
         public_data.len(),
         plasma_constants::BALANCE_TREE_DEPTH
+            + plasma_constants::TOKEN_BIT_WIDTH
             + plasma_constants::BALANCE_BIT_WIDTH
             + plasma_constants::FR_BIT_WIDTH
     );
@@ -532,7 +560,7 @@ mod test {
     fn test_deposit_in_empty_leaf() {
         use crate::CircuitAccountTree;
         use ff::{BitIterator, Field};
-        use models::plasma::circuit::account::CircuitAccount;
+        use models::plasma::circuit::account::{leaf_pub_key_hash, CircuitAccount};
         use pairing::bn256::*;
         use rand::{Rng, SeedableRng, XorShiftRng};
         use sapling_crypto::alt_babyjubjub::AltJubjubBn256;
@@ -573,12 +601,15 @@ mod test {
 
         let transfer_amount_as_field_element = Fr::from_str(&transfer_amount.to_string()).unwrap();
 
-        let sender_leaf = CircuitAccount {
-            balance: transfer_amount_as_field_element,
+        let token: u32 = 2;
+
+        let mut sender_leaf = CircuitAccount::<Bn256> {
             nonce: Fr::zero(),
             pub_x: sender_x,
             pub_y: sender_y,
+            ..Default::default()
         };
+        sender_leaf.set_balance(token, transfer_amount_as_field_element, params);
 
         tree.insert(sender_leaf_number, sender_leaf.clone());
 
@@ -599,15 +630,25 @@ mod test {
 
         let request: DepositRequest<Bn256> = DepositRequest {
             into: from,
+            token: Fr::from_str(&token.to_string()),
             amount: Some(transfer_amount_as_field_element),
             public_key: Some(sender_pk.0),
         };
 
         let leaf_witness = LeafWitness {
             balance: Some(Fr::zero()),
+            balance_path: CircuitAccount::<Bn256>::default()
+                .balance_path(token, params)
+                .into_iter()
+                .map(Some)
+                .collect(),
             nonce: Some(Fr::zero()),
             pub_x: Some(Fr::zero()),
             pub_y: Some(Fr::zero()),
+            pub_key_hash: leaf_pub_key_hash(&Fr::zero(), &Fr::zero()),
+            cheque_bits: Some(Fr::zero()),
+            cheque_start: Some(Fr::zero()),
+            lock_commitment: Some(Fr::zero()),
         };
 
         let witness = DepositWitness {
@@ -716,7 +757,7 @@ mod test {
     fn test_deposit_into_existing_leaf() {
         use crate::CircuitAccountTree;
         use ff::{BitIterator, Field};
-        use models::plasma::circuit::account::CircuitAccount;
+        use models::plasma::circuit::account::{leaf_pub_key_hash, CircuitAccount};
         use pairing::bn256::*;
         use rand::{Rng, SeedableRng, XorShiftRng};
         use sapling_crypto::alt_babyjubjub::AltJubjubBn256;
@@ -754,12 +795,15 @@ mod test {
 
         let transfer_amount_as_field_element = Fr::from_str(&transfer_amount.to_string()).unwrap();
 
-        let sender_leaf = CircuitAccount {
-            balance: transfer_amount_as_field_element,
+        let token: u32 = 2;
+
+        let mut sender_leaf = CircuitAccount::<Bn256> {
             nonce: Fr::zero(),
             pub_x: sender_x,
             pub_y: sender_y,
+            ..Default::default()
         };
+        sender_leaf.set_balance(token, transfer_amount_as_field_element, params);
 
         tree.insert(sender_leaf_number, sender_leaf.clone());
 
@@ -776,12 +820,8 @@ mod test {
         let mut double_the_amount = transfer_amount_as_field_element;
         double_the_amount.double();
 
-        let sender_leaf_redeposited = CircuitAccount {
-            balance: double_the_amount,
-            nonce: Fr::zero(),
-            pub_x: sender_x,
-            pub_y: sender_y,
-        };
+        let mut sender_leaf_redeposited = sender_leaf.clone();
+        sender_leaf_redeposited.set_balance(token, double_the_amount, params);
 
         tree.insert(sender_leaf_number, sender_leaf_redeposited);
 
@@ -795,15 +835,25 @@ mod test {
 
         let request: DepositRequest<Bn256> = DepositRequest {
             into: from,
+            token: Fr::from_str(&token.to_string()),
             amount: Some(transfer_amount_as_field_element),
             public_key: Some(sender_pk.0),
         };
 
         let leaf_witness = LeafWitness {
             balance: Some(transfer_amount_as_field_element),
+            balance_path: sender_leaf
+                .balance_path(token, params)
+                .into_iter()
+                .map(Some)
+                .collect(),
             nonce: Some(Fr::zero()),
             pub_x: Some(sender_x),
             pub_y: Some(sender_y),
+            pub_key_hash: leaf_pub_key_hash(&sender_x, &sender_y),
+            cheque_bits: Some(Fr::zero()),
+            cheque_start: Some(Fr::zero()),
+            lock_commitment: Some(Fr::zero()),
         };
 
         let witness = DepositWitness {
@@ -7,6 +7,7 @@ use sapling_crypto::jubjub::{edwards, edwards::Point, JubjubEngine, Unknown};
 #[derive(Clone)]
 pub struct DepositRequest<E: JubjubEngine> {
     pub into: Option<E::Fr>,
+    pub token: Option<E::Fr>,
     pub amount: Option<E::Fr>,
     // here it's only for ease of data encoding
     pub public_key: Option<edwards::Point<E, Unknown>>,
@@ -28,6 +29,7 @@ impl<E: JubjubEngine> DepositRequest<E> {
     pub fn public_data_into_bits(&self) -> Vec<bool> {
         // fields are
         // - into
+        // - token
         // - amount
         // - compressed public key
         let mut into: Vec<bool> = BitIterator::new(self.into.unwrap().into_repr()).collect();
@@ -36,6 +38,11 @@ impl<E: JubjubEngine> DepositRequest<E> {
         // reverse again to have BE as in Ethereum native types
         into.reverse();
 
+        let mut token: Vec<bool> = BitIterator::new(self.token.unwrap().into_repr()).collect();
+        token.reverse();
+        token.truncate(plasma_constants::TOKEN_BIT_WIDTH);
+        token.reverse();
+
         let mut amount: Vec<bool> = BitIterator::new(self.amount.unwrap().into_repr()).collect();
         amount.reverse();
         amount.truncate(plasma_constants::BALANCE_BIT_WIDTH);
@@ -55,6 +62,7 @@ impl<E: JubjubEngine> DepositRequest<E> {
 
         let mut packed: Vec<bool> = vec![];
         packed.extend(into.into_iter());
+        packed.extend(token.into_iter());
         packed.extend(amount.into_iter());
         packed.extend(y_bits.into_iter());
 
@@ -1,5 +1,8 @@
 //use super::{DepositBlock, TransferBlock, ExitBlock};
-use crate::{CircuitDepositRequest, CircuitExitRequest, CircuitTransferTx};
+use crate::{
+    CircuitChangePubKeyRequest, CircuitChequeRequest, CircuitDepositRequest, CircuitExitRequest,
+    CircuitLockRequest, CircuitTransferTx, CircuitUnlockRequest,
+};
 use models::plasma::block::{Block, BlockData};
 use models::plasma::circuit::utils::be_bit_vector_into_bytes;
 
@@ -28,6 +31,43 @@ fn convert_exit(transactions: &[models::plasma::tx::ExitTx]) -> Result<Vec<Vec<b
         .collect()
 }
 
+fn convert_change_pubkey(
+    transactions: &[models::plasma::tx::ChangePubKeyTx],
+) -> Result<Vec<Vec<bool>>, String> {
+    transactions
+        .iter()
+        .map(|tx| CircuitChangePubKeyRequest::try_from(tx).map(|tx| tx.public_data_into_bits()))
+        .collect()
+}
+
+fn convert_cheque(transactions: &[models::plasma::tx::ChequeTx]) -> Result<Vec<Vec<bool>>, String> {
+    transactions
+        .iter()
+        .map(|tx| CircuitChequeRequest::try_from(tx).map(|tx| tx.public_data_into_bits()))
+        .collect()
+}
+
+fn convert_lock(transactions: &[models::plasma::tx::LockTx]) -> Result<Vec<Vec<bool>>, String> {
+    transactions
+        .iter()
+        .map(|tx| CircuitLockRequest::try_from(tx).map(|tx| tx.public_data_into_bits()))
+        .collect()
+}
+
+fn convert_claim(transactions: &[models::plasma::tx::ClaimTx]) -> Result<Vec<Vec<bool>>, String> {
+    transactions
+        .iter()
+        .map(|tx| CircuitUnlockRequest::try_from_claim(tx).map(|tx| tx.public_data_into_bits()))
+        .collect()
+}
+
+fn convert_refund(transactions: &[models::plasma::tx::RefundTx]) -> Result<Vec<Vec<bool>>, String> {
+    transactions
+        .iter()
+        .map(|tx| CircuitUnlockRequest::try_from_refund(tx).map(|tx| tx.public_data_into_bits()))
+        .collect()
+}
+
 pub fn encode_transactions(block: &Block) -> Result<Vec<u8>, String> {
     let mut encoding: Vec<u8> = vec![];
 
@@ -35,6 +75,11 @@ pub fn encode_transactions(block: &Block) -> Result<Vec<u8>, String> {
         BlockData::Transfer { transactions, .. } => convert_transfer(transactions)?,
         BlockData::Deposit { transactions, .. } => convert_deposit(transactions)?,
         BlockData::Exit { transactions, .. } => convert_exit(transactions)?,
+        BlockData::ChangePubKey { transactions } => convert_change_pubkey(transactions)?,
+        BlockData::Cheque { transactions } => convert_cheque(transactions)?,
+        BlockData::Lock { transactions } => convert_lock(transactions)?,
+        BlockData::Claim { transactions } => convert_claim(transactions)?,
+        BlockData::Refund { transactions } => convert_refund(transactions)?,
     };
 
     for tx_bits in transactions_bits {
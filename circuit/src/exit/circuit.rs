@@ -1,11 +1,15 @@
 use crate::exit::exit_request::ExitRequest;
-use crate::leaf::{make_leaf_content, LeafWitness};
+use crate::leaf::{
+    account_leaf_hash, account_tree_node, balances_root_into_bits, calculate_balances_root,
+    make_leaf_content, LeafWitness,
+};
 use bellman::{Circuit, ConstraintSystem, SynthesisError};
 use ff::{Field, PrimeField};
+use models::plasma::circuit::account::empty_balance_hashes;
 use models::plasma::circuit::utils::allocate_audit_path;
 use models::plasma::params as plasma_constants;
 use sapling_crypto::circuit::num::{AllocatedNum, Num};
-use sapling_crypto::circuit::{boolean, num, pedersen_hash, sha256, Assignment};
+use sapling_crypto::circuit::{boolean, num, sha256, Assignment};
 use sapling_crypto::jubjub::JubjubEngine;
 
 #[derive(Clone)]
@@ -71,12 +75,18 @@ impl<'a, E: JubjubEngine> Circuit<E> for Exit<'a, E> {
 
         // Calculate leaf value commitment
 
+        // balance of the empty leaf is taken for token 0, any token gives the same root
+        let empty_leaf_token_bits =
+            vec![boolean::Boolean::Constant(false); plasma_constants::TOKEN_BIT_WIDTH];
+
         let empty_leaf = make_leaf_content(
             cs.namespace(|| "create leaf"),
             self.empty_leaf_witness.clone(),
+            &empty_leaf_token_bits,
+            self.params,
         )?;
 
-        // constraint empty balance, nonce, pub_x and pub_y
+        // constraint empty balance, balances root, nonce, pub_x, pub_y, cheque window and lock
 
         cs.enforce(
             || "boolean constraint for balance is zero for empty leaf",
@@ -85,6 +95,16 @@ impl<'a, E: JubjubEngine> Circuit<E> for Exit<'a, E> {
             |lc| lc,
         );
 
+        let empty_balances_root =
+            empty_balance_hashes::<E>(self.params)[plasma_constants::TOKEN_BIT_WIDTH];
+
+        cs.enforce(
+            || "boolean constraint for balances are empty for empty leaf",
+            |lc| lc + empty_leaf.balances_root.get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc + (empty_balances_root, CS::one()),
+        );
+
         cs.enforce(
             || "boolean constraint for nonce is zero for empty leaf",
             |lc| lc + empty_leaf.nonce.get_variable(),
@@ -92,24 +112,47 @@ impl<'a, E: JubjubEngine> Circuit<E> for Exit<'a, E> {
             |lc| lc,
         );
 
+        let (pub_x, pub_y) =
+            empty_leaf.public_key(cs.namespace(|| "check empty leaf public key"), self.params)?;
+
         cs.enforce(
             || "boolean constraint for pub_x is zero for empty leaf",
-            |lc| lc + empty_leaf.pub_x.get_variable(),
+            |lc| lc + pub_x.get_variable(),
             |lc| lc + CS::one(),
             |lc| lc,
         );
 
         cs.enforce(
             || "boolean constraint for pub_y is zero for empty leaf",
-            |lc| lc + empty_leaf.pub_y.get_variable(),
+            |lc| lc + pub_y.get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc,
+        );
+
+        cs.enforce(
+            || "boolean constraint for cheque bits are zero for empty leaf",
+            |lc| lc + empty_leaf.cheque_bits.get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc,
+        );
+
+        cs.enforce(
+            || "boolean constraint for cheque start is zero for empty leaf",
+            |lc| lc + empty_leaf.cheque_start.get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc,
+        );
+
+        cs.enforce(
+            || "boolean constraint for lock commitment is zero for empty leaf",
+            |lc| lc + empty_leaf.lock_commitment.get_variable(),
             |lc| lc + CS::one(),
             |lc| lc,
         );
 
         // Compute the hash of the from leaf
-        let empty_leaf_hash = pedersen_hash::pedersen_hash(
+        let empty_leaf_hash = account_leaf_hash(
             cs.namespace(|| "leaf content hash"),
-            pedersen_hash::Personalization::NoteCommitment,
             &empty_leaf.leaf_bits,
             self.params,
         )?;
@@ -117,14 +160,12 @@ impl<'a, E: JubjubEngine> Circuit<E> for Exit<'a, E> {
         // Ok, now we need to update the old root by applying requests in sequence
         let requests = self.requests.clone();
 
-        let empty_leaf_x = empty_leaf_hash.get_x();
-
         for (i, tx) in requests.into_iter().enumerate() {
             let (request, witness) = tx;
             let (intermediate_root, public_data) = apply_request(
                 cs.namespace(|| format!("applying transaction {}", i)),
                 old_root,
-                &empty_leaf_x,
+                &empty_leaf_hash,
                 request,
                 witness,
                 self.params,
@@ -215,7 +256,7 @@ impl<'a, E: JubjubEngine> Circuit<E> for Exit<'a, E> {
 fn apply_request<E, CS>(
     mut cs: CS,
     old_root: AllocatedNum<E>,
-    empty_leaf_x: &AllocatedNum<E>,
+    empty_leaf_hash: &AllocatedNum<E>,
     request: ExitRequest<E>,
     witness: ExitWitness<E>,
     params: &E::Params,
@@ -224,14 +265,27 @@ where
     E: JubjubEngine,
     CS: ConstraintSystem<E>,
 {
+    let token_allocated = AllocatedNum::alloc(cs.namespace(|| "exit token"), || {
+        Ok(*request.token.get()?)
+    })?;
+
+    let mut token_bits =
+        token_allocated.into_bits_le(cs.namespace(|| "token bit decomposition"))?;
+
+    token_bits.truncate(plasma_constants::TOKEN_BIT_WIDTH);
+
     // Calculate leaf value commitment
 
-    let leaf = make_leaf_content(cs.namespace(|| "create leaf"), witness.clone().leaf)?;
+    let leaf = make_leaf_content(
+        cs.namespace(|| "create leaf"),
+        witness.clone().leaf,
+        &token_bits,
+        params,
+    )?;
 
     // Compute the hash of the from leaf
-    let leaf_hash = pedersen_hash::pedersen_hash(
+    let leaf_hash = account_leaf_hash(
         cs.namespace(|| "leaf content hash"),
-        pedersen_hash::Personalization::NoteCommitment,
         &leaf.leaf_bits,
         params,
     )?;
@@ -254,9 +308,7 @@ where
     )?;
 
     {
-        // This is an injective encoding, as cur is a
-        // point in the prime order subgroup.
-        let mut cur = leaf_hash.get_x().clone();
+        let mut cur = leaf_hash.clone();
 
         // Ascend the merkle tree authentication path
         for (i, direction_bit) in path_bits.clone().into_iter().enumerate() {
@@ -277,23 +329,8 @@ where
                 &direction_bit,
             )?;
 
-            // We don't need to be strict, because the function is
-            // collision-resistant. If the prover witnesses a congruency,
-            // they will be unable to find an authentication path in the
-            // tree with high probability.
-            let mut preimage = vec![];
-            preimage.extend(xl.into_bits_le(cs.namespace(|| "xl into bits"))?);
-            preimage.extend(xr.into_bits_le(cs.namespace(|| "xr into bits"))?);
-
             // Compute the new subtree value
-            cur = pedersen_hash::pedersen_hash(
-                cs.namespace(|| "computation of pedersen hash"),
-                pedersen_hash::Personalization::MerkleTree(i),
-                &preimage,
-                params,
-            )?
-            .get_x()
-            .clone(); // Injective encoding
+            cur = account_tree_node(cs.namespace(|| "node hash"), &xl, &xr, i, params)?;
         }
 
         // enforce old root before update
@@ -305,7 +342,115 @@ where
         );
     }
 
-    let mut cur = empty_leaf_x.clone();
+    // The whole balance of the token is withdrawn. Account is replaced by an empty leaf
+    // only if no balances in other tokens are left and no funds are locked,
+    // otherwise nonce, key and the lock are kept
+
+    let zero_balance_bits =
+        vec![boolean::Boolean::Constant(false); plasma_constants::BALANCE_BIT_WIDTH];
+
+    let new_balances_root = calculate_balances_root(
+        cs.namespace(|| "balances root after exit"),
+        &zero_balance_bits,
+        &token_bits,
+        &leaf.balance_path,
+        params,
+    )?;
+
+    let empty_balances_root = empty_balance_hashes::<E>(params)[plasma_constants::TOKEN_BIT_WIDTH];
+
+    let no_balances_left = boolean::AllocatedBit::alloc(
+        cs.namespace(|| "allocate no balances left"),
+        new_balances_root
+            .get_value()
+            .map(|root| root == empty_balances_root),
+    )?;
+
+    let root_difference_inverse = AllocatedNum::alloc(
+        cs.namespace(|| "allocate balances root difference inverse"),
+        || {
+            let mut difference = *new_balances_root.get_value().get()?;
+            difference.sub_assign(&empty_balances_root);
+            Ok(difference.inverse().unwrap_or_else(E::Fr::zero))
+        },
+    )?;
+
+    // (balances_root - empty_root) * no_balances_left == 0
+    cs.enforce(
+        || "balances root is empty if no balances left",
+        |lc| lc + new_balances_root.get_variable() - (empty_balances_root, CS::one()),
+        |lc| lc + no_balances_left.get_variable(),
+        |lc| lc,
+    );
+
+    // (balances_root - empty_root) * inverse == 1 - no_balances_left
+    cs.enforce(
+        || "balances root is not empty if some balances left",
+        |lc| lc + new_balances_root.get_variable() - (empty_balances_root, CS::one()),
+        |lc| lc + root_difference_inverse.get_variable(),
+        |lc| lc + CS::one() - no_balances_left.get_variable(),
+    );
+
+    let no_lock = boolean::AllocatedBit::alloc(
+        cs.namespace(|| "allocate no lock"),
+        leaf.lock_commitment
+            .get_value()
+            .map(|commitment| commitment.is_zero()),
+    )?;
+
+    let lock_commitment_inverse =
+        AllocatedNum::alloc(cs.namespace(|| "allocate lock commitment inverse"), || {
+            let commitment = *leaf.lock_commitment.get_value().get()?;
+            Ok(commitment.inverse().unwrap_or_else(E::Fr::zero))
+        })?;
+
+    // lock_commitment * no_lock == 0
+    cs.enforce(
+        || "lock commitment is zero if there is no lock",
+        |lc| lc + leaf.lock_commitment.get_variable(),
+        |lc| lc + no_lock.get_variable(),
+        |lc| lc,
+    );
+
+    // lock_commitment * inverse == 1 - no_lock
+    cs.enforce(
+        || "lock commitment is not zero if there is a lock",
+        |lc| lc + leaf.lock_commitment.get_variable(),
+        |lc| lc + lock_commitment_inverse.get_variable(),
+        |lc| lc + CS::one() - no_lock.get_variable(),
+    );
+
+    let leaf_is_cleared = boolean::Boolean::and(
+        cs.namespace(|| "no balances left and no lock"),
+        &boolean::Boolean::from(no_balances_left),
+        &boolean::Boolean::from(no_lock),
+    )?;
+
+    let mut leaf_content = vec![];
+
+    leaf_content.extend(balances_root_into_bits(
+        cs.namespace(|| "updated balances root bits"),
+        &new_balances_root,
+    )?);
+
+    leaf_content.extend(leaf.nonce_bits.clone());
+
+    leaf_content.extend(leaf.pub_key_bits.clone());
+
+    leaf_content.extend(leaf.state_bits.clone());
+
+    let updated_leaf_hash = account_leaf_hash(
+        cs.namespace(|| "updated leaf content hash"),
+        &leaf_content,
+        params,
+    )?;
+
+    let mut cur = num::AllocatedNum::conditionally_select(
+        cs.namespace(|| "conditional select updated leaf hash"),
+        empty_leaf_hash,
+        &updated_leaf_hash,
+        &leaf_is_cleared,
+    )?;
 
     // Ascend the merkle tree authentication path
     for (i, direction_bit) in path_bits.clone().into_iter().enumerate() {
@@ -326,23 +471,8 @@ where
             &direction_bit,
         )?;
 
-        // We don't need to be strict, because the function is
-        // collision-resistant. If the prover witnesses a congruency,
-        // they will be unable to find an authentication path in the
-        // tree with high probability.
-        let mut preimage = vec![];
-        preimage.extend(xl.into_bits_le(cs.namespace(|| "xl into bits"))?);
-        preimage.extend(xr.into_bits_le(cs.namespace(|| "xr into bits"))?);
-
         // Compute the new subtree value
-        cur = pedersen_hash::pedersen_hash(
-            cs.namespace(|| "computation of pedersen hash"),
-            pedersen_hash::Personalization::MerkleTree(i),
-            &preimage,
-            params,
-        )?
-        .get_x()
-        .clone(); // Injective encoding
+        cur = account_tree_node(cs.namespace(|| "node hash"), &xl, &xr, i, params)?;
     }
 
     // the last step - we expose public data for later commitment
@@ -352,13 +482,18 @@ where
     let mut path_bits_be = path_bits.clone();
     path_bits_be.reverse();
     public_data.extend(path_bits_be);
+    let mut token_bits_be = token_bits.clone();
+    token_bits_be.reverse();
+    public_data.extend(token_bits_be);
     let mut amount_bits_be = leaf.value_bits.clone();
     amount_bits_be.reverse();
     public_data.extend(amount_bits_be);
 
     assert_eq!(
         public_data.len(),
-        plasma_constants::BALANCE_TREE_DEPTH + plasma_constants::BALANCE_BIT_WIDTH
+        plasma_constants::BALANCE_TREE_DEPTH
+            + plasma_constants::TOKEN_BIT_WIDTH
+            + plasma_constants::BALANCE_BIT_WIDTH
     );
 
     Ok((cur, public_data))
@@ -380,7 +515,7 @@ mod test {
     fn test_exit_from_existing_leaf() {
         use crate::CircuitAccountTree;
         use ff::{BitIterator, Field};
-        use models::plasma::circuit::account::CircuitAccount;
+        use models::plasma::circuit::account::{leaf_pub_key_hash, CircuitAccount};
         use pairing::bn256::*;
         use rand::{Rng, SeedableRng, XorShiftRng};
         use sapling_crypto::alt_babyjubjub::AltJubjubBn256;
@@ -418,12 +553,15 @@ mod test {
 
         let transfer_amount_as_field_element = Fr::from_str(&transfer_amount.to_string()).unwrap();
 
-        let sender_leaf = CircuitAccount {
-            balance: transfer_amount_as_field_element,
+        let token: u32 = 5;
+
+        let mut sender_leaf = CircuitAccount::<Bn256> {
             nonce: Fr::zero(),
             pub_x: sender_x,
             pub_y: sender_y,
+            ..Default::default()
         };
+        sender_leaf.set_balance(token, transfer_amount_as_field_element, params);
 
         tree.insert(sender_leaf_number, sender_leaf.clone());
 
@@ -447,21 +585,40 @@ mod test {
 
         let request: ExitRequest<Bn256> = ExitRequest {
             from,
+            token: Fr::from_str(&token.to_string()),
             amount: Some(transfer_amount_as_field_element),
         };
 
         let leaf_witness = LeafWitness {
             balance: Some(transfer_amount_as_field_element),
+            balance_path: sender_leaf
+                .balance_path(token, params)
+                .into_iter()
+                .map(Some)
+                .collect(),
             nonce: Some(Fr::zero()),
             pub_x: Some(sender_x),
             pub_y: Some(sender_y),
+            pub_key_hash: leaf_pub_key_hash(&sender_x, &sender_y),
+            cheque_bits: Some(Fr::zero()),
+            cheque_start: Some(Fr::zero()),
+            lock_commitment: Some(Fr::zero()),
         };
 
         let empty_leaf_witness = LeafWitness {
             balance: Some(Fr::zero()),
+            balance_path: CircuitAccount::<Bn256>::default()
+                .balance_path(0, params)
+                .into_iter()
+                .map(Some)
+                .collect(),
             nonce: Some(Fr::zero()),
             pub_x: Some(Fr::zero()),
             pub_y: Some(Fr::zero()),
+            pub_key_hash: leaf_pub_key_hash(&Fr::zero(), &Fr::zero()),
+            cheque_bits: Some(Fr::zero()),
+            cheque_start: Some(Fr::zero()),
+            lock_commitment: Some(Fr::zero()),
         };
 
         let witness = ExitWitness {
@@ -469,12 +626,8 @@ mod test {
             auth_path: path_from,
         };
 
-        let emptied_leaf = CircuitAccount {
-            balance: Fr::zero(),
-            nonce: Fr::zero(),
-            pub_x: Fr::zero(),
-            pub_y: Fr::zero(),
-        };
+        // sender has no other tokens, so the leaf is emptied
+        let emptied_leaf = CircuitAccount::<Bn256>::default();
 
         tree.insert(sender_leaf_number, emptied_leaf);
 
@@ -7,6 +7,7 @@ use sapling_crypto::jubjub::JubjubEngine;
 #[derive(Clone)]
 pub struct ExitRequest<E: JubjubEngine> {
     pub from: Option<E::Fr>,
+    pub token: Option<E::Fr>,
     // keep an amount in request for ease of public data serialization
     // it's NOT USED in a zkSNARK
     pub amount: Option<E::Fr>,
@@ -16,6 +17,7 @@ impl<E: JubjubEngine> ExitRequest<E> {
     pub fn public_data_into_bits(&self) -> Vec<bool> {
         // fields are
         // - from
+        // - token
         // - amount
         // - compressed public key
         let mut from: Vec<bool> = BitIterator::new(self.from.unwrap().into_repr()).collect();
@@ -24,6 +26,11 @@ impl<E: JubjubEngine> ExitRequest<E> {
         // reverse again to have BE as in Ethereum native types
         from.reverse();
 
+        let mut token: Vec<bool> = BitIterator::new(self.token.unwrap().into_repr()).collect();
+        token.reverse();
+        token.truncate(plasma_constants::TOKEN_BIT_WIDTH);
+        token.reverse();
+
         let mut amount: Vec<bool> = BitIterator::new(self.amount.unwrap().into_repr()).collect();
         amount.reverse();
         amount.truncate(plasma_constants::BALANCE_BIT_WIDTH);
@@ -32,6 +39,7 @@ impl<E: JubjubEngine> ExitRequest<E> {
 
         let mut packed: Vec<bool> = vec![];
         packed.extend(from.into_iter());
+        packed.extend(token.into_iter());
         packed.extend(amount.into_iter());
 
         packed
@@ -0,0 +1,61 @@
+// Proving keys on disk, tagged with the depth, the leaf layout and the hasher of the account
+// tree they were generated for
+
+use bellman::groth16::Parameters;
+use merkle_tree::POSEIDON_ACCOUNT_TREE;
+use models::plasma::params;
+use pairing::Engine;
+use std::io::{self, Read, Write};
+
+// written before the parameters, followed by the tree depth as a big-endian u32.
+// Keys of the compact leaf layout and of the Poseidon account tree have tags of their own
+fn depth_tag() -> &'static [u8; 4] {
+    match (params::COMPACT_LEAF, POSEIDON_ACCOUNT_TREE) {
+        (false, false) => b"TDEP",
+        (true, false) => b"TDPC",
+        (false, true) => b"TDPS",
+        (true, true) => b"TDCS",
+    }
+}
+
+/// Writes `parameters` generated for `BALANCE_TREE_DEPTH`
+pub fn write_parameters<E: Engine, W: Write>(
+    parameters: &Parameters<E>,
+    mut writer: W,
+) -> io::Result<()> {
+    writer.write_all(depth_tag())?;
+    writer.write_all(&(params::BALANCE_TREE_DEPTH as u32).to_be_bytes())?;
+    parameters.write(writer)
+}
+
+/// Reads parameters written by `write_parameters`. Keys of another tree depth, leaf layout or
+/// account tree hasher, or without the tag, would make every proof invalid, so they are refused
+pub fn read_parameters<E: Engine, R: Read>(
+    mut reader: R,
+    checked: bool,
+) -> io::Result<Parameters<E>> {
+    let mut tag = [0u8; 4];
+    let mut depth = [0u8; 4];
+    reader.read_exact(&mut tag)?;
+    reader.read_exact(&mut depth)?;
+    if tag != *depth_tag() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "proving key has no depth tag or is for another leaf layout or hasher, regenerate it",
+        ));
+    }
+
+    let depth = u32::from_be_bytes(depth) as usize;
+    if depth != params::BALANCE_TREE_DEPTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "proving key is for tree depth {}, BALANCE_TREE_DEPTH is {}",
+                depth,
+                params::BALANCE_TREE_DEPTH
+            ),
+        ));
+    }
+
+    Parameters::read(reader, checked)
+}
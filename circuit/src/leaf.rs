@@ -1,34 +1,86 @@
+use crate::poseidon::{poseidon_compress, poseidon_hash_bits};
 use bellman::{ConstraintSystem, SynthesisError};
-use models::plasma::circuit::utils::append_packed_public_key;
+use ff::Field;
+use merkle_tree::poseidon_hasher::shared_params;
+use merkle_tree::POSEIDON_ACCOUNT_TREE;
+use models::plasma::circuit::account::pub_key_hash;
+use models::plasma::circuit::utils::{allocate_audit_path, append_packed_public_key};
 use models::plasma::params as plasma_constants;
+use models::primitives::GetBitsFixed;
 use sapling_crypto::circuit::num::AllocatedNum;
-use sapling_crypto::circuit::{boolean, Assignment};
+use sapling_crypto::circuit::{boolean, pedersen_hash, Assignment};
 use sapling_crypto::jubjub::JubjubEngine;
 
 #[derive(Clone)]
 pub struct LeafWitness<E: JubjubEngine> {
+    // balance of the token that transaction operates on
     pub balance: Option<E::Fr>,
+    // audit path of this balance in the balance subtree of the account
+    pub balance_path: Vec<Option<E::Fr>>,
     pub nonce: Option<E::Fr>,
-    // x coordinate is supplied and parity is constrained
+    // x coordinate is supplied and parity is constrained. The compact layout
+    // only needs them for a leaf whose key is used, see `LeafContent::public_key`
     pub pub_x: Option<E::Fr>,
     pub pub_y: Option<E::Fr>,
+    // hash of the key as the compact layout stores it, unused by the default one
+    pub pub_key_hash: Option<E::Fr>,
+    // window of redeemed cheques
+    pub cheque_bits: Option<E::Fr>,
+    pub cheque_start: Option<E::Fr>,
+    // commitment to the hash lock, zero if there is none
+    pub lock_commitment: Option<E::Fr>,
 }
 
 pub struct LeafContent<E: JubjubEngine> {
     pub leaf_bits: Vec<boolean::Boolean>,
     pub value: AllocatedNum<E>,
     pub value_bits: Vec<boolean::Boolean>,
+    pub balance_path: Vec<AllocatedNum<E>>,
+    pub balances_root: AllocatedNum<E>,
     pub nonce: AllocatedNum<E>,
     pub nonce_bits: Vec<boolean::Boolean>,
-    pub pub_x: AllocatedNum<E>,
-    pub pub_y: AllocatedNum<E>,
-    pub pub_x_bit: Vec<boolean::Boolean>,
-    pub pub_y_bits: Vec<boolean::Boolean>,
+    // public key as it is stored in the leaf, packed or hashed
+    pub pub_key_bits: Vec<boolean::Boolean>,
+    // the key packed into the leaf by the default layout, the compact one allocates
+    // it from `key_witness` in `public_key`
+    key: Option<(AllocatedNum<E>, AllocatedNum<E>)>,
+    key_witness: (Option<E::Fr>, Option<E::Fr>),
+    pub cheque_bits: AllocatedNum<E>,
+    pub cheque_start: AllocatedNum<E>,
+    // cheque bits and start as they are packed into the leaf
+    pub cheque_window_bits: Vec<boolean::Boolean>,
+    pub lock_commitment: AllocatedNum<E>,
+    pub lock_bits: Vec<boolean::Boolean>,
+    // hash of the cheque window and the lock as it is stored in the leaf
+    pub state_bits: Vec<boolean::Boolean>,
 }
 
 pub fn make_leaf_content<E, CS>(
+    cs: CS,
+    witness: LeafWitness<E>,
+    token_bits: &[boolean::Boolean],
+    params: &E::Params,
+) -> Result<LeafContent<E>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    make_leaf_content_of_layout(
+        cs,
+        witness,
+        token_bits,
+        plasma_constants::COMPACT_LEAF,
+        params,
+    )
+}
+
+// `compact` is COMPACT_LEAF, the layout test builds leafs of both layouts
+fn make_leaf_content_of_layout<E, CS>(
     mut cs: CS,
     witness: LeafWitness<E>,
+    token_bits: &[boolean::Boolean],
+    compact: bool,
+    params: &E::Params,
 ) -> Result<LeafContent<E>, SynthesisError>
 where
     E: JubjubEngine,
@@ -43,7 +95,26 @@ where
     let mut value_bits = value.into_bits_le(cs.namespace(|| "value bits"))?;
 
     value_bits.truncate(plasma_constants::BALANCE_BIT_WIDTH);
-    leaf_bits.extend(value_bits.clone());
+
+    // balance is not a part of the leaf itself, but of the balance subtree
+
+    let balance_path = allocate_audit_path(
+        cs.namespace(|| "allocate balance path"),
+        witness.balance_path.clone(),
+    )?;
+
+    let balances_root = calculate_balances_root(
+        cs.namespace(|| "calculate balances root"),
+        &value_bits,
+        token_bits,
+        &balance_path,
+        params,
+    )?;
+
+    leaf_bits.extend(balances_root_into_bits(
+        cs.namespace(|| "balances root bits"),
+        &balances_root,
+    )?);
 
     let nonce = AllocatedNum::alloc(cs.namespace(|| "allocate leaf nonce witness"), || {
         Ok(*witness.nonce.get()?)
@@ -54,47 +125,656 @@ where
     nonce_bits.truncate(plasma_constants::NONCE_BIT_WIDTH);
     leaf_bits.extend(nonce_bits.clone());
 
-    // we allocate (witness) public X and Y to use them also later for signature check
+    let (pub_key_bits, key) = if compact {
+        // the key itself is only allocated where it is used, a recipient leaf costs
+        // the decomposition of the hash and does not witness the key
+        let hash =
+            AllocatedNum::alloc(cs.namespace(|| "allocate public key hash witness"), || {
+                Ok(*witness.pub_key_hash.get()?)
+            })?;
 
-    let pub_x = AllocatedNum::alloc(cs.namespace(|| "allocate public key x witness"), || {
-        Ok(*witness.pub_x.get()?)
-    })?;
+        let mut hash_bits = hash.into_bits_le(cs.namespace(|| "public key hash bits"))?;
+        hash_bits.truncate(plasma_constants::PUB_KEY_HASH_BIT_WIDTH);
+        (hash_bits, None)
+    } else {
+        // we allocate (witness) public X and Y to use them also later for signature check
+
+        let pub_x = AllocatedNum::alloc(cs.namespace(|| "allocate public key x witness"), || {
+            Ok(*witness.pub_x.get()?)
+        })?;
+
+        let pub_y = AllocatedNum::alloc(cs.namespace(|| "allcoate public key y witness"), || {
+            Ok(*witness.pub_y.get()?)
+        })?;
 
-    let pub_y = AllocatedNum::alloc(cs.namespace(|| "allcoate public key y witness"), || {
-        Ok(*witness.pub_y.get()?)
+        let packed = pack_public_key(cs.namespace(|| "pack public key"), &pub_x, &pub_y)?;
+        (packed, Some((pub_x, pub_y)))
+    };
+
+    leaf_bits.extend(pub_key_bits.clone());
+
+    let cheque_bits = AllocatedNum::alloc(cs.namespace(|| "allocate cheque bits witness"), || {
+        Ok(*witness.cheque_bits.get()?)
     })?;
 
-    let mut pub_x_bit = pub_x.into_bits_le(cs.namespace(|| "pub_x bits"))?;
-    // leave only the parity bit
-    pub_x_bit.truncate(1);
+    let cheque_start = AllocatedNum::alloc(
+        cs.namespace(|| "allocate cheque window start witness"),
+        || Ok(*witness.cheque_start.get()?),
+    )?;
 
-    let mut pub_y_bits = pub_y.into_bits_le(cs.namespace(|| "pub_y bits"))?;
-    pub_y_bits.resize(
-        plasma_constants::FR_BIT_WIDTH - 1,
+    let mut cheque_window_bits = cheque_bits.into_bits_le(cs.namespace(|| "cheque bits"))?;
+    cheque_window_bits.truncate(plasma_constants::CHEQUE_WINDOW_BIT_WIDTH);
+
+    let mut cheque_start_bits = cheque_start.into_bits_le(cs.namespace(|| "cheque start bits"))?;
+    cheque_start_bits.truncate(plasma_constants::CHEQUE_SERIAL_BIT_WIDTH);
+    cheque_window_bits.extend(cheque_start_bits);
+
+    let lock_commitment =
+        AllocatedNum::alloc(cs.namespace(|| "allocate lock commitment witness"), || {
+            Ok(*witness.lock_commitment.get()?)
+        })?;
+
+    let mut lock_bits = lock_commitment.into_bits_le(cs.namespace(|| "lock commitment bits"))?;
+    lock_bits.resize(
+        plasma_constants::FR_BIT_WIDTH,
         boolean::Boolean::Constant(false),
     );
 
-    append_packed_public_key(&mut leaf_bits, pub_x_bit.clone(), pub_y_bits.clone());
+    let state_bits = leaf_state_bits(
+        cs.namespace(|| "leaf state hash"),
+        &cheque_window_bits,
+        &lock_bits,
+        params,
+    )?;
 
-    // leaf_bits.extend(pub_y_bits);
-    // leaf_bits.extend(pub_x_bit);
+    leaf_bits.extend(state_bits.clone());
 
-    assert_eq!(
-        leaf_bits.len(),
-        plasma_constants::BALANCE_BIT_WIDTH
-            + plasma_constants::NONCE_BIT_WIDTH
-            + plasma_constants::FR_BIT_WIDTH
-    );
+    assert_eq!(leaf_bits.len(), leaf_bit_width_of_layout(compact));
 
     Ok(LeafContent {
         leaf_bits,
         value,
         value_bits,
+        balance_path,
+        balances_root,
         nonce,
         nonce_bits,
-        pub_x,
-        pub_y,
-        pub_x_bit,
-        pub_y_bits,
+        pub_key_bits,
+        key,
+        key_witness: (witness.pub_x, witness.pub_y),
+        cheque_bits,
+        cheque_start,
+        cheque_window_bits,
+        lock_commitment,
+        lock_bits,
+        state_bits,
     })
 }
+
+/// Bit width of the account leaf
+pub fn leaf_bit_width() -> usize {
+    leaf_bit_width_of_layout(plasma_constants::COMPACT_LEAF)
+}
+
+fn leaf_bit_width_of_layout(compact: bool) -> usize {
+    let pub_key_bit_width = if compact {
+        plasma_constants::PUB_KEY_HASH_BIT_WIDTH
+    } else {
+        plasma_constants::FR_BIT_WIDTH
+    };
+
+    plasma_constants::FR_BIT_WIDTH
+        + plasma_constants::NONCE_BIT_WIDTH
+        + pub_key_bit_width
+        + plasma_constants::LEAF_STATE_HASH_BIT_WIDTH
+}
+
+/// Cheque window and lock commitment as they are stored in the leaf, LEAF_STATE_HASH_BIT_WIDTH
+/// bits of their hash as `models::plasma::circuit::account::leaf_state_hash`
+pub fn leaf_state_bits<E, CS>(
+    mut cs: CS,
+    cheque_window_bits: &[boolean::Boolean],
+    lock_bits: &[boolean::Boolean],
+    params: &E::Params,
+) -> Result<Vec<boolean::Boolean>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    assert_eq!(
+        cheque_window_bits.len(),
+        plasma_constants::CHEQUE_WINDOW_BIT_WIDTH + plasma_constants::CHEQUE_SERIAL_BIT_WIDTH
+    );
+    assert_eq!(lock_bits.len(), plasma_constants::FR_BIT_WIDTH);
+
+    let mut preimage = cheque_window_bits.to_vec();
+    preimage.extend(lock_bits.iter().cloned());
+
+    let hash = pedersen_hash::pedersen_hash(
+        cs.namespace(|| "state hash"),
+        pedersen_hash::Personalization::NoteCommitment,
+        &preimage,
+        params,
+    )?;
+
+    let mut hash_bits = hash
+        .get_x()
+        .into_bits_le(cs.namespace(|| "state hash bits"))?;
+    hash_bits.truncate(plasma_constants::LEAF_STATE_HASH_BIT_WIDTH);
+
+    Ok(hash_bits)
+}
+
+impl<E: JubjubEngine> LeafContent<E> {
+    /// Public key of the leaf, bound to the key stored in it. The default layout packs the key
+    /// into the leaf bits when the leaf is made. The compact one only has its hash there,
+    /// so the key is allocated and checked against it here, and a circuit must get the key
+    /// of a leaf this way before it uses it
+    pub fn public_key<CS>(
+        &self,
+        mut cs: CS,
+        params: &E::Params,
+    ) -> Result<(AllocatedNum<E>, AllocatedNum<E>), SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        if let Some(ref key) = self.key {
+            return Ok(key.clone());
+        }
+
+        let (witness_x, witness_y) = self.key_witness;
+        let pub_x = AllocatedNum::alloc(cs.namespace(|| "allocate public key x witness"), || {
+            Ok(*witness_x.get()?)
+        })?;
+        let pub_y = AllocatedNum::alloc(cs.namespace(|| "allocate public key y witness"), || {
+            Ok(*witness_y.get()?)
+        })?;
+
+        let packed = pack_public_key(cs.namespace(|| "pack public key"), &pub_x, &pub_y)?;
+        let key_bits = hash_public_key(cs.namespace(|| "public key hash"), &packed, params)?;
+
+        for (i, (computed, stored)) in key_bits.iter().zip(self.pub_key_bits.iter()).enumerate() {
+            boolean::Boolean::enforce_equal(
+                cs.namespace(|| format!("enforce public key hash bit {}", i)),
+                computed,
+                stored,
+            )?;
+        }
+
+        Ok((pub_x, pub_y))
+    }
+
+    /// Whether the leaf stores the empty key (0, 0), its hash by the compact layout
+    pub fn has_empty_key<CS>(
+        &self,
+        mut cs: CS,
+        params: &E::Params,
+    ) -> Result<boolean::Boolean, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        let empty_key_bits = if self.key.is_some() {
+            vec![false; self.pub_key_bits.len()]
+        } else {
+            pub_key_hash::<E>(&E::Fr::zero(), &E::Fr::zero(), params)
+                .get_bits_le_fixed(plasma_constants::PUB_KEY_HASH_BIT_WIDTH)
+        };
+
+        let mut is_empty = boolean::Boolean::Constant(true);
+        for (i, (bit, empty_bit)) in self.pub_key_bits.iter().zip(empty_key_bits).enumerate() {
+            let matches = if empty_bit { bit.clone() } else { bit.not() };
+            is_empty = boolean::Boolean::and(
+                cs.namespace(|| format!("public key bit {} is empty", i)),
+                &is_empty,
+                &matches,
+            )?;
+        }
+
+        Ok(is_empty)
+    }
+}
+
+/// Public key as it is stored in the leaf, from the packed key: as is by the default layout,
+/// PUB_KEY_HASH_BIT_WIDTH bits of its hash by the compact one
+pub fn public_key_leaf_bits<E, CS>(
+    cs: CS,
+    packed: Vec<boolean::Boolean>,
+    params: &E::Params,
+) -> Result<Vec<boolean::Boolean>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    if !plasma_constants::COMPACT_LEAF {
+        return Ok(packed);
+    }
+
+    hash_public_key(cs, &packed, params)
+}
+
+// PUB_KEY_HASH_BIT_WIDTH bits of the hash of the packed key, as
+// `models::plasma::circuit::account::pub_key_hash`
+fn hash_public_key<E, CS>(
+    mut cs: CS,
+    packed: &[boolean::Boolean],
+    params: &E::Params,
+) -> Result<Vec<boolean::Boolean>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    let hash = pedersen_hash::pedersen_hash(
+        cs.namespace(|| "public key hash"),
+        pedersen_hash::Personalization::NoteCommitment,
+        packed,
+        params,
+    )?;
+
+    let mut hash_bits = hash
+        .get_x()
+        .into_bits_le(cs.namespace(|| "public key hash bits"))?;
+    hash_bits.truncate(plasma_constants::PUB_KEY_HASH_BIT_WIDTH);
+
+    Ok(hash_bits)
+}
+
+/// Y and the parity of x, as `models::plasma::circuit::account::packed_public_key`
+pub fn pack_public_key<E, CS>(
+    mut cs: CS,
+    pub_x: &AllocatedNum<E>,
+    pub_y: &AllocatedNum<E>,
+) -> Result<Vec<boolean::Boolean>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    let mut pub_x_bit = pub_x.into_bits_le(cs.namespace(|| "pub_x bits"))?;
+    // leave only the parity bit
+    pub_x_bit.truncate(1);
+
+    let mut pub_y_bits = pub_y.into_bits_le(cs.namespace(|| "pub_y bits"))?;
+    pub_y_bits.resize(
+        plasma_constants::FR_BIT_WIDTH - 1,
+        boolean::Boolean::Constant(false),
+    );
+
+    let mut packed = vec![];
+    append_packed_public_key(&mut packed, pub_x_bit, pub_y_bits);
+
+    Ok(packed)
+}
+
+/// Ascends the balance subtree from the token balance using its audit path.
+/// Updated balances use the same path, as the other balances are not changed.
+pub fn calculate_balances_root<E, CS>(
+    mut cs: CS,
+    balance_bits: &[boolean::Boolean],
+    token_bits: &[boolean::Boolean],
+    balance_path: &[AllocatedNum<E>],
+    params: &E::Params,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    assert_eq!(balance_bits.len(), plasma_constants::BALANCE_BIT_WIDTH);
+    assert_eq!(token_bits.len(), plasma_constants::TOKEN_BIT_WIDTH);
+    assert_eq!(balance_path.len(), plasma_constants::TOKEN_BIT_WIDTH);
+
+    let mut cur = pedersen_hash::pedersen_hash(
+        cs.namespace(|| "balance leaf hash"),
+        pedersen_hash::Personalization::NoteCommitment,
+        balance_bits,
+        params,
+    )?
+    .get_x()
+    .clone();
+
+    for (i, direction_bit) in token_bits.iter().enumerate() {
+        let cs = &mut cs.namespace(|| format!("balance subtree hash {}", i));
+
+        // Swap the two if the current subtree is on the right
+        let (xl, xr) = AllocatedNum::conditionally_reverse(
+            cs.namespace(|| "conditional reversal of preimage"),
+            &cur,
+            &balance_path[i],
+            direction_bit,
+        )?;
+
+        let mut preimage = vec![];
+        preimage.extend(xl.into_bits_le(cs.namespace(|| "xl into bits"))?);
+        preimage.extend(xr.into_bits_le(cs.namespace(|| "xr into bits"))?);
+
+        cur = pedersen_hash::pedersen_hash(
+            cs.namespace(|| "computation of pedersen hash"),
+            pedersen_hash::Personalization::MerkleTree(i),
+            &preimage,
+            params,
+        )?
+        .get_x()
+        .clone();
+    }
+
+    Ok(cur)
+}
+
+/// Hash of the account leaf with `leaf_bits` in the account tree. Pedersen or Poseidon,
+/// as `merkle_tree::AccountHasher`
+pub fn account_leaf_hash<E, CS>(
+    mut cs: CS,
+    leaf_bits: &[boolean::Boolean],
+    params: &E::Params,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    if POSEIDON_ACCOUNT_TREE {
+        return poseidon_hash_bits(
+            cs.namespace(|| "poseidon hash"),
+            &shared_params::<E>(),
+            leaf_bits,
+        );
+    }
+
+    // This is an injective encoding, as the hash is a
+    // point in the prime order subgroup.
+    Ok(pedersen_hash::pedersen_hash(
+        cs.namespace(|| "pedersen hash"),
+        pedersen_hash::Personalization::NoteCommitment,
+        leaf_bits,
+        params,
+    )?
+    .get_x()
+    .clone())
+}
+
+/// Node of the account tree at `level` (0 above the leaves) with the children `xl` and `xr`
+pub fn account_tree_node<E, CS>(
+    mut cs: CS,
+    xl: &AllocatedNum<E>,
+    xr: &AllocatedNum<E>,
+    level: usize,
+    params: &E::Params,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    if POSEIDON_ACCOUNT_TREE {
+        return poseidon_compress(
+            cs.namespace(|| "poseidon compression"),
+            &shared_params::<E>(),
+            xl,
+            xr,
+            level,
+        );
+    }
+
+    // We don't need to be strict, because the function is
+    // collision-resistant. If the prover witnesses a congruency,
+    // they will be unable to find an authentication path in the
+    // tree with high probability.
+    let mut preimage = vec![];
+    preimage.extend(xl.into_bits_le(cs.namespace(|| "xl into bits"))?);
+    preimage.extend(xr.into_bits_le(cs.namespace(|| "xr into bits"))?);
+
+    Ok(pedersen_hash::pedersen_hash(
+        cs.namespace(|| "computation of pedersen hash"),
+        pedersen_hash::Personalization::MerkleTree(level),
+        &preimage,
+        params,
+    )?
+    .get_x()
+    .clone())
+}
+
+/// Balances root as it is packed into the leaf
+pub fn balances_root_into_bits<E, CS>(
+    mut cs: CS,
+    balances_root: &AllocatedNum<E>,
+) -> Result<Vec<boolean::Boolean>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    let mut bits = balances_root.into_bits_le(cs.namespace(|| "balances root into bits"))?;
+    bits.resize(
+        plasma_constants::FR_BIT_WIDTH,
+        boolean::Boolean::Constant(false),
+    );
+
+    Ok(bits)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CircuitAccountTree;
+    use ff::PrimeField;
+    use log::debug;
+    use models::plasma::circuit::account::{pub_key_hash, CircuitAccount};
+    use models::primitives::GetBits;
+    use pairing::bn256::{Bn256, Fr};
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use sapling_crypto::alt_babyjubjub::{AltJubjubBn256, FixedGenerators};
+    use sapling_crypto::circuit::test::TestConstraintSystem;
+    use sapling_crypto::eddsa::{PrivateKey, PublicKey};
+
+    const TOKEN: u32 = 2;
+
+    fn account_with_random_key(
+        rng: &mut XorShiftRng,
+        params: &AltJubjubBn256,
+    ) -> CircuitAccount<Bn256> {
+        let sk = PrivateKey::<Bn256>(rng.gen());
+        let pk = PublicKey::from_private(&sk, FixedGenerators::SpendingKeyGenerator, params);
+        let (pub_x, pub_y) = pk.0.into_xy();
+
+        let mut account = CircuitAccount::<Bn256> {
+            nonce: Fr::from_str("7").unwrap(),
+            pub_x,
+            pub_y,
+            ..Default::default()
+        };
+        account.set_balance(TOKEN, Fr::from_str("1000").unwrap(), params);
+        account
+    }
+
+    // witness of the leaf of `account` with the key of `key_of`
+    fn leaf_witness(
+        account: &CircuitAccount<Bn256>,
+        key_of: &CircuitAccount<Bn256>,
+        params: &AltJubjubBn256,
+    ) -> LeafWitness<Bn256> {
+        LeafWitness {
+            balance: Some(account.get_balance(TOKEN)),
+            balance_path: account
+                .balance_path(TOKEN, params)
+                .into_iter()
+                .map(Some)
+                .collect(),
+            nonce: Some(account.nonce),
+            pub_x: Some(key_of.pub_x),
+            pub_y: Some(key_of.pub_y),
+            pub_key_hash: Some(pub_key_hash::<Bn256>(
+                &account.pub_x,
+                &account.pub_y,
+                params,
+            )),
+            cheque_bits: Some(account.cheque_bits),
+            cheque_start: Some(account.cheque_start),
+            lock_commitment: Some(account.lock_commitment),
+        }
+    }
+
+    fn synthesize_leaf<CS: ConstraintSystem<Bn256>>(
+        cs: CS,
+        witness: LeafWitness<Bn256>,
+        compact: bool,
+        params: &AltJubjubBn256,
+    ) -> LeafContent<Bn256> {
+        let token_bits: Vec<boolean::Boolean> = (0..plasma_constants::TOKEN_BIT_WIDTH)
+            .map(|i| boolean::Boolean::constant((TOKEN >> i) & 1 == 1))
+            .collect();
+
+        make_leaf_content_of_layout(cs, witness, &token_bits, compact, params).unwrap()
+    }
+
+    // constraints of the sender and the recipient leafs of a transfer and of their hashes.
+    // The sender key is used, the recipient one is not and a compact leaf does not witness it
+    fn transfer_leafs_constraints(compact: bool) -> (usize, usize) {
+        let params = &AltJubjubBn256::new();
+        let rng = &mut XorShiftRng::from_seed([0x3dbe_6261, 0x8d31_3d76, 0x3237_db17, 0xe5bc_0654]);
+        let sender = account_with_random_key(rng, params);
+        let recipient = account_with_random_key(rng, params);
+
+        let mut cs = TestConstraintSystem::<Bn256>::new();
+        let sender_leaf = synthesize_leaf(
+            cs.namespace(|| "sender leaf"),
+            leaf_witness(&sender, &sender, params),
+            compact,
+            params,
+        );
+        sender_leaf
+            .public_key(cs.namespace(|| "sender public key"), params)
+            .unwrap();
+        pedersen_hash::pedersen_hash(
+            cs.namespace(|| "sender leaf hash"),
+            pedersen_hash::Personalization::NoteCommitment,
+            &sender_leaf.leaf_bits,
+            params,
+        )
+        .unwrap();
+        let sender_constraints = cs.num_constraints();
+
+        let mut recipient_witness = leaf_witness(&recipient, &recipient, params);
+        if compact {
+            recipient_witness.pub_x = None;
+            recipient_witness.pub_y = None;
+        }
+        let recipient_leaf = synthesize_leaf(
+            cs.namespace(|| "recipient leaf"),
+            recipient_witness,
+            compact,
+            params,
+        );
+        pedersen_hash::pedersen_hash(
+            cs.namespace(|| "recipient leaf hash"),
+            pedersen_hash::Personalization::NoteCommitment,
+            &recipient_leaf.leaf_bits,
+            params,
+        )
+        .unwrap();
+
+        assert!(cs.is_satisfied());
+        (
+            sender_constraints,
+            cs.num_constraints() - sender_constraints,
+        )
+    }
+
+    #[test]
+    fn test_leaf_content_matches_account() {
+        let params = &AltJubjubBn256::new();
+        let rng = &mut XorShiftRng::from_seed([0x3dbe_6259, 0x8d31_3d76, 0x3237_db17, 0xe5bc_0654]);
+        let account = account_with_random_key(rng, params);
+
+        let mut cs = TestConstraintSystem::<Bn256>::new();
+        let leaf = synthesize_leaf(
+            cs.namespace(|| "leaf"),
+            leaf_witness(&account, &account, params),
+            plasma_constants::COMPACT_LEAF,
+            params,
+        );
+        leaf.public_key(cs.namespace(|| "public key"), params)
+            .unwrap();
+
+        let leaf_bits: Vec<bool> = leaf
+            .leaf_bits
+            .iter()
+            .map(|bit| bit.get_value().unwrap())
+            .collect();
+        assert_eq!(leaf_bits, account.get_bits_le());
+        assert!(cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_account_tree_gadgets_match_tree() {
+        let params = &AltJubjubBn256::new();
+        let rng = &mut XorShiftRng::from_seed([0x3dbe_6262, 0x8d31_3d76, 0x3237_db17, 0xe5bc_0654]);
+        let account = account_with_random_key(rng, params);
+        let index = 5;
+
+        let mut tree = CircuitAccountTree::new(plasma_constants::BALANCE_TREE_DEPTH as u32);
+        tree.insert(index, account.clone());
+
+        let mut cs = TestConstraintSystem::<Bn256>::new();
+        let leaf = synthesize_leaf(
+            cs.namespace(|| "leaf"),
+            leaf_witness(&account, &account, params),
+            plasma_constants::COMPACT_LEAF,
+            params,
+        );
+        let mut cur =
+            account_leaf_hash(cs.namespace(|| "leaf hash"), &leaf.leaf_bits, params).unwrap();
+
+        for (i, (sibling, cur_is_right)) in tree.merkle_path(index).into_iter().enumerate() {
+            let sibling =
+                AllocatedNum::alloc(cs.namespace(|| format!("sibling {}", i)), || Ok(sibling))
+                    .unwrap();
+            let (xl, xr) = if cur_is_right {
+                (sibling, cur)
+            } else {
+                (cur, sibling)
+            };
+            cur = account_tree_node(cs.namespace(|| format!("node {}", i)), &xl, &xr, i, params)
+                .unwrap();
+        }
+
+        assert_eq!(cur.get_value(), Some(tree.root_hash()));
+        assert!(cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_check_public_key() {
+        let params = &AltJubjubBn256::new();
+        let rng = &mut XorShiftRng::from_seed([0x3dbe_6260, 0x8d31_3d76, 0x3237_db17, 0xe5bc_0654]);
+        let account = account_with_random_key(rng, params);
+        let other = account_with_random_key(rng, params);
+
+        for &compact in &[false, true] {
+            // the compact leaf stores the hash of the key of `account`,
+            // the signature would be checked with `other`
+            let mut cs = TestConstraintSystem::<Bn256>::new();
+            let leaf = synthesize_leaf(
+                cs.namespace(|| "leaf"),
+                leaf_witness(&account, &other, params),
+                compact,
+                params,
+            );
+            leaf.public_key(cs.namespace(|| "public key"), params)
+                .unwrap();
+
+            // the default layout packs the key it is given into the leaf bits,
+            // only the compact one relies on this check
+            assert_eq!(cs.is_satisfied(), !compact);
+        }
+    }
+
+    #[test]
+    fn test_layout_constraints() {
+        let (sender, recipient) = transfer_leafs_constraints(false);
+        let (compact_sender, compact_recipient) = transfer_leafs_constraints(true);
+        debug!(
+            "default layout: sender {}, recipient {}; compact layout: sender {}, recipient {}",
+            sender, recipient, compact_sender, compact_recipient
+        );
+
+        // a compact leaf whose key is not used is cheaper, checking the key against
+        // the hash costs more than the recipient saves
+        assert!(compact_recipient < recipient);
+        assert!(compact_sender > sender);
+        assert!(compact_sender + compact_recipient > sender + recipient);
+    }
+}
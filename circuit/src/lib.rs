@@ -1,16 +1,26 @@
+pub mod change_pubkey;
 pub mod cheque;
 pub mod deposit;
 pub mod encoder;
 pub mod exit;
+pub mod keys;
 pub mod leaf;
+pub mod lock;
 pub mod plasma_constants;
+pub mod poseidon;
 pub mod transfer;
+pub mod unlock;
 
-use merkle_tree::{PedersenHasher, SparseMerkleTree};
+use merkle_tree::{AccountHasher, SparseMerkleTree};
 use models::plasma::circuit::account::CircuitAccount;
 use pairing::bn256::{Bn256, Fr};
 
-pub type CircuitAccountTree = SparseMerkleTree<CircuitAccount<Bn256>, Fr, PedersenHasher<Bn256>>;
+pub type CircuitAccountTree = SparseMerkleTree<CircuitAccount<Bn256>, Fr, AccountHasher>;
 pub type CircuitTransferTx = models::plasma::circuit::transfer::Tx<Bn256>;
 pub type CircuitDepositRequest = models::plasma::circuit::deposit::DepositRequest<Bn256>;
 pub type CircuitExitRequest = models::plasma::circuit::exit::ExitRequest<Bn256>;
+pub type CircuitChangePubKeyRequest =
+    models::plasma::circuit::change_pubkey::ChangePubKeyRequest<Bn256>;
+pub type CircuitChequeRequest = models::plasma::circuit::cheque::ChequeRequest<Bn256>;
+pub type CircuitLockRequest = models::plasma::circuit::lock::LockRequest<Bn256>;
+pub type CircuitUnlockRequest = models::plasma::circuit::unlock::UnlockRequest<Bn256>;
@@ -0,0 +1,798 @@
+use crate::leaf::{
+    account_leaf_hash, account_tree_node, balances_root_into_bits, calculate_balances_root,
+    leaf_state_bits, make_leaf_content, LeafWitness,
+};
+use crate::lock::lock_request::LockRequest;
+use bellman::{Circuit, ConstraintSystem, SynthesisError};
+use ff::{Field, PrimeField};
+use models::plasma::circuit::utils::allocate_audit_path;
+use models::plasma::params as plasma_constants;
+use models::primitives::bytes_into_be_bits;
+use sapling_crypto::circuit::baby_eddsa::EddsaSignature;
+use sapling_crypto::circuit::float_point::parse_with_exponent_le;
+use sapling_crypto::circuit::num::{AllocatedNum, Num};
+use sapling_crypto::circuit::{boolean, ecc, num, pedersen_hash, sha256, Assignment};
+use sapling_crypto::jubjub::{FixedGenerators, JubjubEngine, JubjubParams};
+
+#[derive(Clone)]
+pub struct LockWitness<E: JubjubEngine> {
+    pub leaf: LeafWitness<E>,
+    pub auth_path: Vec<Option<E::Fr>>,
+}
+
+/// This is an instance of the `Lock` circuit.
+pub struct Lock<'a, E: JubjubEngine> {
+    pub params: &'a E::Params,
+
+    // number of locks per block
+    pub number_of_locks: usize,
+
+    /// Chain id of the deployment, it is fixed when the keys are generated
+    pub chain_id: E::Fr,
+
+    /// The old root of the tree
+    pub old_root: Option<E::Fr>,
+
+    /// The new root of the tree
+    pub new_root: Option<E::Fr>,
+
+    /// Final truncated rolling SHA256
+    pub public_data_commitment: Option<E::Fr>,
+
+    /// Block number
+    pub block_number: Option<E::Fr>,
+
+    /// Requests for this block
+    pub requests: Vec<(LockRequest<E>, LockWitness<E>)>,
+}
+
+impl<'a, E: JubjubEngine> Circuit<E> for Lock<'a, E> {
+    fn synthesize<CS: ConstraintSystem<E>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        // Check that requests are in a right quantity
+        assert!(self.number_of_locks == self.requests.len());
+
+        let old_root_value = self.old_root;
+        // Expose inputs and do the bits decomposition of hash
+        let mut old_root =
+            AllocatedNum::alloc(cs.namespace(|| "old root"), || Ok(*old_root_value.get()?))?;
+        old_root.inputize(cs.namespace(|| "old root input"))?;
+
+        let new_root_value = self.new_root;
+        let new_root =
+            AllocatedNum::alloc(cs.namespace(|| "new root"), || Ok(*new_root_value.get()?))?;
+        new_root.inputize(cs.namespace(|| "new root input"))?;
+
+        let rolling_hash_value = self.public_data_commitment;
+        let rolling_hash = AllocatedNum::alloc(cs.namespace(|| "rolling hash"), || {
+            Ok(*rolling_hash_value.get()?)
+        })?;
+        rolling_hash.inputize(cs.namespace(|| "rolling hash input"))?;
+
+        let mut public_data_vector: Vec<boolean::Boolean> = vec![];
+
+        let public_generator = self
+            .params
+            .generator(FixedGenerators::SpendingKeyGenerator)
+            .clone();
+        let generator = ecc::EdwardsPoint::witness(
+            cs.namespace(|| "allocate public generator"),
+            Some(public_generator),
+            self.params,
+        )?;
+
+        // Ok, now we need to update the old root by applying requests in sequence
+        let requests = self.requests.clone();
+
+        for (i, tx) in requests.into_iter().enumerate() {
+            let (request, witness) = tx;
+            let (intermediate_root, public_data) = apply_request(
+                cs.namespace(|| format!("applying request {}", i)),
+                old_root,
+                request,
+                witness,
+                self.chain_id,
+                self.params,
+                generator.clone(),
+            )?;
+            old_root = intermediate_root;
+            // flatten the public transaction data
+            public_data_vector.extend(public_data.into_iter());
+        }
+
+        // constraint the new hash to be equal to updated hash
+
+        cs.enforce(
+            || "enforce new root equal to recalculated one",
+            |lc| lc + new_root.get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc + old_root.get_variable(),
+        );
+
+        // Now it's time to pack the initial SHA256 hash due to Ethereum BE encoding
+        // and start rolling the hash
+
+        let mut initial_hash_data: Vec<boolean::Boolean> = vec![];
+
+        let block_number_allocated =
+            AllocatedNum::alloc(cs.namespace(|| "allocate block number"), || {
+                Ok(*self.block_number.get()?)
+            })?;
+
+        // make initial hash as sha256(uint256(block_number))
+        let mut block_number_bits = block_number_allocated
+            .into_bits_le(cs.namespace(|| "unpack block number for hashing"))?;
+
+        block_number_bits.resize(
+            plasma_constants::FR_BIT_WIDTH,
+            boolean::Boolean::Constant(false),
+        );
+        block_number_bits.reverse();
+        initial_hash_data.extend(block_number_bits.into_iter());
+
+        assert_eq!(initial_hash_data.len(), 256);
+
+        let mut hash_block = sha256::sha256(
+            cs.namespace(|| "initial rolling sha256"),
+            &initial_hash_data,
+        )?;
+
+        // now pack the public data and do the final hash
+
+        let mut pack_bits = vec![];
+        pack_bits.extend(hash_block);
+        pack_bits.extend(public_data_vector.into_iter());
+
+        hash_block = sha256::sha256(cs.namespace(|| "hash public data"), &pack_bits)?;
+
+        // now pack and enforce equality to the input
+
+        hash_block.reverse();
+        hash_block.truncate(E::Fr::CAPACITY as usize);
+
+        let mut packed_hash_lc = Num::<E>::zero();
+        let mut coeff = E::Fr::one();
+        for bit in hash_block {
+            packed_hash_lc = packed_hash_lc.add_bool_with_coeff(CS::one(), &bit, coeff);
+            coeff.double();
+        }
+
+        cs.enforce(
+            || "enforce external data hash equality",
+            |lc| lc + rolling_hash.get_variable(),
+            |lc| lc + CS::one(),
+            |_| packed_hash_lc.lc(E::Fr::one()),
+        );
+
+        Ok(())
+    }
+}
+
+/// Ascends the merkle tree authentication path from the leaf,
+/// outputs the root
+fn calculate_root<E, CS>(
+    mut cs: CS,
+    leaf_bits: &[boolean::Boolean],
+    path_bits: &[boolean::Boolean],
+    audit_path: &[AllocatedNum<E>],
+    params: &E::Params,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    let mut cur = account_leaf_hash(cs.namespace(|| "leaf content hash"), leaf_bits, params)?;
+
+    for (i, direction_bit) in path_bits.iter().enumerate() {
+        let cs = &mut cs.namespace(|| format!("merkle tree hash {}", i));
+
+        // Swap the two if the current subtree is on the right
+        let (xl, xr) = num::AllocatedNum::conditionally_reverse(
+            cs.namespace(|| "conditional reversal of preimage"),
+            &cur,
+            &audit_path[i],
+            direction_bit,
+        )?;
+
+        // Compute the new subtree value
+        cur = account_tree_node(cs.namespace(|| "node hash"), &xl, &xr, i, params)?;
+    }
+
+    Ok(cur)
+}
+
+/// Repacks a truncated bit decomposition into a number
+fn pack_bits_into_num<E, CS>(
+    mut cs: CS,
+    bits: &[boolean::Boolean],
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    let mut packed_lc = Num::<E>::zero();
+    let mut coeff = E::Fr::one();
+    for bit in bits {
+        packed_lc = packed_lc.add_bool_with_coeff(CS::one(), bit, coeff);
+        coeff.double();
+    }
+
+    let packed = AllocatedNum::alloc(cs.namespace(|| "allocate packed value"), || {
+        Ok(*packed_lc.get_value().get()?)
+    })?;
+
+    cs.enforce(
+        || "pack bits",
+        |lc| lc + packed.get_variable(),
+        |lc| lc + CS::one(),
+        |_| packed_lc.lc(E::Fr::one()),
+    );
+
+    Ok(packed)
+}
+
+/// Applies one request to the tree,
+/// outputs a new root
+fn apply_request<E, CS>(
+    mut cs: CS,
+    old_root: AllocatedNum<E>,
+    request: LockRequest<E>,
+    witness: LockWitness<E>,
+    chain_id: E::Fr,
+    params: &E::Params,
+    generator: ecc::EdwardsPoint<E>,
+) -> Result<(AllocatedNum<E>, Vec<boolean::Boolean>), SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    let token_allocated =
+        AllocatedNum::alloc(cs.namespace(|| "token"), || Ok(*request.token.get()?))?;
+
+    let mut token_bits =
+        token_allocated.into_bits_le(cs.namespace(|| "token bit decomposition"))?;
+
+    token_bits.truncate(plasma_constants::TOKEN_BIT_WIDTH);
+
+    let leaf = make_leaf_content(
+        cs.namespace(|| "create leaf"),
+        witness.clone().leaf,
+        &token_bits,
+        params,
+    )?;
+
+    let address_allocated = AllocatedNum::alloc(cs.namespace(|| "account address"), || {
+        Ok(*request.account.get()?)
+    })?;
+
+    let mut path_bits =
+        address_allocated.into_bits_le(cs.namespace(|| "address bit decomposition"))?;
+
+    path_bits.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+
+    let audit_path = allocate_audit_path(
+        cs.namespace(|| "allocate audit path"),
+        witness.clone().auth_path,
+    )?;
+
+    let old_root_from_leaf = calculate_root(
+        cs.namespace(|| "old root"),
+        &leaf.leaf_bits,
+        &path_bits,
+        &audit_path,
+        params,
+    )?;
+
+    // enforce old root before update
+    cs.enforce(
+        || "enforce correct old root for leaf",
+        |lc| lc + old_root_from_leaf.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + old_root.get_variable(),
+    );
+
+    // an account has at most one lock at a time
+
+    cs.enforce(
+        || "enforce no lock in the leaf",
+        |lc| lc + leaf.lock_commitment.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc,
+    );
+
+    // parse the lock
+
+    let to_address_allocated = AllocatedNum::alloc(cs.namespace(|| "recipient address"), || {
+        Ok(*request.to.get()?)
+    })?;
+
+    let mut to_bits = to_address_allocated
+        .into_bits_le(cs.namespace(|| "recipient address bit decomposition"))?;
+
+    to_bits.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+
+    let amount_encoded =
+        AllocatedNum::alloc(cs.namespace(|| "allocate encoded lock amount"), || {
+            Ok(*request.amount.get()?)
+        })?;
+
+    let mut amount_bits = amount_encoded.into_bits_le(cs.namespace(|| "amount bits"))?;
+
+    amount_bits.truncate(
+        plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH + plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH,
+    );
+
+    let amount = parse_with_exponent_le(
+        cs.namespace(|| "parse amount"),
+        &amount_bits,
+        plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH,
+        plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH,
+        10,
+    )?;
+
+    let mut parsed_amount_bits = amount.into_bits_le(cs.namespace(|| "parsed amount bits"))?;
+
+    parsed_amount_bits.truncate(plasma_constants::BALANCE_BIT_WIDTH);
+
+    let hash_value = request.hash.map(|hash| bytes_into_be_bits(&hash));
+
+    let mut hash_bits = vec![];
+    for i in 0..plasma_constants::HASH_LOCK_BIT_WIDTH {
+        let bit = boolean::AllocatedBit::alloc(
+            cs.namespace(|| format!("allocate hash bit {}", i)),
+            hash_value.as_ref().map(|bits| bits[i]),
+        )?;
+        hash_bits.push(boolean::Boolean::from(bit));
+    }
+
+    let good_until_block =
+        AllocatedNum::alloc(cs.namespace(|| "allocate lock good until block"), || {
+            Ok(*request.good_until_block.get()?)
+        })?;
+
+    let mut block_number_bits =
+        good_until_block.into_bits_le(cs.namespace(|| "block number bits"))?;
+
+    block_number_bits.truncate(plasma_constants::BLOCK_NUMBER_BIT_WIDTH);
+
+    // the lock is kept in the leaf as a commitment
+
+    let mut lock_content = vec![];
+    lock_content.extend(to_bits.clone());
+    lock_content.extend(token_bits.clone());
+    lock_content.extend(parsed_amount_bits);
+    lock_content.extend(hash_bits.clone());
+    lock_content.extend(block_number_bits.clone());
+
+    let mut lock_commitment_bits = pedersen_hash::pedersen_hash(
+        cs.namespace(|| "lock commitment"),
+        pedersen_hash::Personalization::NoteCommitment,
+        &lock_content,
+        params,
+    )?
+    .get_x()
+    .into_bits_le(cs.namespace(|| "lock commitment bits"))?;
+
+    lock_commitment_bits.resize(
+        plasma_constants::FR_BIT_WIDTH,
+        boolean::Boolean::Constant(false),
+    );
+
+    // the lock does not fit into a signed message, so the hash of its commitment is signed instead
+
+    let mut message_bits: Vec<boolean::Boolean> = vec![];
+    message_bits.extend(path_bits.clone());
+    message_bits.extend(leaf.nonce_bits.clone());
+    message_bits.extend(
+        lock_commitment_bits[0..plasma_constants::LOCK_COMMITMENT_HASH_WIDTH]
+            .iter()
+            .cloned(),
+    );
+
+    let request_chain_id =
+        AllocatedNum::alloc(cs.namespace(|| "allocate request chain id"), || {
+            Ok(*request.chain_id.get()?)
+        })?;
+
+    // a lock signed for another deployment is rejected
+    cs.enforce(
+        || "enforce chain id of the deployment",
+        |lc| lc + request_chain_id.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + (chain_id, CS::one()),
+    );
+
+    let mut chain_id_bits = request_chain_id.into_bits_le(cs.namespace(|| "chain id bits"))?;
+    chain_id_bits.truncate(plasma_constants::CHAIN_ID_BIT_WIDTH);
+    message_bits.extend(chain_id_bits);
+
+    let (pub_x, pub_y) = leaf.public_key(cs.namespace(|| "check sender public key"), params)?;
+
+    let sender_pk =
+        ecc::EdwardsPoint::interpret(cs.namespace(|| "sender public key"), &pub_x, &pub_y, params)?;
+
+    let signature_r_x = AllocatedNum::alloc(cs.namespace(|| "signature r_x witness"), || {
+        Ok(request.signature.get()?.r.into_xy().0)
+    })?;
+
+    let signature_r_y = AllocatedNum::alloc(cs.namespace(|| "signature r_y witness"), || {
+        Ok(request.signature.get()?.r.into_xy().1)
+    })?;
+
+    let signature_r = ecc::EdwardsPoint::interpret(
+        cs.namespace(|| "signature r as point"),
+        &signature_r_x,
+        &signature_r_y,
+        params,
+    )?;
+
+    let signature_s = AllocatedNum::alloc(cs.namespace(|| "signature s witness"), || {
+        Ok(request.signature.get()?.s)
+    })?;
+
+    let signature = EddsaSignature {
+        r: signature_r,
+        s: signature_s,
+        pk: sender_pk,
+    };
+
+    let max_message_len = plasma_constants::BALANCE_TREE_DEPTH
+        + plasma_constants::NONCE_BIT_WIDTH
+        + plasma_constants::LOCK_COMMITMENT_HASH_WIDTH
+        + plasma_constants::CHAIN_ID_BIT_WIDTH;
+
+    signature.verify_raw_message_signature(
+        cs.namespace(|| "verify lock signature"),
+        params,
+        &message_bits,
+        generator,
+        max_message_len,
+    )?;
+
+    // take the amount from the balance
+
+    let old_balance = pack_bits_into_num(cs.namespace(|| "pack old balance"), &leaf.value_bits)?;
+
+    let new_balance = AllocatedNum::alloc(cs.namespace(|| "new balance"), || {
+        let mut new_balance_value = *old_balance.get_value().get()?;
+        new_balance_value.sub_assign(amount.get_value().get()?);
+
+        Ok(new_balance_value)
+    })?;
+
+    // constraint no underflow
+    new_balance.limit_number_of_bits(
+        cs.namespace(|| "limit number of bits for new balance"),
+        plasma_constants::BALANCE_BIT_WIDTH,
+    )?;
+
+    cs.enforce(
+        || "enforce new balance",
+        |lc| lc + new_balance.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + old_balance.get_variable() - amount.get_variable(),
+    );
+
+    // lock consumes a nonce, so the signature can not be replayed
+
+    let mut nonce_lc = Num::<E>::zero();
+    let mut coeff = E::Fr::one();
+    for bit in &leaf.nonce_bits {
+        nonce_lc = nonce_lc.add_bool_with_coeff(CS::one(), &bit, coeff);
+        coeff.double();
+    }
+
+    let nonce = AllocatedNum::alloc(cs.namespace(|| "nonce"), || {
+        Ok(*nonce_lc.get_value().get()?)
+    })?;
+
+    cs.enforce(
+        || "pack nonce",
+        |lc| lc + nonce.get_variable(),
+        |lc| lc + CS::one(),
+        |_| nonce_lc.lc(E::Fr::one()),
+    );
+
+    let new_nonce = AllocatedNum::alloc(cs.namespace(|| "new nonce"), || {
+        let mut new_nonce_value = *nonce.get_value().get()?;
+        new_nonce_value.add_assign(&E::Fr::one());
+
+        Ok(new_nonce_value)
+    })?;
+
+    // constraint no overflow
+    new_nonce.limit_number_of_bits(
+        cs.namespace(|| "limit number of bits for new nonce"),
+        plasma_constants::NONCE_BIT_WIDTH,
+    )?;
+
+    cs.enforce(
+        || "enforce nonce to increase",
+        |lc| lc + new_nonce.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + nonce.get_variable() + CS::one(),
+    );
+
+    let mut leaf_content = vec![];
+
+    let mut value_content = new_balance.into_bits_le(cs.namespace(|| "updated amount bits"))?;
+
+    value_content.truncate(plasma_constants::BALANCE_BIT_WIDTH);
+
+    let balances_root = calculate_balances_root(
+        cs.namespace(|| "updated balances root"),
+        &value_content,
+        &token_bits,
+        &leaf.balance_path,
+        params,
+    )?;
+
+    leaf_content.extend(balances_root_into_bits(
+        cs.namespace(|| "updated balances root bits"),
+        &balances_root,
+    )?);
+
+    let mut nonce_content = new_nonce.into_bits_le(cs.namespace(|| "updated nonce bits"))?;
+
+    nonce_content.truncate(plasma_constants::NONCE_BIT_WIDTH);
+    leaf_content.extend(nonce_content);
+
+    // public key and cheque window are kept as is
+    leaf_content.extend(leaf.pub_key_bits.clone());
+    leaf_content.extend(leaf_state_bits(
+        cs.namespace(|| "updated leaf state bits"),
+        &leaf.cheque_window_bits,
+        &lock_commitment_bits,
+        params,
+    )?);
+
+    assert_eq!(leaf_content.len(), leaf.leaf_bits.len());
+
+    let new_root = calculate_root(
+        cs.namespace(|| "updated root"),
+        &leaf_content,
+        &path_bits,
+        &audit_path,
+        params,
+    )?;
+
+    // the last step - we expose public data for later commitment
+
+    // convert to BE for further use in Ethereum
+    let mut path_be = path_bits;
+    path_be.reverse();
+
+    let mut to_be = to_bits;
+    to_be.reverse();
+
+    let mut token_be = token_bits;
+    token_be.reverse();
+
+    let mut block_number_be = block_number_bits;
+    block_number_be.reverse();
+
+    let mut public_data = vec![];
+    public_data.extend(path_be);
+    public_data.extend(to_be);
+    public_data.extend(token_be);
+    public_data.extend(amount_bits);
+    // hash is already in the bit order of SHA256
+    public_data.extend(hash_bits);
+    public_data.extend(block_number_be);
+
+    assert_eq!(
+        public_data.len(),
+        plasma_constants::BALANCE_TREE_DEPTH
+            + plasma_constants::BALANCE_TREE_DEPTH
+            + plasma_constants::TOKEN_BIT_WIDTH
+            + plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH
+            + plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH
+            + plasma_constants::HASH_LOCK_BIT_WIDTH
+            + plasma_constants::BLOCK_NUMBER_BIT_WIDTH
+    );
+
+    Ok((new_root, public_data))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use log::debug;
+
+    use crate::CircuitAccountTree;
+    use ff::{BitIterator, PrimeFieldRepr};
+    use models::plasma::circuit::account::{leaf_pub_key_hash, CircuitAccount};
+    use models::plasma::circuit::utils::{
+        be_bit_vector_into_bytes, le_bit_vector_into_field_element,
+    };
+    use pairing::bn256::*;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use sapling_crypto::alt_babyjubjub::AltJubjubBn256;
+    use sapling_crypto::circuit::float_point::convert_to_float;
+    use sapling_crypto::circuit::test::*;
+    use sapling_crypto::eddsa::{PrivateKey, PublicKey};
+
+    use crypto::digest::Digest;
+    use crypto::sha2::Sha256;
+
+    // locks 100 of 1000 of the account,
+    // returns the constraint system after synthesis
+    fn lock_funds(old_lock_commitment: Fr) -> TestConstraintSystem<Bn256> {
+        let params = &AltJubjubBn256::new();
+        let p_g = FixedGenerators::SpendingKeyGenerator;
+
+        let rng = &mut XorShiftRng::from_seed([0x3dbe_6258, 0x8d31_3d76, 0x3237_db17, 0xe5bc_0654]);
+
+        let tree_depth = plasma_constants::BALANCE_TREE_DEPTH as u32;
+        let mut tree = CircuitAccountTree::new(tree_depth);
+
+        let capacity = tree.capacity();
+
+        let sender_sk = PrivateKey::<Bn256>(rng.gen());
+        let sender_pk = PublicKey::from_private(&sender_sk, p_g, params);
+        let (sender_x, sender_y) = sender_pk.0.into_xy();
+
+        let leaf_number: u32 = rng.gen::<u32>() % capacity;
+        let mut recipient: u32 = rng.gen::<u32>() % capacity;
+        if recipient == leaf_number {
+            recipient = (recipient + 1) % capacity;
+        }
+
+        let token: u32 = 2;
+        let nonce = Fr::from_str("5").unwrap();
+
+        let mut leaf = CircuitAccount::<Bn256> {
+            nonce,
+            pub_x: sender_x,
+            pub_y: sender_y,
+            lock_commitment: old_lock_commitment,
+            ..Default::default()
+        };
+        leaf.set_balance(token, Fr::from_str("1000").unwrap(), params);
+
+        tree.insert(leaf_number, leaf.clone());
+
+        let initial_root = tree.root_hash();
+        debug!("Initial root = {}", initial_root);
+
+        let amount_bits = convert_to_float(
+            100,
+            plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH,
+            plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH,
+            10,
+        )
+        .unwrap();
+
+        let mut hash = [0u8; 32];
+        let mut h = Sha256::new();
+        h.input(b"secret");
+        h.result(&mut hash[..]);
+
+        let mut request: LockRequest<Bn256> = LockRequest {
+            account: Fr::from_str(&leaf_number.to_string()),
+            to: Fr::from_str(&recipient.to_string()),
+            token: Fr::from_str(&token.to_string()),
+            amount: Some(le_bit_vector_into_field_element(&amount_bits)),
+            hash: Some(hash),
+            good_until_block: Fr::from_str("10"),
+            nonce: Some(nonce),
+            chain_id: Some(Fr::one()),
+            signature: None,
+        };
+
+        request.sign(&sender_sk, p_g, params, rng);
+        assert!(request.signature.is_some());
+
+        let auth_path: Vec<Option<Fr>> = tree
+            .merkle_path(leaf_number)
+            .into_iter()
+            .map(|e| Some(e.0))
+            .collect();
+
+        let leaf_witness = LeafWitness {
+            balance: Some(leaf.get_balance(token)),
+            balance_path: leaf
+                .balance_path(token, params)
+                .into_iter()
+                .map(Some)
+                .collect(),
+            nonce: Some(nonce),
+            pub_x: Some(sender_x),
+            pub_y: Some(sender_y),
+            pub_key_hash: leaf_pub_key_hash(&sender_x, &sender_y),
+            cheque_bits: Some(leaf.cheque_bits),
+            cheque_start: Some(leaf.cheque_start),
+            lock_commitment: Some(leaf.lock_commitment),
+        };
+
+        let mut updated_leaf = leaf.clone();
+        updated_leaf.set_balance(token, Fr::from_str("900").unwrap(), params);
+        updated_leaf.nonce.add_assign(&Fr::one());
+        updated_leaf.lock_commitment = request.lock_commitment(params);
+        tree.insert(leaf_number, updated_leaf);
+
+        let new_root = tree.root_hash();
+        debug!("New root = {}", new_root);
+
+        let witness = LockWitness {
+            leaf: leaf_witness,
+            auth_path,
+        };
+
+        let mut public_data_initial_bits = Vec::new();
+
+        // these two are BE encodings because an iterator is BE. This is also an Ethereum standard behavior
+
+        let block_number_bits: Vec<bool> = BitIterator::new(Fr::one().into_repr()).collect();
+        for _ in 0..256 - block_number_bits.len() {
+            public_data_initial_bits.push(false);
+        }
+        public_data_initial_bits.extend(block_number_bits.into_iter());
+
+        let mut h = Sha256::new();
+
+        let bytes_to_hash = be_bit_vector_into_bytes(&public_data_initial_bits);
+
+        h.input(&bytes_to_hash);
+
+        let mut hash_result = [0u8; 32];
+        h.result(&mut hash_result[..]);
+
+        let packed_transaction_data_bytes =
+            be_bit_vector_into_bytes(&request.public_data_into_bits());
+
+        let mut next_round_hash_bytes = vec![];
+        next_round_hash_bytes.extend(hash_result.iter());
+        next_round_hash_bytes.extend(packed_transaction_data_bytes);
+
+        h = Sha256::new();
+        h.input(&next_round_hash_bytes);
+        hash_result = [0u8; 32];
+        h.result(&mut hash_result[..]);
+
+        hash_result[0] &= 0x1f; // temporary solution
+
+        let mut repr = Fr::zero().into_repr();
+        repr.read_be(&hash_result[..])
+            .expect("pack hash as field element");
+
+        let public_data_commitment = Fr::from_repr(repr).unwrap();
+
+        let instance = Lock {
+            params,
+            number_of_locks: 1,
+            chain_id: Fr::one(),
+            old_root: Some(initial_root),
+            new_root: Some(new_root),
+            public_data_commitment: Some(public_data_commitment),
+            block_number: Some(Fr::one()),
+            requests: vec![(request, witness)],
+        };
+
+        let mut cs = TestConstraintSystem::<Bn256>::new();
+
+        instance.synthesize(&mut cs).unwrap();
+
+        debug!("{}", cs.num_constraints());
+
+        assert_eq!(cs.num_inputs(), 4);
+
+        cs
+    }
+
+    #[test]
+    fn test_lock() {
+        let cs = lock_funds(Fr::zero());
+        let err = cs.which_is_unsatisfied();
+        if err.is_some() {
+            panic!("ERROR satisfying in {}", err.unwrap());
+        }
+    }
+
+    #[test]
+    fn test_lock_twice() {
+        // the account already has a lock
+        let cs = lock_funds(Fr::from_str("42").unwrap());
+        assert!(cs.which_is_unsatisfied().is_some());
+    }
+}
@@ -0,0 +1,226 @@
+use ff::{BitIterator, PrimeField};
+use models::plasma::circuit::lock::lock_commitment_hash_bits;
+use models::plasma::circuit::sig::TransactionSignature;
+use models::plasma::circuit::utils::le_bit_vector_into_field_element;
+use models::plasma::params as plasma_constants;
+use models::primitives::{bytes_into_be_bits, get_bits_le_fixed_u128};
+use sapling_crypto::circuit::float_point::parse_float_to_u128;
+use sapling_crypto::eddsa::{PrivateKey, PublicKey};
+use sapling_crypto::jubjub::{FixedGenerators, JubjubEngine};
+use sapling_crypto::pedersen_hash::{baby_pedersen_hash, Personalization};
+
+// This is a request to lock funds of an account under a hash until some block
+
+#[derive(Clone)]
+pub struct LockRequest<E: JubjubEngine> {
+    pub account: Option<E::Fr>,
+    pub to: Option<E::Fr>,
+    pub token: Option<E::Fr>,
+    // packed amount
+    pub amount: Option<E::Fr>,
+    // SHA256 hash of the secret
+    pub hash: Option<[u8; 32]>,
+    pub good_until_block: Option<E::Fr>,
+    // keep a nonce in request for ease of signing,
+    // the circuit takes the nonce from the leaf
+    pub nonce: Option<E::Fr>,
+    // deployment the request is signed for, the circuit checks it against its own
+    pub chain_id: Option<E::Fr>,
+    pub signature: Option<TransactionSignature<E>>,
+}
+
+impl<E: JubjubEngine> LockRequest<E> {
+    pub fn public_data_into_bits(&self) -> Vec<bool> {
+        // fields are
+        // - account
+        // - to
+        // - token
+        // - amount
+        // - hash
+        // - good_until_block
+        let mut account: Vec<bool> = BitIterator::new(self.account.unwrap().into_repr()).collect();
+        account.reverse();
+        account.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+        // reverse again to have BE as in Ethereum native types
+        account.reverse();
+
+        let mut to: Vec<bool> = BitIterator::new(self.to.unwrap().into_repr()).collect();
+        to.reverse();
+        to.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+        to.reverse();
+
+        let mut token: Vec<bool> = BitIterator::new(self.token.unwrap().into_repr()).collect();
+        token.reverse();
+        token.truncate(plasma_constants::TOKEN_BIT_WIDTH);
+        token.reverse();
+
+        // packed amount is kept LE, the same as in transfers
+        let mut amount: Vec<bool> = BitIterator::new(self.amount.unwrap().into_repr()).collect();
+        amount.reverse();
+        amount.truncate(
+            plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH
+                + plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH,
+        );
+
+        let mut good_until_block: Vec<bool> =
+            BitIterator::new(self.good_until_block.unwrap().into_repr()).collect();
+        good_until_block.reverse();
+        good_until_block.truncate(plasma_constants::BLOCK_NUMBER_BIT_WIDTH);
+        good_until_block.reverse();
+
+        let mut packed: Vec<bool> = vec![];
+        packed.extend(account.into_iter());
+        packed.extend(to.into_iter());
+        packed.extend(token.into_iter());
+        packed.extend(amount.into_iter());
+        packed.extend(bytes_into_be_bits(&self.hash.unwrap()));
+        packed.extend(good_until_block.into_iter());
+
+        packed
+    }
+
+    /// Commitment to the lock as it is kept in the leaf,
+    /// the same as `models::plasma::circuit::lock::lock_commitment`
+    pub fn lock_commitment(&self, params: &E::Params) -> E::Fr {
+        let mut to: Vec<bool> = BitIterator::new(self.to.unwrap().into_repr()).collect();
+        to.reverse();
+        to.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+
+        let mut token: Vec<bool> = BitIterator::new(self.token.unwrap().into_repr()).collect();
+        token.reverse();
+        token.truncate(plasma_constants::TOKEN_BIT_WIDTH);
+
+        // the lock keeps the parsed amount
+        let mut amount_bits: Vec<bool> =
+            BitIterator::new(self.amount.unwrap().into_repr()).collect();
+        amount_bits.reverse();
+        amount_bits.truncate(
+            plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH
+                + plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH,
+        );
+        let amount = parse_float_to_u128(
+            amount_bits,
+            plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH,
+            plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH,
+            10,
+        )
+        .expect("amount is a valid float");
+
+        let mut good_until_block: Vec<bool> =
+            BitIterator::new(self.good_until_block.unwrap().into_repr()).collect();
+        good_until_block.reverse();
+        good_until_block.truncate(plasma_constants::BLOCK_NUMBER_BIT_WIDTH);
+
+        let mut lock_bits: Vec<bool> = vec![];
+        lock_bits.extend(to.into_iter());
+        lock_bits.extend(token.into_iter());
+        lock_bits.extend(get_bits_le_fixed_u128(
+            amount,
+            plasma_constants::BALANCE_BIT_WIDTH,
+        ));
+        lock_bits.extend(bytes_into_be_bits(&self.hash.unwrap()));
+        lock_bits.extend(good_until_block.into_iter());
+
+        baby_pedersen_hash::<E, _>(Personalization::NoteCommitment, lock_bits, params)
+            .into_xy()
+            .0
+    }
+
+    // this function returns data to make a request signature
+    // in a format that is later used in zkSNARK
+    pub fn data_for_signature_into_bits(&self, params: &E::Params) -> Vec<bool> {
+        // fields are
+        // - account
+        // - nonce
+        // - hash of the lock commitment
+        // - chain_id
+
+        // LE account
+        let mut account: Vec<bool> = BitIterator::new(self.account.unwrap().into_repr()).collect();
+        account.reverse();
+        account.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+        // LE nonce
+        let mut nonce: Vec<bool> = BitIterator::new(self.nonce.unwrap().into_repr()).collect();
+        nonce.reverse();
+        nonce.truncate(plasma_constants::NONCE_BIT_WIDTH);
+
+        let commitment = self.lock_commitment(params);
+
+        let mut chain_id: Vec<bool> =
+            BitIterator::new(self.chain_id.unwrap().into_repr()).collect();
+        chain_id.reverse();
+        chain_id.truncate(plasma_constants::CHAIN_ID_BIT_WIDTH);
+
+        let mut packed: Vec<bool> = vec![];
+
+        packed.extend(account.into_iter());
+        packed.extend(nonce.into_iter());
+        packed.extend(lock_commitment_hash_bits::<E>(&commitment).into_iter());
+        packed.extend(chain_id.into_iter());
+
+        packed
+    }
+
+    pub fn data_as_bytes(&self, params: &E::Params) -> Vec<u8> {
+        let raw_data: Vec<bool> = self.data_for_signature_into_bits(params);
+
+        let mut message_bytes: Vec<u8> = vec![];
+
+        let byte_chunks = raw_data.chunks(8);
+        for byte_chunk in byte_chunks {
+            let mut byte = 0u8;
+            for (i, bit) in byte_chunk.iter().enumerate() {
+                if *bit {
+                    byte |= 1 << i;
+                }
+            }
+            message_bytes.push(byte);
+        }
+
+        message_bytes
+    }
+
+    pub fn sign<R>(
+        &mut self,
+        private_key: &PrivateKey<E>,
+        p_g: FixedGenerators,
+        params: &E::Params,
+        rng: &mut R,
+    ) where
+        R: rand::Rng,
+    {
+        let message_bytes = self.data_as_bytes(params);
+
+        let max_message_len = plasma_constants::BALANCE_TREE_DEPTH
+            + plasma_constants::NONCE_BIT_WIDTH
+            + plasma_constants::LOCK_COMMITMENT_HASH_WIDTH
+            + plasma_constants::CHAIN_ID_BIT_WIDTH;
+
+        let signature =
+            private_key.sign_raw_message(&message_bytes, rng, p_g, params, max_message_len / 8);
+
+        let pk = PublicKey::from_private(&private_key, p_g, params);
+        let is_valid_signature = pk.verify_for_raw_message(
+            &message_bytes,
+            &signature.clone(),
+            p_g,
+            params,
+            max_message_len / 8,
+        );
+        if !is_valid_signature {
+            return;
+        }
+
+        let mut sigs_le_bits: Vec<bool> = BitIterator::new(signature.s.into_repr()).collect();
+        sigs_le_bits.reverse();
+
+        let sigs_converted = le_bit_vector_into_field_element(&sigs_le_bits);
+
+        let converted_signature = TransactionSignature {
+            r: signature.r,
+            s: sigs_converted,
+        };
+
+        self.signature = Some(converted_signature);
+    }
+}
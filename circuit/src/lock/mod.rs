@@ -0,0 +1,2 @@
+pub mod circuit;
+pub mod lock_request;
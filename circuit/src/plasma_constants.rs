@@ -1,6 +1,3 @@
-/// Balance tree depth
-pub const BALANCE_TREE_DEPTH: &usize = &24;
-
 /// Balance bit width
 pub const BALANCE_BIT_WIDTH: &usize = &128;
 
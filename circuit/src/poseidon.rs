@@ -0,0 +1,404 @@
+// Gadgets of the Poseidon hash, they match `merkle_tree::poseidon_hasher`.
+// The circuits hash the account tree with them under the `poseidon_tree` feature of merkle_tree,
+// see `leaf::account_leaf_hash` and `leaf::account_tree_node`
+
+use bellman::{ConstraintSystem, LinearCombination, SynthesisError};
+use ff::{Field, PrimeField};
+use merkle_tree::poseidon_hasher::{leaf_domain, PoseidonParams, WIDTH};
+use pairing::Engine;
+use sapling_crypto::circuit::boolean::Boolean;
+use sapling_crypto::circuit::num::{AllocatedNum, Num};
+
+// element of the permutation state: a linear combination of allocated variables and its value
+#[derive(Clone)]
+struct StateElement<E: Engine> {
+    lc: LinearCombination<E>,
+    value: Option<E::Fr>,
+}
+
+impl<E: Engine> StateElement<E> {
+    fn constant<CS: ConstraintSystem<E>>(value: E::Fr) -> Self {
+        Self {
+            lc: LinearCombination::zero() + (value, CS::one()),
+            value: Some(value),
+        }
+    }
+
+    fn from_num(num: &AllocatedNum<E>) -> Self {
+        Self {
+            lc: LinearCombination::zero() + num.get_variable(),
+            value: num.get_value(),
+        }
+    }
+
+    fn add(self, other: &Self) -> Self {
+        Self {
+            lc: self.lc + &other.lc,
+            value: self.value.and_then(|mut value| {
+                other.value.map(|other| {
+                    value.add_assign(&other);
+                    value
+                })
+            }),
+        }
+    }
+}
+
+fn alloc_value<E, CS>(cs: CS, value: Option<E::Fr>) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: Engine,
+    CS: ConstraintSystem<E>,
+{
+    AllocatedNum::alloc(cs, || value.ok_or(SynthesisError::AssignmentMissing))
+}
+
+// x^5 in three constraints
+fn sbox<E, CS>(mut cs: CS, x: &StateElement<E>) -> Result<StateElement<E>, SynthesisError>
+where
+    E: Engine,
+    CS: ConstraintSystem<E>,
+{
+    let power = |exponent: u64| x.value.map(|value| value.pow([exponent]));
+
+    let x2 = alloc_value(cs.namespace(|| "x^2"), power(2))?;
+    cs.enforce(
+        || "x * x = x^2",
+        |lc| lc + &x.lc,
+        |lc| lc + &x.lc,
+        |lc| lc + x2.get_variable(),
+    );
+    let x4 = alloc_value(cs.namespace(|| "x^4"), power(4))?;
+    cs.enforce(
+        || "x^2 * x^2 = x^4",
+        |lc| lc + x2.get_variable(),
+        |lc| lc + x2.get_variable(),
+        |lc| lc + x4.get_variable(),
+    );
+    let x5 = alloc_value(cs.namespace(|| "x^5"), power(5))?;
+    cs.enforce(
+        || "x^4 * x = x^5",
+        |lc| lc + x4.get_variable(),
+        |lc| lc + &x.lc,
+        |lc| lc + x5.get_variable(),
+    );
+
+    Ok(StateElement::from_num(&x5))
+}
+
+fn permute<E, CS>(
+    mut cs: CS,
+    params: &PoseidonParams<E>,
+    mut state: Vec<StateElement<E>>,
+) -> Result<Vec<StateElement<E>>, SynthesisError>
+where
+    E: Engine,
+    CS: ConstraintSystem<E>,
+{
+    assert_eq!(state.len(), WIDTH);
+
+    for (round, constants) in params.round_constants.iter().enumerate() {
+        let cs = &mut cs.namespace(|| format!("round {}", round));
+
+        for (i, (element, constant)) in state.iter_mut().zip(constants.iter()).enumerate() {
+            *element = element
+                .clone()
+                .add(&StateElement::constant::<CS>(*constant));
+            if i == 0 || params.is_full_round(round) {
+                *element = sbox(cs.namespace(|| format!("sbox {}", i)), element)?;
+            }
+        }
+
+        // the mixed elements are allocated, otherwise the linear combinations grow every round
+        let mut mixed = Vec::with_capacity(WIDTH);
+        for (i, row) in params.mds.iter().enumerate() {
+            let mut lc = LinearCombination::zero();
+            let mut value = Some(E::Fr::zero());
+            for (coeff, element) in row.iter().zip(state.iter()) {
+                lc = lc + (*coeff, &element.lc);
+                value = value.and_then(|mut value| {
+                    element.value.map(|mut term| {
+                        term.mul_assign(coeff);
+                        value.add_assign(&term);
+                        value
+                    })
+                });
+            }
+
+            let num = alloc_value(cs.namespace(|| format!("mixed {}", i)), value)?;
+            cs.enforce(
+                || format!("mix {}", i),
+                |_| lc,
+                |lc| lc + CS::one(),
+                |lc| lc + num.get_variable(),
+            );
+            mixed.push(StateElement::from_num(&num));
+        }
+        state = mixed;
+    }
+
+    Ok(state)
+}
+
+fn sponge<E, CS>(
+    mut cs: CS,
+    params: &PoseidonParams<E>,
+    domain: u64,
+    inputs: &[StateElement<E>],
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: Engine,
+    CS: ConstraintSystem<E>,
+{
+    let domain = E::Fr::from_repr(<E::Fr as PrimeField>::Repr::from(domain))
+        .expect("u64 fits into a field element");
+    let zero = StateElement::constant::<CS>(E::Fr::zero());
+    let mut state = vec![StateElement::constant::<CS>(domain), zero.clone(), zero];
+
+    if inputs.is_empty() {
+        state = permute(cs.namespace(|| "permutation"), params, state)?;
+    }
+    for (i, chunk) in inputs.chunks(WIDTH - 1).enumerate() {
+        for (element, input) in state[1..].iter_mut().zip(chunk.iter()) {
+            *element = element.clone().add(input);
+        }
+        state = permute(cs.namespace(|| format!("permutation {}", i)), params, state)?;
+    }
+
+    // every permutation ends with allocated elements
+    let output = state[1].clone();
+    let num = alloc_value(cs.namespace(|| "output"), output.value)?;
+    cs.enforce(
+        || "enforce output",
+        |_| output.lc,
+        |lc| lc + CS::one(),
+        |lc| lc + num.get_variable(),
+    );
+    Ok(num)
+}
+
+/// Same as `PoseidonHasher::compress` of the level `level`
+pub fn poseidon_compress<E, CS>(
+    cs: CS,
+    params: &PoseidonParams<E>,
+    lhs: &AllocatedNum<E>,
+    rhs: &AllocatedNum<E>,
+    level: usize,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: Engine,
+    CS: ConstraintSystem<E>,
+{
+    let inputs = [StateElement::from_num(lhs), StateElement::from_num(rhs)];
+    sponge(cs, params, level as u64, &inputs)
+}
+
+/// Same as `PoseidonHasher::hash_bits`, `bits` are little-endian
+pub fn poseidon_hash_bits<E, CS>(
+    cs: CS,
+    params: &PoseidonParams<E>,
+    bits: &[Boolean],
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: Engine,
+    CS: ConstraintSystem<E>,
+{
+    // packing bits into a linear combination is free
+    let inputs: Vec<StateElement<E>> = bits
+        .chunks(E::Fr::CAPACITY as usize)
+        .map(|chunk| {
+            let mut packed = Num::zero();
+            let mut coeff = E::Fr::one();
+            for bit in chunk {
+                packed = packed.add_bool_with_coeff(CS::one(), bit, coeff);
+                coeff.double();
+            }
+            StateElement {
+                lc: packed.lc(E::Fr::one()),
+                value: packed.get_value(),
+            }
+        })
+        .collect();
+
+    sponge(cs, params, leaf_domain(bits.len()), &inputs)
+}
+
+/// Root of the tree with a leaf of `leaf_bits` at the position of `path_bits`, the Poseidon
+/// counterpart of the Pedersen Merkle root computed in every circuit
+pub fn poseidon_merkle_root<E, CS>(
+    mut cs: CS,
+    params: &PoseidonParams<E>,
+    leaf_bits: &[Boolean],
+    path_bits: &[Boolean],
+    audit_path: &[AllocatedNum<E>],
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: Engine,
+    CS: ConstraintSystem<E>,
+{
+    let mut cur = poseidon_hash_bits(cs.namespace(|| "leaf content hash"), params, leaf_bits)?;
+
+    for (i, direction_bit) in path_bits.iter().enumerate() {
+        let cs = &mut cs.namespace(|| format!("merkle tree hash {}", i));
+
+        // Swap the two if the current subtree is on the right
+        let (xl, xr) = AllocatedNum::conditionally_reverse(
+            cs.namespace(|| "conditional reversal of preimage"),
+            &cur,
+            &audit_path[i],
+            direction_bit,
+        )?;
+
+        cur = poseidon_compress(cs.namespace(|| "compression"), params, &xl, &xr, i)?;
+    }
+
+    Ok(cur)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use log::debug;
+    use merkle_tree::hasher::Hasher;
+    use merkle_tree::poseidon_hasher::BabyPoseidonHasher;
+    use merkle_tree::sequential_smt::SparseMerkleTree;
+    use models::plasma::params as plasma_constants;
+    use models::primitives::GetBits;
+    use pairing::bn256::{Bn256, Fr};
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use sapling_crypto::alt_babyjubjub::AltJubjubBn256;
+    use sapling_crypto::circuit::boolean::AllocatedBit;
+    use sapling_crypto::circuit::pedersen_hash;
+    use sapling_crypto::circuit::test::*;
+
+    fn alloc_bits<CS: ConstraintSystem<Bn256>>(cs: &mut CS, bits: &[bool]) -> Vec<Boolean> {
+        bits.iter()
+            .enumerate()
+            .map(|(i, bit)| {
+                Boolean::from(
+                    AllocatedBit::alloc(cs.namespace(|| format!("bit {}", i)), Some(*bit)).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_gadgets_match_native_hasher() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe_6259, 0x8d31_3d76, 0x3237_db17, 0xe5bc_0654]);
+        let hasher = BabyPoseidonHasher::default();
+        let mut cs = TestConstraintSystem::<Bn256>::new();
+
+        let lhs: Fr = rng.gen();
+        let rhs: Fr = rng.gen();
+        let lhs_num = AllocatedNum::alloc(cs.namespace(|| "lhs"), || Ok(lhs)).unwrap();
+        let rhs_num = AllocatedNum::alloc(cs.namespace(|| "rhs"), || Ok(rhs)).unwrap();
+        let node = poseidon_compress(
+            cs.namespace(|| "compress"),
+            &hasher.params,
+            &lhs_num,
+            &rhs_num,
+            5,
+        )
+        .unwrap();
+        assert_eq!(node.get_value(), Some(hasher.compress(&lhs, &rhs, 5)));
+
+        let bits: Vec<bool> = (0..600).map(|_| rng.gen()).collect();
+        let bits_allocated = alloc_bits(&mut cs, &bits);
+        let hash = poseidon_hash_bits(
+            cs.namespace(|| "hash bits"),
+            &hasher.params,
+            &bits_allocated,
+        )
+        .unwrap();
+        assert_eq!(hash.get_value(), Some(hasher.hash_bits(bits)));
+
+        assert!(cs.is_satisfied());
+        assert_eq!(cs.which_is_unsatisfied(), None);
+    }
+
+    #[test]
+    fn test_merkle_root_matches_tree() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe_6258, 0x8d31_3d76, 0x3237_db17, 0xe5bc_0654]);
+        let tree_depth = 4;
+        let mut tree = SparseMerkleTree::<u64, Fr, BabyPoseidonHasher>::new(tree_depth);
+        for index in &[1, 6, 11] {
+            tree.insert(*index, rng.gen::<u64>() % 1000);
+        }
+        let index = 6;
+        let item = tree.get(index).unwrap();
+
+        let mut cs = TestConstraintSystem::<Bn256>::new();
+        let leaf_bits = alloc_bits(&mut cs.namespace(|| "leaf"), &item.get_bits_le());
+        let path = tree.merkle_path(index);
+        let path_bits = alloc_bits(
+            &mut cs.namespace(|| "path bits"),
+            &path.iter().map(|(_, dir)| *dir).collect::<Vec<_>>(),
+        );
+        let audit_path: Vec<AllocatedNum<Bn256>> = path
+            .iter()
+            .enumerate()
+            .map(|(i, (hash, _))| {
+                AllocatedNum::alloc(cs.namespace(|| format!("audit {}", i)), || Ok(*hash)).unwrap()
+            })
+            .collect();
+
+        let root = poseidon_merkle_root(
+            cs.namespace(|| "root"),
+            &tree.hasher.params,
+            &leaf_bits,
+            &path_bits,
+            &audit_path,
+        )
+        .unwrap();
+        assert_eq!(root.get_value(), Some(tree.root_hash()));
+        assert!(cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_constraint_counts() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe_6257, 0x8d31_3d76, 0x3237_db17, 0xe5bc_0654]);
+        let depth = plasma_constants::BALANCE_TREE_DEPTH;
+        let jubjub_params = AltJubjubBn256::new();
+        let poseidon_params = PoseidonParams::<Bn256>::new();
+
+        // a compression of every level of the account tree with both hashers
+        let mut count = |use_poseidon: bool| {
+            let mut cs = TestConstraintSystem::<Bn256>::new();
+            let mut cur = AllocatedNum::alloc(cs.namespace(|| "leaf"), || Ok(rng.gen())).unwrap();
+            for i in 0..depth {
+                let cs = &mut cs.namespace(|| format!("level {}", i));
+                let sibling =
+                    AllocatedNum::alloc(cs.namespace(|| "sibling"), || Ok(Fr::one())).unwrap();
+                cur = if use_poseidon {
+                    poseidon_compress(cs.namespace(|| "hash"), &poseidon_params, &cur, &sibling, i)
+                        .unwrap()
+                } else {
+                    let mut preimage = cur.into_bits_le(cs.namespace(|| "cur bits")).unwrap();
+                    preimage.extend(
+                        sibling
+                            .into_bits_le(cs.namespace(|| "sibling bits"))
+                            .unwrap(),
+                    );
+                    pedersen_hash::pedersen_hash(
+                        cs.namespace(|| "hash"),
+                        pedersen_hash::Personalization::MerkleTree(i),
+                        &preimage,
+                        &jubjub_params,
+                    )
+                    .unwrap()
+                    .get_x()
+                    .clone()
+                };
+            }
+            assert!(cs.is_satisfied());
+            cs.num_constraints()
+        };
+
+        let pedersen = count(false);
+        let poseidon = count(true);
+        debug!(
+            "Merkle path of depth {}: {} constraints with Pedersen, {} with Poseidon",
+            depth, pedersen, poseidon
+        );
+        assert!(poseidon * 3 < pedersen);
+    }
+}
@@ -1,8 +1,13 @@
 pub use crate::leaf::LeafWitness;
-use crate::leaf::{make_leaf_content, LeafContent};
+use crate::leaf::{
+    account_leaf_hash, account_tree_node, balances_root_into_bits, calculate_balances_root,
+    leaf_bit_width, make_leaf_content, pack_public_key, public_key_leaf_bits, LeafContent,
+};
 use crate::transfer::transaction::{Transaction, TransactionContent};
 use bellman::{Circuit, ConstraintSystem, SynthesisError};
 use ff::{Field, PrimeField};
+use models::plasma::circuit::account::empty_balance_hashes;
+use models::plasma::circuit::sig::TransactionSignature;
 use models::plasma::circuit::utils::{allocate_audit_path, append_packed_public_key};
 use models::plasma::params as plasma_constants;
 use sapling_crypto::circuit::baby_eddsa::EddsaSignature;
@@ -18,6 +23,13 @@ pub struct TransactionWitness<E: JubjubEngine> {
 
     pub leaf_to: LeafWitness<E>,
     pub auth_path_to: Vec<Option<E::Fr>>,
+
+    // keys of a multisig sender padded with the identity point up to MULTISIG_MAX_SIGNERS,
+    // and which of them have signed. For a single key sender the threshold is zero
+    pub is_multisig: Option<bool>,
+    pub multisig_threshold: Option<E::Fr>,
+    pub multisig_keys: Vec<(Option<E::Fr>, Option<E::Fr>)>,
+    pub multisig_signed: Vec<Option<bool>>,
 }
 
 /// This is an instance of the `Spend` circuit.
@@ -27,6 +39,9 @@ pub struct Transfer<'a, E: JubjubEngine> {
     // number of transactions per block
     pub number_of_transactions: usize,
 
+    /// Chain id of the deployment, it is fixed when the keys are generated
+    pub chain_id: E::Fr,
+
     /// The old root of the tree
     pub old_root: Option<E::Fr>,
 
@@ -93,6 +108,7 @@ impl<'a, E: JubjubEngine> Circuit<E> for Transfer<'a, E> {
                 old_root,
                 transaction,
                 witness,
+                self.chain_id,
                 self.params,
                 generator.clone(),
             )?;
@@ -386,12 +402,18 @@ where
     Ok(intersection_point_bits)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn check_message_signature<E, CS>(
     mut cs: CS,
     from_path_bits: Vec<boolean::Boolean>,
     to_path_bits: Vec<boolean::Boolean>,
+    token_bits: Vec<boolean::Boolean>,
     leaf: &LeafContent<E>,
+    to_new_account: &boolean::Boolean,
+    new_pub_key_bits: &[boolean::Boolean],
     transaction: &Transaction<E>,
+    witness: &TransactionWitness<E>,
+    chain_id: E::Fr,
     params: &E::Params,
     generator: ecc::EdwardsPoint<E>,
 ) -> Result<TransactionContent<E>, SynthesisError>
@@ -405,6 +427,9 @@ where
     message_bits.extend(from_path_bits.clone());
     message_bits.extend(to_path_bits.clone());
 
+    // add token to check
+    message_bits.extend(token_bits);
+
     let amount_encoded = AllocatedNum::alloc(
         cs.namespace(|| "allocate encoded transaction amount"),
         || Ok(*transaction.amount.get()?),
@@ -449,19 +474,370 @@ where
     // add block number to check
     message_bits.extend(block_number_bits.clone());
 
-    let sender_pk = ecc::EdwardsPoint::interpret(
+    let transaction_chain_id =
+        AllocatedNum::alloc(cs.namespace(|| "allocate transaction chain id"), || {
+            Ok(*transaction.chain_id.get()?)
+        })?;
+
+    // a transaction signed for another deployment is rejected
+    cs.enforce(
+        || "enforce chain id of the deployment",
+        |lc| lc + transaction_chain_id.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + (chain_id, CS::one()),
+    );
+
+    let mut chain_id_bits = transaction_chain_id.into_bits_le(cs.namespace(|| "chain id bits"))?;
+
+    chain_id_bits.truncate(plasma_constants::CHAIN_ID_BIT_WIDTH);
+
+    // add chain id to check
+    message_bits.extend(chain_id_bits);
+
+    // a transfer to a new account signs the hash of the message and the key of the recipient
+    // followed by the marker, see `models::plasma::circuit::transfer::new_account_message_bits`.
+    // The message of other transfers is padded with zeros there
+
+    let max_message_len = plasma_constants::NEW_ACCOUNT_MESSAGE_HASH_WIDTH
+        + plasma_constants::NEW_ACCOUNT_MESSAGE_MARKER_WIDTH;
+
+    let mut preimage = message_bits.clone();
+    preimage.extend(new_pub_key_bits.iter().cloned());
+
+    let mut new_account_message_bits = pedersen_hash::pedersen_hash(
+        cs.namespace(|| "new account message hash"),
+        pedersen_hash::Personalization::NoteCommitment,
+        &preimage,
+        params,
+    )?
+    .get_x()
+    .into_bits_le(cs.namespace(|| "new account message hash bits"))?;
+
+    new_account_message_bits.truncate(plasma_constants::NEW_ACCOUNT_MESSAGE_HASH_WIDTH);
+    new_account_message_bits.push(boolean::Boolean::Constant(true));
+    new_account_message_bits.resize(max_message_len, boolean::Boolean::Constant(false));
+
+    message_bits.resize(max_message_len, boolean::Boolean::Constant(false));
+
+    let message_bits = message_bits
+        .iter()
+        .zip(new_account_message_bits.iter())
+        .enumerate()
+        .map(|(i, (bit, new_account_bit))| {
+            boolean::Boolean::sha256_ch(
+                cs.namespace(|| format!("select message bit {}", i)),
+                to_new_account,
+                new_account_bit,
+                bit,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let is_multisig = boolean::Boolean::from(boolean::AllocatedBit::alloc(
+        cs.namespace(|| "sender is multisig"),
+        witness.is_multisig,
+    )?);
+
+    // the sender key is used by the signature checks below
+    let (pub_x, pub_y) = leaf.public_key(cs.namespace(|| "check sender public key"), params)?;
+
+    // a multisig sender has no private key, its transaction signature is empty
+    // and is checked against the identity point
+    let sender_pk = key_or_identity(
         cs.namespace(|| "sender public key"),
-        &leaf.pub_x,
-        &leaf.pub_y,
+        &is_multisig.not(),
+        &pub_x,
+        &pub_y,
         params,
     )?;
 
+    verify_signature(
+        cs.namespace(|| "verify transaction signature"),
+        sender_pk,
+        &transaction.signature,
+        &message_bits,
+        max_message_len,
+        params,
+        generator.clone(),
+    )?;
+
+    check_multisig_signatures(
+        cs.namespace(|| "check multisig signatures"),
+        &is_multisig,
+        &pub_x,
+        &pub_y,
+        transaction,
+        witness,
+        &message_bits,
+        max_message_len,
+        params,
+        generator,
+    )?;
+
+    Ok(TransactionContent {
+        amount_bits,
+        fee_bits,
+        good_until_block: transaction_max_block_number_allocated,
+    })
+}
+
+/// Checks the signatures by the keys of a multisig sender. The sender key must be
+/// the commitment to the keys and the threshold, and at least `threshold` keys must have signed.
+/// Nothing is required from a single key sender
+#[allow(clippy::too_many_arguments)]
+fn check_multisig_signatures<E, CS>(
+    mut cs: CS,
+    is_multisig: &boolean::Boolean,
+    pub_x: &AllocatedNum<E>,
+    pub_y: &AllocatedNum<E>,
+    transaction: &Transaction<E>,
+    witness: &TransactionWitness<E>,
+    message_bits: &[boolean::Boolean],
+    max_message_len: usize,
+    params: &E::Params,
+    generator: ecc::EdwardsPoint<E>,
+) -> Result<(), SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    assert_eq!(
+        witness.multisig_keys.len(),
+        plasma_constants::MULTISIG_MAX_SIGNERS
+    );
+    assert_eq!(
+        witness.multisig_signed.len(),
+        plasma_constants::MULTISIG_MAX_SIGNERS
+    );
+    assert_eq!(
+        transaction.multisig_signatures.len(),
+        plasma_constants::MULTISIG_MAX_SIGNERS
+    );
+
+    let threshold = AllocatedNum::alloc(cs.namespace(|| "multisig threshold"), || {
+        Ok(*witness.multisig_threshold.get()?)
+    })?;
+
+    threshold.limit_number_of_bits(
+        cs.namespace(|| "limit number of bits for multisig threshold"),
+        plasma_constants::MULTISIG_THRESHOLD_BIT_WIDTH,
+    )?;
+
+    let mut threshold_bits = threshold.into_bits_le(cs.namespace(|| "multisig threshold bits"))?;
+    threshold_bits.truncate(plasma_constants::MULTISIG_THRESHOLD_BIT_WIDTH);
+
+    // see `multisig_commitment`: each key is hashed with the x of the hash of the keys before
+    let mut chained = threshold_bits;
+    let mut commitment = None;
+
+    let mut signed_count = Num::<E>::zero();
+
+    for (i, ((key, signed), signature)) in witness
+        .multisig_keys
+        .iter()
+        .zip(witness.multisig_signed.iter())
+        .zip(transaction.multisig_signatures.iter())
+        .enumerate()
+    {
+        let cs = &mut cs.namespace(|| format!("multisig key {}", i));
+
+        let key_x = AllocatedNum::alloc(cs.namespace(|| "key x"), || Ok(*key.0.get()?))?;
+        let key_y = AllocatedNum::alloc(cs.namespace(|| "key y"), || Ok(*key.1.get()?))?;
+
+        let mut key_x_bit = key_x.into_bits_le(cs.namespace(|| "key x bits"))?;
+        key_x_bit.truncate(1);
+
+        let mut key_y_bits = key_y.into_bits_le(cs.namespace(|| "key y bits"))?;
+        key_y_bits.resize(
+            plasma_constants::FR_BIT_WIDTH - 1,
+            boolean::Boolean::Constant(false),
+        );
+
+        append_packed_public_key(&mut chained, key_x_bit, key_y_bits);
+
+        let key_commitment = pedersen_hash::pedersen_hash(
+            cs.namespace(|| "multisig commitment"),
+            pedersen_hash::Personalization::NoteCommitment,
+            &chained,
+            params,
+        )?;
+        chained = key_commitment
+            .get_x()
+            .into_bits_le_strict(cs.namespace(|| "multisig commitment x bits"))?;
+        commitment = Some(key_commitment);
+
+        let signed = boolean::Boolean::from(boolean::AllocatedBit::alloc(
+            cs.namespace(|| "key has signed"),
+            *signed,
+        )?);
+
+        // keys are padded with the identity point, it must not count as a signer:
+        // key_x * key_x_inverse == signed, so key_x is not zero for a signed key
+        let key_x_inverse = AllocatedNum::alloc(cs.namespace(|| "key x inverse"), || {
+            if *signed.get_value().get()? {
+                let inverse = key_x.get_value().get()?.inverse();
+                Ok(*inverse.get()?)
+            } else {
+                Ok(E::Fr::zero())
+            }
+        })?;
+
+        cs.enforce(
+            || "enforce signed key is not the identity",
+            |lc| lc + key_x.get_variable(),
+            |lc| lc + key_x_inverse.get_variable(),
+            |_| signed.lc(CS::one(), E::Fr::one()),
+        );
+
+        // a key that has not signed gets the empty signature
+        let signer_pk = key_or_identity(
+            cs.namespace(|| "signing key"),
+            &signed,
+            &key_x,
+            &key_y,
+            params,
+        )?;
+
+        verify_signature(
+            cs.namespace(|| "verify multisig signature"),
+            signer_pk,
+            signature,
+            message_bits,
+            max_message_len,
+            params,
+            generator.clone(),
+        )?;
+
+        signed_count = signed_count.add_bool_with_coeff(CS::one(), &signed, E::Fr::one());
+    }
+
+    let commitment = commitment.expect("multisig keys");
+
+    // is_multisig * (commitment - sender key) == 0
+    cs.enforce(
+        || "enforce sender key x is the multisig commitment",
+        |_| is_multisig.lc(CS::one(), E::Fr::one()),
+        |lc| lc + commitment.get_x().get_variable() - pub_x.get_variable(),
+        |lc| lc,
+    );
+
+    cs.enforce(
+        || "enforce sender key y is the multisig commitment",
+        |_| is_multisig.lc(CS::one(), E::Fr::one()),
+        |lc| lc + commitment.get_y().get_variable() - pub_y.get_variable(),
+        |lc| lc,
+    );
+
+    let required = AllocatedNum::alloc(cs.namespace(|| "required signatures"), || {
+        if *is_multisig.get_value().get()? {
+            Ok(*threshold.get_value().get()?)
+        } else {
+            Ok(E::Fr::zero())
+        }
+    })?;
+
+    cs.enforce(
+        || "enforce required signatures",
+        |_| is_multisig.lc(CS::one(), E::Fr::one()),
+        |lc| lc + threshold.get_variable(),
+        |lc| lc + required.get_variable(),
+    );
+
+    // signed_count - required must not underflow
+    let surplus = AllocatedNum::alloc(cs.namespace(|| "surplus signatures"), || {
+        let mut surplus = *signed_count.get_value().get()?;
+        surplus.sub_assign(required.get_value().get()?);
+
+        Ok(surplus)
+    })?;
+
+    surplus.limit_number_of_bits(
+        cs.namespace(|| "check for enough signatures"),
+        plasma_constants::MULTISIG_THRESHOLD_BIT_WIDTH,
+    )?;
+
+    cs.enforce(
+        || "enforce surplus signatures",
+        |lc| lc + surplus.get_variable(),
+        |lc| lc + CS::one(),
+        |_| signed_count.lc(E::Fr::one()) - required.get_variable(),
+    );
+
+    Ok(())
+}
+
+/// The key if `condition` is set, the identity point otherwise.
+/// The empty signature is valid for the identity point and any message
+fn key_or_identity<E, CS>(
+    mut cs: CS,
+    condition: &boolean::Boolean,
+    x: &AllocatedNum<E>,
+    y: &AllocatedNum<E>,
+    params: &E::Params,
+) -> Result<ecc::EdwardsPoint<E>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    let selected_x = AllocatedNum::alloc(cs.namespace(|| "selected x"), || {
+        if *condition.get_value().get()? {
+            Ok(*x.get_value().get()?)
+        } else {
+            Ok(E::Fr::zero())
+        }
+    })?;
+
+    // selected_x == condition * x
+    cs.enforce(
+        || "select x",
+        |_| condition.lc(CS::one(), E::Fr::one()),
+        |lc| lc + x.get_variable(),
+        |lc| lc + selected_x.get_variable(),
+    );
+
+    let selected_y = AllocatedNum::alloc(cs.namespace(|| "selected y"), || {
+        if *condition.get_value().get()? {
+            Ok(*y.get_value().get()?)
+        } else {
+            Ok(E::Fr::one())
+        }
+    })?;
+
+    // selected_y - 1 == condition * (y - 1)
+    cs.enforce(
+        || "select y",
+        |_| condition.lc(CS::one(), E::Fr::one()),
+        |lc| lc + y.get_variable() - CS::one(),
+        |lc| lc + selected_y.get_variable() - CS::one(),
+    );
+
+    ecc::EdwardsPoint::interpret(
+        cs.namespace(|| "selected point"),
+        &selected_x,
+        &selected_y,
+        params,
+    )
+}
+
+fn verify_signature<E, CS>(
+    mut cs: CS,
+    pk: ecc::EdwardsPoint<E>,
+    signature: &Option<TransactionSignature<E>>,
+    message_bits: &[boolean::Boolean],
+    max_message_len: usize,
+    params: &E::Params,
+    generator: ecc::EdwardsPoint<E>,
+) -> Result<(), SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
     let signature_r_x = AllocatedNum::alloc(cs.namespace(|| "signature r_x witness"), || {
-        Ok(transaction.signature.get()?.r.into_xy().0)
+        Ok(signature.get()?.r.into_xy().0)
     })?;
 
     let signature_r_y = AllocatedNum::alloc(cs.namespace(|| "signature r_y witness"), || {
-        Ok(transaction.signature.get()?.r.into_xy().1)
+        Ok(signature.get()?.r.into_xy().1)
     })?;
 
     let signature_r = ecc::EdwardsPoint::interpret(
@@ -472,37 +848,22 @@ where
     )?;
 
     let signature_s = AllocatedNum::alloc(cs.namespace(|| "signature s witness"), || {
-        Ok(transaction.signature.get()?.s)
+        Ok(signature.get()?.s)
     })?;
 
     let signature = EddsaSignature {
         r: signature_r,
         s: signature_s,
-        pk: sender_pk,
+        pk,
     };
 
-    let max_message_len = plasma_constants::BALANCE_TREE_DEPTH
-        + plasma_constants::BALANCE_TREE_DEPTH
-        + plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH
-        + plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH
-        + plasma_constants::FEE_EXPONENT_BIT_WIDTH
-        + plasma_constants::FEE_MANTISSA_BIT_WIDTH
-        + plasma_constants::NONCE_BIT_WIDTH
-        + plasma_constants::BLOCK_NUMBER_BIT_WIDTH;
-
     signature.verify_raw_message_signature(
-        cs.namespace(|| "verify transaction signature"),
+        cs.namespace(|| "verify signature"),
         params,
-        &message_bits,
+        message_bits,
         generator,
         max_message_len,
-    )?;
-
-    Ok(TransactionContent {
-        amount_bits,
-        fee_bits,
-        good_until_block: transaction_max_block_number_allocated,
-    })
+    )
 }
 
 /// Applies one transaction to the tree,
@@ -513,6 +874,7 @@ fn apply_transaction<E, CS>(
     old_root: AllocatedNum<E>,
     transaction: Transaction<E>,
     witness: TransactionWitness<E>,
+    chain_id: E::Fr,
     params: &E::Params,
     generator: ecc::EdwardsPoint<E>,
 ) -> Result<
@@ -528,17 +890,28 @@ where
     E: JubjubEngine,
     CS: ConstraintSystem<E>,
 {
+    // Sender and recipient balances are of the same token
+
+    let token_allocated =
+        AllocatedNum::alloc(cs.namespace(|| "token"), || Ok(*transaction.token.get()?))?;
+
+    let mut token_bits =
+        token_allocated.into_bits_le(cs.namespace(|| "token bit decomposition"))?;
+
+    token_bits.truncate(plasma_constants::TOKEN_BIT_WIDTH);
+
     // Calculate leaf value commitment
 
     let leaf_from = make_leaf_content(
         cs.namespace(|| "create sender's leaf"),
         witness.clone().leaf_from,
+        &token_bits,
+        params,
     )?;
 
     // Compute the hash of the from leaf
-    let mut from_leaf_hash = pedersen_hash::pedersen_hash(
+    let mut from_leaf_hash = account_leaf_hash(
         cs.namespace(|| "sender's leaf content hash"),
-        pedersen_hash::Personalization::NoteCommitment,
         &leaf_from.leaf_bits,
         params,
     )?;
@@ -561,9 +934,7 @@ where
     )?;
 
     {
-        // This is an injective encoding, as cur is a
-        // point in the prime order subgroup.
-        let mut cur_from = from_leaf_hash.get_x().clone();
+        let mut cur_from = from_leaf_hash.clone();
 
         // Ascend the merkle tree authentication path
         for (i, direction_bit) in from_path_bits.clone().into_iter().enumerate() {
@@ -584,23 +955,8 @@ where
                 &direction_bit,
             )?;
 
-            // We don't need to be strict, because the function is
-            // collision-resistant. If the prover witnesses a congruency,
-            // they will be unable to find an authentication path in the
-            // tree with high probability.
-            let mut preimage = vec![];
-            preimage.extend(xl.into_bits_le(cs.namespace(|| "xl into bits"))?);
-            preimage.extend(xr.into_bits_le(cs.namespace(|| "xr into bits"))?);
-
             // Compute the new subtree value
-            cur_from = pedersen_hash::pedersen_hash(
-                cs.namespace(|| "computation of pedersen hash"),
-                pedersen_hash::Personalization::MerkleTree(i),
-                &preimage,
-                params,
-            )?
-            .get_x()
-            .clone(); // Injective encoding
+            cur_from = account_tree_node(cs.namespace(|| "node hash"), &xl, &xr, i, params)?;
         }
 
         // enforce old root before update
@@ -617,12 +973,13 @@ where
     let leaf_to = make_leaf_content(
         cs.namespace(|| "create recipients's leaf"),
         witness.clone().leaf_to,
+        &token_bits,
+        params,
     )?;
 
     // Compute the hash of the from leaf
-    let mut to_leaf_hash = pedersen_hash::pedersen_hash(
+    let mut to_leaf_hash = account_leaf_hash(
         cs.namespace(|| "to leaf content hash"),
-        pedersen_hash::Personalization::NoteCommitment,
         &leaf_to.leaf_bits,
         params,
     )?;
@@ -645,9 +1002,7 @@ where
     )?;
 
     {
-        // This is an injective encoding, as cur is a
-        // point in the prime order subgroup.
-        let mut cur_to = to_leaf_hash.get_x().clone();
+        let mut cur_to = to_leaf_hash.clone();
 
         // Ascend the merkle tree authentication path
         for (i, direction_bit) in to_path_bits.clone().into_iter().enumerate() {
@@ -668,23 +1023,8 @@ where
                 &direction_bit,
             )?;
 
-            // We don't need to be strict, because the function is
-            // collision-resistant. If the prover witnesses a congruency,
-            // they will be unable to find an authentication path in the
-            // tree with high probability.
-            let mut preimage = vec![];
-            preimage.extend(xl.into_bits_le(cs.namespace(|| "xl into bits"))?);
-            preimage.extend(xr.into_bits_le(cs.namespace(|| "xr into bits"))?);
-
             // Compute the new subtree value
-            cur_to = pedersen_hash::pedersen_hash(
-                cs.namespace(|| "computation of pedersen hash"),
-                pedersen_hash::Personalization::MerkleTree(i),
-                &preimage,
-                params,
-            )?
-            .get_x()
-            .clone(); // Injective encoding
+            cur_to = account_tree_node(cs.namespace(|| "node hash"), &xl, &xr, i, params)?;
         }
 
         // enforce old root before update
@@ -701,6 +1041,50 @@ where
     // before having fun with leafs calculate the common prefix
     // of two audit paths
 
+    // a transfer to a new account registers the key of the recipient, the key is zero otherwise
+
+    let to_new_account = boolean::Boolean::from(boolean::AllocatedBit::alloc(
+        cs.namespace(|| "transfer to new account"),
+        transaction.to_new_account,
+    )?);
+
+    let new_pub_x = AllocatedNum::alloc(cs.namespace(|| "allocate new public key x"), || {
+        Ok(*transaction.new_pub_x.get()?)
+    })?;
+
+    let new_pub_y = AllocatedNum::alloc(cs.namespace(|| "allocate new public key y"), || {
+        Ok(*transaction.new_pub_y.get()?)
+    })?;
+
+    cs.enforce(
+        || "enforce new public key x is zero for other transfers",
+        |_| to_new_account.not().lc(CS::one(), E::Fr::one()),
+        |lc| lc + new_pub_x.get_variable(),
+        |lc| lc,
+    );
+
+    cs.enforce(
+        || "enforce new public key y is zero for other transfers",
+        |_| to_new_account.not().lc(CS::one(), E::Fr::one()),
+        |lc| lc + new_pub_y.get_variable(),
+        |lc| lc,
+    );
+
+    // the key of a new account is a point on the curve
+    key_or_identity(
+        cs.namespace(|| "new public key"),
+        &to_new_account,
+        &new_pub_x,
+        &new_pub_y,
+        params,
+    )?;
+
+    let new_pub_key_bits = pack_public_key(
+        cs.namespace(|| "pack new public key"),
+        &new_pub_x,
+        &new_pub_y,
+    )?;
+
     // Ok, old leaf values are exposed, so we can check
     // the signature and parse the rest of transaction data
 
@@ -708,8 +1092,13 @@ where
         cs.namespace(|| "parse and check transaction"),
         from_path_bits.clone(),
         to_path_bits.clone(),
+        token_bits.clone(),
         &leaf_from,
+        &to_new_account,
+        &new_pub_key_bits,
         &transaction,
+        &witness,
+        chain_id,
         params,
         generator,
     )?;
@@ -849,6 +1238,77 @@ where
         |lc| lc + to_address_allocated.get_variable(),
     );
 
+    // funds sent to an account without a key could never be spent. Such a recipient
+    // must be new and get the key of the transfer, unless it is account 0 that gets nothing
+
+    let recipient_has_empty_key =
+        leaf_to.has_empty_key(cs.namespace(|| "recipient has empty key"), params)?;
+
+    let recipient_without_key = boolean::Boolean::and(
+        cs.namespace(|| "recipient without key is not new"),
+        &recipient_has_empty_key,
+        &to_new_account.not(),
+    )?;
+
+    cs.enforce(
+        || "enforce recipient without key is new or zero",
+        |_| recipient_without_key.lc(CS::one(), E::Fr::one()),
+        |lc| lc + CS::one() - recipient_is_zero.get_variable(),
+        |lc| lc,
+    );
+
+    cs.enforce(
+        || "enforce new account is not zero",
+        |_| to_new_account.lc(CS::one(), E::Fr::one()),
+        |lc| lc + recipient_is_zero.get_variable(),
+        |lc| lc,
+    );
+
+    // a new account is an empty leaf, as a deposit checks it
+
+    cs.enforce(
+        || "enforce new account has empty key",
+        |_| to_new_account.lc(CS::one(), E::Fr::one()),
+        |_| recipient_has_empty_key.not().lc(CS::one(), E::Fr::one()),
+        |lc| lc,
+    );
+
+    let empty_balances_root = empty_balance_hashes::<E>(params)[plasma_constants::TOKEN_BIT_WIDTH];
+    cs.enforce(
+        || "enforce new account has empty balances",
+        |lc| lc + leaf_to.balances_root.get_variable() - (empty_balances_root, CS::one()),
+        |_| to_new_account.lc(CS::one(), E::Fr::one()),
+        |lc| lc,
+    );
+
+    cs.enforce(
+        || "enforce new account has zero nonce",
+        |lc| lc + leaf_to.nonce.get_variable(),
+        |_| to_new_account.lc(CS::one(), E::Fr::one()),
+        |lc| lc,
+    );
+
+    cs.enforce(
+        || "enforce new account has zero cheque bits",
+        |lc| lc + leaf_to.cheque_bits.get_variable(),
+        |_| to_new_account.lc(CS::one(), E::Fr::one()),
+        |lc| lc,
+    );
+
+    cs.enforce(
+        || "enforce new account has zero cheque start",
+        |lc| lc + leaf_to.cheque_start.get_variable(),
+        |_| to_new_account.lc(CS::one(), E::Fr::one()),
+        |lc| lc,
+    );
+
+    cs.enforce(
+        || "enforce new account has zero lock commitment",
+        |lc| lc + leaf_to.lock_commitment.get_variable(),
+        |_| to_new_account.lc(CS::one(), E::Fr::one()),
+        |lc| lc,
+    );
+
     // Ok, now a tricky part for an account zero having a special meaning
     // If to == 0 then balance of to is not increased
 
@@ -926,7 +1386,19 @@ where
             new_balance_from.into_bits_le(cs.namespace(|| "from leaf updated amount bits"))?;
 
         value_content.truncate(plasma_constants::BALANCE_BIT_WIDTH);
-        leaf_content.extend(value_content.clone());
+
+        let balances_root = calculate_balances_root(
+            cs.namespace(|| "from leaf updated balances root"),
+            &value_content,
+            &token_bits,
+            &leaf_from.balance_path,
+            params,
+        )?;
+
+        leaf_content.extend(balances_root_into_bits(
+            cs.namespace(|| "from leaf updated balances root bits"),
+            &balances_root,
+        )?);
 
         let mut nonce_content =
             new_nonce.into_bits_le(cs.namespace(|| "from leaf updated nonce bits"))?;
@@ -935,19 +1407,14 @@ where
         leaf_content.extend(nonce_content);
 
         // keep public keys
-        append_packed_public_key(&mut leaf_content, leaf_from.pub_x_bit, leaf_from.pub_y_bits);
+        leaf_content.extend(leaf_from.pub_key_bits);
+        leaf_content.extend(leaf_from.state_bits);
 
-        assert_eq!(
-            leaf_content.len(),
-            plasma_constants::BALANCE_BIT_WIDTH
-                + plasma_constants::NONCE_BIT_WIDTH
-                + plasma_constants::FR_BIT_WIDTH
-        );
+        assert_eq!(leaf_content.len(), leaf_bit_width());
 
         // Compute the hash of the from leaf
-        from_leaf_hash = pedersen_hash::pedersen_hash(
+        from_leaf_hash = account_leaf_hash(
             cs.namespace(|| "from leaf content hash updated"),
-            pedersen_hash::Personalization::NoteCommitment,
             &leaf_content,
             params,
         )?;
@@ -962,31 +1429,57 @@ where
             new_balance_to.into_bits_le(cs.namespace(|| "to leaf updated amount bits"))?;
 
         value_content.truncate(plasma_constants::BALANCE_BIT_WIDTH);
-        leaf_content.extend(value_content);
 
-        // everything else remains the same
+        let balances_root = calculate_balances_root(
+            cs.namespace(|| "to leaf updated balances root"),
+            &value_content,
+            &token_bits,
+            &leaf_to.balance_path,
+            params,
+        )?;
+
+        leaf_content.extend(balances_root_into_bits(
+            cs.namespace(|| "to leaf updated balances root bits"),
+            &balances_root,
+        )?);
+
+        // a new account gets the key of the transfer, everything else remains the same
         leaf_content.extend(leaf_to.nonce_bits);
-        append_packed_public_key(&mut leaf_content, leaf_to.pub_x_bit, leaf_to.pub_y_bits);
 
-        assert_eq!(
-            leaf_content.len(),
-            plasma_constants::BALANCE_BIT_WIDTH
-                + plasma_constants::NONCE_BIT_WIDTH
-                + plasma_constants::FR_BIT_WIDTH
-        );
+        let new_account_key_bits = public_key_leaf_bits(
+            cs.namespace(|| "new account public key leaf bits"),
+            new_pub_key_bits.clone(),
+            params,
+        )?;
+
+        for (i, (new_account_bit, bit)) in new_account_key_bits
+            .iter()
+            .zip(leaf_to.pub_key_bits.iter())
+            .enumerate()
+        {
+            leaf_content.push(boolean::Boolean::sha256_ch(
+                cs.namespace(|| format!("select recipient public key bit {}", i)),
+                &to_new_account,
+                new_account_bit,
+                bit,
+            )?);
+        }
+
+        leaf_content.extend(leaf_to.state_bits);
+
+        assert_eq!(leaf_content.len(), leaf_bit_width());
 
         // Compute the hash of the from leaf
-        to_leaf_hash = pedersen_hash::pedersen_hash(
+        to_leaf_hash = account_leaf_hash(
             cs.namespace(|| "to leaf content hash updated"),
-            pedersen_hash::Personalization::NoteCommitment,
             &leaf_content,
             params,
         )?;
     }
 
     // First assemble new leafs
-    let mut cur_from = from_leaf_hash.get_x().clone();
-    let mut cur_to = to_leaf_hash.get_x().clone();
+    let mut cur_from = from_leaf_hash;
+    let mut cur_to = to_leaf_hash;
 
     let intersection_point_bits = find_intersection_point(
         cs.namespace(|| "find intersection point for merkle paths"),
@@ -1030,10 +1523,6 @@ where
                 &direction_bit_from,
             )?;
 
-            let mut preimage_from = vec![];
-            preimage_from.extend(xl_from.into_bits_le(cs.namespace(|| "xl_from into bits"))?);
-            preimage_from.extend(xr_from.into_bits_le(cs.namespace(|| "xr_from into bits"))?);
-
             // same for to
 
             // If we are on intersection place take a current hash from another branch instead of path element
@@ -1052,29 +1541,15 @@ where
                 &direction_bit_to,
             )?;
 
-            let mut preimage_to = vec![];
-            preimage_to.extend(xl_to.into_bits_le(cs.namespace(|| "xl_to into bits"))?);
-            preimage_to.extend(xr_to.into_bits_le(cs.namespace(|| "xr_to into bits"))?);
-
-            // Compute the new subtree value
-            cur_from = pedersen_hash::pedersen_hash(
-                cs.namespace(|| "computation of pedersen hash from"),
-                pedersen_hash::Personalization::MerkleTree(i),
-                &preimage_from,
-                params,
-            )?
-            .get_x()
-            .clone(); // Injective encoding
-
-            // Compute the new subtree value
-            cur_to = pedersen_hash::pedersen_hash(
-                cs.namespace(|| "computation of pedersen hash to"),
-                pedersen_hash::Personalization::MerkleTree(i),
-                &preimage_to,
+            // Compute the new subtree values, both after the selects above
+            cur_from = account_tree_node(
+                cs.namespace(|| "node hash from"),
+                &xl_from,
+                &xr_from,
+                i,
                 params,
-            )?
-            .get_x()
-            .clone(); // Injective encoding
+            )?;
+            cur_to = account_tree_node(cs.namespace(|| "node hash to"), &xl_to, &xr_to, i, params)?;
         }
 
         // enforce roots are the same
@@ -1095,20 +1570,30 @@ where
     let mut to_path_be = to_path_bits.clone();
     to_path_be.reverse();
 
+    let mut token_be = token_bits.clone();
+    token_be.reverse();
+
+    let mut new_pub_key_be = new_pub_key_bits;
+    new_pub_key_be.reverse();
+
     let mut public_data = vec![];
     public_data.extend(from_path_be);
     public_data.extend(to_path_be);
+    public_data.extend(token_be);
     public_data.extend(transaction_content.amount_bits.clone());
     public_data.extend(transaction_content.fee_bits.clone());
+    public_data.extend(new_pub_key_be);
 
     assert_eq!(
         public_data.len(),
         plasma_constants::BALANCE_TREE_DEPTH
             + plasma_constants::BALANCE_TREE_DEPTH
+            + plasma_constants::TOKEN_BIT_WIDTH
             + plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH
             + plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH
             + plasma_constants::FEE_EXPONENT_BIT_WIDTH
             + plasma_constants::FEE_MANTISSA_BIT_WIDTH
+            + plasma_constants::FR_BIT_WIDTH
     );
 
     Ok((
@@ -1157,7 +1642,7 @@ mod test {
     fn test_transfer_circuit_with_witness() {
         use crate::CircuitAccountTree;
         use ff::Field;
-        use models::plasma::circuit::account::CircuitAccount;
+        use models::plasma::circuit::account::{leaf_pub_key_hash, CircuitAccount};
         use pairing::bn256::*;
         use rand::{Rng, SeedableRng, XorShiftRng};
         use sapling_crypto::alt_babyjubjub::AltJubjubBn256;
@@ -1230,19 +1715,25 @@ mod test {
 
             let fee_encoded: Fr = le_bit_vector_into_field_element(&fee_bits);
 
-            let sender_leaf = CircuitAccount {
-                balance: Fr::from_str("1000").unwrap(),
+            let token: u32 = 3;
+
+            let mut sender_leaf = CircuitAccount::<Bn256> {
                 nonce: Fr::zero(),
                 pub_x: sender_x,
                 pub_y: sender_y,
+                ..Default::default()
             };
+            sender_leaf.set_balance(token, Fr::from_str("1000").unwrap(), params);
+            // balances of other tokens must stay untouched
+            sender_leaf.set_balance(0, Fr::from_str("7").unwrap(), params);
 
-            let recipient_leaf = CircuitAccount {
-                balance: Fr::zero(),
+            let mut recipient_leaf = CircuitAccount::<Bn256> {
                 nonce: Fr::one(),
                 pub_x: recipient_x,
                 pub_y: recipient_y,
+                ..Default::default()
             };
+            recipient_leaf.set_balance(1, Fr::from_str("20").unwrap(), params);
 
             let initial_root = tree.root_hash();
             debug!("Empty root = {}", initial_root);
@@ -1287,11 +1778,20 @@ mod test {
             let mut transaction: Transaction<Bn256> = Transaction {
                 from,
                 to,
+                token: Fr::from_str(&token.to_string()),
                 amount: Some(transfer_amount_encoded),
                 fee: Some(fee_encoded),
                 nonce: Some(Fr::zero()),
                 good_until_block: Some(Fr::one()),
+                chain_id: Some(Fr::one()),
                 signature: None,
+                multisig_signatures: vec![
+                    Some(TransactionSignature::empty(params));
+                    plasma_constants::MULTISIG_MAX_SIGNERS
+                ],
+                to_new_account: Some(false),
+                new_pub_x: Some(Fr::zero()),
+                new_pub_y: Some(Fr::zero()),
             };
 
             transaction.sign(&sender_sk, p_g, params, rng);
@@ -1302,17 +1802,36 @@ mod test {
             let mut updated_recipient_leaf = recipient_leaf.clone();
 
             let leaf_witness_from = LeafWitness {
-                balance: Some(sender_leaf.balance),
+                balance: Some(sender_leaf.get_balance(token)),
+                balance_path: sender_leaf
+                    .balance_path(token, params)
+                    .into_iter()
+                    .map(Some)
+                    .collect(),
                 nonce: Some(sender_leaf.nonce),
                 pub_x: Some(sender_leaf.pub_x),
                 pub_y: Some(sender_leaf.pub_y),
+                pub_key_hash: leaf_pub_key_hash(&sender_leaf.pub_x, &sender_leaf.pub_y),
+                cheque_bits: Some(sender_leaf.cheque_bits),
+                cheque_start: Some(sender_leaf.cheque_start),
+                lock_commitment: Some(sender_leaf.lock_commitment),
             };
 
             let leaf_witness_to = LeafWitness {
-                balance: Some(recipient_leaf.balance),
+                balance: Some(recipient_leaf.get_balance(token)),
+                balance_path: recipient_leaf
+                    .balance_path(token, params)
+                    .into_iter()
+                    .map(Some)
+                    .collect(),
                 nonce: Some(recipient_leaf.nonce),
-                pub_x: Some(recipient_leaf.pub_x),
-                pub_y: Some(recipient_leaf.pub_y),
+                // a compact leaf only needs the hash of a key that is not used
+                pub_x: Some(recipient_leaf.pub_x).filter(|_| !plasma_constants::COMPACT_LEAF),
+                pub_y: Some(recipient_leaf.pub_y).filter(|_| !plasma_constants::COMPACT_LEAF),
+                pub_key_hash: leaf_pub_key_hash(&recipient_leaf.pub_x, &recipient_leaf.pub_y),
+                cheque_bits: Some(recipient_leaf.cheque_bits),
+                cheque_start: Some(recipient_leaf.cheque_start),
+                lock_commitment: Some(recipient_leaf.lock_commitment),
             };
 
             let transaction_witness = TransactionWitness {
@@ -1320,22 +1839,29 @@ mod test {
                 auth_path_from: path_from,
                 leaf_to: leaf_witness_to,
                 auth_path_to: path_to,
+                is_multisig: Some(false),
+                multisig_threshold: Some(Fr::zero()),
+                multisig_keys: vec![
+                    (Some(Fr::zero()), Some(Fr::one()));
+                    plasma_constants::MULTISIG_MAX_SIGNERS
+                ],
+                multisig_signed: vec![Some(false); plasma_constants::MULTISIG_MAX_SIGNERS],
             };
 
-            updated_sender_leaf
-                .balance
-                .sub_assign(&transfer_amount_as_field_element);
+            let mut balance_from = updated_sender_leaf.get_balance(token);
+            balance_from.sub_assign(&transfer_amount_as_field_element);
+            updated_sender_leaf.set_balance(token, balance_from, params);
             updated_sender_leaf.nonce.add_assign(&Fr::one());
 
             debug!("Updated sender:");
-            debug!("Amount: {}", updated_sender_leaf.clone().balance);
+            debug!("Amount: {}", updated_sender_leaf.get_balance(token));
             debug!("Nonce: {}", updated_sender_leaf.clone().nonce);
 
-            updated_recipient_leaf
-                .balance
-                .add_assign(&transfer_amount_as_field_element);
+            let mut balance_to = updated_recipient_leaf.get_balance(token);
+            balance_to.add_assign(&transfer_amount_as_field_element);
+            updated_recipient_leaf.set_balance(token, balance_to, params);
             debug!("Updated recipient:");
-            debug!("Amount: {}", updated_recipient_leaf.clone().balance);
+            debug!("Amount: {}", updated_recipient_leaf.get_balance(token));
             debug!("Nonce: {}", updated_recipient_leaf.clone().nonce);
 
             tree.insert(sender_leaf_number, updated_sender_leaf.clone());
@@ -1432,6 +1958,7 @@ mod test {
                 let instance = Transfer {
                     params,
                     number_of_transactions: 1,
+                    chain_id: Fr::one(),
                     old_root: Some(old_root),
                     new_root: Some(new_root),
                     public_data_commitment: Some(public_data_commitment),
@@ -1456,4 +1983,490 @@ mod test {
         }
     }
 
+    /// Builds a transfer from a 2 of 3 multisig account signed by `signers`
+    /// for `signed_chain_id`, the circuit is for chain 1
+    fn multisig_transfer_constraint_system(
+        signers: &[usize],
+        signed_chain_id: u32,
+    ) -> sapling_crypto::circuit::test::TestConstraintSystem<pairing::bn256::Bn256> {
+        use crate::CircuitAccountTree;
+        use crypto::digest::Digest;
+        use crypto::sha2::Sha256;
+        use ff::Field;
+        use models::plasma::account::Multisig;
+        use models::plasma::circuit::account::{leaf_pub_key_hash, CircuitAccount};
+        use models::plasma::circuit::utils::be_bit_vector_into_bytes;
+        use pairing::bn256::*;
+        use rand::{Rng, SeedableRng, XorShiftRng};
+        use sapling_crypto::alt_babyjubjub::AltJubjubBn256;
+        use sapling_crypto::circuit::test::*;
+        use sapling_crypto::eddsa::{PrivateKey, PublicKey};
+
+        let p_g = FixedGenerators::SpendingKeyGenerator;
+        let params = &AltJubjubBn256::new();
+
+        let rng = &mut XorShiftRng::from_seed([0x3dbe_6258, 0x8d31_3d76, 0x3237_db17, 0xe5bc_0654]);
+
+        let tree_depth = plasma_constants::BALANCE_TREE_DEPTH as u32;
+        let mut tree = CircuitAccountTree::new(tree_depth);
+
+        let signer_sks: Vec<PrivateKey<Bn256>> = (0..plasma_constants::MULTISIG_MAX_SIGNERS)
+            .map(|_| PrivateKey::<Bn256>(rng.gen()))
+            .collect();
+        let multisig = Multisig {
+            threshold: 2,
+            keys: signer_sks
+                .iter()
+                .map(|sk| PublicKey::from_private(sk, p_g, params).0.into_xy())
+                .collect(),
+        };
+        let (sender_x, sender_y) = multisig.commitment();
+
+        let recipient_sk = PrivateKey::<Bn256>(rng.gen());
+        let (recipient_x, recipient_y) = PublicKey::from_private(&recipient_sk, p_g, params)
+            .0
+            .into_xy();
+
+        let sender_leaf_number: u32 = 1;
+        let recipient_leaf_number: u32 = 2;
+        let token: u32 = 0;
+
+        let transfer_amount: u128 = 500;
+        let transfer_amount_bits = convert_to_float(
+            transfer_amount,
+            plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH,
+            plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH,
+            10,
+        )
+        .unwrap();
+        let fee_bits = convert_to_float(
+            0,
+            plasma_constants::FEE_EXPONENT_BIT_WIDTH,
+            plasma_constants::FEE_MANTISSA_BIT_WIDTH,
+            10,
+        )
+        .unwrap();
+
+        let mut sender_leaf = CircuitAccount::<Bn256> {
+            pub_x: sender_x,
+            pub_y: sender_y,
+            ..Default::default()
+        };
+        sender_leaf.set_balance(token, Fr::from_str("1000").unwrap(), params);
+
+        let recipient_leaf = CircuitAccount::<Bn256> {
+            pub_x: recipient_x,
+            pub_y: recipient_y,
+            ..Default::default()
+        };
+
+        tree.insert(sender_leaf_number, sender_leaf.clone());
+        tree.insert(recipient_leaf_number, recipient_leaf.clone());
+
+        let old_root = tree.root_hash();
+
+        let path_from: Vec<Option<Fr>> = tree
+            .merkle_path(sender_leaf_number)
+            .into_iter()
+            .map(|e| Some(e.0))
+            .collect();
+        let path_to: Vec<Option<Fr>> = tree
+            .merkle_path(recipient_leaf_number)
+            .into_iter()
+            .map(|e| Some(e.0))
+            .collect();
+
+        // the sender can not sign, its own signature stays empty
+        let mut transaction: Transaction<Bn256> = Transaction {
+            from: Fr::from_str(&sender_leaf_number.to_string()),
+            to: Fr::from_str(&recipient_leaf_number.to_string()),
+            token: Fr::from_str(&token.to_string()),
+            amount: Some(le_bit_vector_into_field_element(&transfer_amount_bits)),
+            fee: Some(le_bit_vector_into_field_element(&fee_bits)),
+            nonce: Some(Fr::zero()),
+            good_until_block: Some(Fr::one()),
+            chain_id: Fr::from_str(&signed_chain_id.to_string()),
+            signature: Some(TransactionSignature::empty(params)),
+            multisig_signatures: vec![
+                Some(TransactionSignature::empty(params));
+                plasma_constants::MULTISIG_MAX_SIGNERS
+            ],
+            to_new_account: Some(false),
+            new_pub_x: Some(Fr::zero()),
+            new_pub_y: Some(Fr::zero()),
+        };
+
+        for &signer in signers {
+            let mut signed = transaction.clone();
+            signed.sign(&signer_sks[signer], p_g, params, rng);
+            transaction.multisig_signatures[signer] = signed.signature;
+        }
+
+        let leaf_witness = |leaf: &CircuitAccount<Bn256>| LeafWitness {
+            balance: Some(leaf.get_balance(token)),
+            balance_path: leaf
+                .balance_path(token, params)
+                .into_iter()
+                .map(Some)
+                .collect(),
+            nonce: Some(leaf.nonce),
+            pub_x: Some(leaf.pub_x),
+            pub_y: Some(leaf.pub_y),
+            pub_key_hash: leaf_pub_key_hash(&leaf.pub_x, &leaf.pub_y),
+            cheque_bits: Some(leaf.cheque_bits),
+            cheque_start: Some(leaf.cheque_start),
+            lock_commitment: Some(leaf.lock_commitment),
+        };
+
+        let transaction_witness = TransactionWitness {
+            leaf_from: leaf_witness(&sender_leaf),
+            auth_path_from: path_from,
+            leaf_to: leaf_witness(&recipient_leaf),
+            auth_path_to: path_to,
+            is_multisig: Some(true),
+            multisig_threshold: Fr::from_str(&multisig.threshold.to_string()),
+            multisig_keys: multisig
+                .keys
+                .iter()
+                .map(|&(x, y)| (Some(x), Some(y)))
+                .collect(),
+            multisig_signed: (0..plasma_constants::MULTISIG_MAX_SIGNERS)
+                .map(|i| Some(signers.contains(&i)))
+                .collect(),
+        };
+
+        let transfer_amount_as_field_element = Fr::from_str(&transfer_amount.to_string()).unwrap();
+
+        let mut updated_sender_leaf = sender_leaf.clone();
+        let mut balance_from = updated_sender_leaf.get_balance(token);
+        balance_from.sub_assign(&transfer_amount_as_field_element);
+        updated_sender_leaf.set_balance(token, balance_from, params);
+        updated_sender_leaf.nonce.add_assign(&Fr::one());
+
+        let mut updated_recipient_leaf = recipient_leaf.clone();
+        let mut balance_to = updated_recipient_leaf.get_balance(token);
+        balance_to.add_assign(&transfer_amount_as_field_element);
+        updated_recipient_leaf.set_balance(token, balance_to, params);
+
+        tree.insert(sender_leaf_number, updated_sender_leaf);
+        tree.insert(recipient_leaf_number, updated_recipient_leaf);
+
+        let new_root = tree.root_hash();
+
+        let mut public_data_initial_bits = Vec::new();
+
+        let block_number_bits: Vec<bool> = BitIterator::new(Fr::one().into_repr()).collect();
+        for _ in 0..256 - block_number_bits.len() {
+            public_data_initial_bits.push(false);
+        }
+        public_data_initial_bits.extend(block_number_bits.into_iter());
+
+        let total_fee_bits: Vec<bool> = BitIterator::new(Fr::zero().into_repr()).collect();
+        for _ in 0..256 - total_fee_bits.len() {
+            public_data_initial_bits.push(false);
+        }
+        public_data_initial_bits.extend(total_fee_bits.into_iter());
+
+        let mut h = Sha256::new();
+        h.input(&be_bit_vector_into_bytes(&public_data_initial_bits));
+        let mut hash_result = [0u8; 32];
+        h.result(&mut hash_result[..]);
+
+        let mut next_round_hash_bytes = Vec::new();
+        next_round_hash_bytes.extend(hash_result.iter());
+        next_round_hash_bytes.extend(be_bit_vector_into_bytes(
+            &transaction.public_data_into_bits(),
+        ));
+
+        h = Sha256::new();
+        h.input(&next_round_hash_bytes);
+        hash_result = [0u8; 32];
+        h.result(&mut hash_result[..]);
+
+        hash_result[0] &= 0x1f; // temporary solution
+
+        let mut repr = Fr::zero().into_repr();
+        repr.read_be(&hash_result[..])
+            .expect("pack hash as field element");
+
+        let public_data_commitment = Fr::from_repr(repr).unwrap();
+
+        let mut cs = TestConstraintSystem::<Bn256>::new();
+
+        let instance = Transfer {
+            params,
+            number_of_transactions: 1,
+            chain_id: Fr::one(),
+            old_root: Some(old_root),
+            new_root: Some(new_root),
+            public_data_commitment: Some(public_data_commitment),
+            block_number: Some(Fr::one()),
+            total_fee: Some(Fr::zero()),
+            transactions: vec![(transaction, transaction_witness)],
+        };
+
+        instance.synthesize(&mut cs).unwrap();
+
+        cs
+    }
+
+    #[test]
+    fn test_transfer_from_multisig() {
+        let cs = multisig_transfer_constraint_system(&[0, 2], 1);
+
+        assert_eq!(cs.num_inputs(), 4);
+
+        let err = cs.which_is_unsatisfied();
+        if err.is_some() {
+            panic!("ERROR satisfying in {}", err.unwrap());
+        }
+    }
+
+    #[test]
+    fn test_transfer_from_multisig_below_threshold() {
+        let cs = multisig_transfer_constraint_system(&[1], 1);
+
+        assert!(!cs.is_satisfied());
+    }
+
+    #[test]
+    fn test_transfer_signed_for_another_chain() {
+        let cs = multisig_transfer_constraint_system(&[0, 2], 2);
+
+        assert_eq!(
+            cs.which_is_unsatisfied(),
+            Some(
+                "applying transaction 0/parse and check transaction/enforce chain id of the deployment"
+            )
+        );
+    }
+
+    /// Builds a transfer to the empty account 2, that registers the key of the recipient
+    /// if `registers_key` is set
+    fn new_account_transfer_constraint_system(
+        registers_key: bool,
+    ) -> sapling_crypto::circuit::test::TestConstraintSystem<pairing::bn256::Bn256> {
+        use crate::CircuitAccountTree;
+        use crypto::digest::Digest;
+        use crypto::sha2::Sha256;
+        use ff::Field;
+        use models::plasma::circuit::account::{leaf_pub_key_hash, CircuitAccount};
+        use models::plasma::circuit::utils::be_bit_vector_into_bytes;
+        use pairing::bn256::*;
+        use rand::{Rng, SeedableRng, XorShiftRng};
+        use sapling_crypto::alt_babyjubjub::AltJubjubBn256;
+        use sapling_crypto::circuit::test::*;
+        use sapling_crypto::eddsa::{PrivateKey, PublicKey};
+
+        let p_g = FixedGenerators::SpendingKeyGenerator;
+        let params = &AltJubjubBn256::new();
+
+        let rng = &mut XorShiftRng::from_seed([0x3dbe_6258, 0x8d31_3d76, 0x3237_db17, 0xe5bc_0654]);
+
+        let tree_depth = plasma_constants::BALANCE_TREE_DEPTH as u32;
+        let mut tree = CircuitAccountTree::new(tree_depth);
+
+        let sender_sk = PrivateKey::<Bn256>(rng.gen());
+        let (sender_x, sender_y) = PublicKey::from_private(&sender_sk, p_g, params).0.into_xy();
+
+        let recipient_sk = PrivateKey::<Bn256>(rng.gen());
+        let (recipient_x, recipient_y) = PublicKey::from_private(&recipient_sk, p_g, params)
+            .0
+            .into_xy();
+
+        let (new_pub_x, new_pub_y) = if registers_key {
+            (recipient_x, recipient_y)
+        } else {
+            (Fr::zero(), Fr::zero())
+        };
+
+        let sender_leaf_number: u32 = 1;
+        let recipient_leaf_number: u32 = 2;
+        let token: u32 = 0;
+
+        let transfer_amount: u128 = 500;
+        let transfer_amount_bits = convert_to_float(
+            transfer_amount,
+            plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH,
+            plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH,
+            10,
+        )
+        .unwrap();
+        let fee_bits = convert_to_float(
+            0,
+            plasma_constants::FEE_EXPONENT_BIT_WIDTH,
+            plasma_constants::FEE_MANTISSA_BIT_WIDTH,
+            10,
+        )
+        .unwrap();
+
+        let mut sender_leaf = CircuitAccount::<Bn256> {
+            pub_x: sender_x,
+            pub_y: sender_y,
+            ..Default::default()
+        };
+        sender_leaf.set_balance(token, Fr::from_str("1000").unwrap(), params);
+
+        let recipient_leaf = CircuitAccount::<Bn256>::default();
+
+        tree.insert(sender_leaf_number, sender_leaf.clone());
+
+        let old_root = tree.root_hash();
+
+        let path_from: Vec<Option<Fr>> = tree
+            .merkle_path(sender_leaf_number)
+            .into_iter()
+            .map(|e| Some(e.0))
+            .collect();
+        let path_to: Vec<Option<Fr>> = tree
+            .merkle_path(recipient_leaf_number)
+            .into_iter()
+            .map(|e| Some(e.0))
+            .collect();
+
+        let mut transaction: Transaction<Bn256> = Transaction {
+            from: Fr::from_str(&sender_leaf_number.to_string()),
+            to: Fr::from_str(&recipient_leaf_number.to_string()),
+            token: Fr::from_str(&token.to_string()),
+            amount: Some(le_bit_vector_into_field_element(&transfer_amount_bits)),
+            fee: Some(le_bit_vector_into_field_element(&fee_bits)),
+            nonce: Some(Fr::zero()),
+            good_until_block: Some(Fr::one()),
+            chain_id: Some(Fr::one()),
+            signature: None,
+            multisig_signatures: vec![
+                Some(TransactionSignature::empty(params));
+                plasma_constants::MULTISIG_MAX_SIGNERS
+            ],
+            to_new_account: Some(registers_key),
+            new_pub_x: Some(new_pub_x),
+            new_pub_y: Some(new_pub_y),
+        };
+
+        transaction.sign(&sender_sk, p_g, params, rng);
+
+        let leaf_witness = |leaf: &CircuitAccount<Bn256>| LeafWitness {
+            balance: Some(leaf.get_balance(token)),
+            balance_path: leaf
+                .balance_path(token, params)
+                .into_iter()
+                .map(Some)
+                .collect(),
+            nonce: Some(leaf.nonce),
+            pub_x: Some(leaf.pub_x),
+            pub_y: Some(leaf.pub_y),
+            pub_key_hash: leaf_pub_key_hash(&leaf.pub_x, &leaf.pub_y),
+            cheque_bits: Some(leaf.cheque_bits),
+            cheque_start: Some(leaf.cheque_start),
+            lock_commitment: Some(leaf.lock_commitment),
+        };
+
+        let transaction_witness = TransactionWitness {
+            leaf_from: leaf_witness(&sender_leaf),
+            auth_path_from: path_from,
+            leaf_to: leaf_witness(&recipient_leaf),
+            auth_path_to: path_to,
+            is_multisig: Some(false),
+            multisig_threshold: Some(Fr::zero()),
+            multisig_keys: vec![
+                (Some(Fr::zero()), Some(Fr::one()));
+                plasma_constants::MULTISIG_MAX_SIGNERS
+            ],
+            multisig_signed: vec![Some(false); plasma_constants::MULTISIG_MAX_SIGNERS],
+        };
+
+        let transfer_amount_as_field_element = Fr::from_str(&transfer_amount.to_string()).unwrap();
+
+        let mut updated_sender_leaf = sender_leaf.clone();
+        let mut balance_from = updated_sender_leaf.get_balance(token);
+        balance_from.sub_assign(&transfer_amount_as_field_element);
+        updated_sender_leaf.set_balance(token, balance_from, params);
+        updated_sender_leaf.nonce.add_assign(&Fr::one());
+
+        let mut updated_recipient_leaf = CircuitAccount::<Bn256> {
+            pub_x: new_pub_x,
+            pub_y: new_pub_y,
+            ..Default::default()
+        };
+        updated_recipient_leaf.set_balance(token, transfer_amount_as_field_element, params);
+
+        tree.insert(sender_leaf_number, updated_sender_leaf);
+        tree.insert(recipient_leaf_number, updated_recipient_leaf);
+
+        let new_root = tree.root_hash();
+
+        let mut public_data_initial_bits = Vec::new();
+
+        let block_number_bits: Vec<bool> = BitIterator::new(Fr::one().into_repr()).collect();
+        public_data_initial_bits.resize(256 - block_number_bits.len(), false);
+        public_data_initial_bits.extend(block_number_bits);
+
+        let total_fee_bits: Vec<bool> = BitIterator::new(Fr::zero().into_repr()).collect();
+        public_data_initial_bits.resize(512 - total_fee_bits.len(), false);
+        public_data_initial_bits.extend(total_fee_bits);
+
+        let mut h = Sha256::new();
+        h.input(&be_bit_vector_into_bytes(&public_data_initial_bits));
+        let mut hash_result = [0u8; 32];
+        h.result(&mut hash_result[..]);
+
+        let mut next_round_hash_bytes = Vec::new();
+        next_round_hash_bytes.extend(hash_result.iter());
+        next_round_hash_bytes.extend(be_bit_vector_into_bytes(
+            &transaction.public_data_into_bits(),
+        ));
+
+        h = Sha256::new();
+        h.input(&next_round_hash_bytes);
+        hash_result = [0u8; 32];
+        h.result(&mut hash_result[..]);
+
+        hash_result[0] &= 0x1f; // temporary solution
+
+        let mut repr = Fr::zero().into_repr();
+        repr.read_be(&hash_result[..])
+            .expect("pack hash as field element");
+
+        let public_data_commitment = Fr::from_repr(repr).unwrap();
+
+        let mut cs = TestConstraintSystem::<Bn256>::new();
+
+        let instance = Transfer {
+            params,
+            number_of_transactions: 1,
+            chain_id: Fr::one(),
+            old_root: Some(old_root),
+            new_root: Some(new_root),
+            public_data_commitment: Some(public_data_commitment),
+            block_number: Some(Fr::one()),
+            total_fee: Some(Fr::zero()),
+            transactions: vec![(transaction, transaction_witness)],
+        };
+
+        instance.synthesize(&mut cs).unwrap();
+
+        cs
+    }
+
+    #[test]
+    fn test_transfer_to_new_account() {
+        let cs = new_account_transfer_constraint_system(true);
+
+        assert_eq!(cs.num_inputs(), 4);
+
+        let err = cs.which_is_unsatisfied();
+        if err.is_some() {
+            panic!("ERROR satisfying in {}", err.unwrap());
+        }
+    }
+
+    #[test]
+    fn test_transfer_to_recipient_without_key() {
+        let cs = new_account_transfer_constraint_system(false);
+
+        assert_eq!(
+            cs.which_is_unsatisfied(),
+            Some("applying transaction 0/enforce recipient without key is new or zero")
+        );
+    }
 }
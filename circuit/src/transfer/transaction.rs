@@ -1,5 +1,7 @@
 use ff::{BitIterator, PrimeField};
+use models::plasma::circuit::account::packed_public_key;
 use models::plasma::circuit::sig::TransactionSignature;
+use models::plasma::circuit::transfer::new_account_message_bits;
 use models::plasma::circuit::utils::le_bit_vector_into_field_element;
 use models::plasma::params as plasma_constants;
 use sapling_crypto::circuit::boolean;
@@ -12,11 +14,20 @@ use sapling_crypto::jubjub::{FixedGenerators, JubjubEngine};
 pub struct Transaction<E: JubjubEngine> {
     pub from: Option<E::Fr>,
     pub to: Option<E::Fr>,
+    pub token: Option<E::Fr>,
     pub amount: Option<E::Fr>,
     pub fee: Option<E::Fr>,
     pub nonce: Option<E::Fr>,
     pub good_until_block: Option<E::Fr>,
+    pub chain_id: Option<E::Fr>,
     pub signature: Option<TransactionSignature<E>>,
+    // signatures by the keys of a multisig sender, one per key up to MULTISIG_MAX_SIGNERS.
+    // Keys that have not signed get the empty signature
+    pub multisig_signatures: Vec<Option<TransactionSignature<E>>>,
+    // a transfer to a new account registers the key of the recipient, the key is zero otherwise
+    pub to_new_account: Option<bool>,
+    pub new_pub_x: Option<E::Fr>,
+    pub new_pub_y: Option<E::Fr>,
 }
 
 pub struct TransactionContent<E: JubjubEngine> {
@@ -31,8 +42,10 @@ impl<E: JubjubEngine> Transaction<E> {
         // fields are
         // - from
         // - to
+        // - token
         // - amount
         // - fee
+        // - key of a new recipient account
         let mut from: Vec<bool> = BitIterator::new(self.from.unwrap().into_repr()).collect();
         from.reverse();
         from.truncate(plasma_constants::BALANCE_TREE_DEPTH);
@@ -43,6 +56,11 @@ impl<E: JubjubEngine> Transaction<E> {
         to.truncate(plasma_constants::BALANCE_TREE_DEPTH);
         // reverse again cause from and to are the only two fields that are kept BE
         to.reverse();
+        // token id is kept BE as well
+        let mut token: Vec<bool> = BitIterator::new(self.token.unwrap().into_repr()).collect();
+        token.reverse();
+        token.truncate(plasma_constants::TOKEN_BIT_WIDTH);
+        token.reverse();
         let mut amount: Vec<bool> = BitIterator::new(self.amount.unwrap().into_repr()).collect();
         amount.reverse();
         amount.truncate(
@@ -58,22 +76,31 @@ impl<E: JubjubEngine> Transaction<E> {
         let mut packed: Vec<bool> = vec![];
         packed.extend(from.into_iter());
         packed.extend(to.into_iter());
+        packed.extend(token.into_iter());
         packed.extend(amount.into_iter());
         packed.extend(fee.into_iter());
 
+        // BE as a key change publishes it
+        let mut new_pub_key =
+            packed_public_key::<E>(&self.new_pub_x.unwrap(), &self.new_pub_y.unwrap());
+        new_pub_key.reverse();
+        packed.extend(new_pub_key);
+
         packed
     }
 
     // this function returns data to make a transaction signature
     // in a format that is later used in zkSNARK
-    pub fn data_for_signature_into_bits(&self) -> Vec<bool> {
+    pub fn data_for_signature_into_bits(&self, params: &E::Params) -> Vec<bool> {
         // fields are
         // - from
         // - to
+        // - token
         // - amount
         // - fee
         // - nonce
         // - good_until_block
+        // - chain_id
 
         // in data for signature and for latter use in SNARKs everything is LE!
 
@@ -85,6 +112,10 @@ impl<E: JubjubEngine> Transaction<E> {
         let mut to: Vec<bool> = BitIterator::new(self.to.unwrap().into_repr()).collect();
         to.reverse();
         to.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+        // LE token
+        let mut token: Vec<bool> = BitIterator::new(self.token.unwrap().into_repr()).collect();
+        token.reverse();
+        token.truncate(plasma_constants::TOKEN_BIT_WIDTH);
         // amount is encoded as float
         let mut amount: Vec<bool> = BitIterator::new(self.amount.unwrap().into_repr()).collect();
         amount.reverse();
@@ -107,21 +138,38 @@ impl<E: JubjubEngine> Transaction<E> {
             BitIterator::new(self.good_until_block.unwrap().into_repr()).collect();
         good_until_block.reverse();
         good_until_block.truncate(plasma_constants::BLOCK_NUMBER_BIT_WIDTH);
+        // LE chain id of the deployment
+        let mut chain_id: Vec<bool> =
+            BitIterator::new(self.chain_id.unwrap().into_repr()).collect();
+        chain_id.reverse();
+        chain_id.truncate(plasma_constants::CHAIN_ID_BIT_WIDTH);
 
         let mut packed: Vec<bool> = vec![];
 
         packed.extend(from.into_iter());
         packed.extend(to.into_iter());
+        packed.extend(token.into_iter());
         packed.extend(amount.into_iter());
         packed.extend(fee.into_iter());
         packed.extend(nonce.into_iter());
         packed.extend(good_until_block.into_iter());
+        packed.extend(chain_id.into_iter());
+
+        // a transfer to a new account signs the hash of the message and the key
+        if self.to_new_account.unwrap() {
+            return new_account_message_bits::<E>(
+                packed,
+                &self.new_pub_x.unwrap(),
+                &self.new_pub_y.unwrap(),
+                params,
+            );
+        }
 
         packed
     }
 
-    pub fn data_as_bytes(&self) -> Vec<u8> {
-        let raw_data: Vec<bool> = self.data_for_signature_into_bits();
+    pub fn data_as_bytes(&self, params: &E::Params) -> Vec<u8> {
+        let raw_data: Vec<bool> = self.data_for_signature_into_bits(params);
 
         let mut message_bytes: Vec<u8> = vec![];
 
@@ -148,16 +196,10 @@ impl<E: JubjubEngine> Transaction<E> {
     ) where
         R: rand::Rng,
     {
-        let message_bytes = self.data_as_bytes();
-
-        let max_message_len = plasma_constants::BALANCE_TREE_DEPTH
-            + plasma_constants::BALANCE_TREE_DEPTH
-            + plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH
-            + plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH
-            + plasma_constants::FEE_EXPONENT_BIT_WIDTH
-            + plasma_constants::FEE_MANTISSA_BIT_WIDTH
-            + plasma_constants::NONCE_BIT_WIDTH
-            + plasma_constants::BLOCK_NUMBER_BIT_WIDTH;
+        let message_bytes = self.data_as_bytes(params);
+
+        let max_message_len = plasma_constants::NEW_ACCOUNT_MESSAGE_HASH_WIDTH
+            + plasma_constants::NEW_ACCOUNT_MESSAGE_MARKER_WIDTH;
 
         let signature =
             private_key.sign_raw_message(&message_bytes, rng, p_g, params, max_message_len / 8);
@@ -0,0 +1,916 @@
+use crate::leaf::{
+    account_leaf_hash, account_tree_node, balances_root_into_bits, calculate_balances_root,
+    leaf_state_bits, make_leaf_content, LeafWitness,
+};
+use crate::unlock::unlock_request::UnlockRequest;
+use bellman::{Circuit, ConstraintSystem, SynthesisError};
+use ff::{Field, PrimeField};
+use models::plasma::circuit::utils::allocate_audit_path;
+use models::plasma::params as plasma_constants;
+use models::primitives::bytes_into_be_bits;
+use sapling_crypto::circuit::num::{AllocatedNum, Num};
+use sapling_crypto::circuit::{boolean, num, pedersen_hash, sha256, Assignment};
+use sapling_crypto::jubjub::JubjubEngine;
+
+#[derive(Clone)]
+pub struct UnlockWitness<E: JubjubEngine> {
+    pub leaf_from: LeafWitness<E>,
+    pub auth_path_from: Vec<Option<E::Fr>>,
+    // the lock that is committed to in the leaf
+    pub lock_to: Option<E::Fr>,
+    pub lock_token: Option<E::Fr>,
+    pub lock_amount: Option<E::Fr>,
+    pub lock_hash: Option<[u8; 32]>,
+    pub lock_good_until_block: Option<E::Fr>,
+    // destination leaf is opened in the tree where the lock is already removed,
+    // for a refund it's the same leaf as `leaf_from`
+    pub leaf_to: LeafWitness<E>,
+    pub auth_path_to: Vec<Option<E::Fr>>,
+}
+
+/// This is an instance of the `Unlock` circuit, it proves both claims and refunds.
+pub struct Unlock<'a, E: JubjubEngine> {
+    pub params: &'a E::Params,
+
+    // number of unlocks per block
+    pub number_of_unlocks: usize,
+
+    /// The old root of the tree
+    pub old_root: Option<E::Fr>,
+
+    /// The new root of the tree
+    pub new_root: Option<E::Fr>,
+
+    /// Final truncated rolling SHA256
+    pub public_data_commitment: Option<E::Fr>,
+
+    /// Block number
+    pub block_number: Option<E::Fr>,
+
+    /// Requests for this block
+    pub requests: Vec<(UnlockRequest<E>, UnlockWitness<E>)>,
+}
+
+impl<'a, E: JubjubEngine> Circuit<E> for Unlock<'a, E> {
+    fn synthesize<CS: ConstraintSystem<E>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        // Check that requests are in a right quantity
+        assert!(self.number_of_unlocks == self.requests.len());
+
+        let old_root_value = self.old_root;
+        // Expose inputs and do the bits decomposition of hash
+        let mut old_root =
+            AllocatedNum::alloc(cs.namespace(|| "old root"), || Ok(*old_root_value.get()?))?;
+        old_root.inputize(cs.namespace(|| "old root input"))?;
+
+        let new_root_value = self.new_root;
+        let new_root =
+            AllocatedNum::alloc(cs.namespace(|| "new root"), || Ok(*new_root_value.get()?))?;
+        new_root.inputize(cs.namespace(|| "new root input"))?;
+
+        let rolling_hash_value = self.public_data_commitment;
+        let rolling_hash = AllocatedNum::alloc(cs.namespace(|| "rolling hash"), || {
+            Ok(*rolling_hash_value.get()?)
+        })?;
+        rolling_hash.inputize(cs.namespace(|| "rolling hash input"))?;
+
+        let mut lock_expirations = vec![];
+
+        let mut public_data_vector: Vec<boolean::Boolean> = vec![];
+
+        // Ok, now we need to update the old root by applying requests in sequence
+        let requests = self.requests.clone();
+
+        for (i, tx) in requests.into_iter().enumerate() {
+            let (request, witness) = tx;
+            let (intermediate_root, good_until_block, is_claim, public_data) = apply_request(
+                cs.namespace(|| format!("applying request {}", i)),
+                old_root,
+                request,
+                witness,
+                self.params,
+            )?;
+            old_root = intermediate_root;
+            lock_expirations.push((good_until_block, is_claim));
+
+            // flatten the public transaction data
+            public_data_vector.extend(public_data.into_iter());
+        }
+
+        // constraint the new hash to be equal to updated hash
+
+        cs.enforce(
+            || "enforce new root equal to recalculated one",
+            |lc| lc + new_root.get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc + old_root.get_variable(),
+        );
+
+        // Then check that for every claim in this block the lock is not expired yet,
+        // i.e. "good until" is greater or equal than the current block number,
+        // and for every refund it's already expired
+
+        let block_number_allocated =
+            AllocatedNum::alloc(cs.namespace(|| "allocate block number"), || {
+                Ok(*self.block_number.get()?)
+            })?;
+
+        let mut two = E::Fr::one();
+        two.double();
+
+        for (i, (good_until_block, is_claim)) in lock_expirations.into_iter().enumerate() {
+            // difference is (good_until_block - block_number) for a claim
+            // and (block_number - good_until_block - 1) for a refund,
+            // it's a proper subtraction if it does not overflow
+
+            let difference_allocated = AllocatedNum::alloc(
+                cs.namespace(|| format!("allocate block number difference {}", i)),
+                || {
+                    let block_number = *self.block_number.get()?;
+                    let good_until_block = *good_until_block.get_value().get()?;
+                    if *is_claim.get_value().get()? {
+                        let mut difference = good_until_block;
+                        difference.sub_assign(&block_number);
+
+                        Ok(difference)
+                    } else {
+                        let mut difference = block_number;
+                        difference.sub_assign(&good_until_block);
+                        difference.sub_assign(&E::Fr::one());
+
+                        Ok(difference)
+                    }
+                },
+            )?;
+
+            // check for overflow
+
+            difference_allocated.limit_number_of_bits(
+                cs.namespace(|| format!("check for subtraction overflow {}", i)),
+                plasma_constants::BLOCK_NUMBER_BIT_WIDTH,
+            )?;
+
+            // is_claim * (2 * good_until_block - 2 * block_number + 1) ==
+            // difference - block_number + good_until_block + 1
+            cs.enforce(
+                || format!("enforce subtraction in block number calculation {}", i),
+                |_| is_claim.lc(CS::one(), E::Fr::one()),
+                |lc| {
+                    lc + (two, good_until_block.get_variable())
+                        - (two, block_number_allocated.get_variable())
+                        + CS::one()
+                },
+                |lc| {
+                    lc + difference_allocated.get_variable() - block_number_allocated.get_variable()
+                        + good_until_block.get_variable()
+                        + CS::one()
+                },
+            );
+        }
+
+        // Now it's time to pack the initial SHA256 hash due to Ethereum BE encoding
+        // and start rolling the hash
+
+        let mut initial_hash_data: Vec<boolean::Boolean> = vec![];
+
+        // make initial hash as sha256(uint256(block_number))
+        let mut block_number_bits = block_number_allocated
+            .into_bits_le(cs.namespace(|| "unpack block number for hashing"))?;
+
+        block_number_bits.resize(
+            plasma_constants::FR_BIT_WIDTH,
+            boolean::Boolean::Constant(false),
+        );
+        block_number_bits.reverse();
+        initial_hash_data.extend(block_number_bits.into_iter());
+
+        assert_eq!(initial_hash_data.len(), 256);
+
+        let mut hash_block = sha256::sha256(
+            cs.namespace(|| "initial rolling sha256"),
+            &initial_hash_data,
+        )?;
+
+        // now pack the public data and do the final hash
+
+        let mut pack_bits = vec![];
+        pack_bits.extend(hash_block);
+        pack_bits.extend(public_data_vector.into_iter());
+
+        hash_block = sha256::sha256(cs.namespace(|| "hash public data"), &pack_bits)?;
+
+        // now pack and enforce equality to the input
+
+        hash_block.reverse();
+        hash_block.truncate(E::Fr::CAPACITY as usize);
+
+        let mut packed_hash_lc = Num::<E>::zero();
+        let mut coeff = E::Fr::one();
+        for bit in hash_block {
+            packed_hash_lc = packed_hash_lc.add_bool_with_coeff(CS::one(), &bit, coeff);
+            coeff.double();
+        }
+
+        cs.enforce(
+            || "enforce external data hash equality",
+            |lc| lc + rolling_hash.get_variable(),
+            |lc| lc + CS::one(),
+            |_| packed_hash_lc.lc(E::Fr::one()),
+        );
+
+        Ok(())
+    }
+}
+
+/// Ascends the merkle tree authentication path from the leaf,
+/// outputs the root
+fn calculate_root<E, CS>(
+    mut cs: CS,
+    leaf_bits: &[boolean::Boolean],
+    path_bits: &[boolean::Boolean],
+    audit_path: &[AllocatedNum<E>],
+    params: &E::Params,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    let mut cur = account_leaf_hash(cs.namespace(|| "leaf content hash"), leaf_bits, params)?;
+
+    for (i, direction_bit) in path_bits.iter().enumerate() {
+        let cs = &mut cs.namespace(|| format!("merkle tree hash {}", i));
+
+        // Swap the two if the current subtree is on the right
+        let (xl, xr) = num::AllocatedNum::conditionally_reverse(
+            cs.namespace(|| "conditional reversal of preimage"),
+            &cur,
+            &audit_path[i],
+            direction_bit,
+        )?;
+
+        // Compute the new subtree value
+        cur = account_tree_node(cs.namespace(|| "node hash"), &xl, &xr, i, params)?;
+    }
+
+    Ok(cur)
+}
+
+/// Repacks a truncated bit decomposition into a number
+fn pack_bits_into_num<E, CS>(
+    mut cs: CS,
+    bits: &[boolean::Boolean],
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    let mut packed_lc = Num::<E>::zero();
+    let mut coeff = E::Fr::one();
+    for bit in bits {
+        packed_lc = packed_lc.add_bool_with_coeff(CS::one(), bit, coeff);
+        coeff.double();
+    }
+
+    let packed = AllocatedNum::alloc(cs.namespace(|| "allocate packed value"), || {
+        Ok(*packed_lc.get_value().get()?)
+    })?;
+
+    cs.enforce(
+        || "pack bits",
+        |lc| lc + packed.get_variable(),
+        |lc| lc + CS::one(),
+        |_| packed_lc.lc(E::Fr::one()),
+    );
+
+    Ok(packed)
+}
+
+/// Allocates 32 bytes as bits in the bit order of SHA256
+fn allocate_bytes_as_bits<E, CS>(
+    mut cs: CS,
+    bytes: Option<[u8; 32]>,
+) -> Result<Vec<boolean::Boolean>, SynthesisError>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    let values = bytes.map(|bytes| bytes_into_be_bits(&bytes));
+
+    let mut bits = vec![];
+    for i in 0..plasma_constants::HASH_LOCK_BIT_WIDTH {
+        let bit = boolean::AllocatedBit::alloc(
+            cs.namespace(|| format!("allocate bit {}", i)),
+            values.as_ref().map(|values| values[i]),
+        )?;
+        bits.push(boolean::Boolean::from(bit));
+    }
+
+    Ok(bits)
+}
+
+/// Applies one request to the tree,
+/// outputs a new root
+#[allow(clippy::type_complexity)]
+fn apply_request<E, CS>(
+    mut cs: CS,
+    old_root: AllocatedNum<E>,
+    request: UnlockRequest<E>,
+    witness: UnlockWitness<E>,
+    params: &E::Params,
+) -> Result<
+    (
+        AllocatedNum<E>,
+        AllocatedNum<E>,
+        boolean::Boolean,
+        Vec<boolean::Boolean>,
+    ),
+    SynthesisError,
+>
+where
+    E: JubjubEngine,
+    CS: ConstraintSystem<E>,
+{
+    // both leaves are opened for the token of the lock
+
+    let token_allocated = AllocatedNum::alloc(cs.namespace(|| "lock token"), || {
+        Ok(*witness.lock_token.get()?)
+    })?;
+
+    let mut token_bits =
+        token_allocated.into_bits_le(cs.namespace(|| "token bit decomposition"))?;
+
+    token_bits.truncate(plasma_constants::TOKEN_BIT_WIDTH);
+
+    let leaf_from = make_leaf_content(
+        cs.namespace(|| "create owner's leaf"),
+        witness.clone().leaf_from,
+        &token_bits,
+        params,
+    )?;
+
+    let from_address_allocated = AllocatedNum::alloc(cs.namespace(|| "owner address"), || {
+        Ok(*request.account.get()?)
+    })?;
+
+    let mut from_path_bits =
+        from_address_allocated.into_bits_le(cs.namespace(|| "owner address bit decomposition"))?;
+
+    from_path_bits.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+
+    let audit_path_from = allocate_audit_path(
+        cs.namespace(|| "allocate audit path for owner"),
+        witness.clone().auth_path_from,
+    )?;
+
+    let old_root_from = calculate_root(
+        cs.namespace(|| "owner's old root"),
+        &leaf_from.leaf_bits,
+        &from_path_bits,
+        &audit_path_from,
+        params,
+    )?;
+
+    // enforce old root before update
+    cs.enforce(
+        || "enforce correct old root for owner's leaf",
+        |lc| lc + old_root_from.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + old_root.get_variable(),
+    );
+
+    // open the lock that is committed to in the leaf
+
+    let lock_to_allocated = AllocatedNum::alloc(cs.namespace(|| "lock recipient address"), || {
+        Ok(*witness.lock_to.get()?)
+    })?;
+
+    let mut lock_to_bits = lock_to_allocated
+        .into_bits_le(cs.namespace(|| "lock recipient address bit decomposition"))?;
+
+    lock_to_bits.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+
+    let amount = AllocatedNum::alloc(cs.namespace(|| "lock amount"), || {
+        Ok(*witness.lock_amount.get()?)
+    })?;
+
+    let mut amount_bits = amount.into_bits_le(cs.namespace(|| "amount bits"))?;
+
+    amount_bits.truncate(plasma_constants::BALANCE_BIT_WIDTH);
+
+    // amount is used as a whole number in balances, so it must fit the committed bits
+    amount.limit_number_of_bits(
+        cs.namespace(|| "limit number of bits for lock amount"),
+        plasma_constants::BALANCE_BIT_WIDTH,
+    )?;
+
+    let hash_bits =
+        allocate_bytes_as_bits(cs.namespace(|| "allocate lock hash"), witness.lock_hash)?;
+
+    let good_until_block =
+        AllocatedNum::alloc(cs.namespace(|| "allocate lock good until block"), || {
+            Ok(*witness.lock_good_until_block.get()?)
+        })?;
+
+    let mut block_number_bits =
+        good_until_block.into_bits_le(cs.namespace(|| "block number bits"))?;
+
+    block_number_bits.truncate(plasma_constants::BLOCK_NUMBER_BIT_WIDTH);
+
+    // good until block is compared as a whole number with the block number
+    good_until_block.limit_number_of_bits(
+        cs.namespace(|| "limit number of bits for good until block"),
+        plasma_constants::BLOCK_NUMBER_BIT_WIDTH,
+    )?;
+
+    let mut lock_content = vec![];
+    lock_content.extend(lock_to_bits.clone());
+    lock_content.extend(token_bits.clone());
+    lock_content.extend(amount_bits);
+    lock_content.extend(hash_bits.clone());
+    lock_content.extend(block_number_bits);
+
+    let lock_commitment = pedersen_hash::pedersen_hash(
+        cs.namespace(|| "lock commitment"),
+        pedersen_hash::Personalization::NoteCommitment,
+        &lock_content,
+        params,
+    )?;
+
+    cs.enforce(
+        || "enforce lock commitment",
+        |lc| lc + lock_commitment.get_x().get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + leaf_from.lock_commitment.get_variable(),
+    );
+
+    // a claim reveals the preimage of the lock hash, a refund reveals nothing
+
+    let is_claim = boolean::Boolean::from(boolean::AllocatedBit::alloc(
+        cs.namespace(|| "allocate is claim"),
+        request.is_claim,
+    )?);
+
+    let preimage_bits =
+        allocate_bytes_as_bits(cs.namespace(|| "allocate preimage"), request.preimage)?;
+
+    let digest_bits = sha256::sha256(cs.namespace(|| "preimage hash"), &preimage_bits)?;
+
+    for (i, (digest_bit, hash_bit)) in digest_bits.iter().zip(hash_bits.iter()).enumerate() {
+        cs.enforce(
+            || format!("enforce preimage hash bit {} for claim", i),
+            |lc| {
+                lc + &digest_bit.lc(CS::one(), E::Fr::one()) - &hash_bit.lc(CS::one(), E::Fr::one())
+            },
+            |_| is_claim.lc(CS::one(), E::Fr::one()),
+            |lc| lc,
+        );
+    }
+
+    for (i, preimage_bit) in preimage_bits.iter().enumerate() {
+        cs.enforce(
+            || format!("enforce zero preimage bit {} for refund", i),
+            |_| preimage_bit.lc(CS::one(), E::Fr::one()),
+            |lc| lc + CS::one() - &is_claim.lc(CS::one(), E::Fr::one()),
+            |lc| lc,
+        );
+    }
+
+    // funds go to the recipient of the lock for a claim and back to the owner for a refund,
+    // Ch is a bitwise select
+
+    let mut to_path_bits = vec![];
+    for (i, (lock_to_bit, from_bit)) in lock_to_bits.iter().zip(from_path_bits.iter()).enumerate() {
+        to_path_bits.push(boolean::Boolean::sha256_ch(
+            cs.namespace(|| format!("select destination address bit {}", i)),
+            &is_claim,
+            lock_to_bit,
+            from_bit,
+        )?);
+    }
+
+    // remove the lock from the owner's leaf, the balance stays as is
+
+    let mut leaf_content = vec![];
+    leaf_content.extend(
+        leaf_from.leaf_bits
+            [0..leaf_from.leaf_bits.len() - plasma_constants::LEAF_STATE_HASH_BIT_WIDTH]
+            .iter()
+            .cloned(),
+    );
+    leaf_content.extend(leaf_state_bits(
+        cs.namespace(|| "owner's updated leaf state bits"),
+        &leaf_from.cheque_window_bits,
+        &vec![boolean::Boolean::Constant(false); plasma_constants::FR_BIT_WIDTH],
+        params,
+    )?);
+
+    assert_eq!(leaf_content.len(), leaf_from.leaf_bits.len());
+
+    let intermediate_root = calculate_root(
+        cs.namespace(|| "owner's updated root"),
+        &leaf_content,
+        &from_path_bits,
+        &audit_path_from,
+        params,
+    )?;
+
+    // now give the amount to the destination
+
+    let leaf_to = make_leaf_content(
+        cs.namespace(|| "create destination leaf"),
+        witness.clone().leaf_to,
+        &token_bits,
+        params,
+    )?;
+
+    let audit_path_to = allocate_audit_path(
+        cs.namespace(|| "allocate audit path for destination"),
+        witness.clone().auth_path_to,
+    )?;
+
+    let old_root_to = calculate_root(
+        cs.namespace(|| "destination's old root"),
+        &leaf_to.leaf_bits,
+        &to_path_bits,
+        &audit_path_to,
+        params,
+    )?;
+
+    cs.enforce(
+        || "enforce correct intermediate root for destination leaf",
+        |lc| lc + old_root_to.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + intermediate_root.get_variable(),
+    );
+
+    let old_balance_to = pack_bits_into_num(
+        cs.namespace(|| "pack destination's old balance"),
+        &leaf_to.value_bits,
+    )?;
+
+    let new_balance_to = AllocatedNum::alloc(cs.namespace(|| "new balance to"), || {
+        let mut new_balance_value = *old_balance_to.get_value().get()?;
+        new_balance_value.add_assign(amount.get_value().get()?);
+
+        Ok(new_balance_value)
+    })?;
+
+    // constraint no overflow
+    new_balance_to.limit_number_of_bits(
+        cs.namespace(|| "limit number of bits for new balance to"),
+        plasma_constants::BALANCE_BIT_WIDTH,
+    )?;
+
+    cs.enforce(
+        || "enforce new balance to",
+        |lc| lc + new_balance_to.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + old_balance_to.get_variable() + amount.get_variable(),
+    );
+
+    let mut leaf_content = vec![];
+
+    let mut value_content =
+        new_balance_to.into_bits_le(cs.namespace(|| "destination's updated amount bits"))?;
+
+    value_content.truncate(plasma_constants::BALANCE_BIT_WIDTH);
+
+    let balances_root = calculate_balances_root(
+        cs.namespace(|| "destination's updated balances root"),
+        &value_content,
+        &token_bits,
+        &leaf_to.balance_path,
+        params,
+    )?;
+
+    leaf_content.extend(balances_root_into_bits(
+        cs.namespace(|| "destination's updated balances root bits"),
+        &balances_root,
+    )?);
+
+    // everything else remains the same
+    leaf_content.extend(leaf_to.nonce_bits.clone());
+    leaf_content.extend(leaf_to.pub_key_bits.clone());
+    leaf_content.extend(leaf_to.state_bits.clone());
+
+    let new_root = calculate_root(
+        cs.namespace(|| "destination's updated root"),
+        &leaf_content,
+        &to_path_bits,
+        &audit_path_to,
+        params,
+    )?;
+
+    // the last step - we expose public data for later commitment
+
+    // convert to BE for further use in Ethereum
+    let mut from_path_be = from_path_bits;
+    from_path_be.reverse();
+
+    let mut public_data = vec![];
+    public_data.extend(from_path_be);
+    public_data.extend(vec![boolean::Boolean::Constant(false); 7]);
+    public_data.push(is_claim.clone());
+    // preimage is already in the bit order of SHA256
+    public_data.extend(preimage_bits);
+
+    assert_eq!(
+        public_data.len(),
+        plasma_constants::BALANCE_TREE_DEPTH + 8 + plasma_constants::HASH_LOCK_BIT_WIDTH
+    );
+
+    Ok((new_root, good_until_block, is_claim, public_data))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use log::debug;
+
+    use crate::lock::lock_request::LockRequest;
+    use crate::CircuitAccountTree;
+    use ff::{BitIterator, PrimeFieldRepr};
+    use models::plasma::circuit::account::{leaf_pub_key_hash, CircuitAccount};
+    use models::plasma::circuit::utils::{
+        be_bit_vector_into_bytes, le_bit_vector_into_field_element,
+    };
+    use pairing::bn256::*;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use sapling_crypto::alt_babyjubjub::AltJubjubBn256;
+    use sapling_crypto::circuit::float_point::convert_to_float;
+    use sapling_crypto::circuit::test::*;
+    use sapling_crypto::eddsa::{PrivateKey, PublicKey};
+    use sapling_crypto::jubjub::FixedGenerators;
+
+    use crypto::digest::Digest;
+    use crypto::sha2::Sha256;
+
+    const SECRET: [u8; 32] = [7u8; 32];
+
+    fn sha256_of(data: &[u8]) -> [u8; 32] {
+        let mut hash = [0u8; 32];
+        let mut h = Sha256::new();
+        h.input(data);
+        h.result(&mut hash[..]);
+
+        hash
+    }
+
+    // releases a lock of 100 that is good until block 10,
+    // returns the constraint system after synthesis
+    fn unlock(
+        is_claim: bool,
+        preimage: [u8; 32],
+        block_number: u32,
+    ) -> TestConstraintSystem<Bn256> {
+        let params = &AltJubjubBn256::new();
+        let p_g = FixedGenerators::SpendingKeyGenerator;
+
+        let rng = &mut XorShiftRng::from_seed([0x3dbe_6258, 0x8d31_3d76, 0x3237_db17, 0xe5bc_0654]);
+
+        let tree_depth = plasma_constants::BALANCE_TREE_DEPTH as u32;
+        let mut tree = CircuitAccountTree::new(tree_depth);
+
+        let capacity = tree.capacity();
+
+        let owner_sk = PrivateKey::<Bn256>(rng.gen());
+        let owner_pk = PublicKey::from_private(&owner_sk, p_g, params);
+        let (owner_x, owner_y) = owner_pk.0.into_xy();
+
+        let recipient_sk = PrivateKey::<Bn256>(rng.gen());
+        let recipient_pk = PublicKey::from_private(&recipient_sk, p_g, params);
+        let (recipient_x, recipient_y) = recipient_pk.0.into_xy();
+
+        let owner_leaf_number: u32 = rng.gen::<u32>() % capacity;
+        let mut recipient_leaf_number: u32 = rng.gen::<u32>() % capacity;
+        if recipient_leaf_number == owner_leaf_number {
+            recipient_leaf_number = (recipient_leaf_number + 1) % capacity;
+        }
+
+        let token: u32 = 2;
+
+        let amount_bits = convert_to_float(
+            100,
+            plasma_constants::AMOUNT_EXPONENT_BIT_WIDTH,
+            plasma_constants::AMOUNT_MANTISSA_BIT_WIDTH,
+            10,
+        )
+        .unwrap();
+
+        // the lock as it was made by a lock request
+        let lock = LockRequest::<Bn256> {
+            account: Fr::from_str(&owner_leaf_number.to_string()),
+            to: Fr::from_str(&recipient_leaf_number.to_string()),
+            token: Fr::from_str(&token.to_string()),
+            amount: Some(le_bit_vector_into_field_element(&amount_bits)),
+            hash: Some(sha256_of(&SECRET)),
+            good_until_block: Fr::from_str("10"),
+            nonce: None,
+            chain_id: None,
+            signature: None,
+        };
+
+        let mut owner_leaf = CircuitAccount::<Bn256> {
+            nonce: Fr::one(),
+            pub_x: owner_x,
+            pub_y: owner_y,
+            lock_commitment: lock.lock_commitment(params),
+            ..Default::default()
+        };
+        owner_leaf.set_balance(token, Fr::from_str("900").unwrap(), params);
+
+        let mut recipient_leaf = CircuitAccount::<Bn256> {
+            pub_x: recipient_x,
+            pub_y: recipient_y,
+            ..Default::default()
+        };
+        recipient_leaf.set_balance(token, Fr::from_str("20").unwrap(), params);
+
+        tree.insert(owner_leaf_number, owner_leaf.clone());
+        tree.insert(recipient_leaf_number, recipient_leaf.clone());
+
+        let initial_root = tree.root_hash();
+        debug!("Initial root = {}", initial_root);
+
+        let request = UnlockRequest::<Bn256> {
+            account: Fr::from_str(&owner_leaf_number.to_string()),
+            is_claim: Some(is_claim),
+            preimage: Some(preimage),
+        };
+
+        let auth_path_from: Vec<Option<Fr>> = tree
+            .merkle_path(owner_leaf_number)
+            .into_iter()
+            .map(|e| Some(e.0))
+            .collect();
+
+        let leaf_from = LeafWitness {
+            balance: Some(owner_leaf.get_balance(token)),
+            balance_path: owner_leaf
+                .balance_path(token, params)
+                .into_iter()
+                .map(Some)
+                .collect(),
+            nonce: Some(owner_leaf.nonce),
+            pub_x: Some(owner_x),
+            pub_y: Some(owner_y),
+            pub_key_hash: leaf_pub_key_hash(&owner_x, &owner_y),
+            cheque_bits: Some(owner_leaf.cheque_bits),
+            cheque_start: Some(owner_leaf.cheque_start),
+            lock_commitment: Some(owner_leaf.lock_commitment),
+        };
+
+        let mut updated_owner_leaf = owner_leaf.clone();
+        updated_owner_leaf.lock_commitment = Fr::zero();
+        tree.insert(owner_leaf_number, updated_owner_leaf.clone());
+
+        let (to_leaf_number, to_leaf) = if is_claim {
+            (recipient_leaf_number, recipient_leaf)
+        } else {
+            (owner_leaf_number, updated_owner_leaf)
+        };
+
+        let auth_path_to: Vec<Option<Fr>> = tree
+            .merkle_path(to_leaf_number)
+            .into_iter()
+            .map(|e| Some(e.0))
+            .collect();
+
+        let leaf_to = LeafWitness {
+            balance: Some(to_leaf.get_balance(token)),
+            balance_path: to_leaf
+                .balance_path(token, params)
+                .into_iter()
+                .map(Some)
+                .collect(),
+            nonce: Some(to_leaf.nonce),
+            pub_x: Some(to_leaf.pub_x),
+            pub_y: Some(to_leaf.pub_y),
+            pub_key_hash: leaf_pub_key_hash(&to_leaf.pub_x, &to_leaf.pub_y),
+            cheque_bits: Some(to_leaf.cheque_bits),
+            cheque_start: Some(to_leaf.cheque_start),
+            lock_commitment: Some(to_leaf.lock_commitment),
+        };
+
+        let mut new_balance = to_leaf.get_balance(token);
+        new_balance.add_assign(&Fr::from_str("100").unwrap());
+        let mut updated_to_leaf = to_leaf.clone();
+        updated_to_leaf.set_balance(token, new_balance, params);
+        tree.insert(to_leaf_number, updated_to_leaf);
+
+        let new_root = tree.root_hash();
+        debug!("New root = {}", new_root);
+
+        let witness = UnlockWitness {
+            leaf_from,
+            auth_path_from,
+            lock_to: lock.to,
+            lock_token: lock.token,
+            lock_amount: Fr::from_str("100"),
+            lock_hash: lock.hash,
+            lock_good_until_block: lock.good_until_block,
+            leaf_to,
+            auth_path_to,
+        };
+
+        let block_number = Fr::from_str(&block_number.to_string()).unwrap();
+
+        let mut public_data_initial_bits = Vec::new();
+
+        // these two are BE encodings because an iterator is BE. This is also an Ethereum standard behavior
+
+        let block_number_bits: Vec<bool> = BitIterator::new(block_number.into_repr()).collect();
+        for _ in 0..256 - block_number_bits.len() {
+            public_data_initial_bits.push(false);
+        }
+        public_data_initial_bits.extend(block_number_bits.into_iter());
+
+        let mut h = Sha256::new();
+
+        let bytes_to_hash = be_bit_vector_into_bytes(&public_data_initial_bits);
+
+        h.input(&bytes_to_hash);
+
+        let mut hash_result = [0u8; 32];
+        h.result(&mut hash_result[..]);
+
+        let packed_transaction_data_bytes =
+            be_bit_vector_into_bytes(&request.public_data_into_bits());
+
+        let mut next_round_hash_bytes = vec![];
+        next_round_hash_bytes.extend(hash_result.iter());
+        next_round_hash_bytes.extend(packed_transaction_data_bytes);
+
+        h = Sha256::new();
+        h.input(&next_round_hash_bytes);
+        hash_result = [0u8; 32];
+        h.result(&mut hash_result[..]);
+
+        hash_result[0] &= 0x1f; // temporary solution
+
+        let mut repr = Fr::zero().into_repr();
+        repr.read_be(&hash_result[..])
+            .expect("pack hash as field element");
+
+        let public_data_commitment = Fr::from_repr(repr).unwrap();
+
+        let instance = Unlock {
+            params,
+            number_of_unlocks: 1,
+            old_root: Some(initial_root),
+            new_root: Some(new_root),
+            public_data_commitment: Some(public_data_commitment),
+            block_number: Some(block_number),
+            requests: vec![(request, witness)],
+        };
+
+        let mut cs = TestConstraintSystem::<Bn256>::new();
+
+        instance.synthesize(&mut cs).unwrap();
+
+        debug!("{}", cs.num_constraints());
+
+        assert_eq!(cs.num_inputs(), 4);
+
+        cs
+    }
+
+    #[test]
+    fn test_claim() {
+        // up to and including the last block of the lock
+        for block_number in &[1, 10] {
+            let cs = unlock(true, SECRET, *block_number);
+            let err = cs.which_is_unsatisfied();
+            if err.is_some() {
+                panic!("ERROR satisfying in {}", err.unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_claim_wrong_preimage() {
+        let cs = unlock(true, [8u8; 32], 1);
+        assert!(cs.which_is_unsatisfied().is_some());
+    }
+
+    #[test]
+    fn test_claim_expired() {
+        let cs = unlock(true, SECRET, 11);
+        assert!(cs.which_is_unsatisfied().is_some());
+    }
+
+    #[test]
+    fn test_refund() {
+        let cs = unlock(false, [0u8; 32], 11);
+        let err = cs.which_is_unsatisfied();
+        if err.is_some() {
+            panic!("ERROR satisfying in {}", err.unwrap());
+        }
+    }
+
+    #[test]
+    fn test_refund_before_expiration() {
+        let cs = unlock(false, [0u8; 32], 10);
+        assert!(cs.which_is_unsatisfied().is_some());
+    }
+}
@@ -0,0 +1,38 @@
+use ff::{BitIterator, PrimeField};
+use models::plasma::params as plasma_constants;
+use models::primitives::bytes_into_be_bits;
+use sapling_crypto::jubjub::JubjubEngine;
+
+// This is a request to release the hash lock of an account:
+// to the recipient of the lock if it's a claim, back to the account if it's a refund.
+// Neither is signed, a claim is authorized by the preimage and a refund by the lock expiration
+
+#[derive(Clone)]
+pub struct UnlockRequest<E: JubjubEngine> {
+    pub account: Option<E::Fr>,
+    pub is_claim: Option<bool>,
+    // zero for refunds
+    pub preimage: Option<[u8; 32]>,
+}
+
+impl<E: JubjubEngine> UnlockRequest<E> {
+    pub fn public_data_into_bits(&self) -> Vec<bool> {
+        // fields are
+        // - account
+        // - claim flag as a byte
+        // - preimage
+        let mut account: Vec<bool> = BitIterator::new(self.account.unwrap().into_repr()).collect();
+        account.reverse();
+        account.truncate(plasma_constants::BALANCE_TREE_DEPTH);
+        // reverse again to have BE as in Ethereum native types
+        account.reverse();
+
+        let mut packed: Vec<bool> = vec![];
+        packed.extend(account.into_iter());
+        packed.extend(vec![false; 7]);
+        packed.push(self.is_claim.unwrap());
+        packed.extend(bytes_into_be_bits(&self.preimage.unwrap()));
+
+        packed
+    }
+}
@@ -151,22 +151,26 @@ function packBnLe(bn, numBits) {
 }
 
 function serializeTransaction(tx) {
-    const {from, to, amount, fee, nonce, good_until_block} = tx;
+    const {from, to, token, amount, fee, nonce, good_until_block, chain_id} = tx;
     assert(from.bitLength() <= 24);
     assert(to.bitLength() <= 24);
+    assert(token.bitLength() <= 8);
     assert(amount.bitLength() <= 128);
     assert(fee.bitLength() <= 128);
     assert(nonce.bitLength() <= 32);
     assert(good_until_block.bitLength() <= 32);
+    assert(chain_id.bitLength() <= 32);
 
     let amountFloatBytes = integerToFloat(amount, 5, 11, 10);
     let feeFloatBytes = integerToFloat(fee, 5, 3, 10);
 
     const components = [
+        chain_id.toArrayLike(Buffer, "be", 4),
         good_until_block.toArrayLike(Buffer, "be", 4),
         nonce.toArrayLike(Buffer, "be", 4),
         packBnLe(new BN(feeFloatBytes, 16, "be"), 8),
         packBnLe(new BN(amountFloatBytes, 16, "be"), 16),
+        token.toArrayLike(Buffer, "be", 1),
         to.toArrayLike(Buffer, "be", 3),
         from.toArrayLike(Buffer, "be", 3)
     ];
@@ -181,23 +185,27 @@ function serializeTransaction(tx) {
         bytes: serialized,
         from: from,
         to: to,
+        token: token,
         amount: newAmount,
         fee: newFee,
         nonce: nonce,
-        good_until_block: good_until_block
+        good_until_block: good_until_block,
+        chain_id: chain_id
     }
 }
 
 function getPublicData(tx) {
-    const {from, to, amount, fee} = tx;
+    const {from, to, token, amount, fee} = tx;
     assert(from.bitLength() <= 24);
     assert(to.bitLength() <= 24);
+    assert(token.bitLength() <= 8);
     assert(amount.bitLength() <= 128);
     assert(fee.bitLength() <= 128);
 
     const components = [];
     components.push(from.toArrayLike(Buffer, "be", 3));
     components.push(to.toArrayLike(Buffer, "be", 3));
+    components.push(token.toArrayLike(Buffer, "be", 1));
     let amountFloatBytes = integerToFloat(amount, 5, 11, 10);
     components.push(amountFloatBytes);
     let feeFloatBytes = integerToFloat(fee, 5, 3, 10);
@@ -215,10 +223,10 @@ function getPublicData(tx) {
 }
 
 function parsePublicData(bytes) {
-    assert(bytes.length % 9 === 0);
+    assert(bytes.length % 10 === 0);
     const results = [];
-    for (let i = 0; i < bytes.length/9; i++) {
-        const slice = bytes.slice(9*i, 9*i + 9);
+    for (let i = 0; i < bytes.length/10; i++) {
+        const slice = bytes.slice(10*i, 10*i + 10);
         const res = parseSlice(slice);
         results.push(res);
     }
@@ -228,19 +236,22 @@ function parsePublicData(bytes) {
 function parseSlice(slice) {
     const from = new BN(slice.slice(0, 3), 16, "be");
     const to = new BN(slice.slice(3, 6), 16, "be");
-    const amount = floatToInteger(slice.slice(6, 8), 5, 11, 10)
-    const fee = floatToInteger(slice.slice(8, 9), 5, 3, 10)
-    return {from, to, amount, fee};
+    const token = new BN(slice.slice(6, 7), 16, "be");
+    const amount = floatToInteger(slice.slice(7, 9), 5, 11, 10)
+    const fee = floatToInteger(slice.slice(9, 10), 5, 3, 10)
+    return {from, to, token, amount, fee};
 }
 
 function toApiForm(tx, sig) {
     // expected by API server
     // pub from:               u32,
     // pub to:                 u32,
+    // pub token:              u32,
     // pub amount:             BigDecimal,
     // pub fee:                BigDecimal,
     // pub nonce:              u32,
     // pub good_until_block:   u32,
+    // pub chain_id:           u32,
     // pub signature:          TxSignature,
 
     // pub struct TxSignature{
@@ -260,10 +271,12 @@ function toApiForm(tx, sig) {
     let txForApi = {
         from: tx.from.toNumber(),
         to: tx.to.toNumber(),
+        token: tx.token.toNumber(),
         amount: tx.amount.toString(10),
         fee: tx.fee.toString(10),
         nonce: tx.nonce.toNumber(),
         good_until_block: tx.good_until_block.toNumber(),
+        chain_id: tx.chain_id.toNumber(),
         signature: signature
     }
 
@@ -271,14 +284,16 @@ function toApiForm(tx, sig) {
 
 }
 
-function createTransaction(from, to, amount, fee, nonce, good_until_block, privateKey) {
+function createTransaction(from, to, amount, fee, nonce, good_until_block, privateKey, token = 0, chainId = 0) {
     let tx = {
         from: new BN(from),
         to: new BN(to),
+        token: new BN(token),
         amount: new BN(amount),
         fee: new BN(fee),
         nonce: new BN(nonce),
-        good_until_block: new BN(good_until_block)
+        good_until_block: new BN(good_until_block),
+        chain_id: new BN(chainId)
     };
 
     const serializedTx = serializeTransaction(tx);
@@ -293,14 +308,16 @@ function createTransaction(from, to, amount, fee, nonce, good_until_block, priva
     return apiForm;
 }
 
-function createRawTransaction(from, to, amount, fee, nonce, good_until_block, privateKey) {
+function createRawTransaction(from, to, amount, fee, nonce, good_until_block, privateKey, token = 0, chainId = 0) {
     let tx = {
         from: new BN(from),
         to: new BN(to),
+        token: new BN(token),
         amount: new BN(amount),
         fee: new BN(fee),
         nonce: new BN(nonce),
-        good_until_block: new BN(good_until_block)
+        good_until_block: new BN(good_until_block),
+        chain_id: new BN(chainId)
     };
 
     const serializedTx = serializeTransaction(tx);
@@ -0,0 +1,108 @@
+use bellman;
+
+use time::PreciseTime;
+
+use ff::PrimeField;
+use pairing::bn256::*;
+use rand::OsRng;
+use sapling_crypto::alt_babyjubjub::AltJubjubBn256;
+
+use bellman::groth16::generate_random_parameters;
+
+use crate::vk_contract_generator::generate_vk_contract;
+
+use circuit::cheque::cheque_request::ChequeRequest;
+use circuit::cheque::circuit::{Cheque, ChequeWitness};
+use circuit::keys::{read_parameters, write_parameters};
+use circuit::leaf::LeafWitness;
+use models::config::RUNTIME_CONFIG;
+use models::plasma::params as plasma_constants;
+
+const CHEQUE_BATCH_SIZE: usize = 1;
+const FILENAME: &str = "keys/cheque_pk.key";
+const CONTRACT_FILENAME: &str = "ChequeVerificationKey.sol";
+const CONTRACT_NAME: &str = "ChequeVerificationKey";
+const CONTRACT_FUNCTION_NAME: &str = "getVkChequeCircuit";
+
+pub fn make_cheque_key() {
+    let params = &AltJubjubBn256::new();
+    let rng = &mut OsRng::new().unwrap();
+
+    let empty_request = ChequeRequest {
+        from: None,
+        to: None,
+        token: None,
+        amount: None,
+        serial: None,
+        good_until_block: None,
+        chain_id: None,
+        signature: None,
+    };
+
+    let empty_leaf_witness = LeafWitness {
+        balance: None,
+        balance_path: vec![None; plasma_constants::TOKEN_BIT_WIDTH],
+        nonce: None,
+        pub_x: None,
+        pub_y: None,
+        pub_key_hash: None,
+        cheque_bits: None,
+        cheque_start: None,
+        lock_commitment: None,
+    };
+
+    let empty_witness = ChequeWitness {
+        leaf_from: empty_leaf_witness.clone(),
+        auth_path_from: vec![None; plasma_constants::BALANCE_TREE_DEPTH],
+        leaf_to: empty_leaf_witness,
+        auth_path_to: vec![None; plasma_constants::BALANCE_TREE_DEPTH],
+    };
+
+    let instance_for_generation: Cheque<'_, Bn256> = Cheque {
+        params,
+        number_of_cheques: CHEQUE_BATCH_SIZE,
+        chain_id: Fr::from_str(&RUNTIME_CONFIG.chain_id.to_string()).unwrap(),
+        old_root: None,
+        new_root: None,
+        public_data_commitment: None,
+        block_number: None,
+        requests: vec![(empty_request, empty_witness); CHEQUE_BATCH_SIZE],
+    };
+
+    info!("generating setup...");
+    let start = PreciseTime::now();
+    let tmp_cirtuit_params = generate_random_parameters(instance_for_generation, rng).unwrap();
+    info!(
+        "setup generated in {} s",
+        start.to(PreciseTime::now()).num_milliseconds() as f64 / 1000.0
+    );
+
+    use std::fs::File;
+    use std::io::{BufWriter, Write};
+    {
+        let f = File::create(FILENAME).expect("Unable to create file");
+        let mut f = BufWriter::new(f);
+        write_parameters(&tmp_cirtuit_params, &mut f).expect("Unable to write proving key");
+    }
+
+    use std::io::BufReader;
+
+    let f_r = File::open(FILENAME).expect("Unable to open file");
+    let mut r = BufReader::new(f_r);
+    let circuit_params: bellman::groth16::Parameters<Bn256> =
+        read_parameters(&mut r, true).expect("Unable to read proving key");
+
+    let contract_content = generate_vk_contract(
+        &circuit_params.vk,
+        CONTRACT_NAME.to_string(),
+        CONTRACT_FUNCTION_NAME.to_string(),
+    );
+
+    let f_cont = File::create(CONTRACT_FILENAME).expect("Unable to create file");
+    let mut f_cont = BufWriter::new(f_cont);
+    f_cont
+        .write_all(contract_content.as_bytes())
+        .expect("Unable to write contract");
+
+    info!("Done");
+}
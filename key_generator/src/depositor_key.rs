@@ -12,6 +12,7 @@ use crate::vk_contract_generator::generate_vk_contract;
 
 use circuit::deposit::circuit::{Deposit, DepositWitness};
 use circuit::deposit::deposit_request::DepositRequest;
+use circuit::keys::{read_parameters, write_parameters};
 use circuit::leaf::LeafWitness;
 use models::plasma::params as plasma_constants;
 
@@ -29,15 +30,21 @@ pub fn make_depositor_key() {
 
     let empty_request = DepositRequest {
         into: None,
+        token: None,
         amount: None,
         public_key: None,
     };
 
     let empty_leaf_witness = LeafWitness {
         balance: None,
+        balance_path: vec![None; plasma_constants::TOKEN_BIT_WIDTH],
         nonce: None,
         pub_x: None,
         pub_y: None,
+        pub_key_hash: None,
+        cheque_bits: None,
+        cheque_start: None,
+        lock_commitment: None,
     };
 
     let empty_witness = DepositWitness {
@@ -71,17 +78,15 @@ pub fn make_depositor_key() {
     {   
         let f = File::create(FILENAME).expect("Unable to create file");
         let mut f = BufWriter::new(f);
-        tmp_cirtuit_params
-            .write(&mut f)
-            .expect("Unable to write proving key");
+        write_parameters(&tmp_cirtuit_params, &mut f).expect("Unable to write proving key");
     }
 
     use std::io::BufReader;
 
     let f_r = File::open(FILENAME).expect("Unable to open file");
     let mut r = BufReader::new(f_r);
-    let circuit_params = bellman::groth16::Parameters::<Bn256>::read(&mut r, true)
-        .expect("Unable to read proving key");
+    let circuit_params: bellman::groth16::Parameters<Bn256> =
+        read_parameters(&mut r, true).expect("Unable to read proving key");
 
     let contract_content = generate_vk_contract(
         &circuit_params.vk,
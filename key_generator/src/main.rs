@@ -1,15 +1,23 @@
 #[macro_use]
 extern crate log;
 
+pub mod change_pubkey_key;
+pub mod cheque_key;
 pub mod depositor_key;
 pub mod exitor_key;
+pub mod lock_key;
 pub mod read_write_keys;
 pub mod transactor_key;
+pub mod unlock_key;
 pub mod vk_contract_generator;
 
+use change_pubkey_key::make_change_pubkey_key;
+use cheque_key::make_cheque_key;
 use depositor_key::make_depositor_key;
 use exitor_key::make_exitor_key;
+use lock_key::make_lock_key;
 use transactor_key::make_transactor_key;
+use unlock_key::make_unlock_key;
 
 fn main() {
     env_logger::init();
@@ -21,4 +29,8 @@ fn main() {
     make_depositor_key();
     make_exitor_key();
     make_transactor_key();
+    make_change_pubkey_key();
+    make_cheque_key();
+    make_lock_key();
+    make_unlock_key();
 }
@@ -26,7 +26,9 @@ use sapling_crypto::circuit::float_point::convert_to_float;
 use sapling_crypto::eddsa::{PrivateKey, PublicKey};
 use sapling_crypto::jubjub::FixedGenerators;
 
-use models::plasma::circuit::account::CircuitAccount;
+use models::config::RUNTIME_CONFIG;
+use models::plasma::circuit::account::{leaf_pub_key_hash, CircuitAccount};
+use models::plasma::circuit::sig::TransactionSignature;
 use models::plasma::circuit::utils::{be_bit_vector_into_bytes, le_bit_vector_into_field_element};
 use models::plasma::params as plasma_constants;
 
@@ -37,6 +39,7 @@ pub fn read_write_keys() {
     let p_g = FixedGenerators::SpendingKeyGenerator;
     let params = &AltJubjubBn256::new();
     let rng = &mut XorShiftRng::from_seed([0x3dbe_6258, 0x8d31_3d76, 0x3237_db17, 0xe5bc_0654]);
+    let chain_id = Fr::from_str(&RUNTIME_CONFIG.chain_id.to_string()).unwrap();
     let tree_depth = plasma_constants::BALANCE_TREE_DEPTH as u32;
 
     let capacity: u32 = 1 << tree_depth;
@@ -50,6 +53,7 @@ pub fn read_write_keys() {
     let mut existing_account_hm = HashMap::<u32, bool>::new();
 
     let default_balance_string = "1000000";
+    let token = plasma_constants::ETH_TOKEN_ID;
     let transfer_amount: u128 = 1000;
     let fee_amount: u128 = 0;
 
@@ -68,12 +72,13 @@ pub fn read_write_keys() {
 
         existing_accounts.push((leaf_number, sk, pk));
 
-        let leaf = CircuitAccount {
-            balance: Fr::from_str(default_balance_string).unwrap(),
+        let mut leaf = CircuitAccount::<Bn256> {
             nonce: Fr::zero(),
             pub_x: x,
             pub_y: y,
+            ..Default::default()
         };
+        leaf.set_balance(token, Fr::from_str(default_balance_string).unwrap(), params);
 
         tree.insert(leaf_number, leaf.clone());
     }
@@ -130,10 +135,8 @@ pub fn read_write_keys() {
         let sender_leaf_number = sender_account_info.0;
         let recipient_leaf_number = recipient_account_info.0;
 
-        let items = tree.items.clone();
-
-        let sender_leaf = items.get(&sender_leaf_number).unwrap().clone();
-        let recipient_leaf = items.get(&recipient_leaf_number).unwrap().clone();
+        let sender_leaf = tree.get(sender_leaf_number).unwrap();
+        let recipient_leaf = tree.get(recipient_leaf_number).unwrap();
 
         let path_from: Vec<Option<Fr>> = tree
             .merkle_path(sender_leaf_number)
@@ -154,11 +157,20 @@ pub fn read_write_keys() {
         let mut transaction: Transaction<Bn256> = Transaction {
             from,
             to,
+            token: Fr::from_str(&token.to_string()),
             amount: Some(transfer_amount_encoded),
             fee: Some(fee_encoded),
             nonce: Some(sender_leaf.nonce),
             good_until_block: Some(Fr::one()),
+            chain_id: Some(chain_id),
             signature: None,
+            multisig_signatures: vec![
+                Some(TransactionSignature::empty(params));
+                plasma_constants::MULTISIG_MAX_SIGNERS
+            ],
+            to_new_account: Some(false),
+            new_pub_x: Some(Fr::zero()),
+            new_pub_y: Some(Fr::zero()),
         };
 
         let sender_sk = &sender_account_info.1;
@@ -176,18 +188,16 @@ pub fn read_write_keys() {
         let mut updated_sender_leaf = sender_leaf.clone();
         let mut updated_recipient_leaf = recipient_leaf.clone();
 
-        updated_sender_leaf
-            .balance
-            .sub_assign(&transfer_amount_as_field_element);
-        updated_sender_leaf
-            .balance
-            .sub_assign(&fee_as_field_element);
+        let mut sender_balance = updated_sender_leaf.get_balance(token);
+        sender_balance.sub_assign(&transfer_amount_as_field_element);
+        sender_balance.sub_assign(&fee_as_field_element);
+        updated_sender_leaf.set_balance(token, sender_balance, params);
 
         updated_sender_leaf.nonce.add_assign(&Fr::one());
 
-        updated_recipient_leaf
-            .balance
-            .add_assign(&transfer_amount_as_field_element);
+        let mut recipient_balance = updated_recipient_leaf.get_balance(token);
+        recipient_balance.add_assign(&transfer_amount_as_field_element);
+        updated_recipient_leaf.set_balance(token, recipient_balance, params);
 
         total_fees.add_assign(&fee_as_field_element);
 
@@ -204,17 +214,36 @@ pub fn read_write_keys() {
         public_data_vector.extend(public_data.into_iter());
 
         let leaf_witness_from = LeafWitness {
-            balance: Some(sender_leaf.balance),
+            balance: Some(sender_leaf.get_balance(token)),
+            balance_path: sender_leaf
+                .balance_path(token, params)
+                .into_iter()
+                .map(Some)
+                .collect(),
             nonce: Some(sender_leaf.nonce),
             pub_x: Some(sender_leaf.pub_x),
             pub_y: Some(sender_leaf.pub_y),
+            pub_key_hash: leaf_pub_key_hash(&sender_leaf.pub_x, &sender_leaf.pub_y),
+            cheque_bits: Some(sender_leaf.cheque_bits),
+            cheque_start: Some(sender_leaf.cheque_start),
+            lock_commitment: Some(sender_leaf.lock_commitment),
         };
 
         let leaf_witness_to = LeafWitness {
-            balance: Some(recipient_leaf.balance),
+            balance: Some(recipient_leaf.get_balance(token)),
+            balance_path: recipient_leaf
+                .balance_path(token, params)
+                .into_iter()
+                .map(Some)
+                .collect(),
             nonce: Some(recipient_leaf.nonce),
-            pub_x: Some(recipient_leaf.pub_x),
-            pub_y: Some(recipient_leaf.pub_y),
+            // a compact leaf only needs the hash of a key that is not used
+            pub_x: Some(recipient_leaf.pub_x).filter(|_| !plasma_constants::COMPACT_LEAF),
+            pub_y: Some(recipient_leaf.pub_y).filter(|_| !plasma_constants::COMPACT_LEAF),
+            pub_key_hash: leaf_pub_key_hash(&recipient_leaf.pub_x, &recipient_leaf.pub_y),
+            cheque_bits: Some(recipient_leaf.cheque_bits),
+            cheque_start: Some(recipient_leaf.cheque_start),
+            lock_commitment: Some(recipient_leaf.lock_commitment),
         };
 
         let transaction_witness = TransactionWitness {
@@ -222,6 +251,13 @@ pub fn read_write_keys() {
             auth_path_from: path_from,
             leaf_to: leaf_witness_to,
             auth_path_to: path_to,
+            is_multisig: Some(false),
+            multisig_threshold: Some(Fr::zero()),
+            multisig_keys: vec![
+                (Some(Fr::zero()), Some(Fr::one()));
+                plasma_constants::MULTISIG_MAX_SIGNERS
+            ],
+            multisig_signed: vec![Some(false); plasma_constants::MULTISIG_MAX_SIGNERS],
         };
 
         let witness = (transaction.clone(), transaction_witness);
@@ -306,6 +342,7 @@ pub fn read_write_keys() {
     let instance_for_test_cs = Transfer {
         params,
         number_of_transactions: TXES_TO_TEST,
+        chain_id,
         old_root: Some(initial_root),
         new_root: Some(final_root),
         public_data_commitment: Some(public_data_commitment),
@@ -350,18 +387,29 @@ pub fn read_write_keys() {
     let empty_transaction = Transaction {
         from: None,
         to: None,
+        token: None,
         amount: None,
         fee: None,
         nonce: None,
         good_until_block: None,
+        chain_id: None,
         signature: None,
+        multisig_signatures: vec![None; plasma_constants::MULTISIG_MAX_SIGNERS],
+        to_new_account: None,
+        new_pub_x: None,
+        new_pub_y: None,
     };
 
     let empty_leaf_witness = LeafWitness {
         balance: None,
+        balance_path: vec![None; plasma_constants::TOKEN_BIT_WIDTH],
         nonce: None,
         pub_x: None,
         pub_y: None,
+        pub_key_hash: None,
+        cheque_bits: None,
+        cheque_start: None,
+        lock_commitment: None,
     };
 
     let empty_witness = TransactionWitness {
@@ -369,11 +417,16 @@ pub fn read_write_keys() {
         auth_path_from: vec![None; plasma_constants::BALANCE_TREE_DEPTH],
         leaf_to: empty_leaf_witness,
         auth_path_to: vec![None; plasma_constants::BALANCE_TREE_DEPTH],
+        is_multisig: None,
+        multisig_threshold: None,
+        multisig_keys: vec![(None, None); plasma_constants::MULTISIG_MAX_SIGNERS],
+        multisig_signed: vec![None; plasma_constants::MULTISIG_MAX_SIGNERS],
     };
 
     let instance_for_generation: Transfer<'_, Bn256> = Transfer {
         params,
         number_of_transactions: TXES_TO_TEST,
+        chain_id,
         old_root: None,
         new_root: None,
         public_data_commitment: None,
@@ -425,6 +478,7 @@ pub fn read_write_keys() {
     let instance_for_proof = Transfer {
         params,
         number_of_transactions: TXES_TO_TEST,
+        chain_id,
         old_root: Some(initial_root),
         new_root: Some(final_root),
         public_data_commitment: Some(public_data_commitment),
@@ -2,6 +2,7 @@ use bellman;
 
 use time::PreciseTime;
 
+use ff::PrimeField;
 use pairing::bn256::*;
 use rand::OsRng;
 use sapling_crypto::alt_babyjubjub::AltJubjubBn256;
@@ -10,9 +11,11 @@ use bellman::groth16::generate_random_parameters;
 
 use crate::vk_contract_generator::generate_vk_contract;
 
+use circuit::keys::{read_parameters, write_parameters};
 use circuit::leaf::LeafWitness;
 use circuit::transfer::circuit::{TransactionWitness, Transfer};
 use circuit::transfer::transaction::Transaction;
+use models::config::RUNTIME_CONFIG;
 use models::plasma::params as plasma_constants;
 
 const TRANSFER_BATCH_SIZE: usize = 8;
@@ -30,18 +33,29 @@ pub fn make_transactor_key() {
     let empty_transaction = Transaction {
         from: None,
         to: None,
+        token: None,
         amount: None,
         fee: None,
         nonce: None,
         good_until_block: None,
+        chain_id: None,
         signature: None,
+        multisig_signatures: vec![None; plasma_constants::MULTISIG_MAX_SIGNERS],
+        to_new_account: None,
+        new_pub_x: None,
+        new_pub_y: None,
     };
 
     let empty_leaf_witness = LeafWitness {
         balance: None,
+        balance_path: vec![None; plasma_constants::TOKEN_BIT_WIDTH],
         nonce: None,
         pub_x: None,
         pub_y: None,
+        pub_key_hash: None,
+        cheque_bits: None,
+        cheque_start: None,
+        lock_commitment: None,
     };
 
     let empty_witness = TransactionWitness {
@@ -49,11 +63,16 @@ pub fn make_transactor_key() {
         auth_path_from: vec![None; plasma_constants::BALANCE_TREE_DEPTH],
         leaf_to: empty_leaf_witness,
         auth_path_to: vec![None; plasma_constants::BALANCE_TREE_DEPTH],
+        is_multisig: None,
+        multisig_threshold: None,
+        multisig_keys: vec![(None, None); plasma_constants::MULTISIG_MAX_SIGNERS],
+        multisig_signed: vec![None; plasma_constants::MULTISIG_MAX_SIGNERS],
     };
 
     let instance_for_generation: Transfer<'_, Bn256> = Transfer {
         params,
         number_of_transactions: TRANSFER_BATCH_SIZE,
+        chain_id: Fr::from_str(&RUNTIME_CONFIG.chain_id.to_string()).unwrap(),
         old_root: None,
         new_root: None,
         public_data_commitment: None,
@@ -75,17 +94,15 @@ pub fn make_transactor_key() {
     {
         let f = File::create(FILENAME).expect("Unable to create file");
         let mut f = BufWriter::new(f);
-        tmp_cirtuit_params
-            .write(&mut f)
-            .expect("Unable to write proving key");
+        write_parameters(&tmp_cirtuit_params, &mut f).expect("Unable to write proving key");
     }
 
     use std::io::BufReader;
 
     let f_r = File::open(FILENAME).expect("Unable to open file");
     let mut r = BufReader::new(f_r);
-    let circuit_params = bellman::groth16::Parameters::<Bn256>::read(&mut r, true)
-        .expect("Unable to read proving key");
+    let circuit_params: bellman::groth16::Parameters<Bn256> =
+        read_parameters(&mut r, true).expect("Unable to read proving key");
 
     let contract_content = generate_vk_contract(
         &circuit_params.vk,
@@ -0,0 +1,105 @@
+use bellman;
+
+use time::PreciseTime;
+
+use pairing::bn256::*;
+use rand::OsRng;
+use sapling_crypto::alt_babyjubjub::AltJubjubBn256;
+
+use bellman::groth16::generate_random_parameters;
+
+use crate::vk_contract_generator::generate_vk_contract;
+
+use circuit::keys::{read_parameters, write_parameters};
+use circuit::leaf::LeafWitness;
+use circuit::unlock::circuit::{Unlock, UnlockWitness};
+use circuit::unlock::unlock_request::UnlockRequest;
+use models::plasma::params as plasma_constants;
+
+const UNLOCK_BATCH_SIZE: usize = 1;
+const FILENAME: &str = "keys/unlock_pk.key";
+const CONTRACT_FILENAME: &str = "UnlockVerificationKey.sol";
+const CONTRACT_NAME: &str = "UnlockVerificationKey";
+const CONTRACT_FUNCTION_NAME: &str = "getVkUnlockCircuit";
+
+pub fn make_unlock_key() {
+    let params = &AltJubjubBn256::new();
+    let rng = &mut OsRng::new().unwrap();
+
+    let empty_request = UnlockRequest {
+        account: None,
+        is_claim: None,
+        preimage: None,
+    };
+
+    let empty_leaf_witness = LeafWitness {
+        balance: None,
+        balance_path: vec![None; plasma_constants::TOKEN_BIT_WIDTH],
+        nonce: None,
+        pub_x: None,
+        pub_y: None,
+        pub_key_hash: None,
+        cheque_bits: None,
+        cheque_start: None,
+        lock_commitment: None,
+    };
+
+    let empty_witness = UnlockWitness {
+        leaf_from: empty_leaf_witness.clone(),
+        auth_path_from: vec![None; plasma_constants::BALANCE_TREE_DEPTH],
+        lock_to: None,
+        lock_token: None,
+        lock_amount: None,
+        lock_hash: None,
+        lock_good_until_block: None,
+        leaf_to: empty_leaf_witness,
+        auth_path_to: vec![None; plasma_constants::BALANCE_TREE_DEPTH],
+    };
+
+    let instance_for_generation: Unlock<'_, Bn256> = Unlock {
+        params,
+        number_of_unlocks: UNLOCK_BATCH_SIZE,
+        old_root: None,
+        new_root: None,
+        public_data_commitment: None,
+        block_number: None,
+        requests: vec![(empty_request, empty_witness); UNLOCK_BATCH_SIZE],
+    };
+
+    info!("generating setup...");
+    let start = PreciseTime::now();
+    let tmp_cirtuit_params = generate_random_parameters(instance_for_generation, rng).unwrap();
+    info!(
+        "setup generated in {} s",
+        start.to(PreciseTime::now()).num_milliseconds() as f64 / 1000.0
+    );
+
+    use std::fs::File;
+    use std::io::{BufWriter, Write};
+    {
+        let f = File::create(FILENAME).expect("Unable to create file");
+        let mut f = BufWriter::new(f);
+        write_parameters(&tmp_cirtuit_params, &mut f).expect("Unable to write proving key");
+    }
+
+    use std::io::BufReader;
+
+    let f_r = File::open(FILENAME).expect("Unable to open file");
+    let mut r = BufReader::new(f_r);
+    let circuit_params: bellman::groth16::Parameters<Bn256> =
+        read_parameters(&mut r, true).expect("Unable to read proving key");
+
+    let contract_content = generate_vk_contract(
+        &circuit_params.vk,
+        CONTRACT_NAME.to_string(),
+        CONTRACT_FUNCTION_NAME.to_string(),
+    );
+
+    let f_cont = File::create(CONTRACT_FILENAME).expect("Unable to create file");
+    let mut f_cont = BufWriter::new(f_cont);
+    f_cont
+        .write_all(contract_content.as_bytes())
+        .expect("Unable to write contract");
+
+    info!("Done");
+}
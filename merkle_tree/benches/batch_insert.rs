@@ -0,0 +1,43 @@
+// Loading of the account tree at startup: one by one inserts vs a batch insert
+// cargo bench -p merkle_tree --bench batch_insert, BENCH_ACCOUNTS sets the number of accounts
+
+use merkle_tree::AccountTree;
+use models::plasma::account::Account;
+use models::plasma::params;
+use std::env;
+use std::time::Instant;
+
+fn accounts(count: u32) -> Vec<(u32, Account)> {
+    (0..count)
+        .map(|id| {
+            let mut account = Account::default();
+            account.nonce = id;
+            (id, account)
+        })
+        .collect()
+}
+
+fn main() {
+    let count = env::var("BENCH_ACCOUNTS")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(100_000);
+    let tree_depth = params::BALANCE_TREE_DEPTH as u32;
+
+    let started = Instant::now();
+    let mut sequential = AccountTree::new(tree_depth);
+    for (id, account) in accounts(count) {
+        sequential.insert(id, account);
+    }
+    let sequential_time = started.elapsed();
+
+    let started = Instant::now();
+    let mut batched = AccountTree::new(tree_depth);
+    batched.insert_batch(accounts(count));
+    let batched_time = started.elapsed();
+
+    assert_eq!(sequential.root_hash(), batched.root_hash());
+    println!("{} accounts", count);
+    println!("one by one: {:?}", sequential_time);
+    println!("batch:      {:?}", batched_time);
+}
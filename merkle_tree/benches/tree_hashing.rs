@@ -0,0 +1,59 @@
+// Hashing work of the account tree: creating trees, inserting new accounts, updating them
+// and loading the same accounts into another tree, as the state keeper and the prover do.
+// cargo bench -p merkle_tree --bench tree_hashing, BENCH_ACCOUNTS sets the number of accounts
+
+use merkle_tree::AccountTree;
+use models::plasma::account::Account;
+use models::plasma::params;
+use std::env;
+use std::time::Instant;
+
+const NEW_TREES: usize = 1000;
+
+// a different leaf for every id and update
+fn account(id: u32, update: u32) -> Account {
+    let mut account = Account::default();
+    account.nonce = id * 2 + update;
+    account
+}
+
+fn main() {
+    let count = env::var("BENCH_ACCOUNTS")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(10_000);
+    let tree_depth = params::BALANCE_TREE_DEPTH as u32;
+
+    let started = Instant::now();
+    for _ in 0..NEW_TREES {
+        AccountTree::new(tree_depth);
+    }
+    let new_tree_time = started.elapsed();
+
+    let mut tree = AccountTree::new(tree_depth);
+    let started = Instant::now();
+    for id in 0..count {
+        tree.insert(id, account(id, 0));
+    }
+    let insert_time = started.elapsed();
+
+    let started = Instant::now();
+    for id in 0..count {
+        tree.insert(id, account(id, 1));
+    }
+    let update_time = started.elapsed();
+
+    let mut reloaded = AccountTree::new(tree_depth);
+    let started = Instant::now();
+    for id in 0..count {
+        reloaded.insert(id, account(id, 1));
+    }
+    let reload_time = started.elapsed();
+
+    assert_eq!(tree.root_hash(), reloaded.root_hash());
+    println!("{} accounts", count);
+    println!("{} new trees: {:?}", NEW_TREES, new_tree_time);
+    println!("insert:        {:?}", insert_time);
+    println!("update:        {:?}", update_time);
+    println!("reload:        {:?}", reload_time);
+}
@@ -1,6 +1,51 @@
 // Hasher trait
 
+use models::primitives::GetBits;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
 pub trait Hasher<Hash> {
+    /// Tells hashers apart in the header of a stored tree, see `node_store::StoreHeader`
+    const ID: &'static str;
+
     fn hash_bits<I: IntoIterator<Item = bool>>(&self, value: I) -> Hash;
     fn compress(&self, lhs: &Hash, rhs: &Hash, i: usize) -> Hash;
 }
+
+// hasher, leaf type and depth
+type EmptyHashesKey = (TypeId, TypeId, u32);
+
+lazy_static! {
+    static ref EMPTY_HASHES: Mutex<HashMap<EmptyHashesKey, Box<dyn Any + Send + Sync>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Hashes of the empty subtrees of a tree, the root first and an empty leaf last.
+/// They are computed once per hasher, leaf type and depth, a new tree of the same kind shares them
+pub fn empty_hashes<T, Hash, H>(tree_depth: u32) -> Arc<Vec<Hash>>
+where
+    T: GetBits + Default + 'static,
+    Hash: Clone + Send + Sync + 'static,
+    H: Hasher<Hash> + Default + 'static,
+{
+    let key = (TypeId::of::<H>(), TypeId::of::<T>(), tree_depth);
+    let mut computed = EMPTY_HASHES.lock().expect("empty hashes lock poisoned");
+    computed
+        .entry(key)
+        .or_insert_with(|| {
+            let hasher = H::default();
+            let mut hashes = Vec::with_capacity((tree_depth + 1) as usize);
+            let mut cur = hasher.hash_bits(T::default().get_bits_le());
+            hashes.push(cur.clone());
+            for i in 0..tree_depth {
+                cur = hasher.compress(&cur, &cur, i as usize);
+                hashes.push(cur.clone());
+            }
+            hashes.reverse();
+            Box::new(Arc::new(hashes))
+        })
+        .downcast_ref::<Arc<Vec<Hash>>>()
+        .expect("empty hashes of another hash type")
+        .clone()
+}
@@ -1,41 +1,153 @@
+#[macro_use]
+extern crate lazy_static;
+
 pub mod hasher;
+pub mod node_store;
 pub mod parallel_smt;
 pub mod pedersen_hasher;
+pub mod poseidon_hasher;
 pub mod sequential_smt;
 
 use models::plasma::account::Account;
+use models::plasma::params;
 use models::plasma::{Engine, Fr};
+use models::primitives::GetBits;
 
+// Both trees have the same interface and hashes, the `parallel_smt` feature switches the backend
+#[cfg(not(feature = "parallel_smt"))]
 pub type SparseMerkleTree<T, H, HH> = sequential_smt::SparseMerkleTree<T, H, HH>;
+#[cfg(feature = "parallel_smt")]
+pub type SparseMerkleTree<T, H, HH> = parallel_smt::SparseMerkleTree<T, H, HH>;
 pub type PedersenHasher<T> = pedersen_hasher::PedersenHasher<T>;
+pub type PoseidonHasher<T> = poseidon_hasher::PoseidonHasher<T>;
+
+/// Hasher of the account tree: Pedersen, or Poseidon with the `poseidon_tree` feature of this
+/// crate. The circuits hash account leaves and tree nodes with the same one, the balance subtree
+/// and the other hashes in a leaf stay Pedersen. Trees, circuits and keys of the two hashers
+/// are not compatible
+pub const POSEIDON_ACCOUNT_TREE: bool = cfg!(feature = "poseidon_tree");
+
+#[cfg(not(feature = "poseidon_tree"))]
+pub type AccountHasher = PedersenHasher<Engine>;
+#[cfg(feature = "poseidon_tree")]
+pub type AccountHasher = PoseidonHasher<Engine>;
+
+pub type AccountTree = SparseMerkleTree<Account, Fr, AccountHasher>;
+
+/// Account tree with O(1) snapshots, always on the sequential backend
+pub type PersistentAccountTree = sequential_smt::SparseMerkleTree<
+    Account,
+    Fr,
+    AccountHasher,
+    node_store::PersistentStore<Account, Fr>,
+>;
+pub type AccountTreeSnapshot =
+    sequential_smt::Snapshot<Account, Fr, node_store::PersistentStore<Account, Fr>>;
+
+/// Account tree kept on disk between restarts, always on the sequential backend
+pub type DiskAccountTree = sequential_smt::SparseMerkleTree<
+    Account,
+    Fr,
+    AccountHasher,
+    node_store::DiskStore<Account, Fr>,
+>;
+
+/// In-memory tree with the leaves of `tree` converted by `f`, which must not change their
+/// hashes, e.g. a `DiskAccountTree` loaded at startup. Nothing is hashed again, but every
+/// item and hash of `tree` is read, so the copy takes as much memory as a tree built in memory
+#[cfg(not(feature = "parallel_smt"))]
+pub fn copy_tree<T, U, S, F>(
+    tree: &sequential_smt::SparseMerkleTree<T, Fr, AccountHasher, S>,
+    f: F,
+) -> SparseMerkleTree<U, Fr, AccountHasher>
+where
+    T: GetBits + Default + 'static,
+    U: GetBits + Default + Clone + 'static,
+    S: node_store::NodeStore<T, Fr>,
+    F: Fn(T) -> U,
+{
+    tree.map_items(f)
+}
+
+/// The parallel backend has no node store to copy into, the items are hashed again
+#[cfg(feature = "parallel_smt")]
+pub fn copy_tree<T, U, S, F>(
+    tree: &sequential_smt::SparseMerkleTree<T, Fr, AccountHasher, S>,
+    f: F,
+) -> SparseMerkleTree<U, Fr, AccountHasher>
+where
+    T: GetBits + Default + 'static,
+    U: GetBits + Default + Sync + 'static,
+    S: node_store::NodeStore<T, Fr>,
+    F: Fn(T) -> U,
+{
+    let mut copy = SparseMerkleTree::new(tree.tree_depth());
+    copy.insert_batch(
+        tree.items()
+            .into_iter()
+            .map(|(index, item)| (index, f(item))),
+    );
+    copy
+}
+
+/// Checks that `account` is at `index` in an account tree with the `root` hash,
+/// e.g. a balance against the root of a verified block. `proof` is the audit path from `merkle_path`
+pub fn verify_proof(index: u32, account: &Account, proof: &[(Fr, bool)], root: &Fr) -> bool {
+    proof.len() == params::BALANCE_TREE_DEPTH
+        && sequential_smt::verify_proof(&AccountHasher::default(), index, account, proof, root)
+}
+
+/// Checks that all `accounts` are in an account tree with the `root` hash, `proof` is
+/// the `multiproof` of their ids. Ids without an account are proven with `Account::default()`
+pub fn verify_multiproof(accounts: &[(u32, Account)], proof: &[Fr], root: &Fr) -> bool {
+    sequential_smt::verify_multiproof(
+        &AccountHasher::default(),
+        params::BALANCE_TREE_DEPTH as u32,
+        accounts,
+        proof,
+        root,
+    )
+}
 
-pub type AccountTree = SparseMerkleTree<Account, Fr, PedersenHasher<Engine>>;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-// TODO: return the code below and uncomment asserts
+    type SequentialTree = sequential_smt::SparseMerkleTree<Account, Fr, AccountHasher>;
+    type ParallelTree = parallel_smt::SparseMerkleTree<Account, Fr, AccountHasher>;
 
-// pub fn verify_proof<E: Account>(&self, index: u32, item: Account, proof: Vec<(E::Fr, bool)>) -> bool {
-//     use crate::sparse_merkle_tree::hasher::Hasher;
+    #[test]
+    fn test_account_tree_backends() {
+        let depth = params::BALANCE_TREE_DEPTH as u32;
+        let mut sequential = SequentialTree::new(depth);
+        let mut parallel = ParallelTree::new(depth);
 
-//     assert!(index < self.capacity());
-//     let item_bits = item.get_bits_le();
-//     let mut hash = self.hasher.hash_bits(item_bits);
-//     let mut proof_index: u32 = 0;
+        for id in &[0, 1, 7, 1 << 20] {
+            let mut account = Account::default();
+            account.nonce = *id + 1;
+            sequential.insert(*id, account.clone());
+            parallel.insert(*id, account);
+        }
+        sequential.delete(7);
+        parallel.delete(7);
 
-//     for (i, e) in proof.clone().into_iter().enumerate() {
-//         if e.1 {
-//             // current is right
-//             proof_index |= 1 << i;
-//             hash = self.hasher.compress(&e.0, &hash, i);
-//         } else {
-//             // current is left
-//             hash = self.hasher.compress(&hash, &e.0, i);
-//         }
-//         // print!("This level hash is {}\n", hash);
-//     }
+        assert_eq!(sequential.root_hash(), parallel.root_hash());
+        assert_eq!(sequential.merkle_path(1), parallel.merkle_path(1));
 
-//     if proof_index != index {
-//         return false;
-//     }
+        let account = parallel.get(1).unwrap();
+        let path = parallel.merkle_path(1);
+        assert!(verify_proof(1, &account, &path, &sequential.root_hash()));
 
-//     hash == self.root_hash()
-// }
+        let proof = parallel.multiproof(&[1, 7, 1 << 20]);
+        let accounts = [
+            (1, account),
+            (7, Account::default()),
+            (1 << 20, parallel.get(1 << 20).unwrap()),
+        ];
+        assert!(verify_multiproof(
+            &accounts,
+            &proof,
+            &sequential.root_hash()
+        ));
+    }
+}
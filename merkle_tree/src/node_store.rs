@@ -0,0 +1,369 @@
+// Storage of the leaves and hashes of the sequential sparse Merkle tree
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Where `sequential_smt::SparseMerkleTree` keeps its items and the hashes of its non-empty nodes.
+/// Positions missing from the store are empty, the tree falls back to the precomputed hashes
+pub trait NodeStore<T, Hash> {
+    fn get_item(&self, index: u32) -> Option<T>;
+    fn set_item(&mut self, index: u32, item: T);
+    fn remove_item(&mut self, index: u32);
+    /// `index` is the packed (level, position) of the node
+    fn get_hash(&self, index: u64) -> Option<Hash>;
+    fn set_hash(&mut self, index: u64, hash: Hash);
+    fn remove_hash(&mut self, index: u64);
+    /// All stored items in no particular order
+    fn items(&self) -> Vec<(u32, T)>;
+    /// All stored hashes in no particular order, by packed index
+    fn hashes(&self) -> Vec<(u64, Hash)>;
+    /// Header of a store that outlives its tree, None for a new one.
+    /// In-memory stores start empty with every tree, so they keep none
+    fn header(&self) -> Option<StoreHeader> {
+        None
+    }
+    fn set_header(&mut self, _header: &StoreHeader) {}
+}
+
+/// What a stored tree was built with, checked by `SparseMerkleTree::with_store`.
+/// A store reopened with another depth, hasher or leaf layout would give wrong roots
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoreHeader {
+    pub tree_depth: u32,
+    /// `Hasher::ID`
+    pub hasher: String,
+    /// Width of the default item, it changes with the leaf layout
+    pub leaf_bits: u32,
+    /// Block whose state the tree holds, see `SparseMerkleTree::set_block_number`.
+    /// None while the tree is being updated, a store left so is not usable
+    pub block_number: Option<u32>,
+}
+
+impl StoreHeader {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&self.tree_depth.to_be_bytes());
+        bytes.extend_from_slice(&self.leaf_bits.to_be_bytes());
+        match self.block_number {
+            Some(block_number) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&block_number.to_be_bytes());
+            }
+            None => bytes.extend_from_slice(&[0; 5]),
+        }
+        bytes.extend_from_slice(self.hasher.as_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 13 {
+            return None;
+        }
+        let word = |at: usize| {
+            let mut word = [0u8; 4];
+            word.copy_from_slice(&bytes[at..at + 4]);
+            u32::from_be_bytes(word)
+        };
+        Some(Self {
+            tree_depth: word(0),
+            leaf_bits: word(4),
+            block_number: if bytes[8] == 1 { Some(word(9)) } else { None },
+            hasher: String::from_utf8(bytes[13..].to_vec()).ok()?,
+        })
+    }
+}
+
+/// Store that `SparseMerkleTree::snapshot` can copy. The copy must not see later writes
+/// to the tree, so a clone that shares its data with the original does not do
+pub trait Snapshotable<T, Hash> {
+    type Snapshot: NodeStore<T, Hash>;
+
+    fn snapshot(&self) -> Self::Snapshot;
+}
+
+/// Everything in memory, the default store
+#[derive(Debug, Clone)]
+pub struct MemoryStore<T, Hash> {
+    pub items: HashMap<u32, T>,
+    pub hashes: HashMap<u64, Hash>,
+}
+
+impl<T, Hash> Default for MemoryStore<T, Hash> {
+    fn default() -> Self {
+        Self {
+            items: HashMap::new(),
+            hashes: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Clone, Hash: Clone> NodeStore<T, Hash> for MemoryStore<T, Hash> {
+    fn get_item(&self, index: u32) -> Option<T> {
+        self.items.get(&index).cloned()
+    }
+
+    fn set_item(&mut self, index: u32, item: T) {
+        self.items.insert(index, item);
+    }
+
+    fn remove_item(&mut self, index: u32) {
+        self.items.remove(&index);
+    }
+
+    fn get_hash(&self, index: u64) -> Option<Hash> {
+        self.hashes.get(&index).cloned()
+    }
+
+    fn set_hash(&mut self, index: u64, hash: Hash) {
+        self.hashes.insert(index, hash);
+    }
+
+    fn remove_hash(&mut self, index: u64) {
+        self.hashes.remove(&index);
+    }
+
+    fn items(&self) -> Vec<(u32, T)> {
+        self.items
+            .iter()
+            .map(|(index, item)| (*index, item.clone()))
+            .collect()
+    }
+
+    fn hashes(&self) -> Vec<(u64, Hash)> {
+        self.hashes
+            .iter()
+            .map(|(index, hash)| (*index, hash.clone()))
+            .collect()
+    }
+}
+
+/// In memory with structural sharing: a clone of the store costs O(1) and only the nodes changed
+/// afterwards are copied, which makes snapshots of the tree cheap
+#[derive(Debug, Clone)]
+pub struct PersistentStore<T: Clone, Hash: Clone> {
+    pub items: im::HashMap<u32, T>,
+    pub hashes: im::HashMap<u64, Hash>,
+}
+
+impl<T: Clone, Hash: Clone> Default for PersistentStore<T, Hash> {
+    fn default() -> Self {
+        Self {
+            items: im::HashMap::new(),
+            hashes: im::HashMap::new(),
+        }
+    }
+}
+
+impl<T: Clone, Hash: Clone> NodeStore<T, Hash> for PersistentStore<T, Hash> {
+    fn get_item(&self, index: u32) -> Option<T> {
+        self.items.get(&index).cloned()
+    }
+
+    fn set_item(&mut self, index: u32, item: T) {
+        self.items.insert(index, item);
+    }
+
+    fn remove_item(&mut self, index: u32) {
+        self.items.remove(&index);
+    }
+
+    fn get_hash(&self, index: u64) -> Option<Hash> {
+        self.hashes.get(&index).cloned()
+    }
+
+    fn set_hash(&mut self, index: u64, hash: Hash) {
+        self.hashes.insert(index, hash);
+    }
+
+    fn remove_hash(&mut self, index: u64) {
+        self.hashes.remove(&index);
+    }
+
+    fn items(&self) -> Vec<(u32, T)> {
+        self.items
+            .iter()
+            .map(|(index, item)| (*index, item.clone()))
+            .collect()
+    }
+
+    fn hashes(&self) -> Vec<(u64, Hash)> {
+        self.hashes
+            .iter()
+            .map(|(index, hash)| (*index, hash.clone()))
+            .collect()
+    }
+}
+
+impl<T: Clone, Hash: Clone> Snapshotable<T, Hash> for MemoryStore<T, Hash> {
+    type Snapshot = Self;
+
+    /// Copies all nodes
+    fn snapshot(&self) -> Self {
+        self.clone()
+    }
+}
+
+impl<T: Clone, Hash: Clone> Snapshotable<T, Hash> for PersistentStore<T, Hash> {
+    type Snapshot = Self;
+
+    /// Shares all nodes with the store, costs O(1)
+    fn snapshot(&self) -> Self {
+        self.clone()
+    }
+}
+
+const ITEM_PREFIX: u8 = b'i';
+const HASH_PREFIX: u8 = b'h';
+const HEADER_KEY: &[u8] = b"m";
+const OPEN_ATTEMPTS: u32 = 50;
+
+/// Items and hashes in an embedded key-value file. Every write goes to the file as it happens,
+/// so reopening the store gives back the tree without rehashing anything.
+/// Only the pages in use are kept in memory. The tree interface is infallible, so I/O and decoding
+/// errors panic. The store keeps a `StoreHeader`, so it is only reopened with the same tree depth
+/// and hasher. It is not `Clone`: a clone would share the file and see every later write
+pub struct DiskStore<T, Hash> {
+    db: sled::Db,
+    _marker: PhantomData<(T, Hash)>,
+}
+
+impl<T, Hash> DiskStore<T, Hash> {
+    /// A store dropped just before still holds the file lock while sled finishes its last
+    /// writes in the background, so opening is retried for a while
+    pub fn open<P: AsRef<Path>>(path: P) -> sled::Result<Self> {
+        let mut attempts = 0;
+        loop {
+            match sled::open(path.as_ref()) {
+                Ok(db) => {
+                    return Ok(Self {
+                        db,
+                        _marker: PhantomData,
+                    })
+                }
+                Err(sled::Error::Io(_)) if attempts < OPEN_ATTEMPTS => {
+                    attempts += 1;
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Waits until all writes so far are durable
+    pub fn flush(&self) -> sled::Result<()> {
+        self.db.flush().map(|_| ())
+    }
+
+    fn key(prefix: u8, index: &[u8]) -> Vec<u8> {
+        let mut key = Vec::with_capacity(1 + index.len());
+        key.push(prefix);
+        key.extend_from_slice(index);
+        key
+    }
+
+    fn get<V: DeserializeOwned>(&self, key: Vec<u8>) -> Option<V> {
+        self.db
+            .get(key)
+            .expect("tree store read failed")
+            .map(|value| serde_json::from_slice(&value).expect("tree store value is corrupted"))
+    }
+
+    fn set<V: Serialize>(&self, key: Vec<u8>, value: &V) {
+        let value = serde_json::to_vec(value).expect("tree store value encoding failed");
+        self.db.insert(key, value).expect("tree store write failed");
+    }
+
+    fn remove(&self, key: Vec<u8>) {
+        self.db.remove(key).expect("tree store write failed");
+    }
+}
+
+impl<T, Hash> NodeStore<T, Hash> for DiskStore<T, Hash>
+where
+    T: Serialize + DeserializeOwned,
+    Hash: Serialize + DeserializeOwned,
+{
+    fn get_item(&self, index: u32) -> Option<T> {
+        self.get(Self::key(ITEM_PREFIX, &index.to_be_bytes()))
+    }
+
+    fn set_item(&mut self, index: u32, item: T) {
+        self.set(Self::key(ITEM_PREFIX, &index.to_be_bytes()), &item);
+    }
+
+    fn remove_item(&mut self, index: u32) {
+        self.remove(Self::key(ITEM_PREFIX, &index.to_be_bytes()));
+    }
+
+    fn get_hash(&self, index: u64) -> Option<Hash> {
+        self.get(Self::key(HASH_PREFIX, &index.to_be_bytes()))
+    }
+
+    fn set_hash(&mut self, index: u64, hash: Hash) {
+        self.set(Self::key(HASH_PREFIX, &index.to_be_bytes()), &hash);
+    }
+
+    fn remove_hash(&mut self, index: u64) {
+        self.remove(Self::key(HASH_PREFIX, &index.to_be_bytes()));
+    }
+
+    fn items(&self) -> Vec<(u32, T)> {
+        self.db
+            .scan_prefix([ITEM_PREFIX])
+            .map(|entry| {
+                let (key, value) = entry.expect("tree store read failed");
+                let mut index = [0u8; 4];
+                index.copy_from_slice(&key[1..]);
+                let item = serde_json::from_slice(&value).expect("tree store value is corrupted");
+                (u32::from_be_bytes(index), item)
+            })
+            .collect()
+    }
+
+    fn hashes(&self) -> Vec<(u64, Hash)> {
+        self.db
+            .scan_prefix([HASH_PREFIX])
+            .map(|entry| {
+                let (key, value) = entry.expect("tree store read failed");
+                let mut index = [0u8; 8];
+                index.copy_from_slice(&key[1..]);
+                let hash = serde_json::from_slice(&value).expect("tree store value is corrupted");
+                (u64::from_be_bytes(index), hash)
+            })
+            .collect()
+    }
+
+    fn header(&self) -> Option<StoreHeader> {
+        self.db
+            .get(HEADER_KEY)
+            .expect("tree store read failed")
+            .map(|bytes| StoreHeader::from_bytes(&bytes).expect("tree store header is corrupted"))
+    }
+
+    fn set_header(&mut self, header: &StoreHeader) {
+        self.db
+            .insert(HEADER_KEY, header.to_bytes())
+            .expect("tree store write failed");
+    }
+}
+
+impl<T, Hash> Snapshotable<T, Hash> for DiskStore<T, Hash>
+where
+    T: Clone + Serialize + DeserializeOwned,
+    Hash: Clone + Serialize + DeserializeOwned,
+{
+    type Snapshot = PersistentStore<T, Hash>;
+
+    /// Reads the whole file into a `PersistentStore`, so unlike the in-memory stores it costs O(n)
+    fn snapshot(&self) -> PersistentStore<T, Hash> {
+        PersistentStore {
+            items: self.items().into_iter().collect(),
+            hashes: self.hashes().into_iter().collect(),
+        }
+    }
+}
@@ -1,12 +1,10 @@
 // Sparse Merkle tree with batch updates
 
-use crate::hasher::Hasher;
+use crate::hasher::{empty_hashes, Hasher};
 use fnv::FnvHashMap;
 use models::primitives::GetBits;
 use std::fmt::Debug;
-
-// use std::time::Duration;
-// use rayon::prelude::*;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 
 fn select<T>(condition: bool, a: T, b: T) -> (T, T) {
     if condition {
@@ -20,8 +18,8 @@ fn select<T>(condition: bool, a: T, b: T) -> (T, T) {
 // We need 2 * TREE_HEIGHT bits
 type NodeIndex = u64;
 
-// Lead index: 0 <= i < N (u64 to avoid conversions; 64 bit HW should be used anyway)
-type ItemIndex = usize;
+// Leaf index: 0 <= i < N, same as in the sequential tree
+type ItemIndex = u32;
 
 // Tree of depth 0: 1 item (which is root), level 0 only
 // Tree of depth 1: 2 items, levels 0 and 1
@@ -39,7 +37,9 @@ pub struct Node {
     right: Option<NodeRef>,
 }
 
-#[derive(Clone)]
+/// Tree with the same interface and the same hashes as `sequential_smt::SparseMerkleTree`.
+/// Only the nodes where the paths to the items fork are stored, hashes are computed on demand
+/// in parallel and cached until an insert below them
 pub struct SparseMerkleTree<T, Hash, H>
 where
     T: GetBits + Default + Sync,
@@ -47,24 +47,50 @@ where
     H: Hasher<Hash> + Sync,
 {
     pub items: FnvHashMap<ItemIndex, T>,
+    pub hasher: H,
 
-    prehashed: Vec<Hash>,
+    prehashed: Arc<Vec<Hash>>,
     tree_depth: Depth,
-    hasher: H,
 
     // intermediate nodes
     root: NodeRef,
     nodes: Vec<Node>,
-    cache: FnvHashMap<NodeIndex, Hash>,
+    // hashes of non-empty positions, filled by `root_hash` so that it can take `&self`
+    cache: RwLock<FnvHashMap<NodeIndex, Hash>>,
 }
 
-impl<T, Hash, H> SparseMerkleTree<T, Hash, H>
+impl<T, Hash, H> Clone for SparseMerkleTree<T, Hash, H>
 where
-    T: GetBits + Default + Sync,
+    T: GetBits + Default + Sync + Clone,
     Hash: Clone + Debug + Sync + Send,
-    H: Hasher<Hash> + Default + Sync,
+    H: Hasher<Hash> + Sync + Clone,
 {
-    pub fn new(tree_depth: Depth) -> Self {
+    fn clone(&self) -> Self {
+        Self {
+            items: self.items.clone(),
+            hasher: self.hasher.clone(),
+            prehashed: self.prehashed.clone(),
+            tree_depth: self.tree_depth,
+            root: self.root,
+            nodes: self.nodes.clone(),
+            cache: RwLock::new(
+                self.cache
+                    .read()
+                    .expect("merkle tree cache lock poisoned")
+                    .clone(),
+            ),
+        }
+    }
+}
+
+impl<T, Hash, H> SparseMerkleTree<T, Hash, H>
+where
+    T: GetBits + Default + Sync + 'static,
+    Hash: Clone + Debug + Sync + Send + 'static,
+    H: Hasher<Hash> + Default + Sync + 'static,
+{
+    pub fn new(tree_depth: u32) -> Self {
+        let tree_depth = tree_depth as Depth;
         assert!(tree_depth > 1);
         let hasher = H::default();
         let items = FnvHashMap::default();
@@ -76,16 +102,9 @@ where
             right: None,
         });
 
-        let mut prehashed = Vec::with_capacity(tree_depth);
-        let mut cur = hasher.hash_bits(T::default().get_bits_le());
-        prehashed.push(cur.clone());
-        for i in 0..tree_depth {
-            cur = hasher.compress(&cur, &cur, i);
-            prehashed.push(cur.clone());
-        }
-        prehashed.reverse();
+        let prehashed = empty_hashes::<T, Hash, H>(tree_depth as u32);
 
-        let cache = FnvHashMap::default();
+        let cache = RwLock::new(FnvHashMap::default());
 
         Self {
             tree_depth,
@@ -109,9 +128,27 @@ where
         level
     }
 
+    pub fn get(&self, index: ItemIndex) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.items.get(&index).cloned()
+    }
+
+    /// All items in no particular order
+    pub fn items(&self) -> Vec<(ItemIndex, T)>
+    where
+        T: Clone,
+    {
+        self.items
+            .iter()
+            .map(|(index, item)| (*index, item.clone()))
+            .collect()
+    }
+
     // How many items can the tree hold
     #[inline(always)]
-    pub fn capacity(&self) -> usize {
+    pub fn capacity(&self) -> u32 {
         1 << self.tree_depth
     }
 
@@ -122,11 +159,22 @@ where
         (1 << (self.tree_depth + 1)) - 1
     }
 
+    fn read_cache(&self) -> RwLockReadGuard<FnvHashMap<NodeIndex, Hash>> {
+        self.cache.read().expect("merkle tree cache lock poisoned")
+    }
+
+    fn cache_mut(&mut self) -> &mut FnvHashMap<NodeIndex, Hash> {
+        self.cache
+            .get_mut()
+            .expect("merkle tree cache lock poisoned")
+    }
+
     fn wipe_cache(&mut self, child: NodeIndex, parent: NodeIndex) {
-        if self.cache.remove(&child).is_some() {
+        let cache = self.cache_mut();
+        if cache.remove(&child).is_some() {
             let mut i = child >> 1;
             while i > parent {
-                self.cache.remove(&i);
+                cache.remove(&i);
                 i >>= 1;
             }
         }
@@ -135,12 +183,12 @@ where
     pub fn insert(&mut self, item_index: ItemIndex, item: T) {
         assert!(item_index < self.capacity());
         let tree_depth = self.tree_depth;
-        let leaf_index: NodeIndex = ((1 << tree_depth) + item_index) as NodeIndex;
+        let leaf_index: NodeIndex = (1 << tree_depth) + NodeIndex::from(item_index);
 
         self.items.insert(item_index, item);
 
         // invalidate root cache
-        self.cache.remove(&1);
+        self.cache_mut().remove(&1);
 
         // traverse the tree
         let mut cur_ref = self.root;
@@ -188,18 +236,76 @@ where
                     );
                     let inter_ref =
                         self.insert_node(inter_index, Self::depth(inter_index), lhs, rhs);
-                    self.add_child_node(cur_ref, dir, inter_ref);
+                    self.set_child_node(cur_ref, dir, Some(inter_ref));
                     break;
                 }
             } else {
                 // insert the leaf node and update cur
                 let leaf_ref = self.insert_node(leaf_index, tree_depth, None, None);
-                self.add_child_node(cur_ref, dir, leaf_ref);
+                self.set_child_node(cur_ref, dir, Some(leaf_ref));
                 break;
             }
         }
     }
 
+    /// Inserts are already deferred here, hashes are only computed by `root_hash`
+    pub fn insert_deferred(&mut self, index: ItemIndex, item: T) {
+        self.insert(index, item);
+    }
+
+    /// Hashes the nodes changed since the last call, as the sequential tree does
+    pub fn recompute_hashes(&mut self) {
+        self.root_hash();
+    }
+
+    pub fn insert_batch<I: IntoIterator<Item = (ItemIndex, T)>>(&mut self, items: I) {
+        for (index, item) in items {
+            self.insert(index, item);
+        }
+    }
+
+    /// Removes the item and its leaf node, a split node left with one child is replaced by it.
+    /// Unlinked nodes stay in `nodes` until the tree is rebuilt
+    pub fn delete(&mut self, index: ItemIndex) {
+        assert!(index < self.capacity());
+        if self.items.remove(&index).is_none() {
+            return;
+        }
+        let tree_depth = self.tree_depth;
+        let leaf_index: NodeIndex = (1 << tree_depth) + NodeIndex::from(index);
+
+        // links followed from the root down to the leaf
+        let mut links = Vec::new();
+        let mut cur_ref = self.root;
+        loop {
+            let cur = &self.nodes[cur_ref];
+            let dir = (leaf_index & (1 << (tree_depth - cur.depth - 1))) > 0;
+            let link = if dir { cur.right } else { cur.left };
+            let next_ref = link.expect("item without a leaf node");
+            links.push((cur_ref, dir));
+            if self.nodes[next_ref].index == leaf_index {
+                break;
+            }
+            cur_ref = next_ref;
+        }
+
+        let (parent_ref, dir) = links.pop().unwrap();
+        self.set_child_node(parent_ref, dir, None);
+        // the root stays even without children
+        if let Some(&(grandparent_ref, parent_dir)) = links.last() {
+            let parent = &self.nodes[parent_ref];
+            let remaining = if dir { parent.left } else { parent.right };
+            self.set_child_node(grandparent_ref, parent_dir, remaining);
+        }
+
+        let cache = self.cache_mut();
+        let mut i = leaf_index;
+        while i > 0 {
+            cache.remove(&i);
+            i >>= 1;
+        }
+    }
+
     fn insert_node(
         &mut self,
         index: NodeIndex,
@@ -216,19 +322,24 @@ where
         self.nodes.len() - 1
     }
 
-    fn add_child_node(&mut self, node_ref: NodeRef, dir: bool, child: NodeRef) {
+    fn set_child_node(&mut self, node_ref: NodeRef, dir: bool, child: Option<NodeRef>) {
         let node = &mut self.nodes[node_ref];
         if dir {
-            node.right = Some(child);
+            node.right = child;
         } else {
-            node.left = Some(child);
+            node.left = child;
         }
     }
 
-    fn get_hash_line(&self, child_ref: NodeRef, parent: &Node) -> (Hash, Vec<(NodeIndex, Hash)>) {
+    fn get_hash_line(
+        &self,
+        cache: &FnvHashMap<NodeIndex, Hash>,
+        child_ref: NodeRef,
+        parent: &Node,
+    ) -> (Hash, Vec<(NodeIndex, Hash)>) {
         let child = &self.nodes[child_ref];
 
-        let acc = self.get_hash(child_ref);
+        let acc = self.node_hash(cache, child_ref);
         let mut cur_hash = acc.0;
         let mut updates = acc.1;
 
@@ -236,9 +347,6 @@ where
         let mut cur_i = child.index;
 
         while cur_depth > parent.depth {
-            unsafe {
-                HC += 1;
-            }
             let swap = (cur_i & 1) == 0;
             let (lhs, rhs) = select(swap, cur_hash, self.prehashed[cur_depth + 1].clone());
             cur_hash = self
@@ -254,18 +362,19 @@ where
 
     fn get_child_hash(
         &self,
+        cache: &FnvHashMap<NodeIndex, Hash>,
         child_ref: Option<NodeRef>,
         parent: &Node,
         dir: usize,
     ) -> (Hash, Vec<(NodeIndex, Hash)>) {
         let neighbour_index = parent.index * 2 + dir as NodeIndex;
-        match self.cache.get(&neighbour_index) {
+        match cache.get(&neighbour_index) {
             Some(cached) => (
                 cached.clone(),
                 Vec::with_capacity((self.tree_depth + 1) * 2),
             ),
             None => match child_ref {
-                Some(child_ref) => self.get_hash_line(child_ref, parent),
+                Some(child_ref) => self.get_hash_line(cache, child_ref, parent),
                 None => (
                     self.prehashed[parent.depth + 1].clone(),
                     Vec::with_capacity((self.tree_depth + 1) * 2),
@@ -274,21 +383,22 @@ where
         }
     }
 
-    fn get_hash(&self, node_ref: NodeRef) -> (Hash, Vec<(NodeIndex, Hash)>) {
+    fn node_hash(
+        &self,
+        cache: &FnvHashMap<NodeIndex, Hash>,
+        node_ref: NodeRef,
+    ) -> (Hash, Vec<(NodeIndex, Hash)>) {
         let node = &self.nodes[node_ref].clone();
         let mut acc = {
             if node.depth == self.tree_depth {
                 // leaf node: return item hash
                 let item_index: ItemIndex = (node.index - (1 << self.tree_depth)) as ItemIndex;
-                unsafe {
-                    HN += 1;
-                }
                 let item_hash = self.hasher.hash_bits(self.items[&item_index].get_bits_le());
                 (item_hash, vec![])
             } else {
                 let (hl, hr) = rayon::join(
-                    || self.get_child_hash(node.left, node, 0),
-                    || self.get_child_hash(node.right, node, 1),
+                    || self.get_child_hash(cache, node.left, node, 0),
+                    || self.get_child_hash(cache, node.right, node, 1),
                 );
 
                 // level is used by hasher for personalization
@@ -305,66 +415,100 @@ where
         acc
     }
 
-    pub fn root_hash(&mut self) -> Hash {
-        let acc = self.get_hash(0);
-        for v in acc.1 {
-            self.cache.insert(v.0, v.1);
-        }
-        acc.0
+    /// Computes the hashes changed since the last call in parallel and caches them
+    pub fn root_hash(&self) -> Hash {
+        let (root, updates) = {
+            let cache = self.read_cache();
+            if let Some(root) = cache.get(&1) {
+                return root.clone();
+            }
+            self.node_hash(&cache, self.root)
+        };
+
+        let mut cache = self.cache.write().expect("merkle tree cache lock poisoned");
+        cache.extend(updates);
+        root
     }
 
-    pub fn reset_stats() {
-        unsafe {
-            HN = 0;
-            HC = 0;
-        }
+    /// Hash at `index.1` on the level `index.0`, the root being on the level 0
+    pub fn get_hash(&self, index: (u32, u32)) -> Hash {
+        let depth = index.0 as Depth;
+        assert!(depth <= self.tree_depth);
+        assert!(u64::from(index.1) < 1 << depth);
+        self.root_hash();
+
+        let node_index = (1 << depth) + NodeIndex::from(index.1);
+        self.read_cache()
+            .get(&node_index)
+            .cloned()
+            .unwrap_or_else(|| self.prehashed[depth].clone())
     }
 
-    pub fn print_stats() {
-        // unsafe {
-        //            debug!("leaf hashes: {}", HN);
-        //            debug!("tree hashes: {}", HC);
-        // }
+    pub fn merkle_path(&self, index: ItemIndex) -> Vec<(Hash, bool)> {
+        assert!(index < self.capacity());
+        // after `root_hash` every non-empty position is cached, anything else is an empty subtree
+        self.root_hash();
+        let cache = self.read_cache();
+
+        let mut node_index = (1 << self.tree_depth) + NodeIndex::from(index);
+        (1..=self.tree_depth)
+            .rev()
+            .map(|depth| {
+                let dir = (node_index & 1) > 0;
+                let hash = cache
+                    .get(&(node_index ^ 1))
+                    .cloned()
+                    .unwrap_or_else(|| self.prehashed[depth].clone());
+                node_index >>= 1;
+                (hash, dir)
+            })
+            .collect()
     }
 
-    pub fn make_a_future(&self) {
+    /// Checks that `item` is at `index` in the current tree, `proof` is a `merkle_path`
+    pub fn verify_proof(&self, index: ItemIndex, item: &T, proof: &[(Hash, bool)]) -> bool
+    where
+        Hash: Eq,
+    {
+        proof.len() == self.tree_depth
+            && crate::sequential_smt::verify_proof(
+                &self.hasher,
+                index,
+                item,
+                proof,
+                &self.root_hash(),
+            )
+    }
 
-        //        let pool = CpuPool::new_num_cpus();
-        //
-        //        let r = thread::scope(|scope| {
-        //            scope.spawn(move |_| {
-        //                debug!("Hello! {:?}", self.root_hash());
-        //                std::thread::sleep(Duration::from_millis(1400));
-        //                debug!("done");
-        //                3 + 5
-        //            });
-        //        }).unwrap();
-        //        debug!("r {:?}", r);
-
-        //        debug!("testing cpu");
-        //        crossbeam_utils::thread::scope(|scope| {
-        //            scope.spawn(move || {
-        //                debug!("begin");
-        //            })
-        //        });
-
-        //        Box::new(self.pool.spawn(future::lazy(move || {
-        //            debug!("begin");
-        //            let r = self.root_hash();
-        //            debug!("end: {:?}", r);
-        //            future::ok::<(), ()>(())
-        //        })))/*.then(|result| {
-        //            debug!("result {:?}", result);
-        //            future::ok::<(), ()>(())
-        //        }));*/
-        //f.wait();
+    /// One proof for all `indices`, see `sequential_smt::verify_multiproof`
+    pub fn multiproof(&self, indices: &[ItemIndex]) -> Vec<Hash> {
+        assert!(indices.iter().all(|index| *index < self.capacity()));
+        self.root_hash();
+        let cache = self.read_cache();
+
+        crate::sequential_smt::multiproof(self.tree_depth as u32, indices, |(depth, index)| {
+            let node_index = (1 << depth) + NodeIndex::from(index);
+            cache
+                .get(&node_index)
+                .cloned()
+                .unwrap_or_else(|| self.prehashed[depth as usize].clone())
+        })
     }
-}
 
-// testing stats
-// TODO: remove this for production
-static mut HN: usize = 0;
-static mut HC: usize = 0;
+    /// Checks that all `items` are in the current tree, `proof` is a `multiproof` of their indices
+    pub fn verify_multiproof(&self, items: &[(ItemIndex, T)], proof: &[Hash]) -> bool
+    where
+        Hash: Eq,
+    {
+        crate::sequential_smt::verify_multiproof(
+            &self.hasher,
+            self.tree_depth as u32,
+            items,
+            proof,
+            &self.root_hash(),
+        )
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -375,7 +519,7 @@ mod tests {
     #[derive(Debug)]
     struct TestHasher {}
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     struct TestLeaf(u64);
 
     impl Default for TestLeaf {
@@ -403,6 +547,8 @@ mod tests {
     }
 
     impl Hasher<u64> for TestHasher {
+        const ID: &'static str = "test";
+
         fn hash_bits<I: IntoIterator<Item = bool>>(&self, value: I) -> u64 {
             let mut acc = 0;
             let v: Vec<bool> = value.into_iter().collect();
@@ -447,18 +593,11 @@ mod tests {
         for _i in 0..3 {
             let mut tree = TestSMT::new(24);
             let capacity = tree.capacity();
-            unsafe {
-                HN = 0;
-                HC = 0;
-            }
             for _j in 0..n {
-                let insert_into = usize::rand(rng) % capacity;
+                let insert_into = u32::rand(rng) % capacity;
                 tree.insert(insert_into, TestLeaf(2));
             }
-            tree.root_hash();
-            unsafe {
-                debug!("{}: HN = {}, HC = {}\n", n, HN, HC);
-            }
+            debug!("{}: root = {}", n, tree.root_hash());
             n *= 10;
         }
     }
@@ -474,19 +613,89 @@ mod tests {
         assert_eq!(tree.root_hash(), 793_215_819);
     }
 
+    type SequentialSMT = crate::sequential_smt::SparseMerkleTree<TestLeaf, u64, TestHasher>;
+
+    #[test]
+    fn test_same_roots_as_sequential() {
+        let rng = &mut thread_rng();
+        let mut tree = TestSMT::new(10);
+        let mut sequential = SequentialSMT::new(10);
+        let capacity = tree.capacity();
+
+        for i in 0..500 {
+            let index = u32::rand(rng) % capacity;
+            if i % 7 == 0 {
+                tree.delete(index);
+                sequential.delete(index);
+            } else {
+                let value = u64::rand(rng) % 1000;
+                tree.insert(index, TestLeaf(value));
+                sequential.insert(index, TestLeaf(value));
+            }
+            if i % 10 == 0 {
+                assert_eq!(tree.root_hash(), sequential.root_hash());
+            }
+        }
+        assert_eq!(tree.root_hash(), sequential.root_hash());
+        assert_eq!(tree.items.len(), sequential.items().len());
+
+        // deleting everything collapses the tree back to the empty one
+        for (index, _) in sequential.items() {
+            tree.delete(index);
+        }
+        assert!(tree.items.is_empty());
+        assert_eq!(tree.root_hash(), TestSMT::new(10).root_hash());
+
+        let mut batched = TestSMT::new(10);
+        batched.insert_batch(
+            (0..capacity)
+                .step_by(3)
+                .map(|i| (i, TestLeaf(u64::from(i)))),
+        );
+        sequential = SequentialSMT::new(10);
+        sequential.insert_batch(
+            (0..capacity)
+                .step_by(3)
+                .map(|i| (i, TestLeaf(u64::from(i)))),
+        );
+        assert_eq!(batched.root_hash(), sequential.root_hash());
+    }
+
     #[test]
-    fn test_cpu_pool() {
-        let tree = TestSMT::new(3);
+    fn test_merkle_path() {
+        let mut tree = TestSMT::new(4);
+        let mut sequential = SequentialSMT::new(4);
+        for &(index, value) in &[(0, 1), (5, 3), (6, 4), (15, 9)] {
+            tree.insert(index, TestLeaf(value));
+            sequential.insert(index, TestLeaf(value));
+        }
 
-        tree.make_a_future();
+        // occupied leaves, empty leaves and leaves next to long lines of empty nodes
+        for index in 0..tree.capacity() {
+            let path = tree.merkle_path(index);
+            assert_eq!(path, sequential.merkle_path(index));
+            let item = tree.items.get(&index).map(|leaf| leaf.0).unwrap_or(0);
+            assert!(tree.verify_proof(index, &TestLeaf(item), &path));
+        }
 
-        //        tree.insert(0,  TestLeaf(1));
-        //        debug!("{}", tree.root_hash());
-        //        debug!("{:?}", tree.prehashed);
-        //        debug!("{:?}", tree.nodes);
-        //
-        //        tree.insert(0, TestLeaf(2));
-        //        debug!("{}", tree.root_hash());
-        //        debug!("{:?}", tree.nodes);
+        // the path must follow inserts below cached nodes
+        tree.insert(4, TestLeaf(2));
+        sequential.insert(4, TestLeaf(2));
+        assert_eq!(tree.merkle_path(5), sequential.merkle_path(5));
+        assert!(!tree.verify_proof(4, &TestLeaf(0), &tree.merkle_path(4)));
+        assert_eq!(tree.get_hash((4, 4)), sequential.get_hash((4, 4)));
+        assert_eq!(tree.get_hash((2, 3)), sequential.get_hash((2, 3)));
+        assert_eq!(tree.get_hash((0, 0)), sequential.root_hash());
+
+        let indices = [15, 4, 5, 9];
+        let proof = tree.multiproof(&indices);
+        assert_eq!(proof, sequential.multiproof(&indices));
+        let items = [
+            (4, TestLeaf(2)),
+            (5, TestLeaf(3)),
+            (9, TestLeaf(0)),
+            (15, TestLeaf(9)),
+        ];
+        assert!(tree.verify_multiproof(&items, &proof));
     }
 }
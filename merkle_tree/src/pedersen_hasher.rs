@@ -4,28 +4,40 @@ use ff::PrimeField;
 use sapling_crypto::pedersen_hash::{baby_pedersen_hash, Personalization};
 
 use pairing::bn256::Bn256;
-use sapling_crypto::alt_babyjubjub::{AltJubjubBn256, JubjubEngine};
+use sapling_crypto::alt_babyjubjub::JubjubEngine;
 
 use crate::hasher::Hasher;
+use models::plasma::params;
 use models::primitives::BitIteratorLe;
 
-pub struct PedersenHasher<E: JubjubEngine> {
-    params: E::Params,
+/// Pedersen hasher over Jubjub parameters shared by the whole process. The parameters hold
+/// the precomputed generator tables, so creating a tree no longer builds them again
+pub struct PedersenHasher<E: JubjubEngine + 'static> {
+    params: &'static E::Params,
 }
 
-impl<E: JubjubEngine> Hasher<E::Fr> for PedersenHasher<E> {
+impl<E: JubjubEngine + 'static> Clone for PedersenHasher<E> {
+    fn clone(&self) -> Self {
+        Self {
+            params: self.params,
+        }
+    }
+}
+
+impl<E: JubjubEngine + 'static> Hasher<E::Fr> for PedersenHasher<E> {
+    const ID: &'static str = "pedersen";
+
     fn hash_bits<I: IntoIterator<Item = bool>>(&self, input: I) -> E::Fr {
-        baby_pedersen_hash::<E, _>(Personalization::NoteCommitment, input, &self.params)
+        baby_pedersen_hash::<E, _>(Personalization::NoteCommitment, input, self.params)
             .into_xy()
             .0
-        // print!("Leaf hash = {}\n", hash.clone());
     }
 
     fn compress(&self, lhs: &E::Fr, rhs: &E::Fr, i: usize) -> E::Fr {
         let lhs = BitIteratorLe::new(lhs.into_repr()).take(E::Fr::NUM_BITS as usize);
         let rhs = BitIteratorLe::new(rhs.into_repr()).take(E::Fr::NUM_BITS as usize);
         let input = lhs.chain(rhs);
-        baby_pedersen_hash::<E, _>(Personalization::MerkleTree(i), input, &self.params)
+        baby_pedersen_hash::<E, _>(Personalization::MerkleTree(i), input, self.params)
             .into_xy()
             .0
     }
@@ -36,7 +48,7 @@ pub type BabyPedersenHasher = PedersenHasher<Bn256>;
 impl Default for PedersenHasher<Bn256> {
     fn default() -> Self {
         Self {
-            params: AltJubjubBn256::new(),
+            params: &params::JUBJUB_PARAMS,
         }
     }
 }
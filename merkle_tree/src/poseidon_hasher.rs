@@ -0,0 +1,241 @@
+// Poseidon hash implementation of the Hasher trait.
+// The account tree and the circuits hash with it instead of Pedersen under the `poseidon_tree`
+// feature, see `AccountHasher`.
+// The parameters are not standard (ChaCha round constants, Cauchy MDS matrix, own domain
+// separation), so the hashes do not match circomlib or the reference Poseidon implementation
+
+use ff::{Field, PrimeField};
+use pairing::bn256::Bn256;
+use pairing::Engine;
+use rand::{ChaChaRng, Rng, SeedableRng};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::hasher::Hasher;
+
+/// Width of the permutation: one capacity element and two rate elements
+pub const WIDTH: usize = 3;
+
+// 128 bit security for a 254 bit field with x^5 S-boxes and WIDTH = 3
+const FULL_ROUNDS: usize = 8;
+const PARTIAL_ROUNDS: usize = 57;
+
+// the round constants and the MDS matrix are derived from this seed, changing it changes every hash
+const PARAMS_SEED: [u32; 4] = [0x706f_7365, 0x6964_6f6e, 0x706c_6173, 0x6d61_0001];
+
+// capacity element of `hash_bits` is LEAF_DOMAIN + number of bits, of `compress` the tree level,
+// so that leaves of any length and nodes of different levels never collide
+const LEAF_DOMAIN: u64 = 1 << 32;
+
+/// Parameters of the Poseidon permutation over the scalar field of `E`.
+/// They are generated from a fixed seed and are not compatible with other Poseidon instances
+pub struct PoseidonParams<E: Engine> {
+    pub full_rounds: usize,
+    pub partial_rounds: usize,
+    /// Added to the state at the start of every round
+    pub round_constants: Vec<[E::Fr; WIDTH]>,
+    /// Cauchy matrix 1 / (i + WIDTH + j), MDS for any field larger than 2 * WIDTH
+    pub mds: [[E::Fr; WIDTH]; WIDTH],
+}
+
+impl<E: Engine> PoseidonParams<E> {
+    pub fn new() -> Self {
+        let mut rng = ChaChaRng::from_seed(&PARAMS_SEED[..]);
+        let round_constants = (0..FULL_ROUNDS + PARTIAL_ROUNDS)
+            .map(|_| {
+                let mut constants = [E::Fr::zero(); WIDTH];
+                for constant in constants.iter_mut() {
+                    *constant = rng.gen();
+                }
+                constants
+            })
+            .collect();
+
+        let mut mds = [[E::Fr::zero(); WIDTH]; WIDTH];
+        for (i, row) in mds.iter_mut().enumerate() {
+            for (j, element) in row.iter_mut().enumerate() {
+                *element = field_element::<E>((i + WIDTH + j) as u64)
+                    .inverse()
+                    .expect("MDS matrix elements are non-zero");
+            }
+        }
+
+        Self {
+            full_rounds: FULL_ROUNDS,
+            partial_rounds: PARTIAL_ROUNDS,
+            round_constants,
+            mds,
+        }
+    }
+
+    /// Full rounds have an S-box on every element, partial rounds only on the first one.
+    /// Half of the full rounds go before the partial ones, half after
+    pub fn is_full_round(&self, round: usize) -> bool {
+        let half = self.full_rounds / 2;
+        round < half || round >= half + self.partial_rounds
+    }
+
+    pub fn permute(&self, state: &mut [E::Fr; WIDTH]) {
+        for (round, constants) in self.round_constants.iter().enumerate() {
+            for (element, constant) in state.iter_mut().zip(constants.iter()) {
+                element.add_assign(constant);
+            }
+
+            if self.is_full_round(round) {
+                for element in state.iter_mut() {
+                    sbox(element);
+                }
+            } else {
+                sbox(&mut state[0]);
+            }
+
+            let mut mixed = [E::Fr::zero(); WIDTH];
+            for (row, result) in self.mds.iter().zip(mixed.iter_mut()) {
+                for (coeff, element) in row.iter().zip(state.iter()) {
+                    let mut term = *coeff;
+                    term.mul_assign(element);
+                    result.add_assign(&term);
+                }
+            }
+            *state = mixed;
+        }
+    }
+
+    /// Sponge with the rate of two elements, absorbs `inputs` and squeezes one element
+    pub fn sponge(&self, domain: u64, inputs: &[E::Fr]) -> E::Fr {
+        let mut state = [E::Fr::zero(); WIDTH];
+        state[0] = field_element::<E>(domain);
+
+        if inputs.is_empty() {
+            self.permute(&mut state);
+        }
+        for chunk in inputs.chunks(WIDTH - 1) {
+            for (element, input) in state[1..].iter_mut().zip(chunk.iter()) {
+                element.add_assign(input);
+            }
+            self.permute(&mut state);
+        }
+        state[1]
+    }
+}
+
+impl<E: Engine> Default for PoseidonParams<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+lazy_static! {
+    static ref SHARED_PARAMS: Mutex<HashMap<TypeId, Box<dyn Any + Send + Sync>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Parameters of `E`, computed once and shared by every hasher and by the gadgets of every node
+pub fn shared_params<E: Engine>() -> Arc<PoseidonParams<E>> {
+    let mut computed = SHARED_PARAMS.lock().expect("poseidon params lock poisoned");
+    computed
+        .entry(TypeId::of::<E>())
+        .or_insert_with(|| Box::new(Arc::new(PoseidonParams::<E>::new())))
+        .downcast_ref::<Arc<PoseidonParams<E>>>()
+        .expect("poseidon params of another engine")
+        .clone()
+}
+
+/// Capacity element of `hash_bits` for an input of `bits_len` bits
+pub fn leaf_domain(bits_len: usize) -> u64 {
+    LEAF_DOMAIN + bits_len as u64
+}
+
+/// Packs little-endian bits into field elements of `CAPACITY` bits each
+pub fn pack_bits<E: Engine>(bits: &[bool]) -> Vec<E::Fr> {
+    bits.chunks(E::Fr::CAPACITY as usize)
+        .map(|chunk| {
+            let mut repr = <E::Fr as PrimeField>::Repr::default();
+            for (i, bit) in chunk.iter().enumerate() {
+                if *bit {
+                    repr.as_mut()[i / 64] |= 1 << (i % 64);
+                }
+            }
+            E::Fr::from_repr(repr).expect("chunk fits into a field element")
+        })
+        .collect()
+}
+
+fn field_element<E: Engine>(value: u64) -> E::Fr {
+    E::Fr::from_repr(<E::Fr as PrimeField>::Repr::from(value))
+        .expect("u64 fits into a field element")
+}
+
+fn sbox<F: Field>(element: &mut F) {
+    let mut quad = *element;
+    quad.square();
+    quad.square();
+    element.mul_assign(&quad);
+}
+
+/// Algebraic alternative to `PedersenHasher`, much cheaper both natively and in the circuit,
+/// see `circuit::poseidon` for the gadgets
+pub struct PoseidonHasher<E: Engine> {
+    pub params: Arc<PoseidonParams<E>>,
+}
+
+impl<E: Engine> Clone for PoseidonHasher<E> {
+    fn clone(&self) -> Self {
+        Self {
+            params: self.params.clone(),
+        }
+    }
+}
+
+impl<E: Engine> Hasher<E::Fr> for PoseidonHasher<E> {
+    const ID: &'static str = "poseidon";
+
+    fn hash_bits<I: IntoIterator<Item = bool>>(&self, input: I) -> E::Fr {
+        let bits: Vec<bool> = input.into_iter().collect();
+        self.params
+            .sponge(leaf_domain(bits.len()), &pack_bits::<E>(&bits))
+    }
+
+    fn compress(&self, lhs: &E::Fr, rhs: &E::Fr, i: usize) -> E::Fr {
+        self.params.sponge(i as u64, &[*lhs, *rhs])
+    }
+}
+
+pub type BabyPoseidonHasher = PoseidonHasher<Bn256>;
+
+impl Default for PoseidonHasher<Bn256> {
+    fn default() -> Self {
+        Self {
+            params: shared_params(),
+        }
+    }
+}
+
+#[test]
+fn test_poseidon_hash() {
+    let hasher = BabyPoseidonHasher::default();
+
+    let hash = hasher.hash_bits(vec![false, false, false, true, true, true, true, true]);
+    assert_eq!(
+        hash,
+        hasher.hash_bits(vec![false, false, false, true, true, true, true, true])
+    );
+    // trailing zero bits change the length and so the hash
+    assert_ne!(
+        hash,
+        hasher.hash_bits(vec![
+            false, false, false, true, true, true, true, true, false
+        ])
+    );
+
+    let node = hasher.compress(&hash, &hash, 0);
+    assert_ne!(node, hasher.compress(&hash, &hash, 1));
+    assert_ne!(node, hasher.compress(&node, &hash, 0));
+
+    // inputs longer than one field element are absorbed in several blocks
+    let long: Vec<bool> = (0..600).map(|i| i % 3 == 0).collect();
+    let mut changed = long.clone();
+    changed[599] = !changed[599];
+    assert_ne!(hasher.hash_bits(long), hasher.hash_bits(changed));
+}
@@ -1,9 +1,11 @@
 // Sparse Merkle tree with flexible hashing strategy
 
-use crate::hasher::Hasher;
+use crate::hasher::{empty_hashes, Hasher};
+use crate::node_store::{MemoryStore, NodeStore, Snapshotable, StoreHeader};
 use models::primitives::GetBits;
-use std::collections::HashMap;
 use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::sync::Arc;
 
 // Tree of depth 0 should contain ONE element that is also a root
 // Tree of depth 1 should contain TWO elements
@@ -37,47 +39,133 @@ impl PackToIndex for HashIndex {
 }
 
 #[derive(Debug, Clone)]
-pub struct SparseMerkleTree<T: GetBits + Default, Hash: Clone + Eq + Debug, H: Hasher<Hash>> {
+pub struct SparseMerkleTree<
+    T: GetBits + Default,
+    Hash: Clone + Eq + Debug,
+    H: Hasher<Hash>,
+    S: NodeStore<T, Hash> = MemoryStore<T, Hash>,
+> {
     tree_depth: Depth,
-    pub prehashed: Vec<Hash>,
-    pub items: HashMap<ItemIndex, T>,
-    pub hashes: HashMap<ItemIndexPacked, Hash>,
+    pub prehashed: Arc<Vec<Hash>>,
+    /// Items and non-empty hashes
+    pub store: S,
     pub hasher: H,
+    // leaves set by `insert_deferred` whose ancestors are not rehashed yet
+    dirty: Vec<ItemIndex>,
+    // header of the store as last written
+    header: StoreHeader,
+    _marker: PhantomData<T>,
 }
 
-impl<T, Hash, H> SparseMerkleTree<T, Hash, H>
+impl<T, Hash, H, S> SparseMerkleTree<T, Hash, H, S>
 where
-    T: GetBits + Default,
-    Hash: Clone + Eq + Debug,
-    H: Hasher<Hash> + Default,
+    T: GetBits + Default + 'static,
+    Hash: Clone + Eq + Debug + Send + Sync + 'static,
+    H: Hasher<Hash> + Default + 'static,
+    S: NodeStore<T, Hash>,
 {
-    pub fn new(tree_depth: Depth) -> Self {
+    pub fn new(tree_depth: Depth) -> Self
+    where
+        S: Default,
+    {
+        Self::with_store(tree_depth, S::default())
+    }
+
+    /// Tree on top of `store`, a store that already holds a tree is used as is.
+    /// Panics if its header does not match the depth, hasher and leaf layout of this tree
+    pub fn with_store(tree_depth: Depth, mut store: S) -> Self {
         let hasher = H::default();
-        let items = HashMap::new();
-        let hashes = HashMap::new();
         // we need to make sparse hashes for tree depth levels
-        let mut prehashed = Vec::with_capacity((tree_depth + 1) as usize);
-        let mut cur = hasher.hash_bits(T::default().get_bits_le());
-        prehashed.push(cur.clone());
+        let prehashed = empty_hashes::<T, Hash, H>(tree_depth);
 
-        for i in 0..tree_depth {
-            cur = hasher.compress(&cur, &cur, i as usize);
-            prehashed.push(cur.clone());
-        }
-        prehashed.reverse();
+        assert_eq!(prehashed.len() - 1, tree_depth as usize);
 
-        // print!("Made default hashes in quantity {}\n", prehashed.len());
+        let mut header = StoreHeader {
+            tree_depth,
+            hasher: H::ID.to_string(),
+            leaf_bits: T::default().get_bits_le().len() as u32,
+            block_number: None,
+        };
+        match store.header() {
+            Some(stored) => {
+                header.block_number = stored.block_number;
+                assert_eq!(stored, header, "tree store holds another kind of tree");
+            }
+            None => {
+                // the root hash is stored for any non-empty tree
+                assert!(
+                    store.get_hash((0u32, 0u32).pack()).is_none(),
+                    "tree store without a header can not be checked"
+                );
+                store.set_header(&header);
+            }
+        }
 
-        assert_eq!(prehashed.len() - 1, tree_depth as usize);
         Self {
             tree_depth,
             prehashed,
-            items,
-            hashes,
+            store,
             hasher,
+            dirty: Vec::new(),
+            header,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn tree_depth(&self) -> Depth {
+        self.tree_depth
+    }
+
+    /// Block whose state the tree holds, as set by `set_block_number`.
+    /// None for a new tree and for a tree changed since
+    pub fn block_number(&self) -> Option<u32> {
+        self.header.block_number
+    }
+
+    /// Records that the tree holds the state after `block_number`, in the header of the store.
+    /// Any later change clears it, so a store left in the middle of an update is recognized
+    pub fn set_block_number(&mut self, block_number: u32) {
+        assert!(self.dirty.is_empty(), "recompute_hashes is pending");
+        self.header.block_number = Some(block_number);
+        self.store.set_header(&self.header);
+    }
+
+    // clears the block number before the first change after `set_block_number`
+    fn start_update(&mut self) {
+        if self.header.block_number.is_some() {
+            self.header.block_number = None;
+            self.store.set_header(&self.header);
         }
     }
 
+    /// Tree with the same nodes on another store and the items converted by `f`, which must
+    /// not change their leaf hashes. Nothing is hashed again
+    pub fn map_items<U, S2, F>(&self, f: F) -> SparseMerkleTree<U, Hash, H, S2>
+    where
+        U: GetBits + Default + 'static,
+        S2: NodeStore<U, Hash> + Default,
+        F: Fn(T) -> U,
+    {
+        assert!(self.dirty.is_empty(), "recompute_hashes is pending");
+        let mut tree = SparseMerkleTree::with_store(self.tree_depth, S2::default());
+        for (index, item) in self.store.items() {
+            tree.store.set_item(index, f(item));
+        }
+        for (index, hash) in self.store.hashes() {
+            tree.store.set_hash(index, hash);
+        }
+        tree
+    }
+
+    pub fn get(&self, index: ItemIndex) -> Option<T> {
+        self.store.get_item(index)
+    }
+
+    /// All items in no particular order
+    pub fn items(&self) -> Vec<(ItemIndex, T)> {
+        self.store.items()
+    }
+
     // How many items can the tree hold
     pub fn capacity(&self) -> u32 {
         1 << self.tree_depth
@@ -85,15 +173,16 @@ where
 
     pub fn insert(&mut self, index: ItemIndex, item: T) {
         assert!(index < self.capacity());
+        self.start_update();
         let hash_index = (self.tree_depth, index);
 
         let item_bits = item.get_bits_le();
 
         let hash = self.hasher.hash_bits(item_bits);
 
-        self.hashes.insert(hash_index.pack(), hash);
+        self.store.set_hash(hash_index.pack(), hash);
 
-        self.items.insert(index, item);
+        self.store.set_item(index, item);
 
         let mut next_level = (hash_index.0, hash_index.1);
 
@@ -105,19 +194,58 @@ where
         assert_eq!(next_level.0, 0);
     }
 
-    pub fn delete(&mut self, index: ItemIndex) {
+    /// Sets the leaf without rehashing its ancestors, `recompute_hashes` does it for all
+    /// deferred leaves at once. The root and the audit paths are stale until then
+    pub fn insert_deferred(&mut self, index: ItemIndex, item: T) {
         assert!(index < self.capacity());
+        self.start_update();
         let hash_index = (self.tree_depth, index);
 
-        let item = T::default();
+        let hash = self.hasher.hash_bits(item.get_bits_le());
 
-        let item_bits = item.get_bits_le();
+        self.store.set_hash(hash_index.pack(), hash);
 
-        let hash = self.hasher.hash_bits(item_bits);
+        self.store.set_item(index, item);
 
-        self.hashes.insert(hash_index.pack(), hash);
+        self.dirty.push(index);
+    }
 
-        self.items.insert(index, item);
+    /// Rehashes the ancestors of the leaves set by `insert_deferred`, level by level
+    /// so that a node shared by several of them is hashed only once
+    pub fn recompute_hashes(&mut self) {
+        let mut indices = std::mem::replace(&mut self.dirty, Vec::new());
+
+        for level in (0..self.tree_depth).rev() {
+            for index in indices.iter_mut() {
+                *index >>= 1;
+            }
+            indices.sort_unstable();
+            indices.dedup();
+
+            for index in indices.iter() {
+                self.update_hash((level, *index));
+            }
+        }
+    }
+
+    /// Same as inserting the items one by one, but each affected node is hashed once
+    pub fn insert_batch<I: IntoIterator<Item = (ItemIndex, T)>>(&mut self, items: I) {
+        for (index, item) in items {
+            self.insert_deferred(index, item);
+        }
+        self.recompute_hashes();
+    }
+
+    /// Removes the item and its leaf hash. Ancestors left with two empty children are removed
+    /// as well, so the store only holds the non-empty part of the tree
+    pub fn delete(&mut self, index: ItemIndex) {
+        assert!(index < self.capacity());
+        self.start_update();
+        let hash_index = (self.tree_depth, index);
+
+        self.store.remove_item(index);
+
+        self.store.remove_hash(hash_index.pack());
 
         let mut next_level = (hash_index.0, hash_index.1);
 
@@ -149,6 +277,13 @@ where
         let lhs_hash = self.get_hash(lhs_index);
         let rhs_hash = self.get_hash(rhs_index);
 
+        // an empty subtree falls back to the precomputed hash
+        let empty = &self.prehashed[lhs_index.0 as usize];
+        if lhs_hash == *empty && rhs_hash == *empty {
+            self.store.remove_hash(index.pack());
+            return self.prehashed[index.0 as usize].clone();
+        }
+
         //let idx = (1 << index.0) + index.1;
         //debug!("({:?}, {:?}, {})", &lhs_hash, &rhs_hash, (self.tree_depth - 1 - index.0));
 
@@ -160,7 +295,7 @@ where
 
         //debug!("hash [{}] = {:?}", (1 << index.0) + index.1, hash);
 
-        self.hashes.insert(index.pack(), hash.clone());
+        self.store.set_hash(index.pack(), hash.clone());
         hash
     }
 
@@ -170,10 +305,10 @@ where
         assert!(index.0 <= self.tree_depth);
         assert!(index.1 < self.capacity());
 
-        if let Some(hash) = self.hashes.get(&index.pack()) {
+        if let Some(hash) = self.store.get_hash(index.pack()) {
             // if hash for this index exists, return it
             // print!("Found non-default hash for index {}, {}\n", index.0, index.1);
-            hash.clone()
+            hash
         } else {
             // otherwise return pre-computed
             // print!("Found default hash for index {}, {}\n", index.0, index.1);
@@ -184,52 +319,247 @@ where
     pub fn merkle_path(&self, index: ItemIndex) -> Vec<(Hash, bool)> {
         // print!("Making a proof for index {}\n", index);
         assert!(index < self.capacity());
-        let mut hash_index = (self.tree_depth, index);
-
-        (0..self.tree_depth)
-            .rev()
-            .map(|_level| {
-                let dir = (hash_index.1 & 1) > 0;
-                let proof_index = (hash_index.0, hash_index.1 ^ 1);
-                let hash = self.get_hash(proof_index);
-                hash_index = (hash_index.0 - 1, hash_index.1 >> 1);
-                (hash, dir)
-            })
-            .collect()
-    }
-
-    // pub fn verify_proof(&self, index: ItemIndex, item: T, proof: Vec<(Hash, bool)>) -> bool {
-    //     assert!(index < self.capacity());
-    //     let item_bits = item.get_bits_le();
-    //     let mut hash = self.hasher.hash_bits(item_bits);
-    //     let mut proof_index: ItemIndex = 0;
-
-    //     for (i, e) in proof.clone().into_iter().enumerate() {
-    //         if e.1 {
-    //             // current is right
-    //             proof_index |= 1 << i;
-    //             hash = self.hasher.compress(&e.0, &hash, i);
-    //         } else {
-    //             // current is left
-    //             hash = self.hasher.compress(&hash, &e.0, i);
-    //         }
-    //     }
+        debug_assert!(self.dirty.is_empty(), "recompute_hashes is pending");
+        merkle_path(self.tree_depth, index, |index| self.get_hash(index))
+    }
 
-    //     if proof_index != index {
-    //         return false;
-    //     }
+    /// Checks that `item` is at `index` in the current tree, `proof` is a `merkle_path`
+    pub fn verify_proof(&self, index: ItemIndex, item: &T, proof: &[(Hash, bool)]) -> bool {
+        proof.len() == self.tree_depth as usize
+            && verify_proof(&self.hasher, index, item, proof, &self.root_hash())
+    }
 
-    //     hash == self.root_hash()
-    // }
+    /// One proof for all `indices`, see `verify_multiproof`
+    pub fn multiproof(&self, indices: &[ItemIndex]) -> Vec<Hash> {
+        assert!(indices.iter().all(|index| *index < self.capacity()));
+        debug_assert!(self.dirty.is_empty(), "recompute_hashes is pending");
+        multiproof(self.tree_depth, indices, |index| self.get_hash(index))
+    }
+
+    /// Checks that all `items` are in the current tree, `proof` is a `multiproof` of their indices
+    pub fn verify_multiproof(&self, items: &[(ItemIndex, T)], proof: &[Hash]) -> bool {
+        verify_multiproof(
+            &self.hasher,
+            self.tree_depth,
+            items,
+            proof,
+            &self.root_hash(),
+        )
+    }
+
+    pub fn root_hash(&self) -> Hash {
+        debug_assert!(self.dirty.is_empty(), "recompute_hashes is pending");
+        self.get_hash((0, 0))
+    }
+
+    /// Read-only copy of the current tree that later inserts do not affect.
+    /// With a `PersistentStore` it shares all nodes with the tree and costs O(1)
+    pub fn snapshot(&self) -> Snapshot<T, Hash, S::Snapshot>
+    where
+        S: Snapshotable<T, Hash>,
+    {
+        assert!(self.dirty.is_empty(), "recompute_hashes is pending");
+        Snapshot {
+            tree_depth: self.tree_depth,
+            prehashed: self.prehashed.clone(),
+            store: self.store.snapshot(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Version of a tree taken by `SparseMerkleTree::snapshot`, e.g. at a block boundary.
+/// Dropping it releases the nodes that no other version shares
+#[derive(Debug, Clone)]
+pub struct Snapshot<T, Hash, S> {
+    tree_depth: Depth,
+    prehashed: Arc<Vec<Hash>>,
+    store: S,
+    _marker: PhantomData<T>,
+}
+
+impl<T, Hash, S> Snapshot<T, Hash, S>
+where
+    Hash: Clone,
+    S: NodeStore<T, Hash>,
+{
+    pub fn get(&self, index: ItemIndex) -> Option<T> {
+        self.store.get_item(index)
+    }
+
+    pub fn get_hash(&self, index: HashIndex) -> Hash {
+        assert!(index.0 <= self.tree_depth);
+        self.store
+            .get_hash(index.pack())
+            .unwrap_or_else(|| self.prehashed[index.0 as usize].clone())
+    }
+
+    pub fn merkle_path(&self, index: ItemIndex) -> Vec<(Hash, bool)> {
+        assert!(index < (1 << self.tree_depth));
+        merkle_path(self.tree_depth, index, |index| self.get_hash(index))
+    }
+
+    pub fn multiproof(&self, indices: &[ItemIndex]) -> Vec<Hash> {
+        assert!(indices.iter().all(|index| *index < (1 << self.tree_depth)));
+        multiproof(self.tree_depth, indices, |index| self.get_hash(index))
+    }
 
     pub fn root_hash(&self) -> Hash {
         self.get_hash((0, 0))
     }
 }
 
+// audit path of the leaf `index` from the bottom up, `get_hash` reads a node of the tree
+fn merkle_path<Hash, F>(tree_depth: Depth, index: ItemIndex, get_hash: F) -> Vec<(Hash, bool)>
+where
+    F: Fn(HashIndex) -> Hash,
+{
+    let mut hash_index = (tree_depth, index);
+
+    (0..tree_depth)
+        .rev()
+        .map(|_level| {
+            let dir = (hash_index.1 & 1) > 0;
+            let proof_index = (hash_index.0, hash_index.1 ^ 1);
+            let hash = get_hash(proof_index);
+            hash_index = (hash_index.0 - 1, hash_index.1 >> 1);
+            (hash, dir)
+        })
+        .collect()
+}
+
+// positions of a level that a multiproof knows, sorted and without duplicates
+fn multiproof_leaves(indices: &[ItemIndex]) -> Vec<ItemIndex> {
+    let mut indices = indices.to_vec();
+    indices.sort_unstable();
+    indices.dedup();
+    indices
+}
+
+/// Siblings that the audit paths of `indices` need, minus the nodes that the verifier computes
+/// itself. The leaves are walked up level by level, from left to right on each level: a known node
+/// whose sibling is known too takes nothing, any other node takes the hash of its sibling.
+/// `get_hash` reads a node of the tree
+pub(crate) fn multiproof<Hash, F>(
+    tree_depth: Depth,
+    indices: &[ItemIndex],
+    get_hash: F,
+) -> Vec<Hash>
+where
+    F: Fn(HashIndex) -> Hash,
+{
+    let mut known = multiproof_leaves(indices);
+    let mut proof = Vec::new();
+
+    for level in (1..=tree_depth).rev() {
+        let mut parents = Vec::with_capacity(known.len());
+        let mut i = 0;
+        while i < known.len() {
+            let index = known[i];
+            if index & 1 == 0 && known.get(i + 1) == Some(&(index + 1)) {
+                i += 2;
+            } else {
+                proof.push(get_hash((level, index ^ 1)));
+                i += 1;
+            }
+            parents.push(index >> 1);
+        }
+        known = parents;
+    }
+
+    proof
+}
+
+/// Checks that all `items` are in a tree of depth `tree_depth` with the `root` hash,
+/// `proof` is the `multiproof` of their indices. Empty leaves are proven with `T::default()`.
+/// Fails for an empty set or for an index given twice
+pub fn verify_multiproof<T, Hash, H>(
+    hasher: &H,
+    tree_depth: Depth,
+    items: &[(ItemIndex, T)],
+    proof: &[Hash],
+    root: &Hash,
+) -> bool
+where
+    T: GetBits,
+    Hash: Clone + Eq + Debug,
+    H: Hasher<Hash>,
+{
+    let mut known: Vec<(ItemIndex, Hash)> = items
+        .iter()
+        .map(|(index, item)| (*index, hasher.hash_bits(item.get_bits_le())))
+        .collect();
+    known.sort_by_key(|(index, _)| *index);
+
+    let unique = known.windows(2).all(|pair| pair[0].0 != pair[1].0);
+    let in_range = known
+        .last()
+        .map_or(false, |(index, _)| u64::from(*index) < 1 << tree_depth);
+    if !unique || !in_range {
+        return false;
+    }
+
+    let mut siblings = proof.iter();
+    for level in 0..tree_depth as usize {
+        let mut parents = Vec::with_capacity(known.len());
+        let mut known_iter = known.into_iter().peekable();
+        while let Some((index, hash)) = known_iter.next() {
+            let next = known_iter.peek().map(|(next, _)| *next);
+            let hash = if index & 1 == 0 && next == Some(index + 1) {
+                let (_, rhs) = known_iter.next().unwrap();
+                hasher.compress(&hash, &rhs, level)
+            } else {
+                let sibling = match siblings.next() {
+                    Some(sibling) => sibling,
+                    None => return false,
+                };
+                if index & 1 == 0 {
+                    hasher.compress(&hash, sibling, level)
+                } else {
+                    hasher.compress(sibling, &hash, level)
+                }
+            };
+            parents.push((index >> 1, hash));
+        }
+        known = parents;
+    }
+
+    siblings.next().is_none() && known[0].1 == *root
+}
+
+/// Checks that `item` is at `index` in a tree with the `root` hash,
+/// `proof` is the audit path from the leaf up as returned by `merkle_path`
+pub fn verify_proof<T, Hash, H>(
+    hasher: &H,
+    index: ItemIndex,
+    item: &T,
+    proof: &[(Hash, bool)],
+    root: &Hash,
+) -> bool
+where
+    T: GetBits,
+    Hash: Clone + Eq + Debug,
+    H: Hasher<Hash>,
+{
+    let mut hash = hasher.hash_bits(item.get_bits_le());
+    let mut proof_index: u64 = 0;
+
+    for (i, (sibling, current_is_right)) in proof.iter().enumerate() {
+        if *current_is_right {
+            proof_index |= 1 << i;
+            hash = hasher.compress(sibling, &hash, i);
+        } else {
+            hash = hasher.compress(&hash, sibling, i);
+        }
+    }
+
+    proof_index == u64::from(index) && hash == *root
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::node_store::{DiskStore, PersistentStore};
 
     use log::debug;
 
@@ -243,6 +573,8 @@ mod tests {
     }
 
     impl Hasher<u64> for TestHasher {
+        const ID: &'static str = "test";
+
         fn hash_bits<I: IntoIterator<Item = bool>>(&self, value: I) -> u64 {
             let mut acc = 0;
             let v: Vec<bool> = value.into_iter().collect();
@@ -291,6 +623,14 @@ mod tests {
         assert_eq!(tree.root_hash(), 793_215_819);
     }
 
+    #[test]
+    fn test_empty_hashes_shared() {
+        let tree = TestSMT::new(3);
+        assert!(Arc::ptr_eq(&tree.prehashed, &TestSMT::new(3).prehashed));
+        assert!(!Arc::ptr_eq(&tree.prehashed, &TestSMT::new(4).prehashed));
+        assert_eq!(tree.prehashed[..], TestSMT::new(4).prehashed[1..]);
+    }
+
     #[test]
     fn test_merkle_path() {
         let mut tree = TestSMT::new(3);
@@ -299,4 +639,257 @@ mod tests {
         //debug!("{:?}", tree);
         assert_eq!(path, [(32768, false), (917_505, true), (25_690_142, false)]);
     }
+
+    #[test]
+    fn test_verify_proof() {
+        let mut tree = TestSMT::new(3);
+        tree.insert(2, 1);
+        tree.insert(5, 3);
+
+        let path = tree.merkle_path(2);
+        assert!(tree.verify_proof(2, &1, &path));
+        assert!(!tree.verify_proof(2, &3, &path));
+        assert!(!tree.verify_proof(3, &1, &path));
+        assert!(!tree.verify_proof(2, &1, &path[1..]));
+
+        // an empty leaf proves that nothing is stored at the index
+        let path = tree.merkle_path(4);
+        assert!(tree.verify_proof(4, &0, &path));
+
+        // an old proof still holds against the old root
+        let root = tree.root_hash();
+        let path = tree.merkle_path(2);
+        tree.insert(2, 7);
+        assert!(!tree.verify_proof(2, &1, &path));
+        assert!(verify_proof(&tree.hasher, 2, &1, &path, &root));
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut tree = TestSMT::new(3);
+        let empty_root = tree.root_hash();
+        tree.insert(2, 1);
+        let root = tree.root_hash();
+        tree.insert(3, 5);
+        tree.insert(6, 4);
+
+        tree.delete(3);
+        tree.delete(6);
+        assert_eq!(tree.root_hash(), root);
+        assert_eq!(tree.get(3), None);
+        assert_eq!(tree.items(), vec![(2, 1)]);
+        // only the path to the remaining leaf is stored
+        assert_eq!(tree.store.items.len(), 1);
+        assert_eq!(tree.store.hashes.len(), 4);
+
+        // deleting an empty leaf changes nothing
+        tree.delete(5);
+        assert_eq!(tree.root_hash(), root);
+
+        tree.delete(2);
+        assert_eq!(tree.root_hash(), empty_root);
+        assert!(tree.store.items.is_empty());
+        assert!(tree.store.hashes.is_empty());
+
+        tree.insert(2, 1);
+        assert_eq!(tree.root_hash(), root);
+    }
+
+    #[test]
+    fn test_multiproof() {
+        let mut tree = TestSMT::new(3);
+        for &(index, item) in &[(0, 1), (1, 4), (3, 2), (5, 7)] {
+            tree.insert(index, item);
+        }
+
+        // siblings 0-1 need nothing on the leaf level, 3 and 6 take theirs, only 6 one above
+        let proof = tree.multiproof(&[6, 0, 1, 3]);
+        assert_eq!(proof.len(), 3);
+        assert_eq!(proof[0], tree.get_hash((3, 2)));
+        let items = [(3, 2), (0, 1), (6, 0), (1, 4)];
+        assert!(tree.verify_multiproof(&items, &proof));
+
+        // the same as one audit path for a single index
+        let path: Vec<u64> = tree
+            .merkle_path(5)
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .collect();
+        assert_eq!(tree.multiproof(&[5]), path);
+        assert!(tree.verify_multiproof(&[(5, 7)], &path));
+
+        assert!(!tree.verify_multiproof(&[(3, 2), (0, 1), (6, 0), (1, 5)], &proof));
+        assert!(!tree.verify_multiproof(&[(2, 2), (0, 1), (6, 0), (1, 4)], &proof));
+        assert!(!tree.verify_multiproof(&[(3, 2), (0, 1), (1, 4)], &proof));
+        assert!(!tree.verify_multiproof(&[(3, 2), (0, 1), (6, 0), (1, 4), (1, 4)], &proof));
+        assert!(!tree.verify_multiproof(&items, &proof[1..]));
+        assert!(!tree.verify_multiproof(&[], &[]));
+
+        let snapshot = tree.snapshot();
+        tree.insert(6, 3);
+        assert!(!tree.verify_multiproof(&items, &proof));
+        assert_eq!(snapshot.multiproof(&[0, 1, 3, 6]), proof);
+    }
+
+    #[test]
+    fn test_insert_batch() {
+        let items = vec![(0, 1), (3, 2), (5, 7), (3, 4), (7, 9)];
+
+        let mut expected = TestSMT::new(3);
+        for (index, item) in items.clone() {
+            expected.insert(index, item);
+        }
+
+        let mut tree = TestSMT::new(3);
+        tree.insert_batch(items);
+        assert_eq!(tree.root_hash(), expected.root_hash());
+        assert_eq!(tree.merkle_path(5), expected.merkle_path(5));
+
+        // deferred leaves mixed with a regular insert
+        expected.insert(2, 5);
+        expected.insert(6, 6);
+        tree.insert_deferred(2, 5);
+        tree.insert(6, 6);
+        tree.recompute_hashes();
+        assert_eq!(tree.root_hash(), expected.root_hash());
+    }
+
+    #[test]
+    fn test_disk_store() {
+        type DiskSMT = SparseMerkleTree<u64, u64, TestHasher, DiskStore<u64, u64>>;
+
+        let path = std::env::temp_dir().join(format!("smt-test-{}", std::process::id()));
+        let mut expected = TestSMT::new(3);
+        {
+            let mut tree = DiskSMT::with_store(3, DiskStore::open(&path).unwrap());
+            for &(index, item) in &[(0, 1), (3, 2), (5, 7)] {
+                tree.insert(index, item);
+                expected.insert(index, item);
+            }
+            assert_eq!(tree.root_hash(), expected.root_hash());
+            tree.store.flush().unwrap();
+        }
+
+        // the reopened tree continues where the old one stopped
+        let mut tree = DiskSMT::with_store(3, DiskStore::open(&path).unwrap());
+        assert_eq!(tree.root_hash(), expected.root_hash());
+        assert_eq!(tree.get(5), Some(7));
+        assert_eq!(tree.get(6), None);
+        let mut items = tree.items();
+        items.sort();
+        assert_eq!(items, vec![(0, 1), (3, 2), (5, 7)]);
+
+        tree.insert(6, 4);
+        expected.insert(6, 4);
+        assert_eq!(tree.root_hash(), expected.root_hash());
+        assert_eq!(tree.merkle_path(3), expected.merkle_path(3));
+
+        drop(tree);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_disk_store_header() {
+        type DiskSMT = SparseMerkleTree<u64, u64, TestHasher, DiskStore<u64, u64>>;
+
+        let path = std::env::temp_dir().join(format!("smt-header-test-{}", std::process::id()));
+        {
+            let mut tree = DiskSMT::with_store(3, DiskStore::open(&path).unwrap());
+            assert_eq!(tree.block_number(), None);
+            tree.insert_batch(vec![(0, 1), (3, 2)]);
+            tree.set_block_number(7);
+            tree.store.flush().unwrap();
+        }
+
+        let mut tree = DiskSMT::with_store(3, DiskStore::open(&path).unwrap());
+        assert_eq!(tree.block_number(), Some(7));
+
+        // the same nodes in memory, nothing is hashed again
+        let copy: TestSMT = tree.map_items(|item| item);
+        assert_eq!(copy.root_hash(), tree.root_hash());
+        assert_eq!(copy.get(3), Some(2));
+
+        // a tree in the middle of an update holds no block
+        tree.insert_deferred(5, 7);
+        assert_eq!(tree.block_number(), None);
+        tree.recompute_hashes();
+        tree.store.flush().unwrap();
+        drop(tree);
+
+        let tree = DiskSMT::with_store(3, DiskStore::open(&path).unwrap());
+        assert_eq!(tree.block_number(), None);
+        drop(tree);
+
+        // another depth gives other hashes, the store refuses it
+        let reopened = std::panic::catch_unwind(|| {
+            DiskSMT::with_store(4, DiskStore::open(&path).unwrap());
+        });
+        assert!(reopened.is_err());
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_disk_store_snapshot() {
+        type DiskSMT = SparseMerkleTree<u64, u64, TestHasher, DiskStore<u64, u64>>;
+
+        let path = std::env::temp_dir().join(format!("smt-snapshot-test-{}", std::process::id()));
+        let mut tree = DiskSMT::with_store(3, DiskStore::open(&path).unwrap());
+        tree.insert(0, 1);
+        tree.insert(3, 2);
+        let snapshot = tree.snapshot();
+        let root = tree.root_hash();
+        let path_3 = tree.merkle_path(3);
+
+        tree.insert(3, 5);
+        tree.insert(6, 4);
+        tree.delete(0);
+
+        // the snapshot is a copy, the file it was read from goes on changing
+        assert_eq!(snapshot.root_hash(), root);
+        assert_eq!(snapshot.merkle_path(3), path_3);
+        assert_eq!(snapshot.get(0), Some(1));
+        assert_eq!(snapshot.get(3), Some(2));
+        assert_eq!(snapshot.get(6), None);
+        assert_ne!(tree.root_hash(), root);
+
+        drop(tree);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_snapshots() {
+        type PersistentSMT = SparseMerkleTree<u64, u64, TestHasher, PersistentStore<u64, u64>>;
+
+        let mut tree = PersistentSMT::new(3);
+        tree.insert(0, 1);
+        tree.insert(3, 2);
+        let first = tree.snapshot();
+        let first_root = tree.root_hash();
+        let first_path = tree.merkle_path(3);
+
+        tree.insert(3, 5);
+        tree.insert(6, 4);
+        let second = tree.snapshot();
+        tree.insert(0, 9);
+
+        // old versions are unaffected by later inserts
+        assert_eq!(first.root_hash(), first_root);
+        assert_eq!(first.merkle_path(3), first_path);
+        assert_eq!(first.get(3), Some(2));
+        assert_eq!(first.get(6), None);
+        let path = first.merkle_path(3);
+        assert!(verify_proof(&tree.hasher, 3, &2, &path, &first_root));
+
+        let mut expected = TestSMT::new(3);
+        expected.insert(0, 1);
+        expected.insert(3, 5);
+        expected.insert(6, 4);
+        assert_eq!(second.root_hash(), expected.root_hash());
+        assert_eq!(second.merkle_path(0), expected.merkle_path(0));
+        assert_eq!(tree.get(0), Some(9));
+
+        drop(first);
+        assert_eq!(second.get(3), Some(5));
+    }
 }
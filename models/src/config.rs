@@ -1,13 +1,21 @@
 pub const TRANSFER_BATCH_SIZE: usize = 8;
 pub const DEPOSIT_BATCH_SIZE: usize = 1;
 pub const EXIT_BATCH_SIZE: usize = 1;
+pub const CHANGE_PUBKEY_BATCH_SIZE: usize = 1;
+pub const CHEQUE_BATCH_SIZE: usize = 1;
+pub const LOCK_BATCH_SIZE: usize = 1;
+pub const UNLOCK_BATCH_SIZE: usize = 1;
 pub const PADDING_INTERVAL: u64 = 60; // sec
 pub const PROVER_TIMEOUT: usize = 60; // sec
 pub const PROVER_TIMER_TICK: u64 = 5; // sec
 pub const PROVER_CYCLE_WAIT: u64 = 5; // sec
+pub const SHUTDOWN_TIMEOUT: u64 = 30; // sec
 
 pub const DEFAULT_KEYS_PATH: &str = "keys";
 
+// capacity of the request queue of the state keeper
+pub const STATE_KEEPER_QUEUE_SIZE: usize = 1024;
+
 lazy_static! {
     pub static ref RUNTIME_CONFIG: RuntimeConfig = RuntimeConfig::new();
 }
@@ -19,6 +27,19 @@ pub struct RuntimeConfig {
     pub transfer_batch_size: usize,
     pub keys_path: String,
     pub max_outstanding_txs: u32,
+    /// How many blocks may wait for the committer before new txs are refused
+    pub max_committer_lag: usize,
+    /// Replay `account_updates` on startup to locate the first block whose root diverges
+    pub repair_state: bool,
+    /// Token for admin endpoints, admin endpoints are disabled if not set
+    pub admin_token: Option<String>,
+    /// Identifier of the deployment that transfers are signed for,
+    /// so a transfer signed for one deployment is not valid on another
+    pub chain_id: u32,
+    /// Directory of the account tree kept between restarts, so a restart only hashes
+    /// the accounts updated since. The tree is still read into memory in full at startup.
+    /// Each process needs a directory of its own
+    pub account_tree_path: Option<String>,
     pub contract_addr: String,
     pub mainnet_http_endpoint_string: String,
     pub rinkeby_http_endpoint_string: String,
@@ -35,12 +56,28 @@ impl RuntimeConfig {
         let keys_path = env::var("KEY_DIR")
             .ok()
             .unwrap_or_else(|| DEFAULT_KEYS_PATH.to_string());
+        let max_committer_lag = env::var("MAX_COMMITTER_LAG")
+            .unwrap_or_else(|_| "4".to_string())
+            .parse()
+            .expect("MAX_COMMITTER_LAG invalid");
+        let repair_state = env::var("REPAIR_STATE")
+            .map(|v| v == "1" || v == "true")
+            .unwrap_or(false);
+        let chain_id = env::var("CHAIN_ID")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .expect("CHAIN_ID invalid");
 
         Self {
             transfer_batch_size: transfer_size,
             keys_path,
             contract_addr: env::var("CONTRACT_ADDR").unwrap_or("5F939954eA54FA9b61Fd59518945D09E8939f2B2".to_string()),
             max_outstanding_txs: 120000,
+            max_committer_lag,
+            repair_state,
+            admin_token: env::var("ADMIN_TOKEN").ok(),
+            chain_id,
+            account_tree_path: env::var("ACCOUNT_TREE_PATH").ok(),
             mainnet_http_endpoint_string: env::var("TREE_RESTORE_MAINNET_ENDPOINT")
                 .unwrap_or("https://mainnet.infura.io/".to_string()),
             rinkeby_http_endpoint_string: env::var("TREE_RESTORE_RINKEBY_ENDPOINT")
@@ -29,6 +29,12 @@ pub struct TransferTxConfirmation {
 
 pub type TransferTxResult = Result<TransferTxConfirmation, TransferApplicationError>;
 
+pub type ChangePubKeyTxResult = Result<(), TransferApplicationError>;
+
+pub type ChequeTxResult = Result<(), TransferApplicationError>;
+
+pub type HashLockTxResult = Result<(), TransferApplicationError>;
+
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
 pub struct NetworkStatus {
     pub next_block_at_max: Option<u64>,
@@ -36,6 +42,8 @@ pub struct NetworkStatus {
     pub last_verified: BlockNumber,
     pub total_transactions: u32,
     pub outstanding_txs: u32,
+    /// Last committer error, blocks are not produced until it is resolved
+    pub committer_error: Option<String>,
 }
 
 pub type EncodedProof = [U256; 8];
@@ -99,13 +107,31 @@ pub enum ProtoBlock {
     Transfer,
     Deposit(BatchNumber, Vec<DepositTx>),
     Exit(BatchNumber, Vec<ExitTx>),
+    ChangePubKey(Box<ChangePubKeyTx>),
+    Cheque(Box<ChequeTx>),
+    Lock(Box<LockTx>),
+    Claim(ClaimTx),
+    Refund(RefundTx),
 }
 
 pub enum StateKeeperRequest {
     AddTransferTx(Box<TransferTx>, Sender<TransferTxResult>),
+    /// Queue a key change; it is applied when its block is created
+    AddChangePubKeyTx(Box<ChangePubKeyTx>, Sender<ChangePubKeyTxResult>),
+    /// Queue a cheque redemption; it is applied when its block is created
+    AddChequeTx(Box<ChequeTx>, Sender<ChequeTxResult>),
+    /// Queue a hash lock, a claim or a refund; it is applied when its block is created
+    AddLockTx(Box<LockTx>, Sender<HashLockTxResult>),
+    AddClaimTx(ClaimTx, Sender<HashLockTxResult>),
+    AddRefundTx(RefundTx, Sender<HashLockTxResult>),
     AddBlock(ProtoBlock),
     GetAccount(u32, Sender<Option<Account>>),
     GetNetworkStatus(Sender<NetworkStatus>),
+    /// Roll back all committed but not yet verified blocks; replies with the new last committed block
+    RollbackBlocks(Sender<Result<BlockNumber, String>>),
+    /// Seal the current batch and refuse new txs, blocks and rollbacks;
+    /// replies with the last block sent to the committer
+    Shutdown(Sender<BlockNumber>),
     TimerTick,
 }
 
@@ -1,16 +1,246 @@
 use crate::circuit;
 use crate::plasma::params;
-use crate::primitives::GetBits;
-use crate::{Engine, Fr, PublicKey};
-use bigdecimal::BigDecimal;
+use crate::primitives::{
+    bytes_into_be_bits, get_bits_le_fixed_big_decimal, get_bits_le_fixed_u128, GetBits,
+    GetBitsFixed,
+};
+use crate::{AccountId, BlockNumber, Engine, Fr, PublicKey, TokenId, H256};
+use bigdecimal::{BigDecimal, Zero};
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use ff::Field;
 use sapling_crypto::jubjub::{edwards, Unknown};
+use std::collections::BTreeMap;
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(from = "StoredAccount")]
 pub struct Account {
-    pub balance: BigDecimal,
+    /// Non-zero balances by token
+    pub balances: BTreeMap<TokenId, BigDecimal>,
     pub nonce: u32,
     pub public_key_x: Fr,
     pub public_key_y: Fr,
+    /// Serials of the redeemed cheques of this account
+    #[serde(default)]
+    pub cheque_window: ChequeWindow,
+    /// Funds locked by the account for a hash-time-locked transfer
+    #[serde(default)]
+    pub hash_lock: Option<HashLock>,
+    /// Keys of a multisig account, its public key is their commitment
+    #[serde(default)]
+    pub multisig: Option<Multisig>,
+}
+
+// Account as it is read from storage. Rows written before multi-token balances
+// have a single `balance`, which is the balance of the native token
+#[derive(Deserialize)]
+struct StoredAccount {
+    #[serde(default)]
+    balances: BTreeMap<TokenId, BigDecimal>,
+    #[serde(default)]
+    balance: Option<BigDecimal>,
+    nonce: u32,
+    public_key_x: Fr,
+    public_key_y: Fr,
+    #[serde(default)]
+    cheque_window: ChequeWindow,
+    #[serde(default)]
+    hash_lock: Option<HashLock>,
+    #[serde(default)]
+    multisig: Option<Multisig>,
+}
+
+impl From<StoredAccount> for Account {
+    fn from(stored: StoredAccount) -> Self {
+        let mut account = Account {
+            balances: stored.balances,
+            nonce: stored.nonce,
+            public_key_x: stored.public_key_x,
+            public_key_y: stored.public_key_y,
+            cheque_window: stored.cheque_window,
+            hash_lock: stored.hash_lock,
+            multisig: stored.multisig,
+        };
+        if let Some(balance) = stored.balance {
+            account.set_balance(params::ETH_TOKEN_ID, balance);
+        }
+        account
+    }
+}
+
+/// Keys of a multisig account: its transfers need signatures by `threshold` distinct keys.
+/// The public key of the account is the commitment to the keys and the threshold,
+/// nobody knows its private key
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Multisig {
+    pub threshold: u8,
+    /// (x, y) of every key
+    pub keys: Vec<(Fr, Fr)>,
+}
+
+impl Multisig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.keys.len() > params::MULTISIG_MAX_SIGNERS {
+            return Err(format!(
+                "too many multisig keys: {}, at most {} are allowed",
+                self.keys.len(),
+                params::MULTISIG_MAX_SIGNERS
+            ));
+        }
+        if self.threshold == 0 || usize::from(self.threshold) > self.keys.len() {
+            return Err(format!(
+                "threshold {} is out of range for {} keys",
+                self.threshold,
+                self.keys.len()
+            ));
+        }
+        for (i, key) in self.keys.iter().enumerate() {
+            // the identity point is the padding of the key set
+            if key.0.is_zero() || self.get_key(i).is_none() {
+                return Err(format!("multisig key {} is not a valid public key", i));
+            }
+            if self.keys[..i].contains(key) {
+                return Err(format!("multisig key {} is duplicated", i));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get_key(&self, index: usize) -> Option<PublicKey> {
+        let (x, y) = self.keys.get(index)?;
+        edwards::Point::<Engine, Unknown>::from_xy(*x, *y, &params::JUBJUB_PARAMS)
+            .map(sapling_crypto::eddsa::PublicKey::<Engine>)
+    }
+
+    /// Public key of the multisig account
+    pub fn commitment(&self) -> (Fr, Fr) {
+        circuit::multisig::multisig_commitment::<Engine>(self, &params::JUBJUB_PARAMS)
+    }
+}
+
+impl GetBits for Multisig {
+    /// Threshold and the packed keys, padded with the identity point up to MULTISIG_MAX_SIGNERS
+    fn get_bits_le(&self) -> Vec<bool> {
+        let mut bits = get_bits_le_fixed_u128(
+            u128::from(self.threshold),
+            params::MULTISIG_THRESHOLD_BIT_WIDTH,
+        );
+        for i in 0..params::MULTISIG_MAX_SIGNERS {
+            let (x, y) = self.keys.get(i).cloned().unwrap_or((Fr::zero(), Fr::one()));
+            bits.extend(y.get_bits_le_fixed(params::FR_BIT_WIDTH - 1));
+            bits.extend(x.get_bits_le_fixed(1));
+        }
+
+        bits
+    }
+}
+
+/// Funds locked to the recipient under the sha256 `hash`. The recipient gets them
+/// by revealing the preimage up to `good_until_block`, after that they are refunded
+/// to the account that locked them. An account has at most one lock at a time
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HashLock {
+    pub to: AccountId,
+    pub token: TokenId,
+    pub amount: BigDecimal,
+    pub hash: H256,
+    pub good_until_block: BlockNumber,
+}
+
+impl HashLock {
+    pub fn is_unlocked_by(&self, preimage: &H256) -> bool {
+        let mut h = Sha256::new();
+        h.input(&preimage[..]);
+        let mut hash = [0u8; 32];
+        h.result(&mut hash);
+
+        hash[..] == self.hash[..]
+    }
+}
+
+impl GetBits for HashLock {
+    /// Data of the lock the way it is committed to in the leaf,
+    /// the hash is kept in the bit order of SHA256
+    fn get_bits_le(&self) -> Vec<bool> {
+        let mut bits = Vec::new();
+        bits.extend(get_bits_le_fixed_u128(
+            u128::from(self.to),
+            params::BALANCE_TREE_DEPTH,
+        ));
+        bits.extend(get_bits_le_fixed_u128(
+            u128::from(self.token),
+            params::TOKEN_BIT_WIDTH,
+        ));
+        bits.extend(get_bits_le_fixed_big_decimal(
+            self.amount.clone(),
+            params::BALANCE_BIT_WIDTH,
+        ));
+        bits.extend(bytes_into_be_bits(&self.hash[..]));
+        bits.extend(get_bits_le_fixed_u128(
+            u128::from(self.good_until_block),
+            params::BLOCK_NUMBER_BIT_WIDTH,
+        ));
+
+        bits
+    }
+}
+
+/// Bit `i` of the window is set once the cheque with serial `start + i` is redeemed.
+/// Serials before the window can not be redeemed anymore, a serial past the window
+/// moves it forward by at most CHEQUE_WINDOW_SHIFT
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct ChequeWindow {
+    pub start: u32,
+    #[serde(with = "u128_as_string")]
+    pub bits: u128,
+}
+
+impl ChequeWindow {
+    /// Window after redemption of the serial, None if the serial is out of the window
+    /// or is already redeemed
+    pub fn redeem(&self, serial: u32) -> Option<ChequeWindow> {
+        if serial < self.start {
+            return None;
+        }
+
+        let distance = u64::from(serial - self.start);
+        let width = params::CHEQUE_WINDOW_BIT_WIDTH as u64;
+        let (start, bits, position) = if distance < width {
+            (self.start, self.bits, distance)
+        } else if distance < width + params::CHEQUE_WINDOW_SHIFT as u64 {
+            // shift the window so that the serial becomes its last bit
+            let shift = distance + 1 - width;
+            (self.start + shift as u32, self.bits >> shift, width - 1)
+        } else {
+            return None;
+        };
+
+        let mask = 1u128 << position;
+        if bits & mask != 0 {
+            return None;
+        }
+
+        Some(ChequeWindow {
+            start,
+            bits: bits | mask,
+        })
+    }
+}
+
+// json numbers are not wide enough for the window bits
+mod u128_as_string {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
 }
 
 impl GetBits for Account {
@@ -29,6 +259,24 @@ impl GetBits for Account {
 }
 
 impl Account {
+    pub fn get_balance(&self, token: TokenId) -> BigDecimal {
+        self.balances.get(&token).cloned().unwrap_or_default()
+    }
+
+    /// Zero balances are not stored, so that the account stays comparable to the default one
+    pub fn set_balance(&mut self, token: TokenId, amount: BigDecimal) {
+        if amount.is_zero() {
+            self.balances.remove(&token);
+        } else {
+            self.balances.insert(token, amount);
+        }
+    }
+
+    /// Empty account is the same as a never created one: no key, no balances and zero nonce
+    pub fn is_empty(&self) -> bool {
+        *self == Account::default()
+    }
+
     pub fn get_pub_key(&self) -> Option<PublicKey> {
         let point = edwards::Point::<Engine, Unknown>::from_xy(
             self.public_key_x,
@@ -44,3 +292,128 @@ fn test_default_account() {
     let a = Account::default();
     a.get_bits_le();
 }
+
+#[test]
+fn test_account_balances() {
+    let mut a = Account::default();
+    a.set_balance(1, BigDecimal::from(10));
+    assert_eq!(a.get_balance(1), BigDecimal::from(10));
+    assert_eq!(a.get_balance(0), BigDecimal::zero());
+
+    a.set_balance(1, BigDecimal::zero());
+    assert_eq!(a, Account::default());
+    assert!(a.is_empty());
+
+    a.nonce = 1;
+    assert!(!a.is_empty());
+}
+
+#[test]
+fn test_cheque_window() {
+    let width = params::CHEQUE_WINDOW_BIT_WIDTH as u32;
+    let shift = params::CHEQUE_WINDOW_SHIFT as u32;
+
+    let w = ChequeWindow::default().redeem(3).unwrap();
+    assert_eq!(w.start, 0);
+    assert_eq!(w.bits, 1 << 3);
+    assert!(w.redeem(3).is_none());
+
+    // the window moves so that the serial is its last bit
+    let moved = w.redeem(width + 1).unwrap();
+    assert_eq!(moved.start, 2);
+    assert_eq!(moved.bits, (1 << 1) | (1 << (width - 1)));
+    assert!(moved.redeem(1).is_none());
+    assert!(moved.redeem(3).is_none());
+    assert!(moved.redeem(4).is_some());
+
+    // too far ahead
+    assert!(w.redeem(width + shift).is_none());
+    assert!(w.redeem(width + shift - 1).is_some());
+}
+
+#[test]
+fn test_hash_lock_preimage() {
+    let preimage = H256::from([7u8; 32]);
+    let mut h = Sha256::new();
+    h.input(&preimage[..]);
+    let mut hash = [0u8; 32];
+    h.result(&mut hash);
+
+    let lock = HashLock {
+        to: 2,
+        token: 0,
+        amount: BigDecimal::from(10),
+        hash: H256::from(hash),
+        good_until_block: 100,
+    };
+    assert!(lock.is_unlocked_by(&preimage));
+    assert!(!lock.is_unlocked_by(&H256::zero()));
+    assert_eq!(
+        lock.get_bits_le().len(),
+        params::BALANCE_TREE_DEPTH
+            + params::TOKEN_BIT_WIDTH
+            + params::BALANCE_BIT_WIDTH
+            + params::HASH_LOCK_BIT_WIDTH
+            + params::BLOCK_NUMBER_BIT_WIDTH
+    );
+}
+
+#[test]
+fn test_multisig_signatures() {
+    use crate::plasma::tx::TransferTx;
+    use crate::PrivateKey;
+    use rand::Rng;
+    use sapling_crypto::jubjub::FixedGenerators;
+
+    let rng = &mut rand::thread_rng();
+    let p_g = FixedGenerators::SpendingKeyGenerator;
+    let sks: Vec<PrivateKey> = (0..params::MULTISIG_MAX_SIGNERS)
+        .map(|_| sapling_crypto::eddsa::PrivateKey(rng.gen()))
+        .collect();
+    let multisig = Multisig {
+        threshold: 2,
+        keys: sks
+            .iter()
+            .map(|sk| {
+                PublicKey::from_private(sk, p_g, &params::JUBJUB_PARAMS)
+                    .0
+                    .into_xy()
+            })
+            .collect(),
+    };
+    assert!(multisig.validate().is_ok());
+    assert!(Multisig {
+        threshold: 0,
+        ..multisig.clone()
+    }
+    .validate()
+    .is_err());
+    assert!(Multisig {
+        threshold: 1,
+        keys: vec![multisig.keys[0], multisig.keys[0]],
+    }
+    .validate()
+    .is_err());
+
+    let tx = TransferTx::create_signed_tx(
+        1,
+        2,
+        0,
+        BigDecimal::from(10),
+        BigDecimal::from(0),
+        0,
+        100,
+        0,
+        &sks[0],
+    );
+
+    let mut signed = tx.clone();
+    signed.sign_multisig(0, &sks[0]);
+    assert!(!signed.verify_multisig(&multisig));
+    signed.sign_multisig(2, &sks[2]);
+    assert!(signed.verify_multisig(&multisig));
+
+    // a signature by a wrong key is rejected even above the threshold
+    signed.sign_multisig(1, &sks[0]);
+    assert!(!signed.verify_multisig(&multisig));
+}
@@ -1,4 +1,6 @@
-pub use crate::plasma::tx::{DepositTx, ExitTx, TransferTx, TxSignature};
+pub use crate::plasma::tx::{
+    ChangePubKeyTx, ChequeTx, ClaimTx, DepositTx, ExitTx, LockTx, RefundTx, TransferTx, TxSignature,
+};
 use crate::plasma::{BatchNumber, BlockNumber, Fr};
 use bigdecimal::BigDecimal;
 
@@ -20,6 +22,21 @@ pub enum BlockData {
         transactions: Vec<ExitTx>,
         batch_number: BatchNumber,
     },
+    ChangePubKey {
+        transactions: Vec<ChangePubKeyTx>,
+    },
+    Cheque {
+        transactions: Vec<ChequeTx>,
+    },
+    Lock {
+        transactions: Vec<LockTx>,
+    },
+    Claim {
+        transactions: Vec<ClaimTx>,
+    },
+    Refund {
+        transactions: Vec<RefundTx>,
+    },
 }
 
 // #[derive(Clone, Serialize, Deserialize)]
@@ -1,50 +1,361 @@
+use crate::plasma::account::{HashLock, Multisig};
+use crate::plasma::circuit::lock::lock_commitment;
 use crate::plasma::params;
-use crate::primitives::{GetBits, GetBitsFixed};
+use crate::plasma::TokenId;
+use crate::primitives::{BitIteratorLe, GetBits, GetBitsFixed};
 use ff::{Field, PrimeField};
+use pairing::bn256::{Bn256, Fr};
 use sapling_crypto::alt_babyjubjub::JubjubEngine;
+use sapling_crypto::pedersen_hash::{baby_pedersen_hash, Personalization};
+use std::collections::BTreeMap;
+
+// Balances of an account live in a small sparse merkle tree of depth TOKEN_BIT_WIDTH,
+// indexed by token id. The account leaf commits to the root of this subtree.
+// Balance leafs are hashed like account leafs, internal nodes like the main tree nodes.
+
+lazy_static! {
+    /// Hashes of empty balance subtrees, computed once and shared by all account leafs
+    static ref EMPTY_BALANCE_HASHES: Vec<Fr> =
+        empty_balance_hashes::<Bn256>(&params::JUBJUB_PARAMS);
+    /// Root of the balance subtree of an account without any tokens
+    pub static ref EMPTY_BALANCES_ROOT: Fr = EMPTY_BALANCE_HASHES[params::TOKEN_BIT_WIDTH];
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CircuitAccount<E: JubjubEngine> {
-    pub balance: E::Fr,
+    /// Non-zero balances by token
+    pub balances: BTreeMap<TokenId, E::Fr>,
+    /// Root of the balance subtree, must be kept in sync with `balances`
+    pub balances_root: E::Fr,
     pub nonce: E::Fr,
     pub pub_x: E::Fr,
     pub pub_y: E::Fr,
+    /// Redeemed cheques window, see `plasma::account::ChequeWindow`
+    pub cheque_bits: E::Fr,
+    pub cheque_start: E::Fr,
+    /// Active hash lock of the account
+    pub hash_lock: Option<HashLock>,
+    /// Commitment to `hash_lock`, zero if there is no lock. Must be kept in sync with it
+    pub lock_commitment: E::Fr,
+    /// Keys of a multisig account. They are not a part of the leaf,
+    /// the public key of the account is their commitment
+    pub multisig: Option<Multisig>,
 }
 
-impl<E: JubjubEngine> std::default::Default for CircuitAccount<E> {
+impl std::default::Default for CircuitAccount<Bn256> {
     fn default() -> Self {
         Self {
-            balance: E::Fr::zero(),
-            nonce: E::Fr::zero(),
-            pub_x: E::Fr::zero(),
-            pub_y: E::Fr::zero(),
+            balances: BTreeMap::new(),
+            balances_root: *EMPTY_BALANCES_ROOT,
+            nonce: Fr::zero(),
+            pub_x: Fr::zero(),
+            pub_y: Fr::zero(),
+            cheque_bits: Fr::zero(),
+            cheque_start: Fr::zero(),
+            hash_lock: None,
+            lock_commitment: Fr::zero(),
+            multisig: None,
         }
     }
 }
 
-impl<E: JubjubEngine> GetBits for CircuitAccount<E> {
+// The compact layout needs the Jubjub params to hash the key, so leaves are Bn256 only
+impl GetBits for CircuitAccount<Bn256> {
     fn get_bits_le(&self) -> Vec<bool> {
         let mut leaf_content = Vec::new();
-        leaf_content.extend(self.balance.get_bits_le_fixed(params::BALANCE_BIT_WIDTH));
+        leaf_content.extend(self.balances_root.get_bits_le_fixed(params::FR_BIT_WIDTH));
         leaf_content.extend(self.nonce.get_bits_le_fixed(params::NONCE_BIT_WIDTH));
-        leaf_content.extend(self.pub_y.get_bits_le_fixed(params::FR_BIT_WIDTH - 1));
-        leaf_content.extend(self.pub_x.get_bits_le_fixed(1));
+        match leaf_pub_key_hash(&self.pub_x, &self.pub_y) {
+            Some(hash) => {
+                leaf_content.extend(hash.get_bits_le_fixed(params::PUB_KEY_HASH_BIT_WIDTH))
+            }
+            None => leaf_content.extend(packed_public_key::<Bn256>(&self.pub_x, &self.pub_y)),
+        }
+        let state_hash = leaf_state_hash::<Bn256>(
+            &self.cheque_bits,
+            &self.cheque_start,
+            &self.lock_commitment,
+            &params::JUBJUB_PARAMS,
+        );
+        leaf_content.extend(state_hash.get_bits_le_fixed(params::LEAF_STATE_HASH_BIT_WIDTH));
 
         leaf_content
     }
 }
 
+impl<E: JubjubEngine> CircuitAccount<E> {
+    pub fn get_balance(&self, token: TokenId) -> E::Fr {
+        self.balances
+            .get(&token)
+            .cloned()
+            .unwrap_or_else(E::Fr::zero)
+    }
+
+    /// Empty leaf is the same as a never created one: no key, no balances, zero nonce,
+    /// no redeemed cheques and no hash lock
+    pub fn is_empty(&self) -> bool {
+        self.balances.is_empty()
+            && self.nonce.is_zero()
+            && self.pub_x.is_zero()
+            && self.pub_y.is_zero()
+            && self.cheque_bits.is_zero()
+            && self.cheque_start.is_zero()
+            && self.lock_commitment.is_zero()
+    }
+
+    /// Sets or removes the hash lock and recalculates its commitment
+    pub fn set_hash_lock(&mut self, hash_lock: Option<HashLock>, jubjub_params: &E::Params) {
+        self.lock_commitment = match hash_lock {
+            Some(ref lock) => lock_commitment::<E>(lock, jubjub_params),
+            None => E::Fr::zero(),
+        };
+        self.hash_lock = hash_lock;
+    }
+
+    /// Sets the balance of the token and recalculates the balance subtree root
+    pub fn set_balance(&mut self, token: TokenId, balance: E::Fr, jubjub_params: &E::Params) {
+        assert!(token < (1 << params::TOKEN_BIT_WIDTH));
+        if balance.is_zero() {
+            self.balances.remove(&token);
+        } else {
+            self.balances.insert(token, balance);
+        }
+        self.balances_root = balances_root::<E>(&self.balances, jubjub_params);
+    }
+
+    /// Audit path of the token balance in the balance subtree, from the leaf up
+    pub fn balance_path(&self, token: TokenId, jubjub_params: &E::Params) -> Vec<E::Fr> {
+        assert!(token < (1 << params::TOKEN_BIT_WIDTH));
+        let empty = empty_balance_hashes::<E>(jubjub_params);
+        let levels = balance_subtree_levels::<E>(&self.balances, &empty, jubjub_params);
+
+        (0..params::TOKEN_BIT_WIDTH)
+            .map(|level| {
+                let sibling = (token >> level) ^ 1;
+                levels[level].get(&sibling).cloned().unwrap_or(empty[level])
+            })
+            .collect()
+    }
+}
+
+/// Public key as it is packed into the leaf of the default layout: y and the parity of x
+pub fn packed_public_key<E: JubjubEngine>(pub_x: &E::Fr, pub_y: &E::Fr) -> Vec<bool> {
+    let mut bits = pub_y.get_bits_le_fixed(params::FR_BIT_WIDTH - 1);
+    bits.extend(pub_x.get_bits_le_fixed(1));
+    bits
+}
+
+/// Hash of the packed public key, the compact layout keeps PUB_KEY_HASH_BIT_WIDTH bits of it
+/// in the leaf. The empty key (0, 0) is hashed like any other
+pub fn pub_key_hash<E: JubjubEngine>(
+    pub_x: &E::Fr,
+    pub_y: &E::Fr,
+    jubjub_params: &E::Params,
+) -> E::Fr {
+    baby_pedersen_hash::<E, _>(
+        Personalization::NoteCommitment,
+        packed_public_key::<E>(pub_x, pub_y),
+        jubjub_params,
+    )
+    .into_xy()
+    .0
+}
+
+/// Public key hash as a compact leaf stores it, `None` for the default layout that packs
+/// the key itself. Leaf witnesses carry it
+pub fn leaf_pub_key_hash(pub_x: &Fr, pub_y: &Fr) -> Option<Fr> {
+    if params::COMPACT_LEAF {
+        Some(pub_key_hash::<Bn256>(pub_x, pub_y, &params::JUBJUB_PARAMS))
+    } else {
+        None
+    }
+}
+
+/// Hash of the cheque window and the lock commitment, the leaf keeps
+/// LEAF_STATE_HASH_BIT_WIDTH bits of it
+pub fn leaf_state_hash<E: JubjubEngine>(
+    cheque_bits: &E::Fr,
+    cheque_start: &E::Fr,
+    lock_commitment: &E::Fr,
+    jubjub_params: &E::Params,
+) -> E::Fr {
+    let mut bits = cheque_bits.get_bits_le_fixed(params::CHEQUE_WINDOW_BIT_WIDTH);
+    bits.extend(cheque_start.get_bits_le_fixed(params::CHEQUE_SERIAL_BIT_WIDTH));
+    bits.extend(lock_commitment.get_bits_le_fixed(params::FR_BIT_WIDTH));
+    baby_pedersen_hash::<E, _>(Personalization::NoteCommitment, bits, jubjub_params)
+        .into_xy()
+        .0
+}
+
+/// Hash of a balance leaf of the balance subtree
+pub fn balance_leaf_hash<E: JubjubEngine>(balance: &E::Fr, jubjub_params: &E::Params) -> E::Fr {
+    baby_pedersen_hash::<E, _>(
+        Personalization::NoteCommitment,
+        balance.get_bits_le_fixed(params::BALANCE_BIT_WIDTH),
+        jubjub_params,
+    )
+    .into_xy()
+    .0
+}
+
+fn balance_node_hash<E: JubjubEngine>(
+    lhs: &E::Fr,
+    rhs: &E::Fr,
+    level: usize,
+    jubjub_params: &E::Params,
+) -> E::Fr {
+    let lhs = BitIteratorLe::new(lhs.into_repr()).take(E::Fr::NUM_BITS as usize);
+    let rhs = BitIteratorLe::new(rhs.into_repr()).take(E::Fr::NUM_BITS as usize);
+    baby_pedersen_hash::<E, _>(
+        Personalization::MerkleTree(level),
+        lhs.chain(rhs),
+        jubjub_params,
+    )
+    .into_xy()
+    .0
+}
+
+/// Hashes of empty balance subtrees, from a single leaf (index 0) up to the root
+pub fn empty_balance_hashes<E: JubjubEngine>(jubjub_params: &E::Params) -> Vec<E::Fr> {
+    let mut cur = balance_leaf_hash::<E>(&E::Fr::zero(), jubjub_params);
+    let mut hashes = Vec::with_capacity(params::TOKEN_BIT_WIDTH + 1);
+    hashes.push(cur);
+    for level in 0..params::TOKEN_BIT_WIDTH {
+        cur = balance_node_hash::<E>(&cur, &cur, level, jubjub_params);
+        hashes.push(cur);
+    }
+    hashes
+}
+
+/// Non-empty nodes of the balance subtree, level by level from the leafs up to the root
+fn balance_subtree_levels<E: JubjubEngine>(
+    balances: &BTreeMap<TokenId, E::Fr>,
+    empty: &[E::Fr],
+    jubjub_params: &E::Params,
+) -> Vec<BTreeMap<TokenId, E::Fr>> {
+    let mut levels = Vec::with_capacity(params::TOKEN_BIT_WIDTH + 1);
+    levels.push(
+        balances
+            .iter()
+            .map(|(token, balance)| (*token, balance_leaf_hash::<E>(balance, jubjub_params)))
+            .collect::<BTreeMap<_, _>>(),
+    );
+
+    for level in 0..params::TOKEN_BIT_WIDTH {
+        let mut parents = BTreeMap::new();
+        {
+            let nodes = &levels[level];
+            for index in nodes.keys() {
+                let parent = index >> 1;
+                if parents.contains_key(&parent) {
+                    continue;
+                }
+                let lhs = nodes.get(&(parent << 1)).unwrap_or(&empty[level]);
+                let rhs = nodes.get(&((parent << 1) | 1)).unwrap_or(&empty[level]);
+                parents.insert(
+                    parent,
+                    balance_node_hash::<E>(lhs, rhs, level, jubjub_params),
+                );
+            }
+        }
+        levels.push(parents);
+    }
+
+    levels
+}
+
+/// Root of the balance subtree
+pub fn balances_root<E: JubjubEngine>(
+    balances: &BTreeMap<TokenId, E::Fr>,
+    jubjub_params: &E::Params,
+) -> E::Fr {
+    let empty = empty_balance_hashes::<E>(jubjub_params);
+    subtree_root::<E>(balances, &empty, jubjub_params)
+}
+
+fn subtree_root<E: JubjubEngine>(
+    balances: &BTreeMap<TokenId, E::Fr>,
+    empty: &[E::Fr],
+    jubjub_params: &E::Params,
+) -> E::Fr {
+    let levels = balance_subtree_levels::<E>(balances, empty, jubjub_params);
+    levels[params::TOKEN_BIT_WIDTH]
+        .get(&0)
+        .cloned()
+        .unwrap_or(empty[params::TOKEN_BIT_WIDTH])
+}
+
 // TODO: this is ugly; the correct way is to introduce Serialize/Deserialize interface into JubjubEngine::Fr
 // this requires deduplication of JubjubEngines
-impl std::convert::From<crate::plasma::Account> for CircuitAccount<pairing::bn256::Bn256> {
+impl std::convert::From<crate::plasma::Account> for CircuitAccount<Bn256> {
     fn from(a: crate::plasma::Account) -> Self {
-        use pairing::bn256::Fr;
+        let balances = a
+            .balances
+            .iter()
+            .map(|(token, balance)| (*token, Fr::from_str(&balance.to_string()).unwrap()))
+            .collect();
+        let balances_root =
+            subtree_root::<Bn256>(&balances, &EMPTY_BALANCE_HASHES, &params::JUBJUB_PARAMS);
 
-        Self {
-            balance: Fr::from_str(&a.balance.to_string()).unwrap(),
+        let mut account = Self {
+            balances,
+            balances_root,
             nonce: Fr::from_str(&a.nonce.to_string()).unwrap(),
             pub_x: a.public_key_x,
             pub_y: a.public_key_y,
+            cheque_bits: Fr::from_str(&a.cheque_window.bits.to_string()).unwrap(),
+            cheque_start: Fr::from_str(&a.cheque_window.start.to_string()).unwrap(),
+            hash_lock: None,
+            lock_commitment: Fr::zero(),
+            multisig: a.multisig,
+        };
+        account.set_hash_lock(a.hash_lock, &params::JUBJUB_PARAMS);
+
+        account
+    }
+}
+
+#[test]
+fn test_balances_root() {
+    let jubjub_params = &params::JUBJUB_PARAMS;
+    let mut account = CircuitAccount::<Bn256>::default();
+    assert_eq!(
+        account.balances_root,
+        balances_root::<Bn256>(&BTreeMap::new(), jubjub_params)
+    );
+
+    account.set_balance(3, Fr::from_str("100").unwrap(), jubjub_params);
+    account.set_balance(200, Fr::from_str("5").unwrap(), jubjub_params);
+    assert_ne!(account.balances_root, *EMPTY_BALANCES_ROOT);
+
+    // climbing the audit path from the balance leaf must give the root
+    for token in [3, 200, 7].iter() {
+        let path = account.balance_path(*token, jubjub_params);
+        let mut cur = balance_leaf_hash::<Bn256>(&account.get_balance(*token), jubjub_params);
+        for (level, sibling) in path.iter().enumerate() {
+            cur = if (token >> level) & 1 == 1 {
+                balance_node_hash::<Bn256>(sibling, &cur, level, jubjub_params)
+            } else {
+                balance_node_hash::<Bn256>(&cur, sibling, level, jubjub_params)
+            };
         }
+        assert_eq!(cur, account.balances_root);
     }
+
+    // zeroing the balances returns to the empty root
+    account.set_balance(3, Fr::zero(), jubjub_params);
+    account.set_balance(200, Fr::zero(), jubjub_params);
+    assert_eq!(account.balances_root, *EMPTY_BALANCES_ROOT);
+}
+
+#[test]
+fn test_leaf_layout() {
+    let leaf_bits = CircuitAccount::<Bn256>::default().get_bits_le();
+    assert_eq!(
+        leaf_bits.len(),
+        params::FR_BIT_WIDTH
+            + params::NONCE_BIT_WIDTH
+            + params::PUB_KEY_LEAF_BIT_WIDTH
+            + params::LEAF_STATE_HASH_BIT_WIDTH
+    );
 }
@@ -0,0 +1,65 @@
+use crate::plasma::circuit::sig::TransactionSignature;
+use crate::plasma::params;
+use crate::primitives::GetBitsFixed;
+use ff::{BitIterator, PrimeField};
+use sapling_crypto::alt_babyjubjub::JubjubEngine;
+use sapling_crypto::pedersen_hash::{baby_pedersen_hash, Personalization};
+
+#[derive(Clone)]
+pub struct ChangePubKeyRequest<E: JubjubEngine> {
+    pub account: E::Fr,
+    pub new_pub_x: E::Fr,
+    pub new_pub_y: E::Fr,
+    pub nonce: E::Fr,
+    /// Deployment the request is signed for, see `RuntimeConfig::chain_id`
+    pub chain_id: E::Fr,
+    pub signature: TransactionSignature<E>,
+}
+
+impl<E: JubjubEngine> ChangePubKeyRequest<E> {
+    // this function returns public data in Ethereum compatible format
+    pub fn public_data_into_bits(&self) -> Vec<bool> {
+        // fields are
+        // - account
+        // - compressed new public key
+        let mut account: Vec<bool> = BitIterator::new(self.account.into_repr()).collect();
+        account.reverse();
+        account.truncate(params::BALANCE_TREE_DEPTH);
+        // reverse again to have BE as in Ethereum native types
+        account.reverse();
+
+        let mut y_bits: Vec<bool> = BitIterator::new(self.new_pub_y.into_repr()).collect();
+        y_bits.reverse();
+        y_bits.truncate(E::Fr::NUM_BITS as usize);
+        y_bits.resize(params::FR_BIT_WIDTH - 1, false);
+
+        let mut x_bits: Vec<bool> = BitIterator::new(self.new_pub_x.into_repr()).collect();
+        x_bits.reverse();
+        // push sign bit
+        y_bits.push(x_bits[0]);
+        // reverse again to have BE as in Ethereum native types
+        y_bits.reverse();
+
+        let mut packed: Vec<bool> = vec![];
+        packed.extend(account.into_iter());
+        packed.extend(y_bits.into_iter());
+
+        packed
+    }
+}
+
+/// LE bits of the hash of a packed public key, truncated to NEW_PUBKEY_HASH_WIDTH.
+/// The whole key does not fit into a signed message, so key change signatures commit to this hash
+pub fn pub_key_hash_bits<E: JubjubEngine>(
+    pub_x: &E::Fr,
+    pub_y: &E::Fr,
+    jubjub_params: &E::Params,
+) -> Vec<bool> {
+    let mut packed = pub_y.get_bits_le_fixed(params::FR_BIT_WIDTH - 1);
+    packed.extend(pub_x.get_bits_le_fixed(1));
+
+    baby_pedersen_hash::<E, _>(Personalization::NoteCommitment, packed, jubjub_params)
+        .into_xy()
+        .0
+        .get_bits_le_fixed(params::NEW_PUBKEY_HASH_WIDTH)
+}
@@ -0,0 +1,62 @@
+use crate::plasma::circuit::sig::TransactionSignature;
+use crate::plasma::params;
+use ff::{BitIterator, PrimeField};
+use sapling_crypto::alt_babyjubjub::JubjubEngine;
+
+#[derive(Clone)]
+pub struct ChequeRequest<E: JubjubEngine> {
+    pub from: E::Fr,
+    pub to: E::Fr,
+    pub token: E::Fr,
+    pub amount: E::Fr, // packed
+    pub serial: E::Fr,
+    pub good_until_block: E::Fr,
+    /// Deployment the request is signed for, see `RuntimeConfig::chain_id`
+    pub chain_id: E::Fr,
+    pub signature: TransactionSignature<E>,
+}
+
+impl<E: JubjubEngine> ChequeRequest<E> {
+    // this function returns public data in Ethereum compatible format
+    pub fn public_data_into_bits(&self) -> Vec<bool> {
+        // fields are
+        // - from
+        // - to
+        // - token
+        // - amount
+        // - serial
+        let mut from: Vec<bool> = BitIterator::new(self.from.into_repr()).collect();
+        from.reverse();
+        from.truncate(params::BALANCE_TREE_DEPTH);
+        from.reverse();
+
+        let mut to: Vec<bool> = BitIterator::new(self.to.into_repr()).collect();
+        to.reverse();
+        to.truncate(params::BALANCE_TREE_DEPTH);
+        to.reverse();
+
+        let mut token: Vec<bool> = BitIterator::new(self.token.into_repr()).collect();
+        token.reverse();
+        token.truncate(params::TOKEN_BIT_WIDTH);
+        token.reverse();
+
+        // packed amount is kept LE, the same as in transfers
+        let mut amount: Vec<bool> = BitIterator::new(self.amount.into_repr()).collect();
+        amount.reverse();
+        amount.truncate(params::AMOUNT_EXPONENT_BIT_WIDTH + params::AMOUNT_MANTISSA_BIT_WIDTH);
+
+        let mut serial: Vec<bool> = BitIterator::new(self.serial.into_repr()).collect();
+        serial.reverse();
+        serial.truncate(params::CHEQUE_SERIAL_BIT_WIDTH);
+        serial.reverse();
+
+        let mut packed: Vec<bool> = vec![];
+        packed.extend(from.into_iter());
+        packed.extend(to.into_iter());
+        packed.extend(token.into_iter());
+        packed.extend(amount.into_iter());
+        packed.extend(serial.into_iter());
+
+        packed
+    }
+}
@@ -5,6 +5,7 @@ use sapling_crypto::jubjub::JubjubEngine;
 #[derive(Clone)]
 pub struct DepositRequest<E: JubjubEngine> {
     pub into: E::Fr,
+    pub token: E::Fr,
     pub amount: E::Fr,
     pub pub_x: E::Fr,
     pub pub_y: E::Fr,
@@ -15,6 +16,7 @@ impl<E: JubjubEngine> DepositRequest<E> {
     pub fn public_data_into_bits(&self) -> Vec<bool> {
         // fields are
         // - into
+        // - token
         // - amount
         // - compressed public key
         let mut into: Vec<bool> = BitIterator::new(self.into.into_repr()).collect();
@@ -23,6 +25,11 @@ impl<E: JubjubEngine> DepositRequest<E> {
         // reverse again to have BE as in Ethereum native types
         into.reverse();
 
+        let mut token: Vec<bool> = BitIterator::new(self.token.into_repr()).collect();
+        token.reverse();
+        token.truncate(params::TOKEN_BIT_WIDTH);
+        token.reverse();
+
         let mut amount: Vec<bool> = BitIterator::new(self.amount.into_repr()).collect();
         amount.reverse();
         amount.truncate(params::BALANCE_BIT_WIDTH);
@@ -43,6 +50,7 @@ impl<E: JubjubEngine> DepositRequest<E> {
 
         let mut packed: Vec<bool> = vec![];
         packed.extend(into.into_iter());
+        packed.extend(token.into_iter());
         packed.extend(amount.into_iter());
         packed.extend(y_bits.into_iter());
 
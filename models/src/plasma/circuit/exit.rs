@@ -7,6 +7,7 @@ use crate::plasma::params;
 #[derive(Clone)]
 pub struct ExitRequest<E: JubjubEngine> {
     pub from: E::Fr,
+    pub token: E::Fr,
     pub amount: E::Fr,
 }
 
@@ -14,6 +15,7 @@ impl<E: JubjubEngine> ExitRequest<E> {
     pub fn public_data_into_bits(&self) -> Vec<bool> {
         // fields are
         // - from
+        // - token
         // - amount
         let mut from: Vec<bool> = BitIterator::new(self.from.into_repr()).collect();
         from.reverse();
@@ -21,6 +23,11 @@ impl<E: JubjubEngine> ExitRequest<E> {
         // reverse again to have BE as in Ethereum native types
         from.reverse();
 
+        let mut token: Vec<bool> = BitIterator::new(self.token.into_repr()).collect();
+        token.reverse();
+        token.truncate(params::TOKEN_BIT_WIDTH);
+        token.reverse();
+
         let mut amount: Vec<bool> = BitIterator::new(self.amount.into_repr()).collect();
         amount.reverse();
         amount.truncate(params::BALANCE_BIT_WIDTH);
@@ -29,6 +36,7 @@ impl<E: JubjubEngine> ExitRequest<E> {
 
         let mut packed: Vec<bool> = vec![];
         packed.extend(from.into_iter());
+        packed.extend(token.into_iter());
         packed.extend(amount.into_iter());
 
         packed
@@ -0,0 +1,87 @@
+use crate::plasma::account::HashLock;
+use crate::plasma::circuit::sig::TransactionSignature;
+use crate::plasma::params;
+use crate::primitives::{bytes_into_be_bits, GetBits, GetBitsFixed};
+use ff::{BitIterator, PrimeField};
+use sapling_crypto::alt_babyjubjub::JubjubEngine;
+use sapling_crypto::pedersen_hash::{baby_pedersen_hash, Personalization};
+
+#[derive(Clone)]
+pub struct LockRequest<E: JubjubEngine> {
+    pub account: E::Fr,
+    pub to: E::Fr,
+    pub token: E::Fr,
+    pub amount: E::Fr, // packed
+    pub hash: [u8; 32],
+    pub good_until_block: E::Fr,
+    pub nonce: E::Fr,
+    /// Deployment the request is signed for, see `RuntimeConfig::chain_id`
+    pub chain_id: E::Fr,
+    pub signature: TransactionSignature<E>,
+}
+
+impl<E: JubjubEngine> LockRequest<E> {
+    // this function returns public data in Ethereum compatible format
+    pub fn public_data_into_bits(&self) -> Vec<bool> {
+        // fields are
+        // - account
+        // - to
+        // - token
+        // - amount
+        // - hash
+        // - good_until_block
+        let mut account: Vec<bool> = BitIterator::new(self.account.into_repr()).collect();
+        account.reverse();
+        account.truncate(params::BALANCE_TREE_DEPTH);
+        // reverse again to have BE as in Ethereum native types
+        account.reverse();
+
+        let mut to: Vec<bool> = BitIterator::new(self.to.into_repr()).collect();
+        to.reverse();
+        to.truncate(params::BALANCE_TREE_DEPTH);
+        to.reverse();
+
+        let mut token: Vec<bool> = BitIterator::new(self.token.into_repr()).collect();
+        token.reverse();
+        token.truncate(params::TOKEN_BIT_WIDTH);
+        token.reverse();
+
+        // packed amount is kept LE, the same as in transfers
+        let mut amount: Vec<bool> = BitIterator::new(self.amount.into_repr()).collect();
+        amount.reverse();
+        amount.truncate(params::AMOUNT_EXPONENT_BIT_WIDTH + params::AMOUNT_MANTISSA_BIT_WIDTH);
+
+        let mut good_until_block: Vec<bool> =
+            BitIterator::new(self.good_until_block.into_repr()).collect();
+        good_until_block.reverse();
+        good_until_block.truncate(params::BLOCK_NUMBER_BIT_WIDTH);
+        good_until_block.reverse();
+
+        let mut packed: Vec<bool> = vec![];
+        packed.extend(account.into_iter());
+        packed.extend(to.into_iter());
+        packed.extend(token.into_iter());
+        packed.extend(amount.into_iter());
+        packed.extend(bytes_into_be_bits(&self.hash));
+        packed.extend(good_until_block.into_iter());
+
+        packed
+    }
+}
+
+/// Commitment to the hash lock that is kept in the account leaf
+pub fn lock_commitment<E: JubjubEngine>(lock: &HashLock, jubjub_params: &E::Params) -> E::Fr {
+    baby_pedersen_hash::<E, _>(
+        Personalization::NoteCommitment,
+        lock.get_bits_le(),
+        jubjub_params,
+    )
+    .into_xy()
+    .0
+}
+
+/// LE bits of the lock commitment, truncated to LOCK_COMMITMENT_HASH_WIDTH.
+/// The lock does not fit into a signed message, so lock signatures commit to this hash
+pub fn lock_commitment_hash_bits<E: JubjubEngine>(commitment: &E::Fr) -> Vec<bool> {
+    commitment.get_bits_le_fixed(params::LOCK_COMMITMENT_HASH_WIDTH)
+}
@@ -1,6 +1,11 @@
 pub mod account;
+pub mod change_pubkey;
+pub mod cheque;
 pub mod deposit;
 pub mod exit;
+pub mod lock;
+pub mod multisig;
 pub mod sig;
 pub mod transfer;
+pub mod unlock;
 pub mod utils;
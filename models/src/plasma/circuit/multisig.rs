@@ -0,0 +1,33 @@
+use crate::plasma::account::Multisig;
+use crate::plasma::params;
+use crate::primitives::{BitIteratorLe, GetBits};
+use ff::PrimeField;
+use sapling_crypto::alt_babyjubjub::JubjubEngine;
+use sapling_crypto::pedersen_hash::{baby_pedersen_hash, Personalization};
+
+/// Public key of a multisig account: the point of the last of a chain of hashes, the first
+/// one over the threshold and the first key, each next one over the x of the previous hash
+/// and the next key. The threshold and all the keys are more bits than the Pedersen hash
+/// gadget takes. Its discrete logarithm is unknown, so the account can not sign with it
+pub fn multisig_commitment<E: JubjubEngine>(
+    multisig: &Multisig,
+    jubjub_params: &E::Params,
+) -> (E::Fr, E::Fr) {
+    let bits = multisig.get_bits_le();
+    let (threshold_bits, key_bits) = bits.split_at(params::MULTISIG_THRESHOLD_BIT_WIDTH);
+
+    let mut chained = threshold_bits.to_vec();
+    let mut commitment = None;
+    for key in key_bits.chunks(params::FR_BIT_WIDTH) {
+        chained.extend_from_slice(key);
+        let (x, y) =
+            baby_pedersen_hash::<E, _>(Personalization::NoteCommitment, chained, jubjub_params)
+                .into_xy();
+        chained = BitIteratorLe::new(x.into_repr())
+            .take(E::Fr::NUM_BITS as usize)
+            .collect();
+        commitment = Some((x, y));
+    }
+
+    commitment.expect("a multisig account has keys")
+}
@@ -1,6 +1,6 @@
 use ff::Field;
 use sapling_crypto::alt_babyjubjub::JubjubEngine;
-use sapling_crypto::jubjub::{edwards, Unknown};
+use sapling_crypto::jubjub::{edwards, FixedGenerators, JubjubParams, Unknown};
 
 // use crate::models::params;
 
@@ -12,12 +12,15 @@ pub struct TransactionSignature<E: JubjubEngine> {
 }
 
 impl<E: JubjubEngine> TransactionSignature<E> {
-    pub fn empty() -> Self {
-        let empty_point: edwards::Point<E, Unknown> = edwards::Point::zero();
+    /// Signature of the identity point for any message: R is the generator and s is one,
+    /// so s * G == R + h * identity. R can not be the identity itself, the circuit
+    /// refuses an R of small order
+    pub fn empty(params: &E::Params) -> Self {
+        let r: edwards::Point<E, Unknown> = params
+            .generator(FixedGenerators::SpendingKeyGenerator)
+            .clone()
+            .into();
 
-        Self {
-            r: empty_point,
-            s: E::Fr::zero(),
-        }
+        Self { r, s: E::Fr::one() }
     }
 }
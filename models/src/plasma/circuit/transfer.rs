@@ -1,23 +1,34 @@
+use crate::plasma::circuit::account::packed_public_key;
 use crate::plasma::circuit::sig::TransactionSignature;
 use crate::plasma::circuit::utils::le_bit_vector_into_field_element;
 use crate::plasma::params;
-use ff::{BitIterator, PrimeField};
+use crate::primitives::{get_bits_le_fixed_u128, GetBitsFixed};
+use ff::{BitIterator, Field, PrimeField};
 use sapling_crypto::alt_babyjubjub::JubjubEngine;
 use sapling_crypto::eddsa::{PrivateKey, PublicKey};
 use sapling_crypto::jubjub::FixedGenerators;
+use sapling_crypto::pedersen_hash::{baby_pedersen_hash, Personalization};
 
 /// Packed transaction data
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Tx<E: JubjubEngine> {
     pub from: E::Fr,
     pub to: E::Fr,
+    pub token: E::Fr,
     pub amount: E::Fr, // packed, TODO: document it here
     pub fee: E::Fr,    // packed
     pub nonce: E::Fr,
     pub good_until_block: E::Fr,
+    /// Deployment the transfer is signed for, see `RuntimeConfig::chain_id`
+    pub chain_id: E::Fr,
 
     #[serde(bound = "")]
     pub signature: TransactionSignature<E>,
+    /// Signatures by the keys of a multisig sender, see `TransferTx::multisig_signatures`
+    #[serde(bound = "")]
+    pub multisig_signatures: Vec<Option<TransactionSignature<E>>>,
+    /// Key of a new recipient account, see `TransferTx::recipient_pub_key`
+    pub recipient_pub_key: Option<(E::Fr, E::Fr)>,
 }
 
 impl<E: JubjubEngine> Tx<E> {
@@ -25,8 +36,24 @@ impl<E: JubjubEngine> Tx<E> {
         // fields are
         // - from
         // - to
+        // - token
         // - amount
         // - fee
+        // - key of a new recipient account
+        let mut packed = self.transfer_data_into_bits();
+
+        // the key is published as by a key change, zeros if the recipient is not new
+        let (new_pub_x, new_pub_y) = self
+            .recipient_pub_key
+            .unwrap_or((E::Fr::zero(), E::Fr::zero()));
+        let mut new_pub_key = packed_public_key::<E>(&new_pub_x, &new_pub_y);
+        new_pub_key.reverse();
+        packed.extend(new_pub_key);
+
+        packed
+    }
+
+    fn transfer_data_into_bits(&self) -> Vec<bool> {
         let mut from: Vec<bool> = BitIterator::new(self.from.into_repr()).collect();
         from.reverse();
         from.truncate(params::BALANCE_TREE_DEPTH);
@@ -37,6 +64,11 @@ impl<E: JubjubEngine> Tx<E> {
         to.truncate(params::BALANCE_TREE_DEPTH);
         to.reverse();
 
+        let mut token: Vec<bool> = BitIterator::new(self.token.into_repr()).collect();
+        token.reverse();
+        token.truncate(params::TOKEN_BIT_WIDTH);
+        token.reverse();
+
         let mut amount: Vec<bool> = BitIterator::new(self.amount.into_repr()).collect();
         amount.reverse();
         amount.truncate(params::AMOUNT_EXPONENT_BIT_WIDTH + params::AMOUNT_MANTISSA_BIT_WIDTH);
@@ -47,20 +79,24 @@ impl<E: JubjubEngine> Tx<E> {
         let mut packed: Vec<bool> = vec![];
         packed.extend(from.into_iter());
         packed.extend(to.into_iter());
+        packed.extend(token.into_iter());
         packed.extend(amount.into_iter());
         packed.extend(fee.into_iter());
 
         packed
     }
 
-    pub fn data_for_signature_into_bits(&self) -> Vec<bool> {
+    pub fn data_for_signature_into_bits(&self, jubjub_params: &E::Params) -> Vec<bool> {
         // fields are
         // - from
         // - to
+        // - token
         // - amount
         // - fee
         // - nonce
         // - good_until_block
+        // - chain_id
+        // hashed with the key of a new recipient account
         let mut nonce: Vec<bool> = BitIterator::new(self.nonce.into_repr()).collect();
         nonce.reverse();
         nonce.truncate(params::NONCE_BIT_WIDTH);
@@ -68,17 +104,26 @@ impl<E: JubjubEngine> Tx<E> {
             BitIterator::new(self.good_until_block.into_repr()).collect();
         good_until_block.reverse();
         good_until_block.truncate(params::BLOCK_NUMBER_BIT_WIDTH);
+        let mut chain_id: Vec<bool> = BitIterator::new(self.chain_id.into_repr()).collect();
+        chain_id.reverse();
+        chain_id.truncate(params::CHAIN_ID_BIT_WIDTH);
         let mut packed: Vec<bool> = vec![];
 
-        packed.extend(self.public_data_into_bits().into_iter());
+        packed.extend(self.transfer_data_into_bits().into_iter());
         packed.extend(nonce.into_iter());
         packed.extend(good_until_block.into_iter());
+        packed.extend(chain_id.into_iter());
 
-        packed
+        match self.recipient_pub_key {
+            Some((new_pub_x, new_pub_y)) => {
+                new_account_message_bits::<E>(packed, &new_pub_x, &new_pub_y, jubjub_params)
+            }
+            None => packed,
+        }
     }
 
-    pub fn data_as_bytes(&self) -> Vec<u8> {
-        let raw_data: Vec<bool> = self.data_for_signature_into_bits();
+    pub fn data_as_bytes(&self, jubjub_params: &E::Params) -> Vec<u8> {
+        let raw_data: Vec<bool> = self.data_for_signature_into_bits(jubjub_params);
 
         let mut message_bytes: Vec<u8> = vec![];
 
@@ -105,16 +150,22 @@ impl<E: JubjubEngine> Tx<E> {
     ) where
         R: rand::Rng,
     {
-        let message_bytes = self.data_as_bytes();
-
-        let max_message_len = params::BALANCE_TREE_DEPTH
-            + params::BALANCE_TREE_DEPTH
-            + params::AMOUNT_EXPONENT_BIT_WIDTH
-            + params::AMOUNT_MANTISSA_BIT_WIDTH
-            + params::FEE_EXPONENT_BIT_WIDTH
-            + params::FEE_MANTISSA_BIT_WIDTH
-            + params::NONCE_BIT_WIDTH
-            + params::BLOCK_NUMBER_BIT_WIDTH;
+        let message_bytes = self.data_as_bytes(params);
+
+        let max_message_len = if self.recipient_pub_key.is_some() {
+            params::NEW_ACCOUNT_MESSAGE_HASH_WIDTH + params::NEW_ACCOUNT_MESSAGE_MARKER_WIDTH
+        } else {
+            params::BALANCE_TREE_DEPTH
+                + params::BALANCE_TREE_DEPTH
+                + params::TOKEN_BIT_WIDTH
+                + params::AMOUNT_EXPONENT_BIT_WIDTH
+                + params::AMOUNT_MANTISSA_BIT_WIDTH
+                + params::FEE_EXPONENT_BIT_WIDTH
+                + params::FEE_MANTISSA_BIT_WIDTH
+                + params::NONCE_BIT_WIDTH
+                + params::BLOCK_NUMBER_BIT_WIDTH
+                + params::CHAIN_ID_BIT_WIDTH
+        };
 
         let signature =
             private_key.sign_raw_message(&message_bytes, rng, p_g, params, max_message_len / 8);
@@ -144,3 +195,27 @@ impl<E: JubjubEngine> Tx<E> {
         self.signature = converted_signature;
     }
 }
+
+/// Message signed by a transfer to a new account, from the message of the transfer and
+/// the key of the recipient: NEW_ACCOUNT_MESSAGE_HASH_WIDTH bits of their hash and the marker
+pub fn new_account_message_bits<E: JubjubEngine>(
+    message: Vec<bool>,
+    pub_x: &E::Fr,
+    pub_y: &E::Fr,
+    jubjub_params: &E::Params,
+) -> Vec<bool> {
+    let mut preimage = message;
+    preimage.extend(packed_public_key::<E>(pub_x, pub_y));
+
+    let mut bits =
+        baby_pedersen_hash::<E, _>(Personalization::NoteCommitment, preimage, jubjub_params)
+            .into_xy()
+            .0
+            .get_bits_le_fixed(params::NEW_ACCOUNT_MESSAGE_HASH_WIDTH);
+    bits.extend(get_bits_le_fixed_u128(
+        1,
+        params::NEW_ACCOUNT_MESSAGE_MARKER_WIDTH,
+    ));
+
+    bits
+}
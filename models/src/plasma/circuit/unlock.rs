@@ -0,0 +1,37 @@
+use crate::plasma::params;
+use crate::primitives::bytes_into_be_bits;
+use ff::{BitIterator, PrimeField};
+use sapling_crypto::alt_babyjubjub::JubjubEngine;
+
+/// Releases the hash lock of the account: to the recipient of the lock if `is_claim`,
+/// back to the account otherwise
+#[derive(Clone)]
+pub struct UnlockRequest<E: JubjubEngine> {
+    pub account: E::Fr,
+    pub is_claim: bool,
+    // zero for refunds
+    pub preimage: [u8; 32],
+}
+
+impl<E: JubjubEngine> UnlockRequest<E> {
+    // this function returns public data in Ethereum compatible format
+    pub fn public_data_into_bits(&self) -> Vec<bool> {
+        // fields are
+        // - account
+        // - claim flag as a byte
+        // - preimage
+        let mut account: Vec<bool> = BitIterator::new(self.account.into_repr()).collect();
+        account.reverse();
+        account.truncate(params::BALANCE_TREE_DEPTH);
+        // reverse again to have BE as in Ethereum native types
+        account.reverse();
+
+        let mut packed: Vec<bool> = vec![];
+        packed.extend(account.into_iter());
+        packed.extend(vec![false; 7]);
+        packed.push(self.is_claim);
+        packed.extend(bytes_into_be_bits(&self.preimage));
+
+        packed
+    }
+}
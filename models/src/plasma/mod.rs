@@ -10,8 +10,10 @@ pub use web3::types::{H256, U128, U256};
 use pairing::bn256;
 use sapling_crypto::eddsa;
 
-pub use crate::plasma::account::Account;
-pub use crate::plasma::tx::{DepositTx, ExitTx, TransferTx, TxSignature};
+pub use crate::plasma::account::{Account, HashLock, Multisig};
+pub use crate::plasma::tx::{
+    ChangePubKeyTx, ChequeTx, ClaimTx, DepositTx, ExitTx, LockTx, RefundTx, TransferTx, TxSignature,
+};
 
 pub type Engine = bn256::Bn256;
 pub type Fr = bn256::Fr;
@@ -24,6 +26,7 @@ pub type PrivateKey = eddsa::PrivateKey<Engine>;
 pub type BatchNumber = u32;
 pub type BlockNumber = u32;
 pub type AccountId = u32;
+pub type TokenId = u32;
 pub type Nonce = u32;
 
 #[derive(Debug)]
@@ -36,4 +39,10 @@ pub enum TransferApplicationError {
     InvalidSigner,
     ExpiredTransaction,
     InvalidTransaction(String),
+    /// Committer lags behind, tx should be resubmitted later
+    Busy,
+    /// Server is draining its queues before exit
+    ShuttingDown,
+    /// Tx is signed for another deployment, see `RuntimeConfig::chain_id`
+    WrongChainId,
 }
@@ -1,3 +1,5 @@
+/// Depth of the account tree, also the bit width of account ids in transactions and
+/// public data. The contracts and the JS client encode account ids in 24 bits too
 pub const BALANCE_TREE_DEPTH: usize = 24;
 
 /// Amount bit widths
@@ -10,12 +12,74 @@ pub const FEE_MANTISSA_BIT_WIDTH: usize = 3;
 
 pub const BALANCE_BIT_WIDTH: usize = 128;
 
+/// Token id bit width, also the depth of the balance subtree of every account
+pub const TOKEN_BIT_WIDTH: usize = 8;
+
+/// Token 0 is the native coin (ETH), it is always registered
+pub const ETH_TOKEN_ID: u32 = 0;
+
 /// Nonce bit width
 pub const NONCE_BIT_WIDTH: usize = 32;
 
+/// Bit width of the new public key hash that a key change signature commits to
+pub const NEW_PUBKEY_HASH_WIDTH: usize = 160;
+
+/// A transfer to a new account registers the key of the recipient. The transfer and the key
+/// do not fit into a signed message together, so the signature commits to this many bits
+/// of their hash followed by the marker
+pub const NEW_ACCOUNT_MESSAGE_HASH_WIDTH: usize = 232;
+
+/// Bit width of the marker of a transfer to a new account, the byte 1. Other transfers have
+/// shorter messages that are padded with zeros there
+pub const NEW_ACCOUNT_MESSAGE_MARKER_WIDTH: usize = 8;
+
+/// Cheque window: the account keeps a bit per redeemed cheque serial in a window of this width.
+/// A power of two
+pub const CHEQUE_WINDOW_BIT_WIDTH: usize = 128;
+
+/// Maximum shift of the cheque window by a single redemption, at most CHEQUE_WINDOW_BIT_WIDTH
+pub const CHEQUE_WINDOW_SHIFT: usize = 64;
+
+/// Cheque serial bit width, also the width of the cheque window start
+pub const CHEQUE_SERIAL_BIT_WIDTH: usize = 32;
+
+/// Width of the sha256 hash of a hash lock, also the width of its preimage
+pub const HASH_LOCK_BIT_WIDTH: usize = 256;
+
+/// Bit width of the lock commitment hash that a lock signature commits to
+pub const LOCK_COMMITMENT_HASH_WIDTH: usize = 160;
+
+/// Bit width of the hash of the cheque window and the lock commitment in the account leaf.
+/// The Pedersen hash gadget takes at most 4 generators, 738 bits after the personalization,
+/// so the leaf keeps this hash instead of the 416 bits it is made of
+pub const LEAF_STATE_HASH_BIT_WIDTH: usize = 160;
+
+/// Account leaf layout. The default one packs the public key into the leaf, the compact one
+/// (`compact_leaf` feature of this crate) keeps PUB_KEY_HASH_BIT_WIDTH bits of its hash instead.
+/// A compact leaf is cheaper in a circuit when its key is not used, as the recipient's in
+/// a transfer, and dearer when it is. A transfer costs about 200 constraints more with it.
+/// Trees, circuits and keys of the two layouts are not compatible
+pub const COMPACT_LEAF: bool = cfg!(feature = "compact_leaf");
+
+/// Bit width of the public key hash in a compact account leaf
+pub const PUB_KEY_HASH_BIT_WIDTH: usize = 160;
+
+/// Bits the public key takes in the account leaf: the packed key, or its hash in a compact leaf
+pub const PUB_KEY_LEAF_BIT_WIDTH: usize =
+    FR_BIT_WIDTH - (FR_BIT_WIDTH - PUB_KEY_HASH_BIT_WIDTH) * (COMPACT_LEAF as usize);
+
+/// Maximum number of keys of a multisig account
+pub const MULTISIG_MAX_SIGNERS: usize = 3;
+
+/// Multisig threshold bit width
+pub const MULTISIG_THRESHOLD_BIT_WIDTH: usize = 8;
+
 /// Block number bit width
 pub const BLOCK_NUMBER_BIT_WIDTH: usize = 32;
 
+/// Bit width of the chain id that transfer signatures commit to
+pub const CHAIN_ID_BIT_WIDTH: usize = 32;
+
 // Signature data
 pub const SIGNATURE_S_BIT_WIDTH: usize = 256;
 pub const SIGNATURE_R_X_BIT_WIDTH: usize = 256;
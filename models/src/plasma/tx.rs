@@ -1,14 +1,19 @@
+use crate::plasma::account::{Account, HashLock, Multisig};
+use crate::plasma::circuit::change_pubkey::{pub_key_hash_bits, ChangePubKeyRequest};
+use crate::plasma::circuit::cheque::ChequeRequest;
 use crate::plasma::circuit::deposit::DepositRequest;
 use crate::plasma::circuit::exit::ExitRequest;
+use crate::plasma::circuit::lock::{lock_commitment, lock_commitment_hash_bits, LockRequest};
 use crate::plasma::circuit::sig::TransactionSignature;
-use crate::plasma::circuit::transfer::Tx;
+use crate::plasma::circuit::transfer::{new_account_message_bits, Tx};
+use crate::plasma::circuit::unlock::UnlockRequest;
 use crate::plasma::circuit::utils::{
     encode_fr_into_fs, encode_fs_into_fr, le_bit_vector_into_field_element,
 };
 use crate::plasma::params;
-use crate::plasma::{Engine, Fr};
+use crate::plasma::{Engine, Fr, TokenId, H256};
 use crate::plasma::{PrivateKey, PublicKey};
-use crate::primitives::{get_bits_le_fixed_u128, pack_bits_into_bytes};
+use crate::primitives::{get_bits_le_fixed_u128, pack_bits_into_bytes, round_down_to_packable};
 use bigdecimal::{BigDecimal, ToPrimitive};
 use ff::PrimeField;
 use sapling_crypto::circuit::float_point::convert_to_float;
@@ -20,12 +25,22 @@ use std::cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd};
 pub const TRANSFER_TX: &str = "Transfer";
 pub const DEPOSIT_TX: &str = "Deposit";
 pub const EXIT_TX: &str = "Exit";
+pub const CHANGE_PUBKEY_TX: &str = "ChangePubKey";
+pub const CHEQUE_TX: &str = "Cheque";
+pub const LOCK_TX: &str = "Lock";
+pub const CLAIM_TX: &str = "Claim";
+pub const REFUND_TX: &str = "Refund";
 
 #[derive(Clone)]
 pub enum TransactionType {
     Transfer { tx: Box<TransferTx> },
     Deposit { tx: DepositTx },
     Exit { tx: ExitTx },
+    ChangePubKey { tx: Box<ChangePubKeyTx> },
+    Cheque { tx: Box<ChequeTx> },
+    Lock { tx: Box<LockTx> },
+    Claim { tx: ClaimTx },
+    Refund { tx: RefundTx },
 }
 
 impl std::string::ToString for TransactionType {
@@ -34,20 +49,48 @@ impl std::string::ToString for TransactionType {
             TransactionType::Transfer { .. } => TRANSFER_TX.to_owned(),
             TransactionType::Deposit { .. } => DEPOSIT_TX.to_owned(),
             TransactionType::Exit { .. } => EXIT_TX.to_owned(),
+            TransactionType::ChangePubKey { .. } => CHANGE_PUBKEY_TX.to_owned(),
+            TransactionType::Cheque { .. } => CHEQUE_TX.to_owned(),
+            TransactionType::Lock { .. } => LOCK_TX.to_owned(),
+            TransactionType::Claim { .. } => CLAIM_TX.to_owned(),
+            TransactionType::Refund { .. } => REFUND_TX.to_owned(),
         }
     }
 }
 
+// txs stored before multi-token balances have no token, they moved the native token
+fn native_token() -> TokenId {
+    params::ETH_TOKEN_ID
+}
+
+// txs stored before they were signed for a chain id have none, they count as chain 0
+fn legacy_chain_id() -> u32 {
+    0
+}
+
 /// Unpacked transaction data
 #[derive(Default, Clone, Serialize, Deserialize)]
 pub struct TransferTx {
     pub from: u32,
     pub to: u32,
+    #[serde(default = "native_token")]
+    pub token: TokenId,
     pub amount: BigDecimal,
     pub fee: BigDecimal,
     pub nonce: u32,
     pub good_until_block: u32,
+    /// Deployment the transfer is signed for, must be `RuntimeConfig::chain_id`
+    #[serde(default = "legacy_chain_id")]
+    pub chain_id: u32,
     pub signature: TxSignature,
+    /// Signatures of a multisig sender by the index of the signing key,
+    /// `signature` is not used then
+    #[serde(default)]
+    pub multisig_signatures: Vec<Option<TxSignature>>,
+    /// Key that a transfer to an empty account registers for the recipient,
+    /// the signature commits to it then
+    #[serde(default)]
+    pub recipient_pub_key: Option<(Fr, Fr)>,
 
     /// If present, it means that the signature has been verified against this key
     #[serde(skip)]
@@ -58,8 +101,8 @@ impl std::fmt::Debug for TransferTx {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "tx{{ from: {}, to: {}, nonce: {}, amount: {} }}",
-            self.from, self.to, self.nonce, self.amount
+            "tx{{ from: {}, to: {}, token: {}, nonce: {}, amount: {} }}",
+            self.from, self.to, self.token, self.nonce, self.amount
         )?;
         write!(
             f,
@@ -94,6 +137,7 @@ impl TransferTx {
         let mut r: Vec<bool> = vec![];
         let from_bits = get_bits_le_fixed_u128(u128::from(self.from), params::BALANCE_TREE_DEPTH);
         let to_bits = get_bits_le_fixed_u128(u128::from(self.to), params::BALANCE_TREE_DEPTH);
+        let token_bits = get_bits_le_fixed_u128(u128::from(self.token), params::TOKEN_BIT_WIDTH);
         let amount_bits = convert_to_float(
             self.amount.to_u128().unwrap(),
             params::AMOUNT_EXPONENT_BIT_WIDTH,
@@ -114,15 +158,34 @@ impl TransferTx {
             u128::from(self.good_until_block),
             params::BLOCK_NUMBER_BIT_WIDTH,
         );
+        // the transfer is only valid on the deployment it was signed for
+        let chain_id_bits =
+            get_bits_le_fixed_u128(u128::from(self.chain_id), params::CHAIN_ID_BIT_WIDTH);
 
         r.extend(from_bits.into_iter());
         r.extend(to_bits.into_iter());
+        r.extend(token_bits.into_iter());
         r.extend(amount_bits.into_iter());
         r.extend(fee_bits.into_iter());
         r.extend(nonce_bits.into_iter());
         r.extend(good_until_block_bits.into_iter());
+        r.extend(chain_id_bits.into_iter());
 
-        r
+        match self.recipient_pub_key {
+            Some((pub_x, pub_y)) => {
+                new_account_message_bits::<Engine>(r, &pub_x, &pub_y, &params::JUBJUB_PARAMS)
+            }
+            None => r,
+        }
+    }
+
+    /// Key that the transfer registers for a new recipient account,
+    /// `None` if there is none or it is not a key of the prime order subgroup
+    pub fn new_account_pub_key(&self) -> Option<PublicKey> {
+        let (pub_x, pub_y) = self.recipient_pub_key?;
+        edwards::Point::<Engine, Unknown>::from_xy(pub_x, pub_y, &params::JUBJUB_PARAMS)
+            .filter(|point| point.as_prime_order(&params::JUBJUB_PARAMS).is_some())
+            .map(sapling_crypto::eddsa::PublicKey)
     }
 
     pub fn tx_data(&self) -> Option<Vec<u8>> {
@@ -136,58 +199,152 @@ impl TransferTx {
         Some(as_bytes)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn create_signed_tx(
         from: u32,
         to: u32,
+        token: TokenId,
         amount: BigDecimal,
         fee: BigDecimal,
         nonce: u32,
         good_until_block: u32,
+        chain_id: u32,
         private_key: &PrivateKey,
     ) -> Self {
-        let tx = TransferTx {
+        let mut tx = TransferTx {
             from,
             to,
-            amount: amount.clone(),
-            fee: fee.clone(),
+            token,
+            amount,
+            fee,
+            nonce,
+            good_until_block,
+            chain_id,
+            signature: TxSignature::default(),
+            multisig_signatures: Vec::new(),
+            recipient_pub_key: None,
+            cached_pub_key: None,
+        };
+
+        tx.sign(private_key);
+        tx
+    }
+
+    /// Transfer to the empty account `to` that registers `recipient_pub_key` for it
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_signed_tx_to_new_account(
+        from: u32,
+        to: u32,
+        token: TokenId,
+        amount: BigDecimal,
+        fee: BigDecimal,
+        nonce: u32,
+        good_until_block: u32,
+        chain_id: u32,
+        recipient_pub_key: &PublicKey,
+        private_key: &PrivateKey,
+    ) -> Self {
+        let mut tx = TransferTx {
+            from,
+            to,
+            token,
+            amount,
+            fee,
             nonce,
             good_until_block,
+            chain_id,
             signature: TxSignature::default(),
+            multisig_signatures: Vec::new(),
+            recipient_pub_key: Some(recipient_pub_key.0.into_xy()),
             cached_pub_key: None,
         };
 
-        let message_bits = tx.message_bits();
+        tx.sign(private_key);
+        tx
+    }
+
+    fn sign(&mut self, private_key: &PrivateKey) {
+        let message_bits = self.message_bits();
         let as_bytes = pack_bits_into_bytes(message_bits);
 
         let rng = &mut rand::thread_rng();
         let p_g = FixedGenerators::SpendingKeyGenerator;
 
-        let signature = TxSignature::from(private_key.sign_raw_message(
+        self.signature = TxSignature::from(private_key.sign_raw_message(
             &as_bytes,
             rng,
             p_g,
             &params::JUBJUB_PARAMS,
             as_bytes.len(),
         ));
-        let cached_pub_key = Some(PublicKey::from_private(
+        self.cached_pub_key = Some(PublicKey::from_private(
             &private_key,
             p_g,
             &params::JUBJUB_PARAMS,
         ));
+    }
 
-        TransferTx {
-            from,
-            to,
-            amount,
-            fee,
-            nonce,
-            good_until_block,
-            signature,
-            cached_pub_key,
+    /// Adds the signature by the key of a multisig sender at `index`
+    pub fn sign_multisig(&mut self, index: usize, private_key: &PrivateKey) {
+        let message_bits = self.message_bits();
+        let as_bytes = pack_bits_into_bytes(message_bits);
+
+        let rng = &mut rand::thread_rng();
+        let p_g = FixedGenerators::SpendingKeyGenerator;
+
+        let signature = TxSignature::from(private_key.sign_raw_message(
+            &as_bytes,
+            rng,
+            p_g,
+            &params::JUBJUB_PARAMS,
+            as_bytes.len(),
+        ));
+
+        if self.multisig_signatures.len() <= index {
+            self.multisig_signatures.resize(index + 1, None);
         }
+        self.multisig_signatures[index] = Some(signature);
     }
 
     pub fn verify_sig(&self, public_key: &PublicKey) -> bool {
+        self.verify_signature(&self.signature, public_key)
+    }
+
+    /// Checks that at least `threshold` distinct keys of the multisig have signed.
+    /// Every present signature must be valid, the circuit checks all of them
+    pub fn verify_multisig(&self, multisig: &Multisig) -> bool {
+        if self.multisig_signatures.len() > multisig.keys.len() {
+            return false;
+        }
+
+        let mut signed = 0;
+        for (i, signature) in self.multisig_signatures.iter().enumerate() {
+            if let Some(signature) = signature {
+                match multisig.get_key(i) {
+                    Some(key) if self.verify_signature(signature, &key) => signed += 1,
+                    _ => return false,
+                }
+            }
+        }
+
+        signed >= usize::from(multisig.threshold)
+    }
+
+    /// Checks the signatures against the key of the sender, whether it's a single key or a multisig
+    pub fn verify_sender(&self, sender: &Account) -> bool {
+        match sender.multisig {
+            Some(ref multisig) => self.verify_multisig(multisig),
+            None => {
+                self.multisig_signatures.is_empty()
+                    && sender
+                        .get_pub_key()
+                        .map(|public_key| self.verify_sig(&public_key))
+                        .unwrap_or(false)
+            }
+        }
+    }
+
+    fn verify_signature(&self, signature: &TxSignature, public_key: &PublicKey) -> bool {
         let message_bits = self.message_bits();
         if message_bits.len() % 8 != 0 {
             error!("Invalid message length");
@@ -197,7 +354,7 @@ impl TransferTx {
         //use rustc_hex::ToHex;
         //let hex: String = as_bytes.clone().to_hex();
         //debug!("Transaction bytes = {}", hex);
-        if let Ok(signature) = self.signature.to_jubjub_eddsa() {
+        if let Ok(signature) = signature.to_jubjub_eddsa() {
             //debug!("Successfuly converted to eddsa signature");
             let p_g = FixedGenerators::SpendingKeyGenerator;
             let valid = public_key.verify_for_raw_message(
@@ -223,14 +380,82 @@ impl TransferTx {
         if self.amount == BigDecimal::zero() {
             return Err("zero amount is not allowed".to_string());
         }
+        if self.token >= (1 << params::TOKEN_BIT_WIDTH) {
+            return Err(format!("token id is out of range: {}", self.token));
+        }
+        if self.recipient_pub_key.is_some() {
+            if self.to == 0 {
+                return Err("account 0 can not get a public key".to_string());
+            }
+            if self.new_account_pub_key().is_none() {
+                return Err("recipient public key is not a valid key".to_string());
+            }
+        }
+        convert_to_float(
+            self.amount.to_u128().unwrap_or(u128::max_value()),
+            params::AMOUNT_EXPONENT_BIT_WIDTH,
+            params::AMOUNT_MANTISSA_BIT_WIDTH,
+            10,
+        )
+        .map_err(|e| format!("wrong amount encoding: {}", e.to_string()))?;
+        convert_to_float(
+            self.fee.to_u128().unwrap_or(u128::max_value()),
+            params::FEE_EXPONENT_BIT_WIDTH,
+            params::FEE_MANTISSA_BIT_WIDTH,
+            10,
+        )
+        .map_err(|e| format!("wrong fee encoding: {}", e.to_string()))?;
 
         Ok(())
     }
+
+    /// Largest amount not above `amount` that a transfer can carry
+    pub fn packable_amount(amount: &BigDecimal) -> Result<BigDecimal, String> {
+        round_down_to_packable_decimal(
+            amount,
+            params::AMOUNT_EXPONENT_BIT_WIDTH,
+            params::AMOUNT_MANTISSA_BIT_WIDTH,
+        )
+    }
+
+    /// Largest fee not above `fee` that a transfer can carry
+    pub fn packable_fee(fee: &BigDecimal) -> Result<BigDecimal, String> {
+        round_down_to_packable_decimal(
+            fee,
+            params::FEE_EXPONENT_BIT_WIDTH,
+            params::FEE_MANTISSA_BIT_WIDTH,
+        )
+    }
+}
+
+fn round_down_to_packable_decimal(
+    value: &BigDecimal,
+    exponent_length: usize,
+    mantissa_length: usize,
+) -> Result<BigDecimal, String> {
+    use bigdecimal::Zero;
+    if *value < BigDecimal::zero() {
+        return Err("negative value is not allowed".to_string());
+    }
+    // the fraction is dropped, values above u128 are rounded down from its maximum
+    let integer = value
+        .with_scale(0)
+        .to_string()
+        .parse::<u128>()
+        .unwrap_or(u128::max_value());
+    let rounded = round_down_to_packable(integer, exponent_length, mantissa_length);
+
+    Ok(rounded
+        .to_string()
+        .parse()
+        .expect("integer is a valid decimal"))
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DepositTx {
     pub account: u32,
+    #[serde(default = "native_token")]
+    pub token: TokenId,
     pub amount: BigDecimal,
     pub pub_x: Fr,
     pub pub_y: Fr,
@@ -239,9 +464,497 @@ pub struct DepositTx {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ExitTx {
     pub account: u32,
+    #[serde(default = "native_token")]
+    pub token: TokenId,
     pub amount: BigDecimal,
 }
 
+/// Replaces the public key of an account, signed by the current key of the account.
+/// Keys of new accounts are registered by deposits and transfers, an empty account
+/// can not sign a change
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ChangePubKeyTx {
+    pub account: u32,
+    pub new_pub_x: Fr,
+    pub new_pub_y: Fr,
+    pub nonce: u32,
+    /// Deployment the change is signed for, must be `RuntimeConfig::chain_id`
+    #[serde(default = "legacy_chain_id")]
+    pub chain_id: u32,
+    pub signature: TxSignature,
+    /// Keys of the account if it becomes a multisig account,
+    /// the new public key must be their commitment then
+    #[serde(default)]
+    pub multisig: Option<Multisig>,
+
+    /// If present, it means that the signature has been verified against this key
+    #[serde(skip)]
+    pub cached_pub_key: Option<PublicKey>,
+}
+
+impl std::fmt::Debug for ChangePubKeyTx {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "change_pubkey_tx{{ account: {}, nonce: {}, new_pub_x: {}, new_pub_y: {} }}",
+            self.account, self.nonce, self.new_pub_x, self.new_pub_y
+        )
+    }
+}
+
+impl ChangePubKeyTx {
+    pub fn message_bits(&self) -> Vec<bool> {
+        let mut r: Vec<bool> = vec![];
+        let account_bits =
+            get_bits_le_fixed_u128(u128::from(self.account), params::BALANCE_TREE_DEPTH);
+        let nonce_bits = get_bits_le_fixed_u128(u128::from(self.nonce), params::NONCE_BIT_WIDTH);
+        let new_pub_key_hash_bits =
+            pub_key_hash_bits::<Engine>(&self.new_pub_x, &self.new_pub_y, &params::JUBJUB_PARAMS);
+        let chain_id_bits =
+            get_bits_le_fixed_u128(u128::from(self.chain_id), params::CHAIN_ID_BIT_WIDTH);
+
+        r.extend(account_bits.into_iter());
+        r.extend(nonce_bits.into_iter());
+        r.extend(new_pub_key_hash_bits.into_iter());
+        r.extend(chain_id_bits.into_iter());
+
+        r
+    }
+
+    pub fn new_pub_key(&self) -> Option<PublicKey> {
+        edwards::Point::<Engine, Unknown>::from_xy(
+            self.new_pub_x,
+            self.new_pub_y,
+            &params::JUBJUB_PARAMS,
+        )
+        .map(sapling_crypto::eddsa::PublicKey)
+    }
+
+    pub fn create_signed_tx(
+        account: u32,
+        new_pub_key: &PublicKey,
+        nonce: u32,
+        chain_id: u32,
+        private_key: &PrivateKey,
+    ) -> Self {
+        let (new_pub_x, new_pub_y) = new_pub_key.0.into_xy();
+        let tx = ChangePubKeyTx {
+            account,
+            new_pub_x,
+            new_pub_y,
+            nonce,
+            chain_id,
+            signature: TxSignature::default(),
+            multisig: None,
+            cached_pub_key: None,
+        };
+
+        let as_bytes = pack_bits_into_bytes(tx.message_bits());
+
+        let rng = &mut rand::thread_rng();
+        let p_g = FixedGenerators::SpendingKeyGenerator;
+
+        let signature = TxSignature::from(private_key.sign_raw_message(
+            &as_bytes,
+            rng,
+            p_g,
+            &params::JUBJUB_PARAMS,
+            as_bytes.len(),
+        ));
+        let cached_pub_key = Some(PublicKey::from_private(
+            &private_key,
+            p_g,
+            &params::JUBJUB_PARAMS,
+        ));
+
+        ChangePubKeyTx {
+            signature,
+            cached_pub_key,
+            ..tx
+        }
+    }
+
+    /// Turns the account into a multisig account, signed by the current key of the account
+    pub fn create_signed_multisig_tx(
+        account: u32,
+        multisig: Multisig,
+        nonce: u32,
+        chain_id: u32,
+        private_key: &PrivateKey,
+    ) -> Self {
+        let (new_pub_x, new_pub_y) = multisig.commitment();
+        let new_pub_key = edwards::Point::<Engine, Unknown>::from_xy(
+            new_pub_x,
+            new_pub_y,
+            &params::JUBJUB_PARAMS,
+        )
+        .map(sapling_crypto::eddsa::PublicKey)
+        .expect("multisig commitment is a curve point");
+
+        let mut tx = Self::create_signed_tx(account, &new_pub_key, nonce, chain_id, private_key);
+        // the keys are not signed themselves, the signed key is their commitment
+        tx.multisig = Some(multisig);
+
+        tx
+    }
+
+    /// Verifies the signature against the current (old) key of the account
+    pub fn verify_sig(&self, public_key: &PublicKey) -> bool {
+        let as_bytes = pack_bits_into_bytes(self.message_bits());
+        if let Ok(signature) = self.signature.to_jubjub_eddsa() {
+            let p_g = FixedGenerators::SpendingKeyGenerator;
+            return public_key.verify_for_raw_message(
+                &as_bytes,
+                &signature,
+                p_g,
+                &params::JUBJUB_PARAMS,
+                as_bytes.len(),
+            );
+        }
+
+        false
+    }
+}
+
+/// Cheque signed off-chain by the payer, the payee redeems it once.
+/// Cheques do not consume the payer's nonce, the serial is marked as redeemed
+/// in the cheque window of the payer instead. Cheques carry no fee
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ChequeTx {
+    pub from: u32,
+    pub to: u32,
+    pub token: TokenId,
+    pub amount: BigDecimal,
+    pub serial: u32,
+    pub good_until_block: u32,
+    /// Deployment the cheque is signed for, must be `RuntimeConfig::chain_id`
+    #[serde(default = "legacy_chain_id")]
+    pub chain_id: u32,
+    pub signature: TxSignature,
+
+    /// If present, it means that the signature has been verified against this key
+    #[serde(skip)]
+    pub cached_pub_key: Option<PublicKey>,
+}
+
+impl std::fmt::Debug for ChequeTx {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "cheque_tx{{ from: {}, to: {}, token: {}, serial: {}, amount: {} }}",
+            self.from, self.to, self.token, self.serial, self.amount
+        )
+    }
+}
+
+impl ChequeTx {
+    pub fn message_bits(&self) -> Vec<bool> {
+        let mut r: Vec<bool> = vec![];
+        let from_bits = get_bits_le_fixed_u128(u128::from(self.from), params::BALANCE_TREE_DEPTH);
+        let to_bits = get_bits_le_fixed_u128(u128::from(self.to), params::BALANCE_TREE_DEPTH);
+        let token_bits = get_bits_le_fixed_u128(u128::from(self.token), params::TOKEN_BIT_WIDTH);
+        let amount_bits = convert_to_float(
+            self.amount.to_u128().unwrap(),
+            params::AMOUNT_EXPONENT_BIT_WIDTH,
+            params::AMOUNT_MANTISSA_BIT_WIDTH,
+            10,
+        )
+        .unwrap();
+        let serial_bits =
+            get_bits_le_fixed_u128(u128::from(self.serial), params::CHEQUE_SERIAL_BIT_WIDTH);
+        let good_until_block_bits = get_bits_le_fixed_u128(
+            u128::from(self.good_until_block),
+            params::BLOCK_NUMBER_BIT_WIDTH,
+        );
+        let chain_id_bits =
+            get_bits_le_fixed_u128(u128::from(self.chain_id), params::CHAIN_ID_BIT_WIDTH);
+
+        r.extend(from_bits.into_iter());
+        r.extend(to_bits.into_iter());
+        r.extend(token_bits.into_iter());
+        r.extend(amount_bits.into_iter());
+        r.extend(serial_bits.into_iter());
+        r.extend(good_until_block_bits.into_iter());
+        r.extend(chain_id_bits.into_iter());
+
+        r
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_signed_tx(
+        from: u32,
+        to: u32,
+        token: TokenId,
+        amount: BigDecimal,
+        serial: u32,
+        good_until_block: u32,
+        chain_id: u32,
+        private_key: &PrivateKey,
+    ) -> Self {
+        let tx = ChequeTx {
+            from,
+            to,
+            token,
+            amount,
+            serial,
+            good_until_block,
+            chain_id,
+            signature: TxSignature::default(),
+            cached_pub_key: None,
+        };
+
+        let as_bytes = pack_bits_into_bytes(tx.message_bits());
+
+        let rng = &mut rand::thread_rng();
+        let p_g = FixedGenerators::SpendingKeyGenerator;
+
+        let signature = TxSignature::from(private_key.sign_raw_message(
+            &as_bytes,
+            rng,
+            p_g,
+            &params::JUBJUB_PARAMS,
+            as_bytes.len(),
+        ));
+        let cached_pub_key = Some(PublicKey::from_private(
+            &private_key,
+            p_g,
+            &params::JUBJUB_PARAMS,
+        ));
+
+        ChequeTx {
+            signature,
+            cached_pub_key,
+            ..tx
+        }
+    }
+
+    /// Verifies the signature against the key of the payer
+    pub fn verify_sig(&self, public_key: &PublicKey) -> bool {
+        let as_bytes = pack_bits_into_bytes(self.message_bits());
+        if let Ok(signature) = self.signature.to_jubjub_eddsa() {
+            let p_g = FixedGenerators::SpendingKeyGenerator;
+            return public_key.verify_for_raw_message(
+                &as_bytes,
+                &signature,
+                p_g,
+                &params::JUBJUB_PARAMS,
+                as_bytes.len(),
+            );
+        }
+
+        false
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        use bigdecimal::Zero;
+        if self.from == self.to {
+            return Err(format!(
+                "cheque.from may not equal cheque.to: {}",
+                self.from
+            ));
+        }
+        if self.to == params::SPECIAL_ACCOUNT_EXIT {
+            return Err("cheque can not be redeemed to the exit account".to_string());
+        }
+        if self.amount == BigDecimal::zero() {
+            return Err("zero amount is not allowed".to_string());
+        }
+        if self.token >= (1 << params::TOKEN_BIT_WIDTH) {
+            return Err(format!("token id is out of range: {}", self.token));
+        }
+        if self.from >= (1 << params::BALANCE_TREE_DEPTH)
+            || self.to >= (1 << params::BALANCE_TREE_DEPTH)
+        {
+            return Err("account id is out of range".to_string());
+        }
+        convert_to_float(
+            self.amount.to_u128().unwrap_or(u128::max_value()),
+            params::AMOUNT_EXPONENT_BIT_WIDTH,
+            params::AMOUNT_MANTISSA_BIT_WIDTH,
+            10,
+        )
+        .map_err(|e| format!("wrong amount encoding: {}", e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// Locks funds of the account to the recipient under a sha256 hash, see `HashLock`.
+/// Signed by the account over the account, the nonce, a hash of the lock and the chain id,
+/// consumes a nonce
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LockTx {
+    pub account: u32,
+    pub to: u32,
+    pub token: TokenId,
+    pub amount: BigDecimal,
+    pub hash: H256,
+    pub good_until_block: u32,
+    pub nonce: u32,
+    /// Deployment the lock is signed for, must be `RuntimeConfig::chain_id`
+    #[serde(default = "legacy_chain_id")]
+    pub chain_id: u32,
+    pub signature: TxSignature,
+
+    /// If present, it means that the signature has been verified against this key
+    #[serde(skip)]
+    pub cached_pub_key: Option<PublicKey>,
+}
+
+impl std::fmt::Debug for LockTx {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "lock_tx{{ account: {}, to: {}, token: {}, amount: {}, nonce: {} }}",
+            self.account, self.to, self.token, self.amount, self.nonce
+        )
+    }
+}
+
+impl LockTx {
+    pub fn hash_lock(&self) -> HashLock {
+        HashLock {
+            to: self.to,
+            token: self.token,
+            amount: self.amount.clone(),
+            hash: self.hash,
+            good_until_block: self.good_until_block,
+        }
+    }
+
+    pub fn message_bits(&self) -> Vec<bool> {
+        let mut r: Vec<bool> = vec![];
+        let account_bits =
+            get_bits_le_fixed_u128(u128::from(self.account), params::BALANCE_TREE_DEPTH);
+        let nonce_bits = get_bits_le_fixed_u128(u128::from(self.nonce), params::NONCE_BIT_WIDTH);
+        let commitment = lock_commitment::<Engine>(&self.hash_lock(), &params::JUBJUB_PARAMS);
+        let chain_id_bits =
+            get_bits_le_fixed_u128(u128::from(self.chain_id), params::CHAIN_ID_BIT_WIDTH);
+
+        r.extend(account_bits.into_iter());
+        r.extend(nonce_bits.into_iter());
+        r.extend(lock_commitment_hash_bits::<Engine>(&commitment).into_iter());
+        r.extend(chain_id_bits.into_iter());
+
+        r
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_signed_tx(
+        account: u32,
+        to: u32,
+        token: TokenId,
+        amount: BigDecimal,
+        hash: H256,
+        good_until_block: u32,
+        nonce: u32,
+        chain_id: u32,
+        private_key: &PrivateKey,
+    ) -> Self {
+        let tx = LockTx {
+            account,
+            to,
+            token,
+            amount,
+            hash,
+            good_until_block,
+            nonce,
+            chain_id,
+            signature: TxSignature::default(),
+            cached_pub_key: None,
+        };
+
+        let as_bytes = pack_bits_into_bytes(tx.message_bits());
+
+        let rng = &mut rand::thread_rng();
+        let p_g = FixedGenerators::SpendingKeyGenerator;
+
+        let signature = TxSignature::from(private_key.sign_raw_message(
+            &as_bytes,
+            rng,
+            p_g,
+            &params::JUBJUB_PARAMS,
+            as_bytes.len(),
+        ));
+        let cached_pub_key = Some(PublicKey::from_private(
+            &private_key,
+            p_g,
+            &params::JUBJUB_PARAMS,
+        ));
+
+        LockTx {
+            signature,
+            cached_pub_key,
+            ..tx
+        }
+    }
+
+    /// Verifies the signature against the key of the account
+    pub fn verify_sig(&self, public_key: &PublicKey) -> bool {
+        let as_bytes = pack_bits_into_bytes(self.message_bits());
+        if let Ok(signature) = self.signature.to_jubjub_eddsa() {
+            let p_g = FixedGenerators::SpendingKeyGenerator;
+            return public_key.verify_for_raw_message(
+                &as_bytes,
+                &signature,
+                p_g,
+                &params::JUBJUB_PARAMS,
+                as_bytes.len(),
+            );
+        }
+
+        false
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        use bigdecimal::Zero;
+        if self.account == self.to {
+            return Err(format!(
+                "lock.account may not equal lock.to: {}",
+                self.account
+            ));
+        }
+        if self.to == params::SPECIAL_ACCOUNT_EXIT {
+            return Err("funds can not be locked to the exit account".to_string());
+        }
+        if self.amount == BigDecimal::zero() {
+            return Err("zero amount is not allowed".to_string());
+        }
+        if self.token >= (1 << params::TOKEN_BIT_WIDTH) {
+            return Err(format!("token id is out of range: {}", self.token));
+        }
+        if self.account >= (1 << params::BALANCE_TREE_DEPTH)
+            || self.to >= (1 << params::BALANCE_TREE_DEPTH)
+        {
+            return Err("account id is out of range".to_string());
+        }
+        convert_to_float(
+            self.amount.to_u128().unwrap_or(u128::max_value()),
+            params::AMOUNT_EXPONENT_BIT_WIDTH,
+            params::AMOUNT_MANTISSA_BIT_WIDTH,
+            10,
+        )
+        .map_err(|e| format!("wrong amount encoding: {}", e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// Moves the locked funds of the account to the recipient of the lock.
+/// Needs no signature, the preimage of the lock hash authorizes it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClaimTx {
+    pub account: u32,
+    pub preimage: H256,
+}
+
+/// Returns the locked funds to the account once the lock has expired.
+/// Needs no signature, the funds can only go back to the account
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RefundTx {
+    pub account: u32,
+}
+
 #[derive(Clone, Default, Serialize, Deserialize)]
 pub struct TxSignature {
     pub r_x: Fr,
@@ -313,12 +1026,30 @@ impl Tx<Engine> {
             // TODO: these conversions are ugly and inefficient, replace with idiomatic std::convert::From trait
             from: Fr::from_str(&transaction.from.to_string()).unwrap(),
             to: Fr::from_str(&transaction.to.to_string()).unwrap(),
+            token: Fr::from_str(&transaction.token.to_string()).unwrap(),
             amount: encoded_amount,
             fee: encoded_fee,
             nonce: Fr::from_str(&transaction.good_until_block.to_string()).unwrap(),
             good_until_block: Fr::from_str(&transaction.good_until_block.to_string()).unwrap(),
+            chain_id: Fr::from_str(&transaction.chain_id.to_string()).unwrap(),
 
-            signature: TransactionSignature::try_from(transaction.signature.clone())?,
+            // a multisig transfer is signed by the multisig keys only
+            signature: if transaction.multisig_signatures.is_empty() {
+                TransactionSignature::try_from(transaction.signature.clone())?
+            } else {
+                TransactionSignature::empty(&*params::JUBJUB_PARAMS)
+            },
+            multisig_signatures: transaction
+                .multisig_signatures
+                .iter()
+                .map(|signature| {
+                    signature
+                        .clone()
+                        .map(TransactionSignature::try_from)
+                        .transpose()
+                })
+                .collect::<Result<_, _>>()?,
+            recipient_pub_key: transaction.recipient_pub_key,
         };
 
         Ok(tx)
@@ -331,6 +1062,7 @@ impl DepositRequest<Engine> {
         let req = Self {
             // TODO: these conversions are ugly and inefficient, replace with idiomatic std::convert::From trait
             into: Fr::from_str(&request.account.to_string()).unwrap(),
+            token: Fr::from_str(&request.token.to_string()).unwrap(),
             amount: Fr::from_str(&request.amount.to_string()).unwrap(),
             pub_x: request.pub_x,
             pub_y: request.pub_y,
@@ -346,9 +1078,103 @@ impl ExitRequest<Engine> {
         let req = Self {
             // TODO: these conversions are ugly and inefficient, replace with idiomatic std::convert::From trait
             from: Fr::from_str(&request.account.to_string()).unwrap(),
+            token: Fr::from_str(&request.token.to_string()).unwrap(),
             amount: Fr::from_str(&request.amount.to_string()).unwrap(),
         };
 
         Ok(req)
     }
 }
+
+impl ChangePubKeyRequest<Engine> {
+    // TODO: introduce errors if necessary
+    pub fn try_from(request: &crate::plasma::tx::ChangePubKeyTx) -> Result<Self, String> {
+        let req = Self {
+            account: Fr::from_str(&request.account.to_string()).unwrap(),
+            new_pub_x: request.new_pub_x,
+            new_pub_y: request.new_pub_y,
+            nonce: Fr::from_str(&request.nonce.to_string()).unwrap(),
+            chain_id: Fr::from_str(&request.chain_id.to_string()).unwrap(),
+            signature: TransactionSignature::try_from(request.signature.clone())?,
+        };
+
+        Ok(req)
+    }
+}
+
+impl ChequeRequest<Engine> {
+    // TODO: introduce errors if necessary
+    pub fn try_from(request: &crate::plasma::tx::ChequeTx) -> Result<Self, String> {
+        let encoded_amount_bits = convert_to_float(
+            request.amount.to_u128().unwrap(),
+            params::AMOUNT_EXPONENT_BIT_WIDTH,
+            params::AMOUNT_MANTISSA_BIT_WIDTH,
+            10,
+        )
+        .map_err(|e| format!("wrong amount encoding: {}", e.to_string()))?;
+        let encoded_amount: Fr = le_bit_vector_into_field_element(&encoded_amount_bits);
+
+        let req = Self {
+            from: Fr::from_str(&request.from.to_string()).unwrap(),
+            to: Fr::from_str(&request.to.to_string()).unwrap(),
+            token: Fr::from_str(&request.token.to_string()).unwrap(),
+            amount: encoded_amount,
+            serial: Fr::from_str(&request.serial.to_string()).unwrap(),
+            good_until_block: Fr::from_str(&request.good_until_block.to_string()).unwrap(),
+            chain_id: Fr::from_str(&request.chain_id.to_string()).unwrap(),
+            signature: TransactionSignature::try_from(request.signature.clone())?,
+        };
+
+        Ok(req)
+    }
+}
+
+impl LockRequest<Engine> {
+    // TODO: introduce errors if necessary
+    pub fn try_from(request: &crate::plasma::tx::LockTx) -> Result<Self, String> {
+        let encoded_amount_bits = convert_to_float(
+            request.amount.to_u128().unwrap(),
+            params::AMOUNT_EXPONENT_BIT_WIDTH,
+            params::AMOUNT_MANTISSA_BIT_WIDTH,
+            10,
+        )
+        .map_err(|e| format!("wrong amount encoding: {}", e.to_string()))?;
+        let encoded_amount: Fr = le_bit_vector_into_field_element(&encoded_amount_bits);
+
+        let req = Self {
+            account: Fr::from_str(&request.account.to_string()).unwrap(),
+            to: Fr::from_str(&request.to.to_string()).unwrap(),
+            token: Fr::from_str(&request.token.to_string()).unwrap(),
+            amount: encoded_amount,
+            hash: request.hash.0,
+            good_until_block: Fr::from_str(&request.good_until_block.to_string()).unwrap(),
+            nonce: Fr::from_str(&request.nonce.to_string()).unwrap(),
+            chain_id: Fr::from_str(&request.chain_id.to_string()).unwrap(),
+            signature: TransactionSignature::try_from(request.signature.clone())?,
+        };
+
+        Ok(req)
+    }
+}
+
+impl UnlockRequest<Engine> {
+    pub fn try_from_claim(request: &crate::plasma::tx::ClaimTx) -> Result<Self, String> {
+        let req = Self {
+            account: Fr::from_str(&request.account.to_string()).unwrap(),
+            is_claim: true,
+            preimage: request.preimage.0,
+        };
+
+        Ok(req)
+    }
+
+    pub fn try_from_refund(request: &crate::plasma::tx::RefundTx) -> Result<Self, String> {
+        let req = Self {
+            account: Fr::from_str(&request.account.to_string()).unwrap(),
+            is_claim: false,
+            preimage: [0u8; 32],
+        };
+
+        Ok(req)
+    }
+}
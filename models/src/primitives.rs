@@ -46,6 +46,26 @@ pub fn get_bits_le_fixed_u128(num: u128, n: usize) -> Vec<bool> {
     r
 }
 
+/// Rounds `value` down to the largest `mantissa * 10^exponent` that fits into
+/// `exponent_length` and `mantissa_length` bits, the float format of `convert_to_float`
+pub fn round_down_to_packable(value: u128, exponent_length: usize, mantissa_length: usize) -> u128 {
+    let max_mantissa = (1u128 << mantissa_length) - 1;
+    let max_exponent = (1u32 << exponent_length) - 1;
+
+    let mut mantissa = value;
+    let mut exponent = 0;
+    while mantissa > max_mantissa {
+        if exponent == max_exponent {
+            // above the largest packable value
+            return max_mantissa * 10u128.pow(max_exponent);
+        }
+        mantissa /= 10;
+        exponent += 1;
+    }
+
+    mantissa * 10u128.pow(exponent)
+}
+
 pub fn get_bits_le_fixed_big_decimal(num: BigDecimal, n: usize) -> Vec<bool> {
     let as_u128 = num.to_u128().unwrap();
 
@@ -235,6 +255,38 @@ pub fn pack_bits_into_bytes(bits: Vec<bool>) -> Vec<u8> {
     message_bytes
 }
 
+/// Bits of the bytes in the order of SHA256: bytes in order, every byte from the highest bit
+pub fn bytes_into_be_bits(bytes: &[u8]) -> Vec<bool> {
+    let mut bits = Vec::with_capacity(bytes.len() * 8);
+    for byte in bytes {
+        for i in (0..8).rev() {
+            bits.push(byte & (1 << i) != 0);
+        }
+    }
+
+    bits
+}
+
+#[test]
+fn test_round_down_to_packable() {
+    use sapling_crypto::circuit::float_point::convert_to_float;
+
+    assert_eq!(round_down_to_packable(0, 5, 11), 0);
+    assert_eq!(round_down_to_packable(2047, 5, 11), 2047);
+    assert_eq!(round_down_to_packable(2048, 5, 11), 2040);
+    assert_eq!(round_down_to_packable(123_456, 5, 11), 123_400);
+    assert_eq!(round_down_to_packable(15, 5, 3), 10);
+    assert_eq!(
+        round_down_to_packable(u128::max_value(), 5, 3),
+        7 * 10u128.pow(31)
+    );
+
+    for value in &[2048, 123_456, 999_999_999, u128::max_value()] {
+        let rounded = round_down_to_packable(*value, 5, 11);
+        assert!(convert_to_float(rounded, 5, 11, 10).is_ok());
+    }
+}
+
 #[test]
 fn test_bit_iterator_e() {
     let test_vector = [0xa953_d79b_83f6_ab59, 0x6dea_2059_e200_bd39];
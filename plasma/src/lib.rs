@@ -2,3 +2,4 @@
 extern crate log;
 
 pub mod state;
+pub mod tree_store;
@@ -2,7 +2,9 @@ use bigdecimal::{BigDecimal, Zero};
 use merkle_tree::AccountTree;
 use models::plasma::account::Account;
 use models::plasma::params;
-use models::plasma::tx::{DepositTx, ExitTx, TransferTx};
+use models::plasma::tx::{
+    ChangePubKeyTx, ChequeTx, ClaimTx, DepositTx, ExitTx, LockTx, RefundTx, TransferTx,
+};
 use models::plasma::{AccountId, AccountMap, Fr, TransferApplicationError};
 
 pub struct PlasmaState {
@@ -26,9 +28,8 @@ impl PlasmaState {
     pub fn new(accounts: AccountMap, current_block: u32) -> Self {
         let tree_depth = params::BALANCE_TREE_DEPTH as u32;
         let mut balance_tree = AccountTree::new(tree_depth);
-        for (id, account) in accounts {
-            balance_tree.insert(id, account);
-        }
+        // storage keeps an empty account for an exited one
+        balance_tree.insert_batch(accounts.into_iter().filter(|(_, acc)| !acc.is_empty()));
 
         //添加默认账户用于填充transaction
         // let defaccount = Account::default();
@@ -40,27 +41,43 @@ impl PlasmaState {
         }
     }
 
+    /// State on a tree built elsewhere, e.g. copied from a `tree_store` tree
+    pub fn from_tree(balance_tree: AccountTree, current_block: u32) -> Self {
+        Self {
+            balance_tree,
+            block_number: current_block,
+        }
+    }
+
+    /// Applies account states loaded from storage, e.g. the diff of a block.
+    /// An empty account is an exited one and is removed from the tree
+    pub fn update_accounts(&mut self, accounts: AccountMap) {
+        let (exited, updated): (Vec<_>, Vec<_>) =
+            accounts.into_iter().partition(|(_, acc)| acc.is_empty());
+        for (id, _) in exited {
+            self.balance_tree.delete(id);
+        }
+        self.balance_tree.insert_batch(updated);
+    }
+
     pub fn get_accounts(&self) -> Vec<(u32, Account)> {
-        self.balance_tree
-            .items
-            .iter()
-            .map(|a| (*a.0 as u32, a.1.clone()))
-            .collect()
+        self.balance_tree.items()
     }
 
     pub fn root_hash(&self) -> Fr {
         self.balance_tree.root_hash()
     }
 
+    /// None for an id that was never used or whose account has exited
     pub fn get_account(&self, account_id: AccountId) -> Option<Account> {
-        self.balance_tree.items.get(&account_id).cloned()
+        self.balance_tree.get(account_id)
     }
 
     pub fn apply_transfer(
         &mut self,
         tx: &TransferTx,
     ) -> Result<BigDecimal, TransferApplicationError> {
-        if let Some(mut from) = self.balance_tree.items.get(&tx.from).cloned() {
+        if let Some(mut from) = self.balance_tree.get(tx.from) {
             // TODO: take from `from` instead and uncomment below
             let pub_key = self
                 .get_account(tx.from)
@@ -77,6 +94,16 @@ impl PlasmaState {
                 return Err(TransferApplicationError::InvalidSigner);
             }
 
+            // the cached key of a multisig sender is only the commitment to its keys,
+            // a single key sender must not carry multisig signatures
+            let signed_by_keys = match from.multisig {
+                Some(ref multisig) => tx.verify_multisig(multisig),
+                None => tx.multisig_signatures.is_empty(),
+            };
+            if !signed_by_keys {
+                return Err(TransferApplicationError::InvalidSigner);
+            }
+
             let mut transacted_amount = BigDecimal::zero();
             transacted_amount += &tx.amount;
             transacted_amount += &tx.fee;
@@ -89,7 +116,7 @@ impl PlasmaState {
                 return Err(TransferApplicationError::NonceIsTooLow);
             }
 
-            if from.balance < transacted_amount {
+            if from.get_balance(tx.token) < transacted_amount {
                 //debug!("Insufficient balance");
                 return Err(TransferApplicationError::InsufficientBalance);
             }
@@ -105,22 +132,51 @@ impl PlasmaState {
             // let mut to = self.balance_tree.items.get(&tx.to).ok_or(())?.clone();
 
             let mut to = Account::default();
-            if let Some(existing_to) = self.balance_tree.items.get(&tx.to) {
-                to = existing_to.clone();
+            if let Some(existing_to) = self.balance_tree.get(tx.to) {
+                to = existing_to;
             }
 
-            from.balance -= transacted_amount;
+            // funds sent to an account without a key could never be spent, a new account
+            // gets its key by a deposit or by the transfer that creates it
+            if tx.recipient_pub_key.is_some() {
+                if tx.to == 0 || !to.is_empty() {
+                    return Err(TransferApplicationError::InvalidTransaction(
+                        "recipient account already exists".to_string(),
+                    ));
+                }
+                let (pub_x, pub_y) = tx
+                    .new_account_pub_key()
+                    .ok_or_else(|| {
+                        TransferApplicationError::InvalidTransaction(
+                            "recipient public key is not a valid key".to_string(),
+                        )
+                    })?
+                    .0
+                    .into_xy();
+                to.public_key_x = pub_x;
+                to.public_key_y = pub_y;
+            } else if tx.to != 0 && to.get_pub_key().is_none() {
+                return Err(TransferApplicationError::InvalidTransaction(
+                    "recipient has no public key".to_string(),
+                ));
+            }
+
+            let from_balance = from.get_balance(tx.token) - transacted_amount;
+            from.set_balance(tx.token, from_balance);
 
             from.nonce += 1;
             if tx.to != 0 {
-                to.balance += &tx.amount;
+                let to_balance = to.get_balance(tx.token) + &tx.amount;
+                to.set_balance(tx.token, to_balance);
             }
 
-            self.balance_tree.insert(tx.to, to);
+            // a transfer to a missing account 0 must not create an empty one
+            if !to.is_empty() {
+                self.balance_tree.insert(tx.to, to);
+            }
             self.balance_tree.insert(tx.from, from);
 
             let collected_fee = tx.fee.clone();
-            println!("transacted root hash {}",self.balance_tree.root_hash());
 
             return Ok(collected_fee);
         }
@@ -128,35 +184,306 @@ impl PlasmaState {
         Err(TransferApplicationError::InvalidSigner)
     }
 
+    /// An id without an account, never used or exited, gets a new account with the key of the
+    /// deposit and a zero nonce. An exited id is reused in the same way as a fresh one
     pub fn apply_deposit(&mut self, tx: &DepositTx) -> Result<(), ()> {
-        let existing_acc = self.balance_tree.items.get(&tx.account);
+        let existing_acc = self.balance_tree.get(tx.account);
 
-        if existing_acc.is_none() || existing_acc.unwrap().balance == BigDecimal::zero(){
+        if existing_acc.is_none() || existing_acc.as_ref().unwrap().is_empty() {
             let mut acc = Account::default();
             let tx = tx.clone();
             acc.public_key_x = tx.pub_x;
             acc.public_key_y = tx.pub_y;
-            acc.balance = tx.amount;
+            acc.set_balance(tx.token, tx.amount);
             self.balance_tree.insert(tx.account, acc);
         } else {
-            let mut acc = existing_acc.unwrap().clone();
-            acc.balance += &tx.amount;
+            let mut acc = existing_acc.unwrap();
+            let balance = acc.get_balance(tx.token) + &tx.amount;
+            acc.set_balance(tx.token, balance);
             self.balance_tree.insert(tx.account, acc);
         }
         Ok(())
     }
 
     pub fn apply_exit(&mut self, tx: &ExitTx) -> Result<ExitTx, ()> {
-        let acc = self.balance_tree.items.get(&tx.account).ok_or(())?.clone();
+        let mut acc = self.balance_tree.get(tx.account).ok_or(())?;
 
         let mut agumented_tx = tx.clone();
 
-        debug!("Adding account balance to ExitTx, value = {}", acc.balance);
+        let balance = acc.get_balance(tx.token);
+
+        debug!("Adding account balance to ExitTx, value = {}", balance);
 
-        agumented_tx.amount = acc.balance;
+        agumented_tx.amount = balance;
 
-        self.balance_tree.delete(tx.account);
+        // account is removed only when it has no balances left in any token
+        // and no locked funds
+        acc.set_balance(tx.token, BigDecimal::zero());
+        if acc.balances.is_empty() && acc.hash_lock.is_none() {
+            self.balance_tree.delete(tx.account);
+        } else {
+            self.balance_tree.insert(tx.account, acc);
+        }
 
         Ok(agumented_tx)
     }
+
+    /// Checks a key change against the current state without applying it.
+    /// The change is signed by the current key, so an empty account has nobody to sign it:
+    /// otherwise anyone could take an empty id with a self-signed key. Keys of new accounts
+    /// are registered by deposits, the contract assigns those ids, or by transfers to them.
+    /// A multisig account has no private key, so its keys can not be changed
+    pub fn check_change_pubkey(&self, tx: &ChangePubKeyTx) -> Result<(), TransferApplicationError> {
+        if tx.account == params::SPECIAL_ACCOUNT_EXIT
+            || tx.account >= (1 << params::BALANCE_TREE_DEPTH)
+        {
+            return Err(TransferApplicationError::InvalidTransaction(format!(
+                "account can not have a key: {}",
+                tx.account
+            )));
+        }
+
+        let acc = self
+            .get_account(tx.account)
+            .ok_or(TransferApplicationError::UnknownSigner)?;
+
+        if let Some(ref multisig) = tx.multisig {
+            multisig
+                .validate()
+                .map_err(TransferApplicationError::InvalidTransaction)?;
+            if multisig.commitment() != (tx.new_pub_x, tx.new_pub_y) {
+                return Err(TransferApplicationError::InvalidTransaction(
+                    "new public key is not the commitment to the multisig keys".to_string(),
+                ));
+            }
+        }
+
+        let signer = acc
+            .get_pub_key()
+            .ok_or(TransferApplicationError::UnknownSigner)?;
+        if !tx.verify_sig(&signer) {
+            return Err(TransferApplicationError::InvalidSigner);
+        }
+
+        if tx.nonce > acc.nonce {
+            return Err(TransferApplicationError::NonceIsTooHigh);
+        } else if tx.nonce < acc.nonce {
+            return Err(TransferApplicationError::NonceIsTooLow);
+        }
+
+        if tx.new_pub_key().is_none() {
+            return Err(TransferApplicationError::InvalidTransaction(
+                "new public key is not a curve point".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the public key of the account
+    pub fn apply_change_pubkey(
+        &mut self,
+        tx: &ChangePubKeyTx,
+    ) -> Result<(), TransferApplicationError> {
+        self.check_change_pubkey(tx)?;
+
+        let mut acc = self
+            .get_account(tx.account)
+            .expect("account is checked to exist");
+        acc.public_key_x = tx.new_pub_x;
+        acc.public_key_y = tx.new_pub_y;
+        acc.multisig = tx.multisig.clone();
+        acc.nonce += 1;
+        self.balance_tree.insert(tx.account, acc);
+
+        Ok(())
+    }
+
+    /// Checks a cheque redemption against the current state without applying it
+    pub fn check_cheque(&self, tx: &ChequeTx) -> Result<(), TransferApplicationError> {
+        tx.validate()
+            .map_err(TransferApplicationError::InvalidTransaction)?;
+
+        let from = self
+            .get_account(tx.from)
+            .ok_or(TransferApplicationError::UnknownSigner)?;
+        let pub_key = from
+            .get_pub_key()
+            .ok_or(TransferApplicationError::UnknownSigner)?;
+        if !tx.verify_sig(&pub_key) {
+            return Err(TransferApplicationError::InvalidSigner);
+        }
+
+        if tx.good_until_block < self.block_number {
+            return Err(TransferApplicationError::ExpiredTransaction);
+        }
+
+        if from.get_balance(tx.token) < tx.amount {
+            return Err(TransferApplicationError::InsufficientBalance);
+        }
+
+        if from.cheque_window.redeem(tx.serial).is_none() {
+            return Err(TransferApplicationError::InvalidTransaction(format!(
+                "cheque {} is already redeemed or is out of the window",
+                tx.serial
+            )));
+        }
+
+        let to = self.get_account(tx.to).unwrap_or_default();
+        if to.get_pub_key().is_none() {
+            return Err(TransferApplicationError::InvalidTransaction(
+                "recipient has no public key".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Moves the cheque amount to the payee and marks the serial as redeemed by the payer
+    pub fn apply_cheque(&mut self, tx: &ChequeTx) -> Result<(), TransferApplicationError> {
+        self.check_cheque(tx)?;
+
+        let mut from = self.get_account(tx.from).unwrap_or_default();
+        let from_balance = from.get_balance(tx.token) - &tx.amount;
+        from.set_balance(tx.token, from_balance);
+        from.cheque_window = from
+            .cheque_window
+            .redeem(tx.serial)
+            .expect("serial is checked");
+        self.balance_tree.insert(tx.from, from);
+
+        let mut to = self.get_account(tx.to).unwrap_or_default();
+        let to_balance = to.get_balance(tx.token) + &tx.amount;
+        to.set_balance(tx.token, to_balance);
+        self.balance_tree.insert(tx.to, to);
+
+        Ok(())
+    }
+
+    /// Checks a hash lock against the current state without applying it
+    pub fn check_lock(&self, tx: &LockTx) -> Result<(), TransferApplicationError> {
+        tx.validate()
+            .map_err(TransferApplicationError::InvalidTransaction)?;
+
+        let acc = self
+            .get_account(tx.account)
+            .ok_or(TransferApplicationError::UnknownSigner)?;
+        let pub_key = acc
+            .get_pub_key()
+            .ok_or(TransferApplicationError::UnknownSigner)?;
+        if !tx.verify_sig(&pub_key) {
+            return Err(TransferApplicationError::InvalidSigner);
+        }
+
+        if tx.nonce > acc.nonce {
+            return Err(TransferApplicationError::NonceIsTooHigh);
+        } else if tx.nonce < acc.nonce {
+            return Err(TransferApplicationError::NonceIsTooLow);
+        }
+
+        if acc.hash_lock.is_some() {
+            return Err(TransferApplicationError::InvalidTransaction(
+                "account already has a hash lock".to_string(),
+            ));
+        }
+
+        if acc.get_balance(tx.token) < tx.amount {
+            return Err(TransferApplicationError::InsufficientBalance);
+        }
+
+        let to = self.get_account(tx.to).unwrap_or_default();
+        if to.get_pub_key().is_none() {
+            return Err(TransferApplicationError::InvalidTransaction(
+                "recipient has no public key".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Takes the amount from the balance and keeps it in the hash lock of the account
+    pub fn apply_lock(&mut self, tx: &LockTx) -> Result<(), TransferApplicationError> {
+        self.check_lock(tx)?;
+
+        let mut acc = self.get_account(tx.account).unwrap_or_default();
+        let balance = acc.get_balance(tx.token) - &tx.amount;
+        acc.set_balance(tx.token, balance);
+        acc.hash_lock = Some(tx.hash_lock());
+        acc.nonce += 1;
+        self.balance_tree.insert(tx.account, acc);
+
+        Ok(())
+    }
+
+    /// Checks a claim against the current state without applying it:
+    /// the preimage must match the lock hash before the lock expires
+    pub fn check_claim(&self, tx: &ClaimTx) -> Result<(), TransferApplicationError> {
+        let lock = self
+            .get_account(tx.account)
+            .and_then(|acc| acc.hash_lock)
+            .ok_or_else(|| {
+                TransferApplicationError::InvalidTransaction("account has no hash lock".to_string())
+            })?;
+
+        if !lock.is_unlocked_by(&tx.preimage) {
+            return Err(TransferApplicationError::InvalidTransaction(
+                "preimage does not match the lock hash".to_string(),
+            ));
+        }
+
+        if lock.good_until_block < self.block_number {
+            return Err(TransferApplicationError::ExpiredTransaction);
+        }
+
+        Ok(())
+    }
+
+    /// Moves the locked amount to the recipient of the lock
+    pub fn apply_claim(&mut self, tx: &ClaimTx) -> Result<(), TransferApplicationError> {
+        self.check_claim(tx)?;
+
+        let mut acc = self.get_account(tx.account).unwrap_or_default();
+        let lock = acc.hash_lock.take().expect("lock is checked");
+        self.balance_tree.insert(tx.account, acc);
+
+        let mut to = self.get_account(lock.to).unwrap_or_default();
+        let to_balance = to.get_balance(lock.token) + &lock.amount;
+        to.set_balance(lock.token, to_balance);
+        self.balance_tree.insert(lock.to, to);
+
+        Ok(())
+    }
+
+    /// Checks a refund against the current state without applying it:
+    /// the lock must be expired
+    pub fn check_refund(&self, tx: &RefundTx) -> Result<(), TransferApplicationError> {
+        let lock = self
+            .get_account(tx.account)
+            .and_then(|acc| acc.hash_lock)
+            .ok_or_else(|| {
+                TransferApplicationError::InvalidTransaction("account has no hash lock".to_string())
+            })?;
+
+        if lock.good_until_block >= self.block_number {
+            return Err(TransferApplicationError::InvalidTransaction(format!(
+                "hash lock is good until block {}",
+                lock.good_until_block
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the locked amount to the account
+    pub fn apply_refund(&mut self, tx: &RefundTx) -> Result<(), TransferApplicationError> {
+        self.check_refund(tx)?;
+
+        let mut acc = self.get_account(tx.account).unwrap_or_default();
+        let lock = acc.hash_lock.take().expect("lock is checked");
+        let balance = acc.get_balance(lock.token) + &lock.amount;
+        acc.set_balance(lock.token, balance);
+        self.balance_tree.insert(tx.account, acc);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,69 @@
+//! Account tree kept on disk between restarts. Loading the state from storage hashes every
+//! account again, a stored tree only needs the accounts updated since it was saved.
+//! The state keeper and the prover copy the tree into memory with `merkle_tree::copy_tree`,
+//! so the store saves the hashing at startup, not the memory or the reading of every node
+
+use merkle_tree::node_store::DiskStore;
+use merkle_tree::DiskAccountTree;
+use models::plasma::params;
+use models::plasma::AccountMap;
+use std::path::Path;
+
+/// Opens the tree at `path` and brings it to the state after `block_number`.
+/// `load_diff(from, to)` loads the accounts updated in blocks `from..to`, as
+/// `load_state_diff` does. `load_state(block_number)` loads all the accounts, for a new tree
+/// and for one that is ahead of the block (after a rollback) or was left in the middle of
+/// an update. The block must be final, i.e. verified, or the tree would have to be rolled back
+pub fn open_account_tree<D, L>(
+    path: &Path,
+    block_number: u32,
+    load_diff: D,
+    load_state: L,
+) -> Result<DiskAccountTree, String>
+where
+    D: FnOnce(u32, u32) -> Result<AccountMap, String>,
+    L: FnOnce(u32) -> Result<AccountMap, String>,
+{
+    let open = || {
+        DiskStore::open(path)
+            .map(|store| DiskAccountTree::with_store(params::BALANCE_TREE_DEPTH as u32, store))
+            .map_err(|e| format!("can not open account tree at {}: {}", path.display(), e))
+    };
+
+    let mut tree = open()?;
+    let accounts = match tree.block_number() {
+        Some(stored) if stored == block_number => return Ok(tree),
+        Some(stored) if stored < block_number => {
+            info!(
+                "account tree is at block {}, loading updates up to {}",
+                stored, block_number
+            );
+            load_diff(stored + 1, block_number + 1)?
+        }
+        stored => {
+            info!(
+                "account tree is at block {:?}, building it anew for block {}",
+                stored, block_number
+            );
+            drop(tree);
+            std::fs::remove_dir_all(path)
+                .map_err(|e| format!("can not remove {}: {}", path.display(), e))?;
+            tree = open()?;
+            load_state(block_number)?
+        }
+    };
+
+    // an empty account is an exited one, see `PlasmaState::update_accounts`
+    let (exited, updated): (Vec<_>, Vec<_>) =
+        accounts.into_iter().partition(|(_, acc)| acc.is_empty());
+    for (id, _) in exited {
+        tree.delete(id);
+    }
+    tree.insert_batch(updated);
+    tree.set_block_number(block_number);
+    tree.store
+        .flush()
+        .map_err(|e| format!("can not save account tree: {}", e))?;
+
+    Ok(tree)
+}
@@ -31,45 +31,67 @@ use bellman::groth16::{
 };
 
 use circuit::CircuitAccountTree;
+use models::plasma::account::ChequeWindow;
 use models::plasma::block::Block;
 use models::plasma::block::BlockData;
-use models::plasma::circuit::account::CircuitAccount;
+use models::plasma::circuit::account::{leaf_pub_key_hash, CircuitAccount};
 use models::plasma::params;
-use models::plasma::tx::{DepositTx, ExitTx, TransferTx};
-use models::plasma::{AccountId, BlockNumber, Engine, Fr};
+use models::plasma::tx::{ChangePubKeyTx, ChequeTx, DepositTx, ExitTx, LockTx, TransferTx};
+use models::plasma::{AccountId, BlockNumber, Engine, Fr, H256};
 use plasma::state::PlasmaState;
+use plasma::tree_store::open_account_tree;
+use std::path::Path;
 
 use circuit::encoder;
 use models::config::{
-    DEPOSIT_BATCH_SIZE, EXIT_BATCH_SIZE, PROVER_CYCLE_WAIT, PROVER_TIMEOUT, PROVER_TIMER_TICK,
-    RUNTIME_CONFIG,
+    CHANGE_PUBKEY_BATCH_SIZE, CHEQUE_BATCH_SIZE, DEPOSIT_BATCH_SIZE, EXIT_BATCH_SIZE,
+    LOCK_BATCH_SIZE, PROVER_CYCLE_WAIT, PROVER_TIMEOUT, PROVER_TIMER_TICK, RUNTIME_CONFIG,
+    UNLOCK_BATCH_SIZE,
 };
 use models::EncodedProof;
 use storage::StorageProcessor;
 
+use circuit::change_pubkey::change_pubkey_request::ChangePubKeyRequest;
+use circuit::change_pubkey::circuit::{ChangePubKey, ChangePubKeyWitness};
+use circuit::cheque::cheque_request::ChequeRequest;
+use circuit::cheque::circuit::{Cheque, ChequeWitness};
 use circuit::deposit::circuit::{Deposit, DepositWitness};
 use circuit::deposit::deposit_request::DepositRequest;
 use circuit::exit::circuit::{Exit, ExitWitness};
 use circuit::exit::exit_request::ExitRequest;
 use circuit::leaf::LeafWitness;
+use circuit::lock::circuit::{Lock, LockWitness};
+use circuit::lock::lock_request::LockRequest;
 use circuit::transfer::transaction::Transaction;
+use circuit::unlock::circuit::{Unlock, UnlockWitness};
+use circuit::unlock::unlock_request::UnlockRequest;
+use models::plasma::circuit::sig::TransactionSignature;
 use models::plasma::circuit::utils::be_bit_vector_into_bytes;
 
 use circuit::transfer::circuit::{TransactionWitness, Transfer};
 
 use models::primitives::{
-    field_element_to_u32, serialize_g1_for_ethereum, serialize_g2_for_ethereum,
+    field_element_to_u128, field_element_to_u32, serialize_g1_for_ethereum,
+    serialize_g2_for_ethereum,
 };
 
 pub struct Prover<E: JubjubEngine> {
     pub transfer_batch_size: usize,
     pub deposit_batch_size: usize,
     pub exit_batch_size: usize,
+    pub change_pubkey_batch_size: usize,
+    pub cheque_batch_size: usize,
+    pub lock_batch_size: usize,
+    pub unlock_batch_size: usize,
     pub current_block_number: BlockNumber,
     pub accounts_tree: CircuitAccountTree,
     pub transfer_parameters: BabyParameters,
     pub deposit_parameters: BabyParameters,
     pub exit_parameters: BabyParameters,
+    pub change_pubkey_parameters: BabyParameters,
+    pub cheque_parameters: BabyParameters,
+    pub lock_parameters: BabyParameters,
+    pub unlock_parameters: BabyParameters,
     pub jubjub_params: E::Params,
     pub worker: String,
     pub prover_id: i32,
@@ -138,7 +160,8 @@ fn read_parameters(file_name: &str) -> Result<BabyParameters, BabyProverErr> {
         return Err(BabyProverErr::IoError(f_r.err().unwrap()));
     }
     let mut r = BufReader::new(f_r.unwrap());
-    let circuit_params = BabyParameters::read(&mut r, true);
+    // fails for keys made for another BALANCE_TREE_DEPTH
+    let circuit_params = circuit::keys::read_parameters(&mut r, true);
 
     if circuit_params.is_err() {
         return Err(BabyProverErr::IoError(circuit_params.err().unwrap()));
@@ -151,11 +174,16 @@ fn extend_accounts<I: Sized + Iterator<Item = (AccountId, models::plasma::Accoun
     tree: &mut CircuitAccountTree,
     accounts: I,
 ) {
-    for e in accounts {
-        let acc_number = e.0;
-        let leaf_copy = CircuitAccount::from(e.1.clone());
-        tree.insert(acc_number, leaf_copy);
+    // an empty account is an exited one, see `PlasmaState::update_accounts`
+    let (exited, updated): (Vec<_>, Vec<_>) = accounts.partition(|(_, account)| account.is_empty());
+    for (id, _) in exited {
+        tree.delete(id);
     }
+    tree.insert_batch(
+        updated
+            .into_iter()
+            .map(|(id, account)| (id, CircuitAccount::from(account))),
+    );
 }
 
 // IMPORTANT: prover does NOT care about some ordering of the transactions, so blocks supplied here MUST be ordered
@@ -200,14 +228,70 @@ impl BabyProver {
         Ok(p)
     }
 
+    /// Verified state from the account tree kept at `path`, copied into memory once for the
+    /// plasma state and once for the circuit. Its root is checked against the last verified block
+    fn load_state_from_tree(
+        storage: &StorageProcessor,
+        path: &Path,
+    ) -> Result<(PlasmaState, CircuitAccountTree), BabyProverErr> {
+        let last_verified = storage
+            .get_last_verified_block()
+            .map_err(|e| BabyProverErr::Other(format!("get_last_verified_block failed: {}", e)))?;
+        let tree = open_account_tree(
+            path,
+            last_verified,
+            |from, to| {
+                storage
+                    .load_state_diff(from, to)
+                    .map(|(_, accounts)| accounts)
+                    .map_err(|e| format!("load_state_diff failed: {}", e))
+            },
+            |block_number| {
+                storage
+                    .load_state_at_block(block_number)
+                    .map(|(_, accounts)| accounts)
+                    .map_err(|e| format!("load_state_at_block failed: {}", e))
+            },
+        )
+        .map_err(BabyProverErr::Other)?;
+
+        if last_verified > 0 {
+            let block = storage.load_committed_block(last_verified).ok_or_else(|| {
+                BabyProverErr::Other(format!("block {} is not committed", last_verified))
+            })?;
+            if block.new_root_hash != tree.root_hash() {
+                return Err(BabyProverErr::Other(format!(
+                    "account tree at {} does not match block {}",
+                    path.display(),
+                    last_verified
+                )));
+            }
+        }
+
+        let state = PlasmaState::from_tree(
+            merkle_tree::copy_tree(&tree, |account| account),
+            last_verified + 1,
+        );
+        let circuit_tree = merkle_tree::copy_tree(&tree, CircuitAccount::from);
+        Ok((state, circuit_tree))
+    }
+
     pub fn create(worker: String) -> Result<BabyProver, BabyProverErr> {
         let storage =
             StorageProcessor::establish_connection().expect("db connection failed for prover");
 
-        let (last_block, accounts) = storage
-            .load_verified_state()
-            .expect("db must be functional");
-        let initial_state = PlasmaState::new(accounts, last_block + 1);
+        let (initial_state, stored_tree) = match RUNTIME_CONFIG.account_tree_path {
+            Some(ref path) => {
+                let (state, tree) = Self::load_state_from_tree(&storage, Path::new(path))?;
+                (state, Some(tree))
+            }
+            None => {
+                let (last_block, accounts) = storage
+                    .load_verified_state()
+                    .expect("db must be functional");
+                (PlasmaState::new(accounts, last_block + 1), None)
+            }
+        };
 
         info!("Reading proving key, may take a while");
 
@@ -241,11 +325,48 @@ impl BabyProver {
 
         debug!("Done reading exit key");
 
-        info!("Copying states to balance tree");
+        let path = format!("{}/change_pubkey_pk.key", keys_path);
+        debug!("Reading key from {}", path);
+        let change_pubkey_circuit_params = read_parameters(&path);
+        if change_pubkey_circuit_params.is_err() {
+            return Err(change_pubkey_circuit_params.err().unwrap());
+        }
+
+        debug!("Done reading change pubkey key");
+
+        let path = format!("{}/cheque_pk.key", keys_path);
+        debug!("Reading key from {}", path);
+        let cheque_circuit_params = read_parameters(&path);
+        if cheque_circuit_params.is_err() {
+            return Err(cheque_circuit_params.err().unwrap());
+        }
+
+        debug!("Done reading cheque key");
+
+        let path = format!("{}/lock_pk.key", keys_path);
+        debug!("Reading key from {}", path);
+        let lock_circuit_params = read_parameters(&path);
+        if lock_circuit_params.is_err() {
+            return Err(lock_circuit_params.err().unwrap());
+        }
+
+        debug!("Done reading lock key");
+
+        let path = format!("{}/unlock_pk.key", keys_path);
+        debug!("Reading key from {}", path);
+        let unlock_circuit_params = read_parameters(&path);
+        if unlock_circuit_params.is_err() {
+            return Err(unlock_circuit_params.err().unwrap());
+        }
+
+        debug!("Done reading unlock key");
 
-        // TODO: replace with .clone() by moving PedersenHasher to static context
-        let mut tree = CircuitAccountTree::new(params::BALANCE_TREE_DEPTH as u32);
-        extend_accounts(&mut tree, initial_state.get_accounts().into_iter());
+        let tree = stored_tree.unwrap_or_else(|| {
+            info!("Copying states to balance tree");
+            let mut tree = CircuitAccountTree::new(params::BALANCE_TREE_DEPTH as u32);
+            extend_accounts(&mut tree, initial_state.get_accounts().into_iter());
+            tree
+        });
         // {
         //     let iter = initial_state.get_accounts().into_iter();
 
@@ -283,11 +404,19 @@ impl BabyProver {
             transfer_batch_size: RUNTIME_CONFIG.transfer_batch_size,
             deposit_batch_size: DEPOSIT_BATCH_SIZE,
             exit_batch_size: EXIT_BATCH_SIZE,
+            change_pubkey_batch_size: CHANGE_PUBKEY_BATCH_SIZE,
+            cheque_batch_size: CHEQUE_BATCH_SIZE,
+            lock_batch_size: LOCK_BATCH_SIZE,
+            unlock_batch_size: UNLOCK_BATCH_SIZE,
             current_block_number: state_block_number,
             accounts_tree: tree,
             transfer_parameters: transfer_circuit_params.unwrap(),
             deposit_parameters: deposit_circuit_params.unwrap(),
             exit_parameters: exit_circuit_params.unwrap(),
+            change_pubkey_parameters: change_pubkey_circuit_params.unwrap(),
+            cheque_parameters: cheque_circuit_params.unwrap(),
+            lock_parameters: lock_circuit_params.unwrap(),
+            unlock_parameters: unlock_circuit_params.unwrap(),
             jubjub_params,
             current_job: Arc::new(AtomicUsize::new(0)),
             worker,
@@ -312,6 +441,29 @@ impl BabyProver {
             BlockData::Transfer {
                 ref transactions, ..
             } => self.apply_and_prove_transfer(&block, &transactions),
+            BlockData::ChangePubKey { ref transactions } => {
+                self.apply_and_prove_change_pubkey(&block, transactions)
+            }
+            BlockData::Cheque { ref transactions } => {
+                self.apply_and_prove_cheque(&block, transactions)
+            }
+            BlockData::Lock { ref transactions } => self.apply_and_prove_lock(&block, transactions),
+            BlockData::Claim { ref transactions } => {
+                let requests = transactions
+                    .iter()
+                    .map(circuit::CircuitUnlockRequest::try_from_claim)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(BabyProverErr::InvalidTransaction)?;
+                self.apply_and_prove_unlock(&block, &requests)
+            }
+            BlockData::Refund { ref transactions } => {
+                let requests = transactions
+                    .iter()
+                    .map(circuit::CircuitUnlockRequest::try_from_refund)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(BabyProverErr::InvalidTransaction)?;
+                self.apply_and_prove_unlock(&block, &requests)
+            }
         }
     }
 
@@ -354,14 +506,14 @@ impl BabyProver {
                 .map_err(|e| BabyProverErr::InvalidTransaction(e.to_string()))?;
             let sender_leaf_number = field_element_to_u32(tx.from);
             let recipient_leaf_number = field_element_to_u32(tx.to);
+            let token = field_element_to_u32(tx.token);
 
             let empty_account = CircuitAccount::default();
 
             let tree = &mut self.accounts_tree;
-            let items = tree.items.clone();
 
-            let sender_leaf = items.get(&sender_leaf_number);
-            let mut recipient_leaf = items.get(&recipient_leaf_number);
+            let sender_leaf = tree.get(sender_leaf_number);
+            let mut recipient_leaf = tree.get(recipient_leaf_number);
 
             if sender_leaf.is_none() {
                 return Err(BabyProverErr::InvalidSender);
@@ -369,7 +521,7 @@ impl BabyProver {
 
             // allow transfers to empty accounts
             if recipient_leaf.is_none() {
-                recipient_leaf = Some(&empty_account);
+                recipient_leaf = Some(empty_account);
             }
 
             // this is LE bits encoding of the transaction amount
@@ -416,32 +568,49 @@ impl BabyProver {
                 .map(|e| Some(e.0))
                 .collect();
 
+            // a transfer to a new account registers the key of the recipient
+            let (new_pub_x, new_pub_y) = tx.recipient_pub_key.unwrap_or((Fr::zero(), Fr::zero()));
+
             let transaction = Transaction {
                 from: Some(tx.from),
                 to: Some(tx.to),
+                token: Some(tx.token),
                 amount: Some(tx.amount),
                 fee: Some(tx.fee),
                 nonce: Some(tx.nonce),
                 good_until_block: Some(tx.good_until_block),
+                chain_id: Some(tx.chain_id),
                 signature: Some(tx.signature.clone()),
+                multisig_signatures: (0..params::MULTISIG_MAX_SIGNERS)
+                    .map(|i| match tx.multisig_signatures.get(i) {
+                        Some(Some(signature)) => Some(signature.clone()),
+                        _ => Some(TransactionSignature::empty(&*params::JUBJUB_PARAMS)),
+                    })
+                    .collect(),
+                to_new_account: Some(tx.recipient_pub_key.is_some()),
+                new_pub_x: Some(new_pub_x),
+                new_pub_y: Some(new_pub_y),
             };
 
-            let mut updated_sender_leaf = sender_leaf.unwrap().clone();
-            let mut updated_recipient_leaf = recipient_leaf.unwrap().clone();
+            let mut updated_sender_leaf = sender_leaf.clone().unwrap();
+            let mut updated_recipient_leaf = recipient_leaf.clone().unwrap();
 
-            updated_sender_leaf
-                .balance
-                .sub_assign(&transfer_amount_as_field_element);
-            updated_sender_leaf
-                .balance
-                .sub_assign(&fee_as_field_element);
+            let mut sender_balance = updated_sender_leaf.get_balance(token);
+            sender_balance.sub_assign(&transfer_amount_as_field_element);
+            sender_balance.sub_assign(&fee_as_field_element);
+            updated_sender_leaf.set_balance(token, sender_balance, &self.jubjub_params);
 
             updated_sender_leaf.nonce.add_assign(&Fr::one());
 
+            if tx.recipient_pub_key.is_some() {
+                updated_recipient_leaf.pub_x = new_pub_x;
+                updated_recipient_leaf.pub_y = new_pub_y;
+            }
+
             if recipient_leaf_number != 0 {
-                updated_recipient_leaf
-                    .balance
-                    .add_assign(&transfer_amount_as_field_element);
+                let mut recipient_balance = updated_recipient_leaf.get_balance(token);
+                recipient_balance.add_assign(&transfer_amount_as_field_element);
+                updated_recipient_leaf.set_balance(token, recipient_balance, &self.jubjub_params);
             }
 
             total_fees.add_assign(&fee_as_field_element);
@@ -449,29 +618,71 @@ impl BabyProver {
             tree.insert(recipient_leaf_number, updated_recipient_leaf.clone());
             tree.insert(sender_leaf_number, updated_sender_leaf.clone());
 
-
-            println!("trancation root hash {}",tree.root_hash());
-
             {
                 let sender_leaf = sender_leaf.unwrap();
 
                 let recipient_leaf = recipient_leaf.unwrap();
 
+                // keys of a single key sender are all the identity point and none of them signs
+                let multisig_threshold = sender_leaf.multisig.as_ref().map_or(0, |m| m.threshold);
+                let multisig_keys: Vec<(Fr, Fr)> = (0..params::MULTISIG_MAX_SIGNERS)
+                    .map(|i| {
+                        sender_leaf
+                            .multisig
+                            .as_ref()
+                            .and_then(|m| m.keys.get(i).cloned())
+                            .unwrap_or((Fr::zero(), Fr::one()))
+                    })
+                    .collect();
+                let signed_by =
+                    |i: usize| tx.multisig_signatures.get(i).map_or(false, Option::is_some);
+
                 let transaction_witness = TransactionWitness::<Engine> {
                     auth_path_from: path_from,
                     leaf_from: LeafWitness::<Engine> {
-                        balance: Some(sender_leaf.balance),
+                        balance: Some(sender_leaf.get_balance(token)),
+                        balance_path: sender_leaf
+                            .balance_path(token, &self.jubjub_params)
+                            .into_iter()
+                            .map(Some)
+                            .collect(),
                         nonce: Some(sender_leaf.nonce),
                         pub_x: Some(sender_leaf.pub_x),
                         pub_y: Some(sender_leaf.pub_y),
+                        pub_key_hash: leaf_pub_key_hash(&sender_leaf.pub_x, &sender_leaf.pub_y),
+                        cheque_bits: Some(sender_leaf.cheque_bits),
+                        cheque_start: Some(sender_leaf.cheque_start),
+                        lock_commitment: Some(sender_leaf.lock_commitment),
                     },
                     auth_path_to: path_to,
                     leaf_to: LeafWitness::<Engine> {
-                        balance: Some(recipient_leaf.balance),
+                        balance: Some(recipient_leaf.get_balance(token)),
+                        balance_path: recipient_leaf
+                            .balance_path(token, &self.jubjub_params)
+                            .into_iter()
+                            .map(Some)
+                            .collect(),
                         nonce: Some(recipient_leaf.nonce),
-                        pub_x: Some(recipient_leaf.pub_x),
-                        pub_y: Some(recipient_leaf.pub_y),
+                        // a compact leaf only needs the hash of a key that is not used
+                        pub_x: Some(recipient_leaf.pub_x).filter(|_| !params::COMPACT_LEAF),
+                        pub_y: Some(recipient_leaf.pub_y).filter(|_| !params::COMPACT_LEAF),
+                        pub_key_hash: leaf_pub_key_hash(
+                            &recipient_leaf.pub_x,
+                            &recipient_leaf.pub_y,
+                        ),
+                        cheque_bits: Some(recipient_leaf.cheque_bits),
+                        cheque_start: Some(recipient_leaf.cheque_start),
+                        lock_commitment: Some(recipient_leaf.lock_commitment),
                     },
+                    is_multisig: Some(sender_leaf.multisig.is_some()),
+                    multisig_threshold: Fr::from_str(&multisig_threshold.to_string()),
+                    multisig_keys: multisig_keys
+                        .into_iter()
+                        .map(|(x, y)| (Some(x), Some(y)))
+                        .collect(),
+                    multisig_signed: (0..params::MULTISIG_MAX_SIGNERS)
+                        .map(|i| Some(sender_leaf.multisig.is_some() && signed_by(i)))
+                        .collect(),
                 };
 
                 let witness = (transaction.clone(), transaction_witness);
@@ -571,6 +782,7 @@ impl BabyProver {
         let instance = Transfer {
             params: &self.jubjub_params,
             number_of_transactions: num_txes,
+            chain_id: Fr::from_str(&RUNTIME_CONFIG.chain_id.to_string()).unwrap(),
             old_root: Some(initial_root),
             new_root: Some(final_root),
             public_data_commitment: Some(public_data_commitment),
@@ -700,24 +912,26 @@ impl BabyProver {
                 .map_err(|e| BabyProverErr::InvalidTransaction(e.to_string()))?;
 
             let into_leaf_number = field_element_to_u32(tx.into);
+            let token = field_element_to_u32(tx.token);
 
             let tree = &mut self.accounts_tree;
-            let items = tree.items.clone();
 
-            let existing_leaf = items.get(&into_leaf_number);
+            let existing_leaf = tree.get(into_leaf_number);
             let mut leaf_is_empty = true;
 
-            let (old_leaf, new_leaf) = if existing_leaf.is_none() || existing_leaf.unwrap().balance == Fr::zero() {
+            let (old_leaf, new_leaf) = if existing_leaf.is_none() || existing_leaf.as_ref().unwrap().is_empty() {
                 let mut new_leaf = CircuitAccount::default();
-                new_leaf.balance = tx.amount;
+                new_leaf.set_balance(token, tx.amount, &self.jubjub_params);
                 new_leaf.pub_x = tx.pub_x;
                 new_leaf.pub_y = tx.pub_y;
 
                 (CircuitAccount::default(), new_leaf)
             } else {
-                let old_leaf = existing_leaf.unwrap().clone();
+                let old_leaf = existing_leaf.unwrap();
                 let mut new_leaf = old_leaf.clone();
-                new_leaf.balance.add_assign(&tx.amount);
+                let mut balance = new_leaf.get_balance(token);
+                balance.add_assign(&tx.amount);
+                new_leaf.set_balance(token, balance, &self.jubjub_params);
                 leaf_is_empty = false;
 
                 (old_leaf, new_leaf)
@@ -738,6 +952,7 @@ impl BabyProver {
 
             let request = DepositRequest {
                 into: Fr::from_str(&into_leaf_number.to_string()),
+                token: Some(tx.token),
                 amount: Some(tx.amount),
                 public_key,
             };
@@ -748,10 +963,19 @@ impl BabyProver {
                 let deposit_witness = DepositWitness::<Engine> {
                     auth_path: path,
                     leaf: LeafWitness::<Engine> {
-                        balance: Some(old_leaf.balance),
+                        balance: Some(old_leaf.get_balance(token)),
+                        balance_path: old_leaf
+                            .balance_path(token, &self.jubjub_params)
+                            .into_iter()
+                            .map(Some)
+                            .collect(),
                         nonce: Some(old_leaf.nonce),
                         pub_x: Some(old_leaf.pub_x),
                         pub_y: Some(old_leaf.pub_y),
+                        pub_key_hash: leaf_pub_key_hash(&old_leaf.pub_x, &old_leaf.pub_y),
+                        cheque_bits: Some(old_leaf.cheque_bits),
+                        cheque_start: Some(old_leaf.cheque_start),
+                        lock_commitment: Some(old_leaf.lock_commitment),
                     },
 
                     leaf_is_empty: Some(leaf_is_empty),
@@ -951,11 +1175,11 @@ impl BabyProver {
                 .map_err(|e| BabyProverErr::InvalidTransaction(e.to_string()))?;
 
             let from_leaf_number = field_element_to_u32(tx.from);
+            let token = field_element_to_u32(tx.token);
 
             let tree = &mut self.accounts_tree;
-            let items = tree.items.clone();
 
-            let existing_leaf = items.get(&from_leaf_number);
+            let existing_leaf = tree.get(from_leaf_number);
 
             if existing_leaf.is_none() {
                 return Err(BabyProverErr::Other("existing_leaf.is_none()".to_owned()));
@@ -963,7 +1187,11 @@ impl BabyProver {
 
             let old_leaf = existing_leaf.unwrap();
 
-            let new_leaf = CircuitAccount::default();
+            // the whole balance of the token is withdrawn, the leaf is emptied
+            // only if there are no other tokens left
+            let mut new_leaf = old_leaf.clone();
+            new_leaf.set_balance(token, Fr::zero(), &self.jubjub_params);
+            let exited = new_leaf.balances.is_empty() && new_leaf.hash_lock.is_none();
 
             let path: Vec<Option<Fr>> = tree
                 .merkle_path(from_leaf_number)
@@ -973,7 +1201,8 @@ impl BabyProver {
 
             let request = ExitRequest {
                 from: Fr::from_str(&from_leaf_number.to_string()),
-                amount: Some(old_leaf.balance),
+                token: Some(tx.token),
+                amount: Some(old_leaf.get_balance(token)),
             };
 
             // we have the leaf info, so add it to the public data
@@ -981,16 +1210,30 @@ impl BabyProver {
             let tx_encoding = be_bit_vector_into_bytes(&tx_bits);
             public_data.extend(tx_encoding.into_iter());
 
-            tree.insert(from_leaf_number, new_leaf.clone());
+            // an empty leaf hashes the same as a removed one
+            if exited {
+                tree.delete(from_leaf_number);
+            } else {
+                tree.insert(from_leaf_number, new_leaf);
+            }
 
             {
                 let deposit_witness = ExitWitness::<Engine> {
                     auth_path: path,
                     leaf: LeafWitness::<Engine> {
-                        balance: Some(old_leaf.balance),
+                        balance: Some(old_leaf.get_balance(token)),
+                        balance_path: old_leaf
+                            .balance_path(token, &self.jubjub_params)
+                            .into_iter()
+                            .map(Some)
+                            .collect(),
                         nonce: Some(old_leaf.nonce),
                         pub_x: Some(old_leaf.pub_x),
                         pub_y: Some(old_leaf.pub_y),
+                        pub_key_hash: leaf_pub_key_hash(&old_leaf.pub_x, &old_leaf.pub_y),
+                        cheque_bits: Some(old_leaf.cheque_bits),
+                        cheque_start: Some(old_leaf.cheque_start),
+                        lock_commitment: Some(old_leaf.lock_commitment),
                     },
                 };
 
@@ -1085,9 +1328,18 @@ impl BabyProver {
 
         let empty_leaf_witness = LeafWitness::<Engine> {
             balance: Some(Fr::zero()),
+            balance_path: CircuitAccount::default()
+                .balance_path(0, &self.jubjub_params)
+                .into_iter()
+                .map(Some)
+                .collect(),
             nonce: Some(Fr::zero()),
             pub_x: Some(Fr::zero()),
             pub_y: Some(Fr::zero()),
+            pub_key_hash: leaf_pub_key_hash(&Fr::zero(), &Fr::zero()),
+            cheque_bits: Some(Fr::zero()),
+            cheque_start: Some(Fr::zero()),
+            lock_commitment: Some(Fr::zero()),
         };
 
         let instance = Exit {
@@ -1148,6 +1400,1003 @@ impl BabyProver {
         Ok(full_proof)
     }
 
+    pub fn apply_and_prove_change_pubkey(
+        &mut self,
+        block: &Block,
+        transactions: &[ChangePubKeyTx],
+    ) -> Result<FullBabyProof, Err> {
+        let block_number = block.block_number;
+        if block_number != self.current_block_number {
+            info!(
+                "Change pubkey proof request is for block {}, while prover state is block {}",
+                block_number, self.current_block_number
+            );
+            return Err(BabyProverErr::Other(
+                "block_number != self.current_block_number".to_owned(),
+            ));
+        }
+        let block_final_root = block.new_root_hash;
+
+        let num_txes = transactions.len();
+
+        if num_txes != self.change_pubkey_batch_size {
+            return Err(BabyProverErr::Other(
+                "num_txes != self.change_pubkey_batch_size".to_owned(),
+            ));
+        }
+
+        let mut witnesses: Vec<(ChangePubKeyRequest<Engine>, ChangePubKeyWitness<Engine>)> =
+            Vec::new();
+
+        let initial_root = self.accounts_tree.root_hash();
+
+        let mut public_data: Vec<u8> = Vec::new();
+
+        for transaction in transactions {
+            let tx = circuit::CircuitChangePubKeyRequest::try_from(transaction)
+                .map_err(|e| BabyProverErr::InvalidTransaction(e.to_string()))?;
+
+            let leaf_number = field_element_to_u32(tx.account);
+
+            let tree = &mut self.accounts_tree;
+
+            // keys of new accounts are registered by deposits and transfers
+            let old_leaf = tree
+                .get(leaf_number)
+                .ok_or_else(|| BabyProverErr::Other("existing_leaf.is_none()".to_owned()))?;
+
+            let mut new_leaf = old_leaf.clone();
+            new_leaf.nonce.add_assign(&Fr::one());
+            new_leaf.pub_x = tx.new_pub_x;
+            new_leaf.pub_y = tx.new_pub_y;
+            new_leaf.multisig = transaction.multisig.clone();
+
+            let path: Vec<Option<Fr>> = tree
+                .merkle_path(leaf_number)
+                .into_iter()
+                .map(|e| Some(e.0))
+                .collect();
+
+            let request = ChangePubKeyRequest {
+                account: Some(tx.account),
+                new_pub_x: Some(tx.new_pub_x),
+                new_pub_y: Some(tx.new_pub_y),
+                nonce: Some(tx.nonce),
+                chain_id: Some(tx.chain_id),
+                signature: Some(tx.signature.clone()),
+            };
+
+            let tx_bits = request.public_data_into_bits();
+            let tx_encoding = be_bit_vector_into_bytes(&tx_bits);
+            public_data.extend(tx_encoding.into_iter());
+
+            tree.insert(leaf_number, new_leaf.clone());
+
+            {
+                // balances are not changed, the leaf is opened for token 0
+                let change_pubkey_witness = ChangePubKeyWitness::<Engine> {
+                    auth_path: path,
+                    leaf: LeafWitness::<Engine> {
+                        balance: Some(old_leaf.get_balance(params::ETH_TOKEN_ID)),
+                        balance_path: old_leaf
+                            .balance_path(params::ETH_TOKEN_ID, &self.jubjub_params)
+                            .into_iter()
+                            .map(Some)
+                            .collect(),
+                        nonce: Some(old_leaf.nonce),
+                        pub_x: Some(old_leaf.pub_x),
+                        pub_y: Some(old_leaf.pub_y),
+                        pub_key_hash: leaf_pub_key_hash(&old_leaf.pub_x, &old_leaf.pub_y),
+                        cheque_bits: Some(old_leaf.cheque_bits),
+                        cheque_start: Some(old_leaf.cheque_start),
+                        lock_commitment: Some(old_leaf.lock_commitment),
+                    },
+                };
+
+                witnesses.push((request, change_pubkey_witness));
+            }
+        }
+
+        let block_number = Fr::from_str(&block_number.to_string()).unwrap();
+
+        let final_root = self.accounts_tree.root_hash();
+
+        debug!(
+            "Prover final root = {}, final root from state keeper = {}",
+            final_root, block_final_root
+        );
+
+        if block_final_root != final_root {
+            return Err(BabyProverErr::Other(
+                "block_final_root != final_root".to_owned(),
+            ));
+        }
+
+        self.current_block_number += 1;
+
+        let mut public_data_initial_bits = vec![];
+
+        // these two are BE encodings because an iterator is BE. This is also an Ethereum standard behavior
+
+        let block_number_bits: Vec<bool> = BitIterator::new(block_number.into_repr()).collect();
+        for _ in 0..256 - block_number_bits.len() {
+            public_data_initial_bits.push(false);
+        }
+        public_data_initial_bits.extend(block_number_bits.into_iter());
+
+        assert_eq!(public_data_initial_bits.len(), 256);
+
+        let mut h = Sha256::new();
+
+        let bytes_to_hash = be_bit_vector_into_bytes(&public_data_initial_bits);
+
+        h.input(&bytes_to_hash);
+
+        let mut hash_result = [0u8; 32];
+        h.result(&mut hash_result[..]);
+
+        {
+            let packed_transaction_data_bytes = public_data.clone();
+
+            let mut next_round_hash_bytes = Vec::new();
+            next_round_hash_bytes.extend(hash_result.iter());
+            next_round_hash_bytes.extend(packed_transaction_data_bytes);
+
+            let mut h = Sha256::new();
+
+            h.input(&next_round_hash_bytes);
+
+            h.result(&mut hash_result[..]);
+        }
+
+        // clip to fit into field element
+
+        hash_result[0] &= 0x1f; // temporary solution
+
+        let mut repr = Fr::zero().into_repr();
+        repr.read_be(&hash_result[..])
+            .expect("pack hash as field element");
+
+        let public_data_commitment = Fr::from_repr(repr).unwrap();
+
+        let instance = ChangePubKey {
+            params: &self.jubjub_params,
+            number_of_changes: num_txes,
+            chain_id: Fr::from_str(&RUNTIME_CONFIG.chain_id.to_string()).unwrap(),
+            old_root: Some(initial_root),
+            new_root: Some(final_root),
+            public_data_commitment: Some(public_data_commitment),
+            block_number: Some(block_number),
+            requests: witnesses,
+        };
+
+        let mut rng = OsRng::new().unwrap();
+        debug!("Prover has started to work on key changes");
+        let proof = create_random_proof(instance, &self.change_pubkey_parameters, &mut rng);
+        if proof.is_err() {
+            return Err(BabyProverErr::Other("proof.is_err()".to_owned()));
+        }
+
+        let p = proof.unwrap();
+
+        let pvk = prepare_verifying_key(&self.change_pubkey_parameters.vk);
+
+        info!(
+            "Made a change pubkey proof for initial root = {}, final root = {}, public data = {}",
+            initial_root,
+            final_root,
+            public_data_commitment.to_hex()
+        );
+        let success = verify_proof(
+            &pvk,
+            &p.clone(),
+            &[initial_root, final_root, public_data_commitment],
+        );
+
+        if success.is_err() {
+            error!(
+                "Proof verification failed with error {}",
+                success.err().unwrap()
+            );
+            return Err(BabyProverErr::Other("Proof verification failed".to_owned()));
+        }
+        if !success.unwrap() {
+            error!("Proof is invalid");
+            return Err(BabyProverErr::Other("Proof is invalid".to_owned()));
+        }
+        info!("Proof generation is complete");
+
+        let full_proof = FullBabyProof {
+            proof: p,
+            inputs: [initial_root, final_root, public_data_commitment],
+            total_fees: Fr::zero(),
+            block_number,
+            public_data,
+        };
+
+        Ok(full_proof)
+    }
+
+    pub fn apply_and_prove_cheque(
+        &mut self,
+        block: &Block,
+        transactions: &[ChequeTx],
+    ) -> Result<FullBabyProof, Err> {
+        let block_number = block.block_number;
+        if block_number != self.current_block_number {
+            info!(
+                "Cheque proof request is for block {}, while prover state is block {}",
+                block_number, self.current_block_number
+            );
+            return Err(BabyProverErr::Other(
+                "block_number != self.current_block_number".to_owned(),
+            ));
+        }
+        let block_final_root = block.new_root_hash;
+
+        let num_txes = transactions.len();
+
+        if num_txes != self.cheque_batch_size {
+            return Err(BabyProverErr::Other(
+                "num_txes != self.cheque_batch_size".to_owned(),
+            ));
+        }
+
+        let mut witnesses: Vec<(ChequeRequest<Engine>, ChequeWitness<Engine>)> = Vec::new();
+
+        let initial_root = self.accounts_tree.root_hash();
+
+        let mut public_data: Vec<u8> = Vec::new();
+
+        for tx in transactions {
+            let tx = circuit::CircuitChequeRequest::try_from(tx)
+                .map_err(|e| BabyProverErr::InvalidTransaction(e.to_string()))?;
+            let from_leaf_number = field_element_to_u32(tx.from);
+            let to_leaf_number = field_element_to_u32(tx.to);
+            let token = field_element_to_u32(tx.token);
+
+            let tree = &mut self.accounts_tree;
+
+            let from_leaf = match tree.get(from_leaf_number) {
+                Some(leaf) => leaf,
+                None => return Err(BabyProverErr::InvalidSender),
+            };
+
+            // this is LE bits encoding of the cheque amount
+            let mut amount_bits: Vec<bool> = BitIterator::new(tx.amount.into_repr()).collect();
+            amount_bits.reverse();
+            amount_bits
+                .truncate(params::AMOUNT_EXPONENT_BIT_WIDTH + params::AMOUNT_MANTISSA_BIT_WIDTH);
+
+            let parsed_amount = parse_float_to_u128(
+                amount_bits,
+                params::AMOUNT_EXPONENT_BIT_WIDTH,
+                params::AMOUNT_MANTISSA_BIT_WIDTH,
+                10,
+            )
+            .map_err(|_| BabyProverErr::InvalidAmountEncoding)?;
+            let amount_as_field_element = Fr::from_str(&parsed_amount.to_string()).unwrap();
+
+            let window = ChequeWindow {
+                start: field_element_to_u32(from_leaf.cheque_start),
+                bits: field_element_to_u128(from_leaf.cheque_bits),
+            };
+            let new_window = window
+                .redeem(field_element_to_u32(tx.serial))
+                .ok_or_else(|| {
+                    BabyProverErr::InvalidTransaction("cheque can not be redeemed".to_owned())
+                })?;
+
+            let path_from: Vec<Option<Fr>> = tree
+                .merkle_path(from_leaf_number)
+                .into_iter()
+                .map(|e| Some(e.0))
+                .collect();
+
+            let mut updated_from_leaf = from_leaf.clone();
+            let mut from_balance = updated_from_leaf.get_balance(token);
+            from_balance.sub_assign(&amount_as_field_element);
+            updated_from_leaf.set_balance(token, from_balance, &self.jubjub_params);
+            updated_from_leaf.cheque_bits = Fr::from_str(&new_window.bits.to_string()).unwrap();
+            updated_from_leaf.cheque_start = Fr::from_str(&new_window.start.to_string()).unwrap();
+
+            tree.insert(from_leaf_number, updated_from_leaf);
+
+            // the payee is opened after the payer is updated
+            let to_leaf = match tree.get(to_leaf_number) {
+                Some(leaf) => leaf,
+                None => {
+                    return Err(BabyProverErr::InvalidTransaction(
+                        "payee account does not exist".to_owned(),
+                    ))
+                }
+            };
+
+            let path_to: Vec<Option<Fr>> = tree
+                .merkle_path(to_leaf_number)
+                .into_iter()
+                .map(|e| Some(e.0))
+                .collect();
+
+            let mut updated_to_leaf = to_leaf.clone();
+            let mut to_balance = updated_to_leaf.get_balance(token);
+            to_balance.add_assign(&amount_as_field_element);
+            updated_to_leaf.set_balance(token, to_balance, &self.jubjub_params);
+
+            tree.insert(to_leaf_number, updated_to_leaf);
+
+            let request = ChequeRequest {
+                from: Some(tx.from),
+                to: Some(tx.to),
+                token: Some(tx.token),
+                amount: Some(tx.amount),
+                serial: Some(tx.serial),
+                good_until_block: Some(tx.good_until_block),
+                chain_id: Some(tx.chain_id),
+                signature: Some(tx.signature.clone()),
+            };
+
+            let tx_bits = request.public_data_into_bits();
+            let tx_encoding = be_bit_vector_into_bytes(&tx_bits);
+            public_data.extend(tx_encoding.into_iter());
+
+            let cheque_witness = ChequeWitness::<Engine> {
+                leaf_from: LeafWitness::<Engine> {
+                    balance: Some(from_leaf.get_balance(token)),
+                    balance_path: from_leaf
+                        .balance_path(token, &self.jubjub_params)
+                        .into_iter()
+                        .map(Some)
+                        .collect(),
+                    nonce: Some(from_leaf.nonce),
+                    pub_x: Some(from_leaf.pub_x),
+                    pub_y: Some(from_leaf.pub_y),
+                    pub_key_hash: leaf_pub_key_hash(&from_leaf.pub_x, &from_leaf.pub_y),
+                    cheque_bits: Some(from_leaf.cheque_bits),
+                    cheque_start: Some(from_leaf.cheque_start),
+                    lock_commitment: Some(from_leaf.lock_commitment),
+                },
+                auth_path_from: path_from,
+                leaf_to: LeafWitness::<Engine> {
+                    balance: Some(to_leaf.get_balance(token)),
+                    balance_path: to_leaf
+                        .balance_path(token, &self.jubjub_params)
+                        .into_iter()
+                        .map(Some)
+                        .collect(),
+                    nonce: Some(to_leaf.nonce),
+                    // a compact leaf only needs the hash of a key that is not used
+                    pub_x: Some(to_leaf.pub_x).filter(|_| !params::COMPACT_LEAF),
+                    pub_y: Some(to_leaf.pub_y).filter(|_| !params::COMPACT_LEAF),
+                    pub_key_hash: leaf_pub_key_hash(&to_leaf.pub_x, &to_leaf.pub_y),
+                    cheque_bits: Some(to_leaf.cheque_bits),
+                    cheque_start: Some(to_leaf.cheque_start),
+                    lock_commitment: Some(to_leaf.lock_commitment),
+                },
+                auth_path_to: path_to,
+            };
+
+            witnesses.push((request, cheque_witness));
+        }
+
+        let block_number = Fr::from_str(&block_number.to_string()).unwrap();
+
+        let final_root = self.accounts_tree.root_hash();
+
+        debug!(
+            "Prover final root = {}, final root from state keeper = {}",
+            final_root, block_final_root
+        );
+
+        if block_final_root != final_root {
+            return Err(BabyProverErr::Other(
+                "block_final_root != final_root".to_owned(),
+            ));
+        }
+
+        self.current_block_number += 1;
+
+        let mut public_data_initial_bits = vec![];
+
+        // these two are BE encodings because an iterator is BE. This is also an Ethereum standard behavior
+
+        let block_number_bits: Vec<bool> = BitIterator::new(block_number.into_repr()).collect();
+        for _ in 0..256 - block_number_bits.len() {
+            public_data_initial_bits.push(false);
+        }
+        public_data_initial_bits.extend(block_number_bits.into_iter());
+
+        assert_eq!(public_data_initial_bits.len(), 256);
+
+        let mut h = Sha256::new();
+
+        let bytes_to_hash = be_bit_vector_into_bytes(&public_data_initial_bits);
+
+        h.input(&bytes_to_hash);
+
+        let mut hash_result = [0u8; 32];
+        h.result(&mut hash_result[..]);
+
+        {
+            let packed_transaction_data_bytes = public_data.clone();
+
+            let mut next_round_hash_bytes = Vec::new();
+            next_round_hash_bytes.extend(hash_result.iter());
+            next_round_hash_bytes.extend(packed_transaction_data_bytes);
+
+            let mut h = Sha256::new();
+
+            h.input(&next_round_hash_bytes);
+
+            h.result(&mut hash_result[..]);
+        }
+
+        // clip to fit into field element
+
+        hash_result[0] &= 0x1f; // temporary solution
+
+        let mut repr = Fr::zero().into_repr();
+        repr.read_be(&hash_result[..])
+            .expect("pack hash as field element");
+
+        let public_data_commitment = Fr::from_repr(repr).unwrap();
+
+        let instance = Cheque {
+            params: &self.jubjub_params,
+            number_of_cheques: num_txes,
+            chain_id: Fr::from_str(&RUNTIME_CONFIG.chain_id.to_string()).unwrap(),
+            old_root: Some(initial_root),
+            new_root: Some(final_root),
+            public_data_commitment: Some(public_data_commitment),
+            block_number: Some(block_number),
+            requests: witnesses,
+        };
+
+        let mut rng = OsRng::new().unwrap();
+        debug!("Prover has started to work on cheques");
+        let proof = create_random_proof(instance, &self.cheque_parameters, &mut rng);
+        if proof.is_err() {
+            return Err(BabyProverErr::Other("proof.is_err()".to_owned()));
+        }
+
+        let p = proof.unwrap();
+
+        let pvk = prepare_verifying_key(&self.cheque_parameters.vk);
+
+        info!(
+            "Made a cheque proof for initial root = {}, final root = {}, public data = {}",
+            initial_root,
+            final_root,
+            public_data_commitment.to_hex()
+        );
+        let success = verify_proof(
+            &pvk,
+            &p.clone(),
+            &[initial_root, final_root, public_data_commitment],
+        );
+
+        if success.is_err() {
+            error!(
+                "Proof verification failed with error {}",
+                success.err().unwrap()
+            );
+            return Err(BabyProverErr::Other("Proof verification failed".to_owned()));
+        }
+        if !success.unwrap() {
+            error!("Proof is invalid");
+            return Err(BabyProverErr::Other("Proof is invalid".to_owned()));
+        }
+        info!("Proof generation is complete");
+
+        let full_proof = FullBabyProof {
+            proof: p,
+            inputs: [initial_root, final_root, public_data_commitment],
+            total_fees: Fr::zero(),
+            block_number,
+            public_data,
+        };
+
+        Ok(full_proof)
+    }
+
+    pub fn apply_and_prove_lock(
+        &mut self,
+        block: &Block,
+        transactions: &[LockTx],
+    ) -> Result<FullBabyProof, Err> {
+        let block_number = block.block_number;
+        if block_number != self.current_block_number {
+            info!(
+                "Lock proof request is for block {}, while prover state is block {}",
+                block_number, self.current_block_number
+            );
+            return Err(BabyProverErr::Other(
+                "block_number != self.current_block_number".to_owned(),
+            ));
+        }
+        let block_final_root = block.new_root_hash;
+
+        let num_txes = transactions.len();
+
+        if num_txes != self.lock_batch_size {
+            return Err(BabyProverErr::Other(
+                "num_txes != self.lock_batch_size".to_owned(),
+            ));
+        }
+
+        let mut witnesses: Vec<(LockRequest<Engine>, LockWitness<Engine>)> = Vec::new();
+
+        let initial_root = self.accounts_tree.root_hash();
+
+        let mut public_data: Vec<u8> = Vec::new();
+
+        for transaction in transactions {
+            let tx = circuit::CircuitLockRequest::try_from(transaction)
+                .map_err(|e| BabyProverErr::InvalidTransaction(e.to_string()))?;
+            let leaf_number = field_element_to_u32(tx.account);
+            let token = field_element_to_u32(tx.token);
+
+            let tree = &mut self.accounts_tree;
+
+            let old_leaf = match tree.get(leaf_number) {
+                Some(leaf) => leaf,
+                None => return Err(BabyProverErr::InvalidSender),
+            };
+
+            // this is LE bits encoding of the locked amount
+            let mut amount_bits: Vec<bool> = BitIterator::new(tx.amount.into_repr()).collect();
+            amount_bits.reverse();
+            amount_bits
+                .truncate(params::AMOUNT_EXPONENT_BIT_WIDTH + params::AMOUNT_MANTISSA_BIT_WIDTH);
+
+            let parsed_amount = parse_float_to_u128(
+                amount_bits,
+                params::AMOUNT_EXPONENT_BIT_WIDTH,
+                params::AMOUNT_MANTISSA_BIT_WIDTH,
+                10,
+            )
+            .map_err(|_| BabyProverErr::InvalidAmountEncoding)?;
+            let amount_as_field_element = Fr::from_str(&parsed_amount.to_string()).unwrap();
+
+            let path: Vec<Option<Fr>> = tree
+                .merkle_path(leaf_number)
+                .into_iter()
+                .map(|e| Some(e.0))
+                .collect();
+
+            let mut new_leaf = old_leaf.clone();
+            let mut balance = new_leaf.get_balance(token);
+            balance.sub_assign(&amount_as_field_element);
+            new_leaf.set_balance(token, balance, &self.jubjub_params);
+            new_leaf.nonce.add_assign(&Fr::one());
+            new_leaf.set_hash_lock(Some(transaction.hash_lock()), &self.jubjub_params);
+
+            tree.insert(leaf_number, new_leaf);
+
+            let request = LockRequest {
+                account: Some(tx.account),
+                to: Some(tx.to),
+                token: Some(tx.token),
+                amount: Some(tx.amount),
+                hash: Some(tx.hash),
+                good_until_block: Some(tx.good_until_block),
+                nonce: Some(tx.nonce),
+                chain_id: Some(tx.chain_id),
+                signature: Some(tx.signature.clone()),
+            };
+
+            let tx_bits = request.public_data_into_bits();
+            let tx_encoding = be_bit_vector_into_bytes(&tx_bits);
+            public_data.extend(tx_encoding.into_iter());
+
+            let lock_witness = LockWitness::<Engine> {
+                leaf: LeafWitness::<Engine> {
+                    balance: Some(old_leaf.get_balance(token)),
+                    balance_path: old_leaf
+                        .balance_path(token, &self.jubjub_params)
+                        .into_iter()
+                        .map(Some)
+                        .collect(),
+                    nonce: Some(old_leaf.nonce),
+                    pub_x: Some(old_leaf.pub_x),
+                    pub_y: Some(old_leaf.pub_y),
+                    pub_key_hash: leaf_pub_key_hash(&old_leaf.pub_x, &old_leaf.pub_y),
+                    cheque_bits: Some(old_leaf.cheque_bits),
+                    cheque_start: Some(old_leaf.cheque_start),
+                    lock_commitment: Some(old_leaf.lock_commitment),
+                },
+                auth_path: path,
+            };
+
+            witnesses.push((request, lock_witness));
+        }
+
+        let block_number = Fr::from_str(&block_number.to_string()).unwrap();
+
+        let final_root = self.accounts_tree.root_hash();
+
+        debug!(
+            "Prover final root = {}, final root from state keeper = {}",
+            final_root, block_final_root
+        );
+
+        if block_final_root != final_root {
+            return Err(BabyProverErr::Other(
+                "block_final_root != final_root".to_owned(),
+            ));
+        }
+
+        self.current_block_number += 1;
+
+        let mut public_data_initial_bits = vec![];
+
+        // these two are BE encodings because an iterator is BE. This is also an Ethereum standard behavior
+
+        let block_number_bits: Vec<bool> = BitIterator::new(block_number.into_repr()).collect();
+        for _ in 0..256 - block_number_bits.len() {
+            public_data_initial_bits.push(false);
+        }
+        public_data_initial_bits.extend(block_number_bits.into_iter());
+
+        assert_eq!(public_data_initial_bits.len(), 256);
+
+        let mut h = Sha256::new();
+
+        let bytes_to_hash = be_bit_vector_into_bytes(&public_data_initial_bits);
+
+        h.input(&bytes_to_hash);
+
+        let mut hash_result = [0u8; 32];
+        h.result(&mut hash_result[..]);
+
+        {
+            let packed_transaction_data_bytes = public_data.clone();
+
+            let mut next_round_hash_bytes = Vec::new();
+            next_round_hash_bytes.extend(hash_result.iter());
+            next_round_hash_bytes.extend(packed_transaction_data_bytes);
+
+            let mut h = Sha256::new();
+
+            h.input(&next_round_hash_bytes);
+
+            h.result(&mut hash_result[..]);
+        }
+
+        // clip to fit into field element
+
+        hash_result[0] &= 0x1f; // temporary solution
+
+        let mut repr = Fr::zero().into_repr();
+        repr.read_be(&hash_result[..])
+            .expect("pack hash as field element");
+
+        let public_data_commitment = Fr::from_repr(repr).unwrap();
+
+        let instance = Lock {
+            params: &self.jubjub_params,
+            number_of_locks: num_txes,
+            chain_id: Fr::from_str(&RUNTIME_CONFIG.chain_id.to_string()).unwrap(),
+            old_root: Some(initial_root),
+            new_root: Some(final_root),
+            public_data_commitment: Some(public_data_commitment),
+            block_number: Some(block_number),
+            requests: witnesses,
+        };
+
+        let mut rng = OsRng::new().unwrap();
+        debug!("Prover has started to work on locks");
+        let proof = create_random_proof(instance, &self.lock_parameters, &mut rng);
+        if proof.is_err() {
+            return Err(BabyProverErr::Other("proof.is_err()".to_owned()));
+        }
+
+        let p = proof.unwrap();
+
+        let pvk = prepare_verifying_key(&self.lock_parameters.vk);
+
+        info!(
+            "Made a lock proof for initial root = {}, final root = {}, public data = {}",
+            initial_root,
+            final_root,
+            public_data_commitment.to_hex()
+        );
+        let success = verify_proof(
+            &pvk,
+            &p.clone(),
+            &[initial_root, final_root, public_data_commitment],
+        );
+
+        if success.is_err() {
+            error!(
+                "Proof verification failed with error {}",
+                success.err().unwrap()
+            );
+            return Err(BabyProverErr::Other("Proof verification failed".to_owned()));
+        }
+        if !success.unwrap() {
+            error!("Proof is invalid");
+            return Err(BabyProverErr::Other("Proof is invalid".to_owned()));
+        }
+        info!("Proof generation is complete");
+
+        let full_proof = FullBabyProof {
+            proof: p,
+            inputs: [initial_root, final_root, public_data_commitment],
+            total_fees: Fr::zero(),
+            block_number,
+            public_data,
+        };
+
+        Ok(full_proof)
+    }
+
+    // Claims and refunds share the unlock circuit, `requests` are made from either
+    pub fn apply_and_prove_unlock(
+        &mut self,
+        block: &Block,
+        requests: &[circuit::CircuitUnlockRequest],
+    ) -> Result<FullBabyProof, Err> {
+        let block_number = block.block_number;
+        if block_number != self.current_block_number {
+            info!(
+                "Unlock proof request is for block {}, while prover state is block {}",
+                block_number, self.current_block_number
+            );
+            return Err(BabyProverErr::Other(
+                "block_number != self.current_block_number".to_owned(),
+            ));
+        }
+        let block_final_root = block.new_root_hash;
+
+        let num_txes = requests.len();
+
+        if num_txes != self.unlock_batch_size {
+            return Err(BabyProverErr::Other(
+                "num_txes != self.unlock_batch_size".to_owned(),
+            ));
+        }
+
+        let mut witnesses: Vec<(UnlockRequest<Engine>, UnlockWitness<Engine>)> = Vec::new();
+
+        let initial_root = self.accounts_tree.root_hash();
+
+        let mut public_data: Vec<u8> = Vec::new();
+
+        for tx in requests {
+            let from_leaf_number = field_element_to_u32(tx.account);
+
+            let tree = &mut self.accounts_tree;
+
+            let from_leaf = match tree.get(from_leaf_number) {
+                Some(leaf) => leaf,
+                None => return Err(BabyProverErr::InvalidSender),
+            };
+
+            let lock = from_leaf.hash_lock.clone().ok_or_else(|| {
+                BabyProverErr::InvalidTransaction("account has no hash lock".to_owned())
+            })?;
+
+            if tx.is_claim && !lock.is_unlocked_by(&H256::from(tx.preimage)) {
+                return Err(BabyProverErr::InvalidTransaction(
+                    "preimage does not match the lock hash".to_owned(),
+                ));
+            }
+
+            let token = lock.token;
+            let to_leaf_number = if tx.is_claim {
+                lock.to
+            } else {
+                from_leaf_number
+            };
+            let amount_as_field_element = Fr::from_str(&lock.amount.to_string()).unwrap();
+
+            let path_from: Vec<Option<Fr>> = tree
+                .merkle_path(from_leaf_number)
+                .into_iter()
+                .map(|e| Some(e.0))
+                .collect();
+
+            let mut updated_from_leaf = from_leaf.clone();
+            updated_from_leaf.set_hash_lock(None, &self.jubjub_params);
+
+            tree.insert(from_leaf_number, updated_from_leaf);
+
+            // the destination is opened after the lock is released,
+            // for a refund it's the same leaf
+            let to_leaf = match tree.get(to_leaf_number) {
+                Some(leaf) => leaf,
+                None => return Err(BabyProverErr::InvalidRecipient),
+            };
+
+            let path_to: Vec<Option<Fr>> = tree
+                .merkle_path(to_leaf_number)
+                .into_iter()
+                .map(|e| Some(e.0))
+                .collect();
+
+            let mut updated_to_leaf = to_leaf.clone();
+            let mut to_balance = updated_to_leaf.get_balance(token);
+            to_balance.add_assign(&amount_as_field_element);
+            updated_to_leaf.set_balance(token, to_balance, &self.jubjub_params);
+
+            tree.insert(to_leaf_number, updated_to_leaf);
+
+            let request = UnlockRequest {
+                account: Some(tx.account),
+                is_claim: Some(tx.is_claim),
+                preimage: Some(tx.preimage),
+            };
+
+            let tx_bits = request.public_data_into_bits();
+            let tx_encoding = be_bit_vector_into_bytes(&tx_bits);
+            public_data.extend(tx_encoding.into_iter());
+
+            let unlock_witness = UnlockWitness::<Engine> {
+                leaf_from: LeafWitness::<Engine> {
+                    balance: Some(from_leaf.get_balance(token)),
+                    balance_path: from_leaf
+                        .balance_path(token, &self.jubjub_params)
+                        .into_iter()
+                        .map(Some)
+                        .collect(),
+                    nonce: Some(from_leaf.nonce),
+                    pub_x: Some(from_leaf.pub_x),
+                    pub_y: Some(from_leaf.pub_y),
+                    pub_key_hash: leaf_pub_key_hash(&from_leaf.pub_x, &from_leaf.pub_y),
+                    cheque_bits: Some(from_leaf.cheque_bits),
+                    cheque_start: Some(from_leaf.cheque_start),
+                    lock_commitment: Some(from_leaf.lock_commitment),
+                },
+                auth_path_from: path_from,
+                lock_to: Some(Fr::from_str(&lock.to.to_string()).unwrap()),
+                lock_token: Some(Fr::from_str(&lock.token.to_string()).unwrap()),
+                lock_amount: Some(amount_as_field_element),
+                lock_hash: Some(lock.hash.0),
+                lock_good_until_block: Some(
+                    Fr::from_str(&lock.good_until_block.to_string()).unwrap(),
+                ),
+                leaf_to: LeafWitness::<Engine> {
+                    balance: Some(to_leaf.get_balance(token)),
+                    balance_path: to_leaf
+                        .balance_path(token, &self.jubjub_params)
+                        .into_iter()
+                        .map(Some)
+                        .collect(),
+                    nonce: Some(to_leaf.nonce),
+                    // a compact leaf only needs the hash of a key that is not used
+                    pub_x: Some(to_leaf.pub_x).filter(|_| !params::COMPACT_LEAF),
+                    pub_y: Some(to_leaf.pub_y).filter(|_| !params::COMPACT_LEAF),
+                    pub_key_hash: leaf_pub_key_hash(&to_leaf.pub_x, &to_leaf.pub_y),
+                    cheque_bits: Some(to_leaf.cheque_bits),
+                    cheque_start: Some(to_leaf.cheque_start),
+                    lock_commitment: Some(to_leaf.lock_commitment),
+                },
+                auth_path_to: path_to,
+            };
+
+            witnesses.push((request, unlock_witness));
+        }
+
+        let block_number = Fr::from_str(&block_number.to_string()).unwrap();
+
+        let final_root = self.accounts_tree.root_hash();
+
+        debug!(
+            "Prover final root = {}, final root from state keeper = {}",
+            final_root, block_final_root
+        );
+
+        if block_final_root != final_root {
+            return Err(BabyProverErr::Other(
+                "block_final_root != final_root".to_owned(),
+            ));
+        }
+
+        self.current_block_number += 1;
+
+        let mut public_data_initial_bits = vec![];
+
+        // these two are BE encodings because an iterator is BE. This is also an Ethereum standard behavior
+
+        let block_number_bits: Vec<bool> = BitIterator::new(block_number.into_repr()).collect();
+        for _ in 0..256 - block_number_bits.len() {
+            public_data_initial_bits.push(false);
+        }
+        public_data_initial_bits.extend(block_number_bits.into_iter());
+
+        assert_eq!(public_data_initial_bits.len(), 256);
+
+        let mut h = Sha256::new();
+
+        let bytes_to_hash = be_bit_vector_into_bytes(&public_data_initial_bits);
+
+        h.input(&bytes_to_hash);
+
+        let mut hash_result = [0u8; 32];
+        h.result(&mut hash_result[..]);
+
+        {
+            let packed_transaction_data_bytes = public_data.clone();
+
+            let mut next_round_hash_bytes = Vec::new();
+            next_round_hash_bytes.extend(hash_result.iter());
+            next_round_hash_bytes.extend(packed_transaction_data_bytes);
+
+            let mut h = Sha256::new();
+
+            h.input(&next_round_hash_bytes);
+
+            h.result(&mut hash_result[..]);
+        }
+
+        // clip to fit into field element
+
+        hash_result[0] &= 0x1f; // temporary solution
+
+        let mut repr = Fr::zero().into_repr();
+        repr.read_be(&hash_result[..])
+            .expect("pack hash as field element");
+
+        let public_data_commitment = Fr::from_repr(repr).unwrap();
+
+        let instance = Unlock {
+            params: &self.jubjub_params,
+            number_of_unlocks: num_txes,
+            old_root: Some(initial_root),
+            new_root: Some(final_root),
+            public_data_commitment: Some(public_data_commitment),
+            block_number: Some(block_number),
+            requests: witnesses,
+        };
+
+        let mut rng = OsRng::new().unwrap();
+        debug!("Prover has started to work on unlocks");
+        let proof = create_random_proof(instance, &self.unlock_parameters, &mut rng);
+        if proof.is_err() {
+            return Err(BabyProverErr::Other("proof.is_err()".to_owned()));
+        }
+
+        let p = proof.unwrap();
+
+        let pvk = prepare_verifying_key(&self.unlock_parameters.vk);
+
+        info!(
+            "Made an unlock proof for initial root = {}, final root = {}, public data = {}",
+            initial_root,
+            final_root,
+            public_data_commitment.to_hex()
+        );
+        let success = verify_proof(
+            &pvk,
+            &p.clone(),
+            &[initial_root, final_root, public_data_commitment],
+        );
+
+        if success.is_err() {
+            error!(
+                "Proof verification failed with error {}",
+                success.err().unwrap()
+            );
+            return Err(BabyProverErr::Other("Proof verification failed".to_owned()));
+        }
+        if !success.unwrap() {
+            error!("Proof is invalid");
+            return Err(BabyProverErr::Other("Proof is invalid".to_owned()));
+        }
+        info!("Proof generation is complete");
+
+        let full_proof = FullBabyProof {
+            proof: p,
+            inputs: [initial_root, final_root, public_data_commitment],
+            total_fees: Fr::zero(),
+            block_number,
+            public_data,
+        };
+
+        Ok(full_proof)
+    }
+
     fn rewind_state(
         &mut self,
         storage: &StorageProcessor,
@@ -1,15 +1,24 @@
+use crate::committer::CommitterHealth;
 use crate::nonce_futures::NonceFutures;
+use crate::proof_trees::ProofTrees;
 use actix_web::{
-    http::Method, middleware, middleware::cors::Cors, server, App, AsyncResponder, Error,
-    HttpMessage, HttpRequest, HttpResponse,
+    http::header, http::Method, middleware, middleware::cors::Cors, server, App, AsyncResponder,
+    Error, HttpMessage, HttpRequest, HttpResponse,
 };
+use crypto::util::fixed_time_eq;
+use merkle_tree::AccountTreeSnapshot;
 use models::config::RUNTIME_CONFIG;
-use models::plasma::{Account, PublicKey, TransferTx};
+use models::plasma::params::{BALANCE_TREE_DEPTH, ETH_TOKEN_ID};
+use models::plasma::{
+    Account, ChangePubKeyTx, ChequeTx, Fr, LockTx, PublicKey, TokenId, TransferApplicationError,
+    TransferTx,
+};
 use models::{ActionType, NetworkStatus, ProtoBlock, StateKeeperRequest, TransferTxConfirmation};
 use std::sync::mpsc;
-use storage::{BlockDetails, ConnectionPool};
+use storage::{BlockDetails, ConnectionPool, StorageProcessor, Token};
 
 use futures::Future;
+use serde::de::DeserializeOwned;
 use std::env;
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
@@ -47,6 +56,12 @@ struct AccountDetailsResponse {
     committed: Option<Account>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct AccountAtBlockResponse {
+    block_number: u32,
+    account: Option<Account>,
+}
+
 #[derive(Default, Clone)]
 struct SharedNetworkStatus(Arc<RwLock<NetworkStatus>>);
 
@@ -64,12 +79,14 @@ struct Accounts{
 
 #[derive(Clone)]
 pub struct AppState {
-    tx_for_state: mpsc::Sender<StateKeeperRequest>,
+    tx_for_state: mpsc::SyncSender<StateKeeperRequest>,
     //contract_address: String,
     connection_pool: ConnectionPool,
     nonce_futures: NonceFutures,
     network_status: SharedNetworkStatus,
     accounts:Accounts,
+    committer_health: CommitterHealth,
+    proof_trees: ProofTrees,
 }
 
 // fn handle_get_testnet_config(req: &HttpRequest<AppState>) -> ActixResult<HttpResponse> {
@@ -87,6 +104,34 @@ fn handle_get_network_status(req: &HttpRequest<AppState>) -> ActixResult<HttpRes
 const TIMEOUT: u64 = 500;
 const NONCE_ORDER_TIMEOUT: u64 = 800;
 
+const BUSY_ERROR: &str = "Server is busy, try again later";
+
+/// Enqueues a request for the state keeper without blocking the API thread on a full queue
+fn send_to_state_keeper(
+    tx_for_state: &mpsc::SyncSender<StateKeeperRequest>,
+    request: StateKeeperRequest,
+) -> Result<(), String> {
+    tx_for_state.try_send(request).map_err(|err| match err {
+        mpsc::TrySendError::Full(_) => BUSY_ERROR.to_string(),
+        mpsc::TrySendError::Disconnected(_) => "Internal error: state keeper is gone".to_string(),
+    })
+}
+
+/// Only registered tokens can be deposited, transferred or withdrawn
+fn check_token_registered(pool: &ConnectionPool, token: TokenId) -> Result<(), String> {
+    let storage = pool
+        .access_storage()
+        .map_err(|err| format!("db connection failed: {}", err))?;
+    let tokens = storage
+        .load_tokens()
+        .map_err(|err| format!("db error: {}", err))?;
+    if tokens.iter().any(|t| t.id as TokenId == token) {
+        Ok(())
+    } else {
+        Err(format!("unknown token: {}", token))
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct MyObj {
     name: String,
@@ -110,16 +155,16 @@ fn mytest(req: &HttpRequest<AppState>) -> Box<Future<Item = HttpResponse, Error
 use models::plasma::tx::{DepositTx};
 fn handle_deposit(req: &HttpRequest<AppState>) -> Box<Future<Item = HttpResponse, Error = Error>> {
     let tx_for_state = req.state().tx_for_state.clone();
+    let pool = req.state().connection_pool.clone();
     req.json()
         .map_err(|e| format!("{}", e))
         .and_then(move |tx: DepositTx| {
+            check_token_registered(&pool, tx.token)?;
             let mut all_deposits = vec![];
             all_deposits.push(tx);
             let block = ProtoBlock::Deposit(0, all_deposits);
             let request = StateKeeperRequest::AddBlock(block);
-            tx_for_state
-                .send(request)
-                .map_err(|err| format!("channel.send() failed: {}", err))?;
+            send_to_state_keeper(&tx_for_state, request)?;
             let resp: String = "sucess deposit".to_string();
             Ok(HttpResponse::Ok().json(resp))
         })
@@ -137,10 +182,12 @@ use crate::DepositReq;
 fn handle_depositReq<'a>(req: &'a HttpRequest<AppState>) -> Box<Future<Item = HttpResponse, Error = Error>> {
     let tx_for_state = req.state().tx_for_state.clone();
     let accounts = req.state().accounts.clone();
+    let pool = req.state().connection_pool.clone();
     //req.match_info().get(key: &str)
     req.json()
         .map_err(|e| format!("{}", e))
         .and_then(move |tx: DepositReq| {
+            check_token_registered(&pool, tx.token)?;
 
             let deposit_tx = tx.get_DepositTx();
 
@@ -172,9 +219,7 @@ fn handle_depositReq<'a>(req: &'a HttpRequest<AppState>) -> Box<Future<Item = Ht
             all_deposits.push(deposit_tx);
             let block = ProtoBlock::Deposit(0, all_deposits);
             let request = StateKeeperRequest::AddBlock(block);
-            tx_for_state
-                .send(request)
-                .map_err(|err| format!("channel.send() failed: {}", err))?;
+            send_to_state_keeper(&tx_for_state, request)?;
 
             let resp: String = format!("success deposit id:{}",account);
             Ok(HttpResponse::Ok().json(resp))
@@ -198,6 +243,23 @@ fn handle_exitReq(req: &HttpRequest<AppState>) -> ActixResult<HttpResponse>{
         }));
     }
 
+    // ?token=N, ETH by default
+    let token = match req.query().get("token") {
+        Some(token) => match token.parse::<TokenId>() {
+            Ok(token) => token,
+            Err(_) => {
+                return Ok(HttpResponse::Ok().json(ApiError {
+                    error: "invalid token".to_string(),
+                }));
+            }
+        },
+        None => ETH_TOKEN_ID,
+    };
+
+    if let Err(err) = check_token_registered(&req.state().connection_pool, token) {
+        return Ok(HttpResponse::Ok().json(ApiError { error: err }));
+    }
+
     let mut accountid = Option::None;
 
     if let Some(id) = req.state().accounts.accounts.as_ref().read().unwrap().get(&address){
@@ -207,9 +269,26 @@ fn handle_exitReq(req: &HttpRequest<AppState>) -> ActixResult<HttpResponse>{
             error: format!("not found address: {} deposited",&address),
         }));
     }
-    
+
+    // the account stays in the tree while it holds other tokens
+    let (acc_tx, acc_rx) = mpsc::channel();
+    let request = StateKeeperRequest::GetAccount(accountid.unwrap(), acc_tx);
+    if let Err(err) = send_to_state_keeper(&req.state().tx_for_state, request) {
+        return Ok(HttpResponse::Ok().json(ApiError { error: err }));
+    }
+    let account_removed = match acc_rx.recv_timeout(Duration::from_millis(TIMEOUT)) {
+        Ok(Some(account)) => account.balances.keys().all(|t| *t == token),
+        Ok(None) => true,
+        Err(_) => {
+            return Ok(HttpResponse::Ok().json(ApiError {
+                error: "account request timeout".to_string(),
+            }));
+        }
+    };
+
     let tx:ExitTx = ExitTx{
         account: accountid.unwrap(),
+        token,
         amount: BigDecimal::zero(),
     };
 
@@ -219,15 +298,13 @@ fn handle_exitReq(req: &HttpRequest<AppState>) -> ActixResult<HttpResponse>{
     let block = ProtoBlock::Exit(0, all_exits);
     let request = StateKeeperRequest::AddBlock(block);
 
-    let send_result = req.state().tx_for_state.send(request);
-
-    if send_result.is_err(){
-        return Ok(HttpResponse::Ok().json(ApiError {
-            error: "Couldn't send for processing".to_string(),
-        }));
+    if let Err(err) = send_to_state_keeper(&req.state().tx_for_state, request) {
+        return Ok(HttpResponse::Ok().json(ApiError { error: err }));
     }
 
-    req.state().accounts.accounts.as_ref().write().unwrap().remove(&address);
+    if account_removed {
+        req.state().accounts.accounts.as_ref().write().unwrap().remove(&address);
+    }
 
     let response = "sucess exit".to_string();
     Ok(HttpResponse::Ok().json(response))
@@ -310,9 +387,7 @@ fn handle_submit_tx(
             // TODO: the code below will block the current thread; switch to futures instead
             let (key_tx, key_rx) = mpsc::channel();
             let request = StateKeeperRequest::GetAccount(tx.from, key_tx);
-            tx_for_state
-                .send(request)
-                .expect("must send a new transaction to queue");
+            send_to_state_keeper(&tx_for_state, request)?;
             let account = key_rx
                 .recv_timeout(std::time::Duration::from_millis(TIMEOUT))
                 .map_err(|_| "Internal error: timeout on GetAccount".to_string())?;
@@ -344,7 +419,7 @@ fn handle_submit_tx(
             let pub_key: PublicKey = account
                 .get_pub_key()
                 .ok_or_else(|| "Pubkey expired".to_string())?;
-            let verified = tx.verify_sig(&pub_key);
+            let verified = tx.verify_sender(&account);
             if !verified {
                 let (x, y) = pub_key.0.into_xy();
                 warn!("Got public key: {:?}, {:?}", x, y);
@@ -363,14 +438,15 @@ fn handle_submit_tx(
 
             let (add_tx, add_rx) = mpsc::channel();
             let (account, nonce) = (tx.from, tx.nonce);
-            tx_for_state
-                .send(StateKeeperRequest::AddTransferTx(Box::new(tx), add_tx))
-                .expect("sending to sate keeper failed");
+            send_to_state_keeper(
+                &tx_for_state,
+                StateKeeperRequest::AddTransferTx(Box::new(tx), add_tx),
+            )?;
             // TODO: reconsider timeouts
             let confirmation = add_rx
                 .recv_timeout(std::time::Duration::from_millis(500))
                 .map_err(|_| "Internal error: timeout on AddTransferTx".to_string())?
-                .map_err(|e| format!("Tx rejected: {:?}", e))?;
+                .map_err(rejection_message)?;
 
             // Notify futures waiting for nonce
 
@@ -396,7 +472,89 @@ fn handle_submit_tx(
         .responder()
 }
 
+/// Maps a refusal of the state keeper to the error returned to the client
+fn rejection_message(err: TransferApplicationError) -> String {
+    match err {
+        TransferApplicationError::Busy => BUSY_ERROR.to_string(),
+        TransferApplicationError::ShuttingDown => "Server is shutting down".to_string(),
+        TransferApplicationError::WrongChainId => "Wrong chain id".to_string(),
+        TransferApplicationError::InvalidSigner => "Invalid signature".to_string(),
+        e => format!("Tx rejected: {:?}", e),
+    }
+}
+
+/// Reads a tx of type `T` from the request body, queues it for the state keeper
+/// with `make_request` and answers once the state keeper accepted or refused it
+fn submit_tx<T, F>(
+    req: &HttpRequest<AppState>,
+    make_request: F,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>>
+where
+    T: DeserializeOwned + 'static,
+    F: FnOnce(T, mpsc::Sender<Result<(), TransferApplicationError>>) -> StateKeeperRequest
+        + 'static,
+{
+    let tx_for_state = req.state().tx_for_state.clone();
+    req.json()
+        .map_err(|e| format!("{}", e))
+        .and_then(move |tx: T| {
+            let (add_tx, add_rx) = mpsc::channel();
+            send_to_state_keeper(&tx_for_state, make_request(tx, add_tx))?;
+            add_rx
+                .recv_timeout(std::time::Duration::from_millis(TIMEOUT))
+                .map_err(|_| "Internal error: timeout on state keeper".to_string())?
+                .map_err(rejection_message)?;
+
+            let resp = TransactionResponse {
+                accepted: true,
+                error: None,
+                confirmation: None,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        })
+        .or_else(|err: String| {
+            let resp = TransactionResponse {
+                accepted: false,
+                error: Some(err),
+                confirmation: None,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        })
+        .responder()
+}
+
+/// Replaces the public key of an account, the request must be signed by the current key.
+/// New accounts get their key by a deposit, an empty account can not change it.
+/// The new key is in effect once the block with the key change is created
+fn handle_change_pubkey(
+    req: &HttpRequest<AppState>,
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    submit_tx(req, |tx: ChangePubKeyTx, add_tx| {
+        StateKeeperRequest::AddChangePubKeyTx(Box::new(tx), add_tx)
+    })
+}
 
+/// Redeems a cheque signed by the payer. Cheques do not consume the payer nonce,
+/// every serial may be redeemed once while it is inside the payer cheque window
+fn handle_cheque(req: &HttpRequest<AppState>) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    submit_tx(req, |tx: ChequeTx, add_tx| {
+        StateKeeperRequest::AddChequeTx(Box::new(tx), add_tx)
+    })
+}
+
+fn handle_lock(req: &HttpRequest<AppState>) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    submit_tx(req, |tx: LockTx, add_tx| {
+        StateKeeperRequest::AddLockTx(Box::new(tx), add_tx)
+    })
+}
+
+fn handle_claim(req: &HttpRequest<AppState>) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    submit_tx(req, StateKeeperRequest::AddClaimTx)
+}
+
+fn handle_refund(req: &HttpRequest<AppState>) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    submit_tx(req, StateKeeperRequest::AddRefundTx)
+}
 
 fn handle_get_account_state(req: &HttpRequest<AppState>) -> ActixResult<HttpResponse> {
     let tx_for_state = req.state().tx_for_state.clone();
@@ -424,12 +582,16 @@ fn handle_get_account_state(req: &HttpRequest<AppState>) -> ActixResult<HttpResp
         }));
     }
 
-    let (acc_tx, acc_rx) = mpsc::channel();
     let account_id_u32 = account_id.unwrap();
+    if let Some(block) = req.query().get("block") {
+        return handle_get_account_state_at_block(&storage, account_id_u32, block);
+    }
+
+    let (acc_tx, acc_rx) = mpsc::channel();
     let request = StateKeeperRequest::GetAccount(account_id_u32, acc_tx);
-    tx_for_state
-        .send(request)
-        .expect("must send a request for an account state");
+    if let Err(err) = send_to_state_keeper(&tx_for_state, request) {
+        return Ok(HttpResponse::Ok().json(ApiError { error: err }));
+    }
 
     let pending: Result<Option<Account>, _> =
         acc_rx.recv_timeout(std::time::Duration::from_millis(TIMEOUT));
@@ -472,6 +634,187 @@ fn handle_get_account_state(req: &HttpRequest<AppState>) -> ActixResult<HttpResp
     Ok(HttpResponse::Ok().json(response))
 }
 
+/// Account as it was right after the committed block `block` was applied
+fn handle_get_account_state_at_block(
+    storage: &StorageProcessor,
+    account_id: u32,
+    block: &str,
+) -> ActixResult<HttpResponse> {
+    let block_number = match block.parse::<u32>() {
+        Ok(block_number) => block_number,
+        Err(_) => {
+            return Ok(HttpResponse::Ok().json(ApiError {
+                error: "invalid block".to_string(),
+            }));
+        }
+    };
+
+    match storage.get_last_committed_block() {
+        Ok(last_committed) if block_number > last_committed => {
+            return Ok(HttpResponse::Ok().json(ApiError {
+                error: "block is not committed yet".to_string(),
+            }));
+        }
+        Ok(_) => (),
+        Err(err) => {
+            return Ok(HttpResponse::Ok().json(ApiError {
+                error: format!("db error: {}", err),
+            }));
+        }
+    }
+
+    match storage.state_for_account_at_block(account_id, block_number) {
+        Ok(account) => Ok(HttpResponse::Ok().json(AccountAtBlockResponse {
+            block_number,
+            account,
+        })),
+        Err(err) => Ok(HttpResponse::Ok().json(ApiError {
+            error: format!("db error: {}", err),
+        })),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AccountProofResponse {
+    block_number: u32,
+    /// None for an empty leaf, the proof then shows that the account does not exist
+    account: Option<Account>,
+    /// Audit path from the leaf up, the flag is set where the path goes through a right child
+    path: Vec<(Fr, bool)>,
+    root: Fr,
+}
+
+/// Tree of the last verified block, or of the last committed one with `block=committed`.
+/// Trees are cached by block number in `ProofTrees`, only new blocks are loaded from storage
+fn tree_for_proof(
+    storage: &StorageProcessor,
+    proof_trees: &ProofTrees,
+    block: Option<&str>,
+) -> Result<(u32, AccountTreeSnapshot), String> {
+    let block_number = match block {
+        None | Some("verified") => storage.get_last_verified_block(),
+        Some("committed") => storage.get_last_committed_block(),
+        Some(_) => return Err("block must be verified or committed".to_string()),
+    }
+    .map_err(|err| format!("db error: {}", err))?;
+
+    proof_trees
+        .at_block(storage, block_number)
+        .map(|tree| (block_number, tree))
+}
+
+/// Merkle proof of an account, see `tree_for_proof` for the `block` parameter.
+/// Clients check it with `merkle_tree::verify_proof`
+fn handle_get_account_proof(req: &HttpRequest<AppState>) -> ActixResult<HttpResponse> {
+    let storage = match req.state().connection_pool.access_storage() {
+        Ok(storage) => storage,
+        Err(_) => {
+            return Ok(HttpResponse::Ok().json(ApiError {
+                error: "rate limit".to_string(),
+            }));
+        }
+    };
+
+    let account_id = match req.match_info().get("id").map(str::parse::<u32>) {
+        Some(Ok(id)) if id < (1 << BALANCE_TREE_DEPTH) => id,
+        _ => {
+            return Ok(HttpResponse::Ok().json(ApiError {
+                error: "invalid account_id".to_string(),
+            }));
+        }
+    };
+
+    let block = req.query().get("block").cloned();
+    let tree = tree_for_proof(
+        &storage,
+        &req.state().proof_trees,
+        block.as_ref().map(String::as_str),
+    );
+    let (block_number, tree) = match tree {
+        Ok(tree) => tree,
+        Err(error) => return Ok(HttpResponse::Ok().json(ApiError { error })),
+    };
+
+    let response = AccountProofResponse {
+        block_number,
+        account: tree.get(account_id),
+        path: tree.merkle_path(account_id),
+        root: tree.root_hash(),
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// a larger proof takes a while to compute and holds an API worker meanwhile
+const MAX_MULTIPROOF_ACCOUNTS: usize = 1000;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AccountsMultiproofResponse {
+    block_number: u32,
+    /// Sorted by id without duplicates, None for an empty leaf
+    accounts: Vec<(u32, Option<Account>)>,
+    /// Siblings shared by all the audit paths, in the order of `SparseMerkleTree::multiproof`
+    proof: Vec<Fr>,
+    root: Fr,
+}
+
+/// One Merkle proof for the comma separated `ids` instead of a path per account,
+/// see `tree_for_proof` for the `block` parameter. Clients check it with
+/// `merkle_tree::verify_multiproof`, an empty leaf counts as `Account::default()`
+fn handle_get_accounts_multiproof(req: &HttpRequest<AppState>) -> ActixResult<HttpResponse> {
+    let storage = match req.state().connection_pool.access_storage() {
+        Ok(storage) => storage,
+        Err(_) => {
+            return Ok(HttpResponse::Ok().json(ApiError {
+                error: "rate limit".to_string(),
+            }));
+        }
+    };
+
+    let ids: Result<Vec<u32>, _> = req
+        .query()
+        .get("ids")
+        .map(String::as_str)
+        .unwrap_or("")
+        .split(',')
+        .map(str::parse::<u32>)
+        .collect();
+    let mut ids = match ids {
+        Ok(ids) if ids.iter().all(|id| *id < (1 << BALANCE_TREE_DEPTH)) => ids,
+        _ => {
+            return Ok(HttpResponse::Ok().json(ApiError {
+                error: "invalid ids".to_string(),
+            }));
+        }
+    };
+    ids.sort_unstable();
+    ids.dedup();
+    if ids.len() > MAX_MULTIPROOF_ACCOUNTS {
+        return Ok(HttpResponse::Ok().json(ApiError {
+            error: format!("at most {} ids per request", MAX_MULTIPROOF_ACCOUNTS),
+        }));
+    }
+
+    let block = req.query().get("block").cloned();
+    let tree = tree_for_proof(
+        &storage,
+        &req.state().proof_trees,
+        block.as_ref().map(String::as_str),
+    );
+    let (block_number, tree) = match tree {
+        Ok(tree) => tree,
+        Err(error) => return Ok(HttpResponse::Ok().json(ApiError { error })),
+    };
+
+    let response = AccountsMultiproofResponse {
+        block_number,
+        accounts: ids.iter().map(|id| (*id, tree.get(*id))).collect(),
+        proof: tree.multiproof(&ids),
+        root: tree.root_hash(),
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
 
 fn handle_get_account_transactions(_req: &HttpRequest<AppState>) -> ActixResult<HttpResponse> {
     Ok(HttpResponse::Ok().json("{transactions}"))
@@ -692,8 +1035,117 @@ fn handle_search(req: &HttpRequest<AppState>) -> Box<Future<Item = HttpResponse,
         .responder()
 }
 
-fn start_server(state: AppState, bind_to: String) {
-    server::new(move || {
+#[derive(Debug, Serialize, Deserialize)]
+struct RollbackResponse {
+    last_committed: u32,
+}
+
+fn handle_get_tokens(req: &HttpRequest<AppState>) -> ActixResult<HttpResponse> {
+    let storage = req.state().connection_pool.access_storage();
+    if storage.is_err() {
+        return Ok(HttpResponse::Ok().json(ApiError {
+            error: "rate limit".to_string(),
+        }));
+    }
+    let storage = storage.unwrap();
+
+    let tokens: Result<Vec<Token>, _> = storage.load_tokens();
+    match tokens {
+        Ok(tokens) => Ok(HttpResponse::Ok().json(tokens)),
+        Err(err) => Ok(HttpResponse::Ok().json(ApiError {
+            error: format!("db error: {}", err),
+        })),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PackableResponse {
+    amount: Option<BigDecimal>,
+    fee: Option<BigDecimal>,
+}
+
+/// Rounds the query parameter `name` down with `round`, if it is present
+fn round_down_query_param(
+    req: &HttpRequest<AppState>,
+    name: &str,
+    round: fn(&BigDecimal) -> Result<BigDecimal, String>,
+) -> Result<Option<BigDecimal>, String> {
+    let value = match req.query().get(name) {
+        Some(value) => value
+            .parse::<BigDecimal>()
+            .map_err(|_| format!("invalid {}", name))?,
+        None => return Ok(None),
+    };
+
+    round(&value).map(Some)
+}
+
+/// Rounds `amount` and `fee` of the query down to the nearest values a transfer can carry
+fn handle_get_packable(req: &HttpRequest<AppState>) -> ActixResult<HttpResponse> {
+    let amount = round_down_query_param(req, "amount", TransferTx::packable_amount);
+    let fee = round_down_query_param(req, "fee", TransferTx::packable_fee);
+    match (amount, fee) {
+        (Ok(amount), Ok(fee)) => Ok(HttpResponse::Ok().json(PackableResponse { amount, fee })),
+        (Err(error), _) | (_, Err(error)) => Ok(HttpResponse::Ok().json(ApiError { error })),
+    }
+}
+
+// rollback replays the reverted transactions, so it may take a while
+const ROLLBACK_TIMEOUT: u64 = 30_000;
+
+/// Checks the `Authorization: Bearer <token>` header of an admin request. The token is
+/// compared in constant time, so its prefix can not be guessed from response times
+fn is_admin_request(req: &HttpRequest<AppState>, admin_token: &str) -> bool {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            if value.starts_with("Bearer ") {
+                Some(&value["Bearer ".len()..])
+            } else {
+                None
+            }
+        })
+        .map_or(false, |token| {
+            fixed_time_eq(token.as_bytes(), admin_token.as_bytes())
+        })
+}
+
+/// Reverts all committed but not verified blocks, admin only.
+/// Blocks already sent to Ethereum are not reverted
+fn handle_rollback(req: &HttpRequest<AppState>) -> ActixResult<HttpResponse> {
+    let admin_token = match RUNTIME_CONFIG.admin_token.as_ref() {
+        Some(token) => token,
+        None => {
+            return Ok(HttpResponse::Ok().json(ApiError {
+                error: "admin endpoints are disabled".to_string(),
+            }));
+        }
+    };
+    if !is_admin_request(req, admin_token) {
+        return Ok(HttpResponse::Ok().json(ApiError {
+            error: "invalid token".to_string(),
+        }));
+    }
+
+    let (rollback_tx, rollback_rx) = mpsc::channel();
+    let request = StateKeeperRequest::RollbackBlocks(rollback_tx);
+    if let Err(err) = send_to_state_keeper(&req.state().tx_for_state, request) {
+        return Ok(HttpResponse::Ok().json(ApiError { error: err }));
+    }
+
+    let result = rollback_rx.recv_timeout(Duration::from_millis(ROLLBACK_TIMEOUT));
+    match result {
+        Ok(Ok(last_committed)) => Ok(HttpResponse::Ok().json(RollbackResponse { last_committed })),
+        Ok(Err(err)) => Ok(HttpResponse::Ok().json(ApiError { error: err })),
+        Err(_) => Ok(HttpResponse::Ok().json(ApiError {
+            error: "rollback request timeout".to_string(),
+        })),
+    }
+}
+
+fn start_server(state: AppState, bind_to: String) -> actix::Recipient<server::StopServer> {
+    let api_server = server::new(move || {
         App::with_state(state.clone()) // <- create app with shared state
             .middleware(middleware::Logger::default())
             .middleware(Cors::build().send_wildcard().max_age(3600).finish())
@@ -708,12 +1160,33 @@ fn start_server(state: AppState, bind_to: String) {
                     .resource("/submit_tx", |r| {
                         r.method(Method::POST).f(handle_submit_tx);
                     })
+                    .resource("/change_pubkey", |r| {
+                        r.method(Method::POST).f(handle_change_pubkey);
+                    })
+                    .resource("/cheque", |r| {
+                        r.method(Method::POST).f(handle_cheque);
+                    })
+                    .resource("/lock", |r| {
+                        r.method(Method::POST).f(handle_lock);
+                    })
+                    .resource("/claim", |r| {
+                        r.method(Method::POST).f(handle_claim);
+                    })
+                    .resource("/refund", |r| {
+                        r.method(Method::POST).f(handle_refund);
+                    })
                     .resource("/mytest", |r| {
                         r.method(Method::POST).f(mytest);
                     })
                     .resource("/account/{id}", |r| {
                         r.method(Method::GET).f(handle_get_account_state);
                     })
+                    .resource("/account/{id}/proof", |r| {
+                        r.method(Method::GET).f(handle_get_account_proof);
+                    })
+                    .resource("/accounts/proof", |r| {
+                        r.method(Method::GET).f(handle_get_accounts_multiproof);
+                    })
                     .resource("/account/{id}/transactions", |r| {
                         r.method(Method::GET).f(handle_get_account_transactions);
                     })
@@ -744,6 +1217,15 @@ fn start_server(state: AppState, bind_to: String) {
                     .resource("/search", |r| {
                         r.method(Method::GET).f(handle_search);
                     })
+                    .resource("/tokens", |r| {
+                        r.method(Method::GET).f(handle_get_tokens);
+                    })
+                    .resource("/packable", |r| {
+                        r.method(Method::GET).f(handle_get_packable);
+                    })
+                    .resource("/admin/rollback", |r| {
+                        r.method(Method::POST).f(handle_rollback);
+                    })
             })
     })
     .bind(&bind_to)
@@ -751,6 +1233,7 @@ fn start_server(state: AppState, bind_to: String) {
     .shutdown_timeout(1)
     .start();
     println!("myrollup server bind to {}", &bind_to);
+    api_server.recipient()
 }
 
 pub fn start_status_interval(state: AppState) {
@@ -768,6 +1251,7 @@ pub fn start_status_interval(state: AppState) {
                 last_verified: 0,
                 total_transactions: 0,
                 outstanding_txs: 0,
+                committer_error: state.committer_health.last_error(),
             };
 
             // TODO: send StateKeeperRequest::GetNetworkStatus(tx) and get result
@@ -785,10 +1269,13 @@ pub fn start_status_interval(state: AppState) {
     });
 }
 
+/// Starts the API in its own thread, returns where to send the request to stop it
 pub fn start_api_server(
-    tx_for_state: mpsc::Sender<StateKeeperRequest>,
+    tx_for_state: mpsc::SyncSender<StateKeeperRequest>,
     connection_pool: ConnectionPool,
-) {
+    committer_health: CommitterHealth,
+) -> actix::Recipient<server::StopServer> {
+    let (api_server_tx, api_server_rx) = mpsc::channel();
     std::thread::Builder::new()
         .name("actix".to_string())
         .spawn(move || {
@@ -819,19 +1306,35 @@ pub fn start_api_server(
                 nonce_futures: NonceFutures::default(),
                 network_status: SharedNetworkStatus::default(),
                 accounts:Accounts::default(),
+                committer_health,
+                proof_trees: ProofTrees::default(),
             };
             
-            start_server(state.clone(), bind_to.clone());
+            let api_server = start_server(state.clone(), bind_to.clone());
             info!("Started http server at {}", &bind_to);
+            api_server_tx.send(api_server).expect("api server address");
             start_status_interval(state.clone());
             sys.run();
         })
         .expect("Api server thread");
+    api_server_rx.recv().expect("api server failed to start")
+}
+
+/// Stops accepting requests and waits for the server to stop,
+/// requests in flight get the shutdown timeout of the server to finish
+pub fn stop_api_server(api_server: &actix::Recipient<server::StopServer>) {
+    match api_server
+        .send(server::StopServer { graceful: true })
+        .wait()
+    {
+        Ok(Ok(())) => info!("api server stopped"),
+        _ => warn!("api server did not stop cleanly"),
+    }
 }
 
 
 use bigdecimal::{BigDecimal, Zero};
-fn add_paddingAccount(tx_for_state: mpsc::Sender<StateKeeperRequest>){
+fn add_paddingAccount(tx_for_state: mpsc::SyncSender<StateKeeperRequest>){
 
     // let applyAccount = DepositReq{
     //     address: "0xeA0e46565075E7d230C15881F1147517B3F64B93".to_string(),
@@ -847,6 +1350,7 @@ fn add_paddingAccount(tx_for_state: mpsc::Sender<StateKeeperRequest>){
             "0x0f933c18160257e0aa54056652e6bc2b8673b31c80cda933421f99dada946bf4".to_string(),
         ],
         deposit_amount: BigDecimal::zero(),
+        token: ETH_TOKEN_ID,
     };
 
     let deposit_tx = applyAccount.get_DepositTx();
@@ -1,39 +1,137 @@
 //use eth_client::ETHClient;
 //use models::abi::TEST_PLASMA_ALWAYS_VERIFY;
-use models::{Action, CommitRequest, Operation};
+use models::{Action, ActionType, CommitRequest, Operation};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::Duration;
-use storage::ConnectionPool;
+use storage::{ConnectionPool, StorageProcessor};
+
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+// about two minutes with the delays above, then the server has to be looked at
+const MAX_ATTEMPTS: usize = 20;
+
+/// Last error of the committer, `None` while it works fine.
+/// Shared with the state keeper (which stops producing blocks while unhealthy) and the API.
+#[derive(Default, Clone)]
+pub struct CommitterHealth(Arc<RwLock<Option<String>>>);
+
+impl CommitterHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.0.read().unwrap().is_none()
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.0.read().unwrap().clone()
+    }
+
+    fn set_error(&self, err: String) {
+        *self.0.write().unwrap() = Some(err);
+    }
+
+    fn clear(&self) {
+        if !self.is_healthy() {
+            info!("committer recovered");
+            *self.0.write().unwrap() = None;
+        }
+    }
+}
 
 pub fn start_committer(
     rx_for_ops: Receiver<CommitRequest>,
     //tx_for_eth: Sender<Operation>,
     pool: ConnectionPool,
+    committed_block: Arc<AtomicUsize>,
+    health: CommitterHealth,
 ) {
     thread::Builder::new()
         .name("committer".to_string())
         .spawn(move || {
-            run_committer(rx_for_ops, pool);
+            run_committer(rx_for_ops, pool, committed_block, health);
         })
         .expect("thread creation failed");
 }
 
+/// Repeats `f` with exponential backoff until it succeeds, the error is reported via `health`.
+/// Blocks are committed strictly in order, so there is nothing else to do meanwhile.
+/// Panics after `MAX_ATTEMPTS`, the committer can not go on without the operation.
+fn with_retries<T, F>(health: &CommitterHealth, what: &str, mut f: F) -> T
+where
+    F: FnMut() -> Result<T, String>,
+{
+    let mut delay = INITIAL_RETRY_DELAY;
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match f() {
+            Ok(result) => {
+                health.clear();
+                return result;
+            }
+            Err(err) => {
+                let err = format!("{} failed: {}", what, err);
+                health.set_error(err.clone());
+                if attempt >= MAX_ATTEMPTS {
+                    panic!("{}, giving up after {} attempts", err, attempt);
+                }
+                error!("{}, retrying in {:?}", err, delay);
+                thread::sleep(delay);
+                delay = std::cmp::min(delay * 2, MAX_RETRY_DELAY);
+            }
+        }
+    }
+}
+
+/// Stores the operation unless it is stored already. A failed attempt may have been
+/// committed by the database before its connection broke, storing it again would fail
+/// on every attempt or apply the state update twice
+fn execute_operation_once(storage: &StorageProcessor, op: &Operation) -> Result<(), String> {
+    let action_type = match op.action {
+        Action::Commit => ActionType::COMMIT,
+        Action::Verify { .. } => ActionType::VERIFY,
+    };
+    let stored = storage
+        .is_operation_stored(op.block.block_number, action_type)
+        .map_err(|e| e.to_string())?;
+    if stored {
+        warn!(
+            "{:?} of block #{} is stored already",
+            op.action, op.block.block_number
+        );
+        return Ok(());
+    }
+    storage
+        .execute_operation(op)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
 fn run_committer(
     rx_for_ops: Receiver<CommitRequest>,
     //tx_for_eth: Sender<Operation>,
     pool: ConnectionPool,
+    committed_block: Arc<AtomicUsize>,
+    health: CommitterHealth,
 ) {
     info!("committer started");
-    let storage = pool
-        .access_storage()
-        .expect("db connection failed for committer");;
+
+    // storage is accessed anew for every attempt, so that a broken connection is replaced
+    let storage = || {
+        pool.access_storage()
+            .map_err(|e| format!("db connection failed: {}", e))
+    };
 
     //let eth_client = ETHClient::new(TEST_PLASMA_ALWAYS_VERIFY);
     //let current_nonce = eth_client.current_nonce().expect("can not get nonce");
     //let _ = storage.prepare_nonce_scheduling(&eth_client.current_sender(), current_nonce);
 
-    let mut last_verified_block = storage.get_last_verified_block().expect("db failed");
+    let mut last_verified_block = with_retries(&health, "get_last_verified_block", || {
+        storage()?
+            .get_last_verified_block()
+            .map_err(|e| e.to_string())
+    });
     loop {
         let req = rx_for_ops.recv_timeout(Duration::from_millis(100));
         if let Ok(CommitRequest {
@@ -41,7 +139,7 @@ fn run_committer(
             accounts_updated,
         }) = req
         {
-            let block_number = block.block_number.clone();
+            let block_number = block.block_number;
             let op = Operation {
                 action: Action::Commit,
                 block,
@@ -50,9 +148,11 @@ fn run_committer(
                 id: None,
             };
             info!("commit block #{}", op.block.block_number);
-            let op = storage
-                .execute_operation(&op)
-                .expect("committer must commit the op into db");
+            with_retries(&health, "commit", || {
+                execute_operation_once(&storage()?, &op)
+            });
+            // let the state keeper know how far behind we are
+            committed_block.store(block_number as usize, Ordering::SeqCst);
             //tx_for_proof_requests.send(ProverRequest(op.block.block_number)).expect("must send a proof request");
             // tx_for_eth
             //     .send(op)
@@ -66,11 +166,16 @@ fn run_committer(
             // there was a timeout, so check for the new ready proofs
             loop {
                 let block_number = last_verified_block + 1;
-                let proof = storage.load_proof(block_number);
+                let proof = storage().and_then(|s| {
+                    s.load_proof(block_number).map_err(|e| e.to_string())
+                });
                 if let Ok(proof) = proof {
-                    let block = storage
+                    // there is a proof, so the block must be committed
+                    let block = with_retries(&health, "access storage", &storage)
                         .load_committed_block(block_number)
-                        .unwrap_or_else(|| panic!("failed to load block #{}", block_number));
+                        .unwrap_or_else(|| {
+                            panic!("block #{} has a proof but is not committed", block_number)
+                        });
                     let op = Operation {
                         action: Action::Verify {
                             proof: Box::new(proof),
@@ -80,14 +185,15 @@ fn run_committer(
                         tx_meta: None,
                         id: None,
                     };
-                    let op = storage
-                        .execute_operation(&op)
-                        .expect("committer must commit the op into db");
+                    with_retries(&health, "verify", || {
+                        execute_operation_once(&storage()?, &op)
+                    });
                     // tx_for_eth
                     //     .send(op)
                     //     .expect("must send an operation for commitment to ethereum");
                     last_verified_block += 1;
                 } else {
+                    // no proof yet; db errors are retried on the next timeout
                     break;
                 }
             }
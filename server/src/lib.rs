@@ -8,6 +8,7 @@ pub mod committer;
 //pub mod eth_sender;
 //pub mod eth_watch;
 pub mod nonce_futures;
+pub mod proof_trees;
 pub mod state_keeper;
 
 
@@ -16,7 +17,7 @@ use bigdecimal::BigDecimal;
 use ff::{Field, PrimeField, PrimeFieldRepr};
 use models::plasma::params;
 use models::plasma::tx::{DepositTx};
-use models::plasma::{Engine, Fr};
+use models::plasma::{Engine, Fr, TokenId};
 use sapling_crypto::jubjub::{edwards, Unknown};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -24,7 +25,9 @@ pub struct DepositReq{
     pub address: String,
     pub public_key:[String;2],
     pub deposit_amount:BigDecimal,
-    
+    /// Token to deposit, ETH if omitted
+    #[serde(default)]
+    pub token: TokenId,
 }
 
 impl DepositReq{
@@ -34,7 +37,9 @@ impl DepositReq{
             let address_str = upper_to_lower(&self.address);
 
             let address_vec = hex256_to_u8vec(&address_str);
-            let account_id = U256::from(H256::from(address_vec.as_slice())).low_u32() >> 8;
+            // the id is the top bits of the low 32 bits of the address, as many as the tree is deep
+            let account_id = U256::from(H256::from(address_vec.as_slice())).low_u32()
+                >> (32 - params::BALANCE_TREE_DEPTH);
             
             
 
@@ -67,6 +72,7 @@ impl DepositReq{
 
             let deposit_tx = DepositTx{
                 account: account_id.clone(),
+                token: self.token,
                 amount: self.deposit_amount.clone(),
                 pub_x: pub_x,
                 pub_y: pub_y,
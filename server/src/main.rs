@@ -1,13 +1,13 @@
 //use tokio::runtime::Runtime;
 #[macro_use]
 extern crate log;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::channel;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel, SyncSender, TrySendError};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use server::api_server::start_api_server;
+use server::api_server::{start_api_server, stop_api_server};
 use server::committer::start_committer;
 //use server::eth_sender;
 //use server::eth_watch::{start_eth_watch, EthWatch};
@@ -35,6 +35,16 @@ fn main() {
     //let rt = Runtime::new().unwrap();
 
     let connection_pool = ConnectionPool::new(); //创建数据库连接池
+
+    if config::RUNTIME_CONFIG.repair_state {
+        match PlasmaStateKeeper::find_first_diverging_block(&connection_pool) {
+            Ok(Some(block_number)) => error!("state diverges starting at block {}", block_number),
+            Ok(None) => info!("replayed state matches all committed roots"),
+            Err(err) => error!("state repair failed: {}", err),
+        }
+        return;
+    }
+
     let state_keeper = PlasmaStateKeeper::new(connection_pool.clone());
     //let eth_watch = EthWatch::new(0, 0, connection_pool.clone()); //初始化监视器
     // let storage = connection_pool
@@ -60,25 +70,42 @@ fn main() {
     info!("starting actors");
     println!("init merkle_root_hash: {}\n",state_keeper.state.root_hash());
 
-    let (tx_for_state, rx_for_state) = channel();
-    start_api_server(tx_for_state.clone(),connection_pool.clone());
+    // queues are bounded: a slow committer stalls the state keeper which in turn makes the API report "busy"
+    let (tx_for_state, rx_for_state) = sync_channel(config::STATE_KEEPER_QUEUE_SIZE);
+    let api_server = start_api_server(
+        tx_for_state.clone(),
+        connection_pool.clone(),
+        state_keeper.committer_health(),
+    );
     //start_eth_watch(eth_watch, tx_for_state.clone());
-    let (tx_for_ops, rx_for_ops) = channel();
+    let (tx_for_ops, rx_for_ops) = sync_channel(config::RUNTIME_CONFIG.max_committer_lag);
+    let committed_block = state_keeper.committed_block();
+    let committer_health = state_keeper.committer_health();
     start_state_keeper(state_keeper, rx_for_state, tx_for_ops.clone());
     //let tx_for_eth = eth_sender::start_eth_sender(connection_pool.clone());
-    start_committer(rx_for_ops, connection_pool.clone());
+    start_committer(
+        rx_for_ops,
+        connection_pool.clone(),
+        committed_block.clone(),
+        committer_health,
+    );
 
     // start_prover(connection_pool.clone(), "worker 1");
     // start_prover(connection_pool.clone(), "worker 2");
     // start_prover(connection_pool.clone(), "worker 3");
 
+    let tx_for_shutdown = tx_for_state.clone();
+
     // Simple timer, pings every 100 ms
     thread::Builder::new()
         .name("timer".to_string())
         .spawn(move || loop {
-            tx_for_state
-                .send(StateKeeperRequest::TimerTick)
-                .expect("tx_for_state channel failed");
+            // skip the tick if the state keeper is flooded, the next one will do
+            if let Err(TrySendError::Disconnected(_)) =
+                tx_for_state.try_send(StateKeeperRequest::TimerTick)
+            {
+                panic!("tx_for_state channel failed");
+            }
             thread::sleep(Duration::from_millis(100));
         })
         .expect("thread creation failed");
@@ -87,4 +114,58 @@ fn main() {
         thread::sleep(Duration::from_secs(1));
     }
     info!("terminate signal received");
+
+    // no new txs may reach the state keeper once the last batch is sealed
+    stop_api_server(&api_server);
+
+    match drain_pipeline(&tx_for_shutdown, &committed_block) {
+        Ok(()) => info!("all blocks are committed, exiting"),
+        Err(err) => error!("shutdown is not clean: {}", err),
+    }
+}
+
+/// Seals the current batch in the state keeper and waits for the committer to persist it
+fn drain_pipeline(
+    tx_for_state: &SyncSender<StateKeeperRequest>,
+    committed_block: &AtomicUsize,
+) -> Result<(), String> {
+    let deadline = Instant::now() + Duration::from_secs(config::SHUTDOWN_TIMEOUT);
+    let time_left = || {
+        let now = Instant::now();
+        if now < deadline {
+            deadline - now
+        } else {
+            Duration::from_millis(0)
+        }
+    };
+
+    let (shutdown_tx, shutdown_rx) = channel();
+    let mut request = StateKeeperRequest::Shutdown(shutdown_tx);
+    loop {
+        match tx_for_state.try_send(request) {
+            Ok(()) => break,
+            Err(TrySendError::Full(r)) if time_left() > Duration::from_millis(0) => {
+                request = r;
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(TrySendError::Full(_)) => return Err("state keeper queue is full".to_string()),
+            Err(TrySendError::Disconnected(_)) => return Err("state keeper is gone".to_string()),
+        }
+    }
+
+    let last_block = shutdown_rx
+        .recv_timeout(time_left())
+        .map_err(|_| "timeout waiting for the state keeper".to_string())?;
+    info!("waiting for the committer to persist block #{}", last_block);
+
+    while committed_block.load(Ordering::SeqCst) < last_block as usize {
+        if time_left() == Duration::from_millis(0) {
+            return Err(format!(
+                "timeout waiting for the committer, last committed block #{}",
+                committed_block.load(Ordering::SeqCst)
+            ));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Ok(())
 }
@@ -0,0 +1,106 @@
+//! Account trees of recent blocks for the Merkle proof endpoints of the API
+
+use merkle_tree::{AccountTreeSnapshot, PersistentAccountTree};
+use models::plasma::params;
+use models::plasma::{AccountMap, BlockNumber};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use storage::StorageProcessor;
+
+// the last verified and committed blocks, with room for the ones between them
+const MAX_CACHED_BLOCKS: usize = 16;
+
+/// Account trees by block number, shared by the API workers. A tree is built from storage
+/// once, the trees of later blocks are O(1) copies of it advanced by the account updates
+/// of the blocks in between. The state of a block is loaded by its number only, so the
+/// tree always matches the block number it is served with
+#[derive(Default, Clone)]
+pub struct ProofTrees(Arc<Mutex<BTreeMap<BlockNumber, PersistentAccountTree>>>);
+
+impl ProofTrees {
+    /// Read-only tree right after `block_number`, its root is checked against the block.
+    /// Requests wait for each other here, so a tree is built once however many ask for it
+    pub fn at_block(
+        &self,
+        storage: &StorageProcessor,
+        block_number: BlockNumber,
+    ) -> Result<AccountTreeSnapshot, String> {
+        let expected_root = if block_number == 0 {
+            None
+        } else {
+            let block = storage
+                .load_committed_block(block_number)
+                .ok_or_else(|| format!("block #{} is not committed", block_number))?;
+            Some(block.new_root_hash)
+        };
+
+        let mut trees = self.0.lock().unwrap();
+
+        if let Some(tree) = trees.get(&block_number) {
+            if expected_root.map_or(true, |root| tree.root_hash() == root) {
+                return Ok(tree.snapshot());
+            }
+            // the block was rolled back and committed again, cached trees are stale
+            trees.clear();
+        }
+
+        let base = trees
+            .range(..block_number)
+            .next_back()
+            .map(|(number, tree)| (*number, tree.clone()));
+        let mut tree = build_tree(storage, base, block_number)?;
+        if expected_root.map_or(false, |root| tree.root_hash() != root) {
+            // the base was rolled back and committed again, the block is built anew
+            trees.clear();
+            tree = build_tree(storage, None, block_number)?;
+            if expected_root != Some(tree.root_hash()) {
+                return Err(format!(
+                    "state of block #{} does not match its root",
+                    block_number
+                ));
+            }
+        }
+
+        let snapshot = tree.snapshot();
+        trees.insert(block_number, tree);
+        while trees.len() > MAX_CACHED_BLOCKS {
+            let oldest = *trees.keys().next().unwrap();
+            trees.remove(&oldest);
+        }
+
+        Ok(snapshot)
+    }
+}
+
+// advances the tree of an earlier block, or builds the tree from all the accounts
+fn build_tree(
+    storage: &StorageProcessor,
+    base: Option<(BlockNumber, PersistentAccountTree)>,
+    block_number: BlockNumber,
+) -> Result<PersistentAccountTree, String> {
+    let (mut tree, accounts) = match base {
+        // load_state_diff takes the numbers of the next expected blocks
+        Some((base_number, tree)) => (
+            tree,
+            storage.load_state_diff(base_number + 1, block_number + 1),
+        ),
+        None => (
+            PersistentAccountTree::new(params::BALANCE_TREE_DEPTH as u32),
+            storage.load_state_at_block(block_number),
+        ),
+    };
+    let (_, accounts) = accounts.map_err(|e| format!("db error: {}", e))?;
+    update_accounts(&mut tree, accounts);
+    Ok(tree)
+}
+
+// storage keeps an empty account for an exited one, it is removed from the tree
+fn update_accounts(tree: &mut PersistentAccountTree, accounts: AccountMap) {
+    for (id, account) in accounts {
+        if account.is_empty() {
+            tree.delete(id);
+        } else {
+            tree.insert(id, account);
+        }
+    }
+}
@@ -4,28 +4,40 @@ use pairing::bn256::Bn256;
 
 use models::plasma::account::Account;
 use models::plasma::block::{Block, BlockData};
-use models::plasma::tx::{DepositTx, ExitTx, TransferTx};
-use models::plasma::{AccountId, AccountMap, BatchNumber};
+use models::plasma::params::ETH_TOKEN_ID;
+use models::plasma::tx::{
+    ChangePubKeyTx, ChequeTx, ClaimTx, DepositTx, ExitTx, LockTx, RefundTx, TransferTx,
+};
+use models::plasma::{
+    AccountId, AccountMap, BatchNumber, BlockNumber, TokenId, TransferApplicationError,
+};
 use plasma::state::PlasmaState;
+use plasma::tree_store::open_account_tree;
 use rayon::prelude::*;
 use sapling_crypto::eddsa::PrivateKey;
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use web3::types::H256;
 
 use models::config;
 
+use crate::committer::CommitterHealth;
+
 use models::{
-    CommitRequest, NetworkStatus, ProtoBlock, StateKeeperRequest, TransferTxConfirmation,
-    TransferTxResult,
+    ChangePubKeyTxResult, ChequeTxResult, CommitRequest, HashLockTxResult, NetworkStatus,
+    ProtoBlock, StateKeeperRequest, TransferTxConfirmation, TransferTxResult,
 };
 
-use storage::ConnectionPool;
+use diesel::QueryResult;
+use storage::{ConnectionPool, StorageProcessor};
 
 use bigdecimal::{BigDecimal, FromPrimitive, ToPrimitive, Zero};
 use fnv::FnvHashMap;
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::Arc;
 
 use std::io::BufReader;
+use std::path::Path;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub struct PlasmaStateKeeper {
@@ -43,8 +55,26 @@ pub struct PlasmaStateKeeper {
     /// Promised latest UNIX timestamp of the next block
     /// 承诺的下一个块的最新UNIX时间戳
     next_block_at_max: Option<SystemTime>,
+
+    /// Connection pool, used for rollbacks
+    db_conn_pool: ConnectionPool,
+
+    /// Last block persisted by the committer
+    committed_block: Arc<AtomicUsize>,
+
+    /// No blocks are produced while the committer fails
+    committer_health: CommitterHealth,
+
+    /// Set once shutdown is requested, no txs are accepted afterwards
+    shutting_down: bool,
+
+    /// Tokens which can be transferred, loaded on startup
+    registered_tokens: HashSet<TokenId>,
 }
 
+/// Account which signs the zero transfers used to fill up incomplete batches
+const PADDING_ACCOUNT_ID: AccountId = 2; // TODO: 1
+
 #[allow(dead_code)]
 type RootHash = H256;
 #[allow(dead_code)]
@@ -58,15 +88,47 @@ impl PlasmaStateKeeper {
             .access_storage()
             .expect("db connection failed for statekeeper");
 
+        let last_verified = storage.get_last_verified_block().expect("db failed");
+        let last_committed_block = storage.get_last_committed_block().expect("db failed");
+
         //读取数据库中的账户信息并初始化AccountTree.
-        let (last_committed, accounts) = storage.load_committed_state().expect("db failed");
+        let (last_committed, state) = match config::RUNTIME_CONFIG.account_tree_path {
+            Some(ref path) => {
+                let state = Self::load_state_from_tree(
+                    &storage,
+                    Path::new(path),
+                    last_verified,
+                    last_committed_block,
+                )
+                .expect("account tree failed");
+                (last_committed_block, state)
+            }
+            None => {
+                let (last_committed, accounts) = storage.load_committed_state().expect("db failed");
+                (
+                    last_committed,
+                    PlasmaState::new(accounts, last_committed + 1),
+                )
+            }
+        };
 
-        
-        
-        let last_verified = storage.get_last_verified_block().expect("db failed");
-        let state = PlasmaState::new(accounts, last_committed + 1);
+        // the rebuilt tree must match the root we have committed last, otherwise
+        // every next block would be built on top of a state nobody can prove
+        if let Err(err) = Self::check_committed_root(&storage, &state, last_committed_block) {
+            error!("{}", err);
+            error!("restart with REPAIR_STATE=1 to find the first diverging block");
+            panic!("state keeper refuses to start: {}", err);
+        }
         //let outstanding_txs = storage.count_outstanding_proofs(last_verified).expect("db failed");
 
+        let registered_tokens: HashSet<TokenId> = storage
+            .load_tokens()
+            .expect("db failed")
+            .into_iter()
+            .map(|token| token.id as TokenId)
+            .collect();
+        info!("registered tokens: {:?}", registered_tokens);
+
         info!(
             "last_committed = {}, last_verified = {}",
             last_committed, last_verified
@@ -78,6 +140,11 @@ impl PlasmaStateKeeper {
             block_queue: VecDeque::default(),
             transfer_tx_queue: Vec::default(),
             next_block_at_max: None,
+            db_conn_pool: pool,
+            committed_block: Arc::new(AtomicUsize::new(last_committed_block as usize)),
+            committer_health: CommitterHealth::default(),
+            shutting_down: false,
+            registered_tokens,
         };
 
         let root = keeper.state.root_hash();
@@ -86,10 +153,109 @@ impl PlasmaStateKeeper {
         keeper
     }
 
+    /// Counter of the last block persisted by the committer, shared with the committer thread
+    pub fn committed_block(&self) -> Arc<AtomicUsize> {
+        self.committed_block.clone()
+    }
+
+    /// Committer health flag, shared with the committer thread and the API
+    pub fn committer_health(&self) -> CommitterHealth {
+        self.committer_health.clone()
+    }
+
+    /// Number of blocks sent to the committer but not yet persisted
+    fn committer_lag(&self) -> usize {
+        let sent = (self.state.block_number - 1) as usize;
+        sent.saturating_sub(self.committed_block.load(Ordering::SeqCst))
+    }
+
+    /// State from the account tree kept at `path`, which follows the verified blocks.
+    /// The committed blocks after those are applied to its copy in memory
+    fn load_state_from_tree(
+        storage: &StorageProcessor,
+        path: &Path,
+        last_verified: BlockNumber,
+        last_committed: BlockNumber,
+    ) -> Result<PlasmaState, String> {
+        let tree = open_account_tree(
+            path,
+            last_verified,
+            |from, to| {
+                storage
+                    .load_state_diff(from, to)
+                    .map(|(_, accounts)| accounts)
+                    .map_err(|e| format!("load_state_diff failed: {}", e))
+            },
+            |block_number| {
+                storage
+                    .load_state_at_block(block_number)
+                    .map(|(_, accounts)| accounts)
+                    .map_err(|e| format!("load_state_at_block failed: {}", e))
+            },
+        )?;
+
+        let balance_tree = merkle_tree::copy_tree(&tree, |account| account);
+        let mut state = PlasmaState::from_tree(balance_tree, last_committed + 1);
+        let (_, committed) = storage
+            .load_state_diff(last_verified + 1, last_committed + 1)
+            .map_err(|e| format!("load_state_diff failed: {}", e))?;
+        state.update_accounts(committed);
+        Ok(state)
+    }
+
+    /// Compares the root of the tree rebuilt from storage with `new_root_hash`
+    /// of the last committed block in `operations`
+    fn check_committed_root(
+        storage: &StorageProcessor,
+        state: &PlasmaState,
+        block_number: BlockNumber,
+    ) -> Result<(), String> {
+        if block_number == 0 {
+            // nothing committed yet
+            return Ok(());
+        }
+        let block = storage
+            .load_committed_block(block_number)
+            .ok_or_else(|| format!("commit operation for block {} not found", block_number))?;
+        let root = state.root_hash();
+        if block.new_root_hash != root {
+            return Err(format!(
+                "root hash mismatch at block {}: rebuilt from accounts = {}, committed = {}",
+                block_number, root, block.new_root_hash
+            ));
+        }
+        Ok(())
+    }
+
+    /// Repair mode: replays `account_updates` block by block starting from the empty tree
+    /// and returns the first block whose root differs from the committed one, if any
+    pub fn find_first_diverging_block(pool: &ConnectionPool) -> QueryResult<Option<BlockNumber>> {
+        let storage = pool
+            .access_storage()
+            .expect("db connection failed for state repair");
+        let last_committed_block = storage.get_last_committed_block()?;
+        info!("replaying account updates for blocks 1..={}", last_committed_block);
+
+        let mut state = PlasmaState::empty();
+        for block_number in 1..=last_committed_block {
+            let (_, updates) = storage.load_state_diff_for_block(block_number)?;
+            state.update_accounts(updates);
+            state.block_number = block_number;
+
+            if let Err(err) = Self::check_committed_root(&storage, &state, block_number) {
+                warn!("{}", err);
+                return Ok(Some(block_number));
+            }
+            debug!("block {} replayed, root hash = {}", block_number, state.root_hash());
+        }
+
+        Ok(None)
+    }
+
     fn run(
         &mut self,
         rx_for_blocks: Receiver<StateKeeperRequest>,
-        tx_for_commitments: Sender<CommitRequest>,
+        tx_for_commitments: SyncSender<CommitRequest>,
     ) {
         for req in rx_for_blocks {
             match req {
@@ -102,6 +268,7 @@ impl PlasmaStateKeeper {
                         last_verified: 0,
                         outstanding_txs: 0,
                         total_transactions: 0,
+                        committer_error: self.committer_health.last_error(),
                     });
                     if r.is_err() {
                         error!(
@@ -117,39 +284,410 @@ impl PlasmaStateKeeper {
                     }
                 }
                 StateKeeperRequest::AddTransferTx(tx, sender) => {
-                    let result = self.apply_transfer_tx(*tx);
-                    if result.is_ok() && self.next_block_at_max.is_none() {
-                        self.next_block_at_max =
-                            Some(SystemTime::now() + Duration::from_secs(config::PADDING_INTERVAL));
-                    }
+                    let result = self
+                        .check_accepting_txs()
+                        .and_then(|_| self.add_transfer_tx(*tx, &tx_for_commitments));
                     let r = sender.send(result);
                     if r.is_err() {
                         error!("StateKeeperRequest::AddTransferTx: channel closed, sending failed");
                     }
-
-                    if self.transfer_tx_queue.len() == config::RUNTIME_CONFIG.transfer_batch_size {
-                        self.finalize_current_batch(&tx_for_commitments);
+                }
+                StateKeeperRequest::AddChangePubKeyTx(tx, sender) => {
+                    let result = self
+                        .check_accepting_txs()
+                        .and_then(|_| self.add_change_pubkey_tx(*tx, &tx_for_commitments));
+                    let r = sender.send(result);
+                    if r.is_err() {
+                        error!(
+                            "StateKeeperRequest::AddChangePubKeyTx: channel closed, sending failed"
+                        );
+                    }
+                }
+                StateKeeperRequest::AddChequeTx(tx, sender) => {
+                    let result = self
+                        .check_accepting_txs()
+                        .and_then(|_| self.add_cheque_tx(*tx, &tx_for_commitments));
+                    let r = sender.send(result);
+                    if r.is_err() {
+                        error!("StateKeeperRequest::AddChequeTx: channel closed, sending failed");
+                    }
+                }
+                StateKeeperRequest::AddLockTx(tx, sender) => {
+                    let result = self
+                        .check_accepting_txs()
+                        .and_then(|_| self.add_lock_tx(*tx, &tx_for_commitments));
+                    let r = sender.send(result);
+                    if r.is_err() {
+                        error!("StateKeeperRequest::AddLockTx: channel closed, sending failed");
+                    }
+                }
+                StateKeeperRequest::AddClaimTx(tx, sender) => {
+                    let result = self
+                        .check_accepting_txs()
+                        .and_then(|_| self.add_claim_tx(tx, &tx_for_commitments));
+                    let r = sender.send(result);
+                    if r.is_err() {
+                        error!("StateKeeperRequest::AddClaimTx: channel closed, sending failed");
+                    }
+                }
+                StateKeeperRequest::AddRefundTx(tx, sender) => {
+                    let result = self
+                        .check_accepting_txs()
+                        .and_then(|_| self.add_refund_tx(tx, &tx_for_commitments));
+                    let r = sender.send(result);
+                    if r.is_err() {
+                        error!("StateKeeperRequest::AddRefundTx: channel closed, sending failed");
                     }
                 }
                 StateKeeperRequest::AddBlock(block) => {
-                    self.block_queue.push_back(block);
-                    //debug!("new protoblock, transfer_tx_queue.len() = {}", self.transfer_tx_queue.len());
-                    if self.transfer_tx_queue.is_empty() {
-                        self.process_block_queue(&tx_for_commitments);
+                    if self.shutting_down {
+                        warn!("server is shutting down, queued block dropped");
+                    } else {
+                        self.add_block(block, &tx_for_commitments);
+                    }
+                }
+                StateKeeperRequest::RollbackBlocks(sender) => {
+                    let result = if self.shutting_down {
+                        Err("server is shutting down".to_string())
+                    } else {
+                        self.rollback_to_verified_block(&tx_for_commitments)
+                    };
+                    if let Err(ref err) = result {
+                        error!("rollback failed: {}", err);
+                    }
+                    let r = sender.send(result);
+                    if r.is_err() {
+                        error!("StateKeeperRequest::RollbackBlocks: channel closed, sending failed");
+                    }
+                }
+                StateKeeperRequest::Shutdown(sender) => {
+                    let last_block = self.seal_for_shutdown(&tx_for_commitments);
+                    let r = sender.send(last_block);
+                    if r.is_err() {
+                        error!("StateKeeperRequest::Shutdown: channel closed, sending failed");
                     }
                 }
+                // the last block is sealed on shutdown, no later one may be produced
+                StateKeeperRequest::TimerTick if self.shutting_down => {}
                 StateKeeperRequest::TimerTick => {
-                    if let Some(next_block_at) = self.next_block_at_max {
-                        if next_block_at <= SystemTime::now() {
-                            self.finalize_current_batch(&tx_for_commitments);
-                        }
+                    let batch_is_due = self.transfer_tx_queue.len()
+                        == config::RUNTIME_CONFIG.transfer_batch_size
+                        || self
+                            .next_block_at_max
+                            .map(|next_block_at| next_block_at <= SystemTime::now())
+                            .unwrap_or(false);
+                    if batch_is_due {
+                        self.finalize_current_batch(&tx_for_commitments);
+                    } else if self.transfer_tx_queue.is_empty() && !self.block_queue.is_empty() {
+                        // blocks held back while the committer was failing
+                        self.process_block_queue(&tx_for_commitments);
+                    }
+                }
+            }
+        }
+    }
+
+    /// New txs are refused while shutting down and while the committer lags or fails
+    fn check_accepting_txs(&self) -> Result<(), TransferApplicationError> {
+        if self.shutting_down {
+            Err(TransferApplicationError::ShuttingDown)
+        } else if self.committer_lag() >= config::RUNTIME_CONFIG.max_committer_lag {
+            warn!("committer lags {} blocks behind, tx refused", self.committer_lag());
+            Err(TransferApplicationError::Busy)
+        } else if !self.committer_health.is_healthy() {
+            warn!("committer is failing, tx refused");
+            Err(TransferApplicationError::Busy)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn add_transfer_tx(
+        &mut self,
+        tx: TransferTx,
+        tx_for_commitments: &SyncSender<CommitRequest>,
+    ) -> TransferTxResult {
+        let result = self.apply_transfer_tx(tx);
+        if result.is_ok() && self.next_block_at_max.is_none() {
+            self.next_block_at_max =
+                Some(SystemTime::now() + Duration::from_secs(config::PADDING_INTERVAL));
+        }
+
+        if self.transfer_tx_queue.len() == config::RUNTIME_CONFIG.transfer_batch_size {
+            self.finalize_current_batch(tx_for_commitments);
+        }
+        result
+    }
+
+    fn add_block(&mut self, block: ProtoBlock, tx_for_commitments: &SyncSender<CommitRequest>) {
+        self.block_queue.push_back(block);
+        //debug!("new protoblock, transfer_tx_queue.len() = {}", self.transfer_tx_queue.len());
+        if self.transfer_tx_queue.is_empty() {
+            self.process_block_queue(tx_for_commitments);
+        }
+    }
+
+    /// Checks the key change against the current state and queues its block.
+    /// The key is replaced when the block is created, i.e. after the transfers of the current batch
+    fn add_change_pubkey_tx(
+        &mut self,
+        tx: ChangePubKeyTx,
+        tx_for_commitments: &SyncSender<CommitRequest>,
+    ) -> ChangePubKeyTxResult {
+        check_chain_id(tx.chain_id)?;
+        self.state.check_change_pubkey(&tx)?;
+
+        self.add_block(ProtoBlock::ChangePubKey(Box::new(tx)), tx_for_commitments);
+        Ok(())
+    }
+
+    /// Checks the cheque against the current state and queues its block.
+    /// Like key changes, the cheque is redeemed when the block is created
+    fn add_cheque_tx(
+        &mut self,
+        tx: ChequeTx,
+        tx_for_commitments: &SyncSender<CommitRequest>,
+    ) -> ChequeTxResult {
+        check_chain_id(tx.chain_id)?;
+        if !self.registered_tokens.contains(&tx.token) {
+            return Err(TransferApplicationError::InvalidTransaction(format!(
+                "unknown token: {}",
+                tx.token
+            )));
+        }
+        self.state.check_cheque(&tx)?;
+
+        self.add_block(ProtoBlock::Cheque(Box::new(tx)), tx_for_commitments);
+        Ok(())
+    }
+
+    /// Checks the hash lock against the current state and queues its block.
+    /// The funds are locked when the block is created
+    fn add_lock_tx(
+        &mut self,
+        tx: LockTx,
+        tx_for_commitments: &SyncSender<CommitRequest>,
+    ) -> HashLockTxResult {
+        check_chain_id(tx.chain_id)?;
+        if !self.registered_tokens.contains(&tx.token) {
+            return Err(TransferApplicationError::InvalidTransaction(format!(
+                "unknown token: {}",
+                tx.token
+            )));
+        }
+        self.state.check_lock(&tx)?;
+
+        self.add_block(ProtoBlock::Lock(Box::new(tx)), tx_for_commitments);
+        Ok(())
+    }
+
+    /// Checks the claim against the current state and queues its block
+    fn add_claim_tx(
+        &mut self,
+        tx: ClaimTx,
+        tx_for_commitments: &SyncSender<CommitRequest>,
+    ) -> HashLockTxResult {
+        self.state.check_claim(&tx)?;
+
+        self.add_block(ProtoBlock::Claim(tx), tx_for_commitments);
+        Ok(())
+    }
+
+    /// Checks the refund against the current state and queues its block
+    fn add_refund_tx(
+        &mut self,
+        tx: RefundTx,
+        tx_for_commitments: &SyncSender<CommitRequest>,
+    ) -> HashLockTxResult {
+        self.state.check_refund(&tx)?;
+
+        self.add_block(ProtoBlock::Refund(tx), tx_for_commitments);
+        Ok(())
+    }
+
+    /// Reverts all committed but not yet verified blocks: removes them from storage,
+    /// rewinds the state to the last verified block and puts their transactions back
+    /// into the mempool in the original order, followed by the txs of the current batch.
+    /// Transactions which are no longer valid are dropped.
+    /// Provers working on the removed blocks must be restarted.
+    /// Refused once an operation of any of the blocks has been sent to Ethereum.
+    fn rollback_to_verified_block(
+        &mut self,
+        tx_for_commitments: &SyncSender<CommitRequest>,
+    ) -> Result<BlockNumber, String> {
+        let storage = self
+            .db_conn_pool
+            .access_storage()
+            .map_err(|e| format!("db connection failed: {}", e))?;
+
+        let last_committed = storage
+            .get_last_committed_block()
+            .map_err(|e| format!("get_last_committed_block failed: {}", e))?;
+        if last_committed + 1 != self.state.block_number {
+            return Err(format!(
+                "block #{} is not committed yet, try again later",
+                self.state.block_number - 1
+            ));
+        }
+        let last_verified = storage
+            .get_last_verified_block()
+            .map_err(|e| format!("get_last_verified_block failed: {}", e))?;
+        // a block sent to Ethereum may still be mined, it can not be replaced
+        let sent_block = storage
+            .load_first_sent_block_after(last_verified)
+            .map_err(|e| format!("load_first_sent_block_after failed: {}", e))?;
+        if let Some(sent_block) = sent_block {
+            return Err(format!(
+                "block #{} is sent to Ethereum, it can not be rolled back",
+                sent_block
+            ));
+        }
+        info!(
+            "rolling back blocks #{}..=#{}",
+            last_verified + 1,
+            last_committed
+        );
+
+        // the diff must be loaded before the account updates are removed
+        let (_, restored_accounts) = storage
+            .load_state_diff(self.state.block_number, last_verified + 1)
+            .map_err(|e| format!("load_state_diff failed: {}", e))?;
+        let removed_ops = storage
+            .rollback_blocks(last_verified)
+            .map_err(|e| format!("rollback_blocks failed: {}", e))?;
+
+        // accounts created after the last verified block are not in the diff
+        for op in removed_ops.iter() {
+            if let Some(accounts_updated) = op.accounts_updated.as_ref() {
+                for id in accounts_updated.keys() {
+                    if !restored_accounts.contains_key(id) {
+                        self.state.balance_tree.delete(*id);
+                    }
+                }
+            }
+        }
+        self.state.update_accounts(restored_accounts);
+        self.state.block_number = last_verified + 1;
+        self.committed_block.store(last_verified as usize, Ordering::SeqCst);
+        info!(
+            "state rewound to block #{}, root hash = {}",
+            last_verified,
+            self.state.root_hash()
+        );
+
+        let pending_txs = std::mem::replace(&mut self.transfer_tx_queue, Vec::default());
+        self.next_block_at_max = None;
+
+        for op in removed_ops {
+            match op.block.block_data {
+                BlockData::Transfer { transactions, .. } => {
+                    for tx in transactions {
+                        self.readd_transfer_tx(tx, tx_for_commitments);
+                    }
+                }
+                BlockData::Deposit {
+                    batch_number,
+                    transactions,
+                } => self.add_block(
+                    ProtoBlock::Deposit(batch_number, transactions),
+                    tx_for_commitments,
+                ),
+                BlockData::Exit {
+                    batch_number,
+                    transactions,
+                } => self.add_block(
+                    ProtoBlock::Exit(batch_number, transactions),
+                    tx_for_commitments,
+                ),
+                BlockData::ChangePubKey { transactions } => {
+                    for tx in transactions {
+                        self.add_block(ProtoBlock::ChangePubKey(Box::new(tx)), tx_for_commitments);
+                    }
+                }
+                BlockData::Cheque { transactions } => {
+                    for tx in transactions {
+                        self.add_block(ProtoBlock::Cheque(Box::new(tx)), tx_for_commitments);
                     }
                 }
+                BlockData::Lock { transactions } => {
+                    for tx in transactions {
+                        self.add_block(ProtoBlock::Lock(Box::new(tx)), tx_for_commitments);
+                    }
+                }
+                BlockData::Claim { transactions } => {
+                    for tx in transactions {
+                        self.add_block(ProtoBlock::Claim(tx), tx_for_commitments);
+                    }
+                }
+                BlockData::Refund { transactions } => {
+                    for tx in transactions {
+                        self.add_block(ProtoBlock::Refund(tx), tx_for_commitments);
+                    }
+                }
+            }
+        }
+        for tx in pending_txs {
+            self.readd_transfer_tx(tx, tx_for_commitments);
+        }
+
+        Ok(last_verified)
+    }
+
+    /// Stops accepting txs and blocks and sends everything pending to the committer.
+    /// Returns the number of the last block sent, no block is produced after it
+    fn seal_for_shutdown(&mut self, tx_for_commitments: &SyncSender<CommitRequest>) -> BlockNumber {
+        self.shutting_down = true;
+        if !self.transfer_tx_queue.is_empty() {
+            info!("sealing the current batch of {} txs", self.transfer_tx_queue.len());
+            self.finalize_current_batch(tx_for_commitments);
+        }
+        self.process_block_queue(tx_for_commitments);
+
+        if !self.transfer_tx_queue.is_empty() || !self.block_queue.is_empty() {
+            warn!(
+                "committer is failing, abandoning {} txs and {} blocks",
+                self.transfer_tx_queue.len(),
+                self.block_queue.len()
+            );
+        }
+        self.state.block_number - 1
+    }
+
+    /// Puts a transfer from a reverted block back into the mempool,
+    /// the signature is checked again since stored txs carry no cached public key
+    fn readd_transfer_tx(&mut self, mut tx: TransferTx, tx_for_commitments: &SyncSender<CommitRequest>) {
+        if tx.from == PADDING_ACCOUNT_ID {
+            // padding is generated again when the batch is finalized
+            return;
+        }
+        let (account, pub_key) = match self
+            .state
+            .get_account(tx.from)
+            .and_then(|a| a.get_pub_key().map(|pub_key| (a, pub_key)))
+        {
+            Some(signer) => signer,
+            None => {
+                warn!("dropping tx from account {}, nonce {}: unknown signer", tx.from, tx.nonce);
+                return;
             }
+        };
+        if !tx.verify_sender(&account) {
+            warn!("dropping tx from account {}, nonce {}: invalid signature", tx.from, tx.nonce);
+            return;
+        }
+        tx.cached_pub_key = Some(pub_key);
+
+        let (from, nonce) = (tx.from, tx.nonce);
+        if let Err(err) = self.add_transfer_tx(tx, tx_for_commitments) {
+            warn!("dropping tx from account {}, nonce {}: {:?}", from, nonce, err);
         }
     }
 
-    fn process_block_queue(&mut self, tx_for_commitments: &Sender<CommitRequest>) {
+    fn process_block_queue(&mut self, tx_for_commitments: &SyncSender<CommitRequest>) {
+        if !self.committer_health.is_healthy() {
+            // keep the blocks queued, they are processed on a timer tick after recovery
+            return;
+        }
         let blocks = std::mem::replace(&mut self.block_queue, VecDeque::default());
         for block in blocks.into_iter() {
             let req = match block {
@@ -160,6 +698,26 @@ impl PlasmaStateKeeper {
                 ProtoBlock::Exit(batch_number, transactions) => {
                     self.create_exit_block(batch_number, transactions)
                 }
+                ProtoBlock::ChangePubKey(tx) => match self.create_change_pubkey_block(*tx) {
+                    Some(req) => req,
+                    None => continue,
+                },
+                ProtoBlock::Cheque(tx) => match self.create_cheque_block(*tx) {
+                    Some(req) => req,
+                    None => continue,
+                },
+                ProtoBlock::Lock(tx) => match self.create_lock_block(*tx) {
+                    Some(req) => req,
+                    None => continue,
+                },
+                ProtoBlock::Claim(tx) => match self.create_claim_block(tx) {
+                    Some(req) => req,
+                    None => continue,
+                },
+                ProtoBlock::Refund(tx) => match self.create_refund_block(tx) {
+                    Some(req) => req,
+                    None => continue,
+                },
             };
             //debug!("sending request to committer {:?}", req);
             tx_for_commitments
@@ -171,12 +729,20 @@ impl PlasmaStateKeeper {
                 self.state.block_number,
                 self.state.root_hash()
             );
-            println!("Account :{:?}", self.state.balance_tree.items);
+            println!("Account :{:?}", self.state.get_accounts());
             self.state.block_number += 1; // bump current block number as we've made one
         }
     }
 
     fn apply_transfer_tx(&mut self, tx: TransferTx) -> TransferTxResult {
+        check_chain_id(tx.chain_id)?;
+        if !self.registered_tokens.contains(&tx.token) {
+            return Err(TransferApplicationError::InvalidTransaction(format!(
+                "unknown token: {}",
+                tx.token
+            )));
+        }
+
         let appication_result = self.state.apply_transfer(&tx);
         if appication_result.is_ok() {
             //debug!("accepted transaction for account {}, nonce {}", tx.from, tx.nonce);
@@ -190,7 +756,10 @@ impl PlasmaStateKeeper {
         })
     }
 
-    fn finalize_current_batch(&mut self, tx_for_commitments: &Sender<CommitRequest>) {
+    fn finalize_current_batch(&mut self, tx_for_commitments: &SyncSender<CommitRequest>) {
+        if !self.committer_health.is_healthy() {
+            return;
+        }
         self.apply_padding();
         self.block_queue.push_front(ProtoBlock::Transfer);
         self.process_block_queue(&tx_for_commitments);
@@ -216,7 +785,7 @@ impl PlasmaStateKeeper {
             // let rr = Fs::to_uniform_32(&pk_bytes);
             // let mypk:PrivateKey<Bn256> = PrivateKey::<Bn256>(rr);
             
-            let padding_account_id = 2; // TODO: 1
+            let padding_account_id = PADDING_ACCOUNT_ID;
             let base_nonce = self.account(padding_account_id).nonce;
 
             let prepared_transactions: Vec<TransferTx> = (0..(to_pad as u32))
@@ -226,10 +795,12 @@ impl PlasmaStateKeeper {
                     let tx = TransferTx::create_signed_tx(
                         padding_account_id, // from
                         0,                  // to
+                        ETH_TOKEN_ID,       // token
                         BigDecimal::zero(), // amount
                         BigDecimal::zero(), // fee
                         nonce,              // nonce
                         2_147_483_647,      // good until max_block
+                        config::RUNTIME_CONFIG.chain_id,
                         &private_key,
                     );
                     //assert!(tx.verify_sig(&pub_key));
@@ -395,6 +966,140 @@ impl PlasmaStateKeeper {
         }
     }
 
+    /// The key change was checked when queued, but the account may have changed since then
+    /// (e.g. by a transfer consuming the nonce); such a change is dropped without a block
+    fn create_change_pubkey_block(&mut self, tx: ChangePubKeyTx) -> Option<CommitRequest> {
+        if let Err(err) = self.state.apply_change_pubkey(&tx) {
+            warn!(
+                "dropping key change of account {}, nonce {}: {:?}",
+                tx.account, tx.nonce, err
+            );
+            return None;
+        }
+
+        let mut accounts_updated = FnvHashMap::<u32, Account>::default();
+        accounts_updated.insert(tx.account, self.account(tx.account));
+
+        let block = Block {
+            block_number: self.state.block_number,
+            new_root_hash: self.state.root_hash(),
+            block_data: BlockData::ChangePubKey {
+                transactions: vec![tx],
+            },
+        };
+
+        Some(CommitRequest {
+            block,
+            accounts_updated,
+        })
+    }
+
+    /// The cheque was checked when queued, but the payer may have spent the funds since then
+    fn create_cheque_block(&mut self, tx: ChequeTx) -> Option<CommitRequest> {
+        if let Err(err) = self.state.apply_cheque(&tx) {
+            warn!(
+                "dropping cheque {} of account {}: {:?}",
+                tx.serial, tx.from, err
+            );
+            return None;
+        }
+
+        let mut accounts_updated = FnvHashMap::<u32, Account>::default();
+        accounts_updated.insert(tx.from, self.account(tx.from));
+        accounts_updated.insert(tx.to, self.account(tx.to));
+
+        let block = Block {
+            block_number: self.state.block_number,
+            new_root_hash: self.state.root_hash(),
+            block_data: BlockData::Cheque {
+                transactions: vec![tx],
+            },
+        };
+
+        Some(CommitRequest {
+            block,
+            accounts_updated,
+        })
+    }
+
+    /// The lock was checked when queued, but the account may have changed since then
+    fn create_lock_block(&mut self, tx: LockTx) -> Option<CommitRequest> {
+        if let Err(err) = self.state.apply_lock(&tx) {
+            warn!(
+                "dropping hash lock of account {}, nonce {}: {:?}",
+                tx.account, tx.nonce, err
+            );
+            return None;
+        }
+
+        let mut accounts_updated = FnvHashMap::<u32, Account>::default();
+        accounts_updated.insert(tx.account, self.account(tx.account));
+
+        let block = Block {
+            block_number: self.state.block_number,
+            new_root_hash: self.state.root_hash(),
+            block_data: BlockData::Lock {
+                transactions: vec![tx],
+            },
+        };
+
+        Some(CommitRequest {
+            block,
+            accounts_updated,
+        })
+    }
+
+    /// The claim was checked when queued, but the lock may have expired since then
+    fn create_claim_block(&mut self, tx: ClaimTx) -> Option<CommitRequest> {
+        let lock = self.account(tx.account).hash_lock;
+        if let Err(err) = self.state.apply_claim(&tx) {
+            warn!("dropping claim of the lock of account {}: {:?}", tx.account, err);
+            return None;
+        }
+        let lock = lock.expect("lock is claimed");
+
+        let mut accounts_updated = FnvHashMap::<u32, Account>::default();
+        accounts_updated.insert(tx.account, self.account(tx.account));
+        accounts_updated.insert(lock.to, self.account(lock.to));
+
+        let block = Block {
+            block_number: self.state.block_number,
+            new_root_hash: self.state.root_hash(),
+            block_data: BlockData::Claim {
+                transactions: vec![tx],
+            },
+        };
+
+        Some(CommitRequest {
+            block,
+            accounts_updated,
+        })
+    }
+
+    /// The refund was checked when queued, but the lock may have been claimed since then
+    fn create_refund_block(&mut self, tx: RefundTx) -> Option<CommitRequest> {
+        if let Err(err) = self.state.apply_refund(&tx) {
+            warn!("dropping refund of the lock of account {}: {:?}", tx.account, err);
+            return None;
+        }
+
+        let mut accounts_updated = FnvHashMap::<u32, Account>::default();
+        accounts_updated.insert(tx.account, self.account(tx.account));
+
+        let block = Block {
+            block_number: self.state.block_number,
+            new_root_hash: self.state.root_hash(),
+            block_data: BlockData::Refund {
+                transactions: vec![tx],
+            },
+        };
+
+        Some(CommitRequest {
+            block,
+            accounts_updated,
+        })
+    }
+
     // sorting is required to ensure that all accounts affected are unique, see the smart contract
     fn sort_deposit_block(mut txes: Vec<DepositTx>) -> Vec<DepositTx> {
         txes.sort_by_key(|l| l.account);
@@ -413,10 +1118,20 @@ impl PlasmaStateKeeper {
     }
 }
 
+/// Signed txs are only valid on the deployment they are signed for, the circuits check
+/// the same chain id, which is fixed when their keys are generated
+fn check_chain_id(chain_id: u32) -> Result<(), TransferApplicationError> {
+    if chain_id == config::RUNTIME_CONFIG.chain_id {
+        Ok(())
+    } else {
+        Err(TransferApplicationError::WrongChainId)
+    }
+}
+
 pub fn start_state_keeper(
     mut sk: PlasmaStateKeeper,
     rx_for_blocks: Receiver<StateKeeperRequest>,
-    tx_for_commitments: Sender<CommitRequest>,
+    tx_for_commitments: SyncSender<CommitRequest>,
 ) {
     std::thread::Builder::new()
         .name("state_keeper".to_string())
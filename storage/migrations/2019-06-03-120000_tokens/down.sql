@@ -0,0 +1,2 @@
+ALTER TABLE transactions DROP COLUMN token;
+DROP TABLE IF EXISTS tokens CASCADE;
@@ -0,0 +1,10 @@
+CREATE TABLE tokens (
+    id              integer primary key,
+    address         text not null,
+    symbol          text not null
+);
+
+-- token 0 is the native coin and is always registered
+INSERT INTO tokens (id, address, symbol) VALUES (0, '0x0000000000000000000000000000000000000000', 'ETH');
+
+ALTER TABLE transactions ADD COLUMN token integer not null default 0;
@@ -0,0 +1,10 @@
+-- balances of other tokens are dropped
+UPDATE accounts
+SET data = ((data::jsonb - 'balances') || jsonb_build_object('balance',
+    COALESCE(data->'balances'->'0', '"0"'::json)))::json
+WHERE data::jsonb ? 'balances';
+
+UPDATE account_updates
+SET data = ((data::jsonb - 'balances') || jsonb_build_object('balance',
+    COALESCE(data->'balances'->'0', '"0"'::json)))::json
+WHERE data::jsonb ? 'balances';
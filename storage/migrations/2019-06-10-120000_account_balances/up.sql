@@ -0,0 +1,13 @@
+-- accounts stored before multi-token balances have a single `balance` of the native token 0,
+-- zero balances are not kept in `balances`
+UPDATE accounts
+SET data = ((data::jsonb - 'balance') || jsonb_build_object('balances',
+    CASE WHEN (data->>'balance')::numeric = 0 THEN '{}'::jsonb
+    ELSE jsonb_build_object('0', data->'balance') END))::json
+WHERE data::jsonb ? 'balance';
+
+UPDATE account_updates
+SET data = ((data::jsonb - 'balance') || jsonb_build_object('balances',
+    CASE WHEN (data->>'balance')::numeric = 0 THEN '{}'::jsonb
+    ELSE jsonb_build_object('0', data->'balance') END))::json
+WHERE data::jsonb ? 'balance';
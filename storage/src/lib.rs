@@ -10,11 +10,16 @@ use chrono::prelude::*;
 use diesel::dsl::*;
 use models::plasma::block::Block;
 use models::plasma::block::BlockData;
-use models::plasma::tx::TransactionType::{Deposit, Exit, Transfer};
+use models::plasma::params::ETH_TOKEN_ID;
+use models::plasma::tx::TransactionType::{
+    ChangePubKey, Cheque, Claim, Deposit, Exit, Lock, Refund, Transfer,
+};
 use models::plasma::tx::{
-    DepositTx, ExitTx, TransactionType, TransferTx, TxSignature, DEPOSIT_TX, EXIT_TX, TRANSFER_TX,
+    ChangePubKeyTx, ChequeTx, ClaimTx, DepositTx, ExitTx, LockTx, RefundTx, TransactionType,
+    TransferTx, TxSignature, CHANGE_PUBKEY_TX, CHEQUE_TX, CLAIM_TX, DEPOSIT_TX, EXIT_TX, LOCK_TX,
+    REFUND_TX, TRANSFER_TX,
 };
-use models::plasma::{AccountId, AccountMap, BlockNumber, Fr, Nonce};
+use models::plasma::{AccountId, AccountMap, BlockNumber, Fr, Nonce, H256};
 use models::{Action, ActionType, EncodedProof, Operation, TxMeta, ACTION_COMMIT, ACTION_VERIFY};
 use serde_derive::{Deserialize, Serialize};
 use std::cmp;
@@ -77,6 +82,18 @@ struct StorageAccount {
     pub data: Value,
 }
 
+impl StorageAccount {
+    /// None for an exited account, its last update is the empty account
+    fn into_existing_account(self) -> Option<models::plasma::account::Account> {
+        let account: models::plasma::account::Account = serde_json::from_value(self.data).unwrap();
+        if account.is_empty() {
+            None
+        } else {
+            Some(account)
+        }
+    }
+}
+
 #[derive(Insertable, Queryable, QueryableByName)]
 #[table_name = "account_updates"]
 struct StorageAccountUpdate {
@@ -142,15 +159,17 @@ impl StoredOperation {
 #[derive(Insertable)]
 #[table_name = "transactions"]
 struct NewTx {
-    pub tx_type: String, // 'transfer', 'deposit', 'exit'
+    pub tx_type: String, // 'transfer', 'deposit', 'exit', 'change_pubkey', 'cheque', 'lock', 'claim', 'refund'
     pub from_account: i32,
-    pub to_account: Option<i32>, // only used for transfers
-    pub nonce: Option<i32>,      // only used for transfers
+    pub to_account: Option<i32>, // only used for transfers, cheques and locks
+    pub nonce: Option<i32>,      // used for transfers, key changes and locks, serial for cheques
     pub amount: i32,
     pub fee: i32,
 
     pub block_number: Option<i32>,
     pub state_root: Option<String>, // unique block id (for possible reorgs)
+
+    pub token: i32,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Queryable, QueryableByName)]
@@ -158,10 +177,10 @@ struct NewTx {
 pub struct StoredTx {
     pub id: i32,
     //pub data:           serde_json::Value,
-    pub tx_type: String, // 'transfer', 'deposit', 'exit'
+    pub tx_type: String, // 'transfer', 'deposit', 'exit', 'change_pubkey', 'cheque', 'lock', 'claim', 'refund'
     pub from_account: i32,
-    pub to_account: Option<i32>, // only used for transfers
-    pub nonce: Option<i32>,      // only used for transfers
+    pub to_account: Option<i32>, // only used for transfers, cheques and locks
+    pub nonce: Option<i32>,      // used for transfers, key changes and locks, serial for cheques
     pub amount: i32,
     pub fee: i32,
 
@@ -169,6 +188,8 @@ pub struct StoredTx {
     pub state_root: Option<String>, // unique block id (for possible reorgs)
 
     pub created_at: NaiveDateTime,
+
+    pub token: i32,
 }
 
 impl StoredTx {
@@ -183,6 +204,21 @@ impl StoredTx {
             e if e == EXIT_TX => Exit {
                 tx: self.into_exit_transaction(),
             },
+            c if c == CHANGE_PUBKEY_TX => ChangePubKey {
+                tx: Box::new(self.into_change_pubkey_transaction()),
+            },
+            c if c == CHEQUE_TX => Cheque {
+                tx: Box::new(self.into_cheque_transaction()),
+            },
+            l if l == LOCK_TX => Lock {
+                tx: Box::new(self.into_lock_transaction()),
+            },
+            c if c == CLAIM_TX => Claim {
+                tx: self.into_claim_transaction(),
+            },
+            r if r == REFUND_TX => Refund {
+                tx: self.into_refund_transaction(),
+            },
             _ => return Err(Error::NotFound),
         };
         Ok(res)
@@ -192,11 +228,15 @@ impl StoredTx {
         TransferTx {
             from: self.from_account as u32,
             to: self.to_account.unwrap() as u32,
+            token: self.token as u32,
             amount: BigDecimal::from(self.amount),
             fee: BigDecimal::from(self.fee),
             nonce: 0,
             good_until_block: 0,
+            chain_id: 0,
             signature: TxSignature::default(),
+            multisig_signatures: Vec::new(),
+            recipient_pub_key: None,
             cached_pub_key: None,
         }
     }
@@ -204,6 +244,7 @@ impl StoredTx {
     pub fn into_deposit_transaction(&self) -> DepositTx {
         DepositTx {
             account: self.from_account as u32,
+            token: self.token as u32,
             amount: BigDecimal::from(self.amount),
             pub_x: Fr::zero(),
             pub_y: Fr::zero(),
@@ -213,7 +254,63 @@ impl StoredTx {
     pub fn into_exit_transaction(&self) -> ExitTx {
         ExitTx {
             account: self.from_account as u32,
+            token: self.token as u32,
+            amount: BigDecimal::from(self.amount),
+        }
+    }
+
+    pub fn into_change_pubkey_transaction(&self) -> ChangePubKeyTx {
+        ChangePubKeyTx {
+            account: self.from_account as u32,
+            new_pub_x: Fr::zero(),
+            new_pub_y: Fr::zero(),
+            nonce: self.nonce.unwrap_or_default() as u32,
+            chain_id: 0,
+            signature: TxSignature::default(),
+            multisig: None,
+            cached_pub_key: None,
+        }
+    }
+
+    pub fn into_cheque_transaction(&self) -> ChequeTx {
+        ChequeTx {
+            from: self.from_account as u32,
+            to: self.to_account.unwrap() as u32,
+            token: self.token as u32,
+            amount: BigDecimal::from(self.amount),
+            serial: self.nonce.unwrap_or_default() as u32,
+            good_until_block: 0,
+            chain_id: 0,
+            signature: TxSignature::default(),
+            cached_pub_key: None,
+        }
+    }
+
+    pub fn into_lock_transaction(&self) -> LockTx {
+        LockTx {
+            account: self.from_account as u32,
+            to: self.to_account.unwrap() as u32,
+            token: self.token as u32,
             amount: BigDecimal::from(self.amount),
+            hash: H256::zero(),
+            good_until_block: 0,
+            nonce: self.nonce.unwrap_or_default() as u32,
+            chain_id: 0,
+            signature: TxSignature::default(),
+            cached_pub_key: None,
+        }
+    }
+
+    pub fn into_claim_transaction(&self) -> ClaimTx {
+        ClaimTx {
+            account: self.from_account as u32,
+            preimage: H256::zero(),
+        }
+    }
+
+    pub fn into_refund_transaction(&self) -> RefundTx {
+        RefundTx {
+            account: self.from_account as u32,
         }
     }
 }
@@ -266,6 +363,13 @@ pub struct ServerConfig {
     pub contract_addr: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Queryable)]
+pub struct Token {
+    pub id: i32,
+    pub address: String,
+    pub symbol: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, QueryableByName)]
 pub struct BlockDetails {
     #[sql_type = "Integer"]
@@ -323,6 +427,12 @@ impl StorageProcessor {
         server_config.first(self.conn())
     }
 
+    /// Tokens registered for transfers, deposits and exits
+    pub fn load_tokens(&self) -> QueryResult<Vec<Token>> {
+        use crate::schema::tokens::dsl::*;
+        tokens.order(id.asc()).load(self.conn())
+    }
+
     /// Execute an operation: store op, modify state accordingly, load additional data and meta tx info
     /// - Commit => store account updates
     /// - Verify => apply account updates
@@ -373,6 +483,17 @@ impl StorageProcessor {
             BlockData::Exit { transactions, .. } => {
                 self.save_exit_transactions(op, &transactions)?
             }
+            BlockData::ChangePubKey { transactions } => {
+                self.save_change_pubkey_transactions(op, &transactions)?
+            }
+            BlockData::Cheque { transactions } => {
+                self.save_cheque_transactions(op, &transactions)?
+            }
+            BlockData::Lock { transactions } => self.save_lock_transactions(op, &transactions)?,
+            BlockData::Claim { transactions } => self.save_claim_transactions(op, &transactions)?,
+            BlockData::Refund { transactions } => {
+                self.save_refund_transactions(op, &transactions)?
+            }
         }
         Ok(())
     }
@@ -403,6 +524,7 @@ impl StorageProcessor {
                         .unwrap(),
                     block_number: Some(op.block.block_number as i32),
                     state_root: Some(op.block.new_root_hash.to_hex()),
+                    token: tx.token as i32,
                 })
                 .execute(self.conn())?;
             if 0 == inserted {
@@ -432,6 +554,7 @@ impl StorageProcessor {
                     fee: 0,
                     block_number: Some(op.block.block_number as i32),
                     state_root: Some(op.block.new_root_hash.to_hex()),
+                    token: tx.token as i32,
                 })
                 .execute(self.conn())?;
             if 0 == inserted {
@@ -461,6 +584,142 @@ impl StorageProcessor {
                     fee: 0,
                     block_number: Some(op.block.block_number as i32),
                     state_root: Some(op.block.new_root_hash.to_hex()),
+                    token: tx.token as i32,
+                })
+                .execute(self.conn())?;
+            if 0 == inserted {
+                error!("Error: could not commit all new transactions!");
+                return Err(Error::RollbackTransaction);
+            }
+        }
+        Ok(())
+    }
+
+    fn save_change_pubkey_transactions(
+        &self,
+        op: &Operation,
+        txs: &[ChangePubKeyTx],
+    ) -> QueryResult<()> {
+        for tx in txs.iter() {
+            let inserted = diesel::insert_into(transactions::table)
+                .values(&NewTx {
+                    tx_type: String::from("change_pubkey"),
+                    from_account: tx.account as i32,
+                    to_account: None,
+                    nonce: Some(tx.nonce as i32),
+                    amount: 0,
+                    fee: 0,
+                    block_number: Some(op.block.block_number as i32),
+                    state_root: Some(op.block.new_root_hash.to_hex()),
+                    token: ETH_TOKEN_ID as i32,
+                })
+                .execute(self.conn())?;
+            if 0 == inserted {
+                error!("Error: could not commit all new transactions!");
+                return Err(Error::RollbackTransaction);
+            }
+        }
+        Ok(())
+    }
+
+    fn save_cheque_transactions(&self, op: &Operation, txs: &[ChequeTx]) -> QueryResult<()> {
+        for tx in txs.iter() {
+            let inserted = diesel::insert_into(transactions::table)
+                .values(&NewTx {
+                    tx_type: String::from("cheque"),
+                    from_account: tx.from as i32,
+                    to_account: Some(tx.to as i32),
+                    // cheques consume no nonce, the serial is kept instead
+                    nonce: Some(tx.serial as i32),
+                    amount: tx
+                        .amount
+                        .as_bigint_and_exponent()
+                        .0
+                        .to_str_radix(10)
+                        .as_str()
+                        .parse()
+                        .unwrap(),
+                    fee: 0,
+                    block_number: Some(op.block.block_number as i32),
+                    state_root: Some(op.block.new_root_hash.to_hex()),
+                    token: tx.token as i32,
+                })
+                .execute(self.conn())?;
+            if 0 == inserted {
+                error!("Error: could not commit all new transactions!");
+                return Err(Error::RollbackTransaction);
+            }
+        }
+        Ok(())
+    }
+
+    fn save_lock_transactions(&self, op: &Operation, txs: &[LockTx]) -> QueryResult<()> {
+        for tx in txs.iter() {
+            let inserted = diesel::insert_into(transactions::table)
+                .values(&NewTx {
+                    tx_type: String::from("lock"),
+                    from_account: tx.account as i32,
+                    to_account: Some(tx.to as i32),
+                    nonce: Some(tx.nonce as i32),
+                    amount: tx
+                        .amount
+                        .as_bigint_and_exponent()
+                        .0
+                        .to_str_radix(10)
+                        .as_str()
+                        .parse()
+                        .unwrap(),
+                    fee: 0,
+                    block_number: Some(op.block.block_number as i32),
+                    state_root: Some(op.block.new_root_hash.to_hex()),
+                    token: tx.token as i32,
+                })
+                .execute(self.conn())?;
+            if 0 == inserted {
+                error!("Error: could not commit all new transactions!");
+                return Err(Error::RollbackTransaction);
+            }
+        }
+        Ok(())
+    }
+
+    // claims and refunds carry only the account, the lock itself is stored with the lock tx
+    fn save_claim_transactions(&self, op: &Operation, txs: &[ClaimTx]) -> QueryResult<()> {
+        for tx in txs.iter() {
+            let inserted = diesel::insert_into(transactions::table)
+                .values(&NewTx {
+                    tx_type: String::from("claim"),
+                    from_account: tx.account as i32,
+                    to_account: None,
+                    nonce: None,
+                    amount: 0,
+                    fee: 0,
+                    block_number: Some(op.block.block_number as i32),
+                    state_root: Some(op.block.new_root_hash.to_hex()),
+                    token: ETH_TOKEN_ID as i32,
+                })
+                .execute(self.conn())?;
+            if 0 == inserted {
+                error!("Error: could not commit all new transactions!");
+                return Err(Error::RollbackTransaction);
+            }
+        }
+        Ok(())
+    }
+
+    fn save_refund_transactions(&self, op: &Operation, txs: &[RefundTx]) -> QueryResult<()> {
+        for tx in txs.iter() {
+            let inserted = diesel::insert_into(transactions::table)
+                .values(&NewTx {
+                    tx_type: String::from("refund"),
+                    from_account: tx.account as i32,
+                    to_account: None,
+                    nonce: None,
+                    amount: 0,
+                    fee: 0,
+                    block_number: Some(op.block.block_number as i32),
+                    state_root: Some(op.block.new_root_hash.to_hex()),
+                    token: ETH_TOKEN_ID as i32,
                 })
                 .execute(self.conn())?;
             if 0 == inserted {
@@ -578,7 +837,38 @@ impl StorageProcessor {
         self.load_state_diff(block_number, block_number + 1)
     }
 
+    /// loads the state of all accounts as it was right after `block_number` was applied,
+    /// i.e. the latest update of every account in blocks up to and including `block_number`.
+    /// Accounts that had exited by then are left out
+    pub fn load_state_at_block(&self, block_number: u32) -> QueryResult<(u32, AccountMap)> {
+        let select = format!(
+            "
+            SELECT DISTINCT ON (account_id) account_id AS id, block_number AS last_block, data
+            FROM account_updates
+            WHERE block_number <= {}
+            ORDER BY account_id, block_number DESC",
+            block_number
+        );
+        let (last_block, accounts) = self.load_accounts(select.as_str())?;
+        let result = AccountMap::from_iter(accounts.into_iter().filter_map(|a| {
+            let id = a.id as u32;
+            a.into_existing_account().map(|account| (id, account))
+        }));
+        Ok((last_block, result))
+    }
+
     fn load_state(&self, query: &str) -> QueryResult<(u32, AccountMap)> {
+        let (last_block, accounts) = self.load_accounts(query)?;
+        let result = AccountMap::from_iter(
+            accounts
+                .into_iter()
+                .map(|a| (a.id as u32, serde_json::from_value(a.data).unwrap())),
+        );
+        Ok((last_block, result))
+    }
+
+    // rows of the query with the last block any of them was updated in
+    fn load_accounts(&self, query: &str) -> QueryResult<(u32, Vec<StorageAccount>)> {
         diesel::sql_query(query)
             .load(self.conn())
             .map(|accounts: Vec<StorageAccount>| {
@@ -587,12 +877,7 @@ impl StorageProcessor {
                     .map(|a| a.last_block as u32)
                     .max()
                     .unwrap_or(0);
-                let result = AccountMap::from_iter(
-                    accounts
-                        .into_iter()
-                        .map(|a| (a.id as u32, serde_json::from_value(a.data).unwrap())),
-                );
-                (last_block, result)
+                (last_block, accounts)
             })
     }
 
@@ -635,6 +920,36 @@ impl StorageProcessor {
             .ok()
     }
 
+    /// Whether the operation is stored, unlike `load_stored_op_with_block_number`
+    /// a failed query is an error and not a missing operation
+    pub fn is_operation_stored(
+        &self,
+        block_number: BlockNumber,
+        action_type: ActionType,
+    ) -> QueryResult<bool> {
+        use crate::schema::operations::dsl;
+        select(exists(
+            dsl::operations
+                .filter(dsl::block_number.eq(block_number as i32))
+                .filter(dsl::action_type.eq(action_type.to_string())),
+        ))
+        .get_result(self.conn())
+    }
+
+    /// The first block after `block_number` with an operation sent to Ethereum, if any
+    pub fn load_first_sent_block_after(
+        &self,
+        block_number: BlockNumber,
+    ) -> QueryResult<Option<BlockNumber>> {
+        use crate::schema::operations::dsl;
+        dsl::operations
+            .filter(dsl::block_number.gt(block_number as i32))
+            .filter(dsl::tx_hash.is_not_null())
+            .select(min(dsl::block_number))
+            .get_result::<Option<i32>>(self.conn())
+            .map(|block| block.map(|block| block as BlockNumber))
+    }
+
     pub fn load_block_range(
         &self,
         max_block: BlockNumber,
@@ -784,6 +1099,60 @@ impl StorageProcessor {
         })
     }
 
+    /// Removes all blocks after `last_block` together with their account updates,
+    /// transactions, proofs and prover runs. Verified blocks and blocks with an operation
+    /// sent to Ethereum can not be removed. Returns the removed commit operations ordered by block number
+    pub fn rollback_blocks(&self, last_block: BlockNumber) -> QueryResult<Vec<Operation>> {
+        self.conn().transaction(|| {
+            let last_verified = self.get_last_verified_block()?;
+            if last_block < last_verified {
+                error!(
+                    "Error: can not roll back to block {}, block {} is already verified",
+                    last_block, last_verified
+                );
+                return Err(Error::RollbackTransaction);
+            }
+            if let Some(sent_block) = self.load_first_sent_block_after(last_block)? {
+                error!(
+                    "Error: can not roll back to block {}, block {} is sent to Ethereum",
+                    last_block, sent_block
+                );
+                return Err(Error::RollbackTransaction);
+            }
+
+            let ops: Vec<StoredOperation> = diesel::sql_query(format!(
+                "
+                SELECT * FROM operations
+                WHERE action_type = 'Commit'
+                AND block_number > {}
+                ORDER BY block_number
+            ",
+                last_block as i32
+            ))
+            .load(self.conn())?;
+            let ops = ops
+                .into_iter()
+                .map(|o| o.into_op(self))
+                .collect::<QueryResult<Vec<Operation>>>()?;
+
+            let last_block = last_block as i32;
+            diesel::delete(operations::table.filter(operations::block_number.gt(last_block)))
+                .execute(self.conn())?;
+            diesel::delete(
+                account_updates::table.filter(account_updates::block_number.gt(last_block)),
+            )
+            .execute(self.conn())?;
+            diesel::delete(transactions::table.filter(transactions::block_number.gt(last_block)))
+                .execute(self.conn())?;
+            diesel::delete(proofs::table.filter(proofs::block_number.gt(last_block)))
+                .execute(self.conn())?;
+            diesel::delete(prover_runs::table.filter(prover_runs::block_number.gt(last_block)))
+                .execute(self.conn())?;
+
+            Ok(ops)
+        })
+    }
+
     fn load_number(&self, query: &str) -> QueryResult<i32> {
         diesel::sql_query(query)
             .get_result::<IntegerNumber>(self.conn())
@@ -821,7 +1190,7 @@ impl StorageProcessor {
         let r = diesel::sql_query(query)
             .get_result(self.conn())
             .optional()?;
-        Ok(r.map(|acc: StorageAccount| serde_json::from_value(acc.data).unwrap()))
+        Ok(r.and_then(StorageAccount::into_existing_account))
     }
 
     pub fn last_verified_state_for_account(
@@ -832,8 +1201,28 @@ impl StorageProcessor {
         let mut r = accounts
             .filter(id.eq(account_id as i32))
             .load(self.conn())?;
-        Ok(r.pop()
-            .map(|acc: StorageAccount| serde_json::from_value(acc.data).unwrap()))
+        Ok(r.pop().and_then(StorageAccount::into_existing_account))
+    }
+
+    /// State of the account right after `block_number` was applied,
+    /// None if it did not exist yet or had exited
+    pub fn state_for_account_at_block(
+        &self,
+        account_id: AccountId,
+        block_number: BlockNumber,
+    ) -> QueryResult<Option<models::plasma::account::Account>> {
+        let query = format!(
+            "
+            SELECT account_id AS id, block_number AS last_block, data
+            FROM account_updates WHERE account_id = {} AND block_number <= {}
+            ORDER BY block_number DESC LIMIT 1
+        ",
+            account_id, block_number
+        );
+        let r = diesel::sql_query(query)
+            .get_result(self.conn())
+            .optional()?;
+        Ok(r.and_then(StorageAccount::into_existing_account))
     }
 
     pub fn count_outstanding_proofs(&self, after_block: BlockNumber) -> QueryResult<u32> {
@@ -1059,6 +1448,7 @@ mod test {
     use bigdecimal::Num;
     use diesel::Connection;
     use ff::Field;
+    use models::plasma::params::ETH_TOKEN_ID;
     use web3::types::U256;
     //use diesel::RunQueryDsl;
 
@@ -1094,7 +1484,7 @@ mod test {
         let mut accounts = fnv::FnvHashMap::default();
         let acc = |balance| {
             let mut a = models::plasma::account::Account::default();
-            a.balance = BigDecimal::from(balance);
+            a.set_balance(ETH_TOKEN_ID, BigDecimal::from(balance));
             a
         };
 
@@ -1159,6 +1549,224 @@ mod test {
 
         let (_, reverse) = conn.load_state_diff(3, 2).unwrap();
         assert_eq!(reverse.get(&2).unwrap(), &acc(2));
+
+        // historical state must not see later updates
+        let (last_block, state) = conn.load_state_at_block(1).unwrap();
+        assert_eq!(last_block, 1);
+        assert_eq!(state.len(), 3);
+        assert_eq!(state.get(&2).unwrap(), &acc(2));
+        let (last_block, state) = conn.load_state_at_block(2).unwrap();
+        assert_eq!(last_block, 2);
+        assert_eq!(state.len(), 4);
+        assert_eq!(state.get(&2).unwrap(), &acc(23));
+        assert_eq!(state.get(&1).unwrap(), &acc(1));
+        assert_eq!(conn.load_state_at_block(0).unwrap().1.len(), 0);
+
+        assert_eq!(conn.state_for_account_at_block(2, 1).unwrap(), Some(acc(2)));
+        assert_eq!(
+            conn.state_for_account_at_block(2, 5).unwrap(),
+            Some(acc(23))
+        );
+        assert_eq!(conn.state_for_account_at_block(4, 1).unwrap(), None);
+
+        // an exited account is stored as the empty one and reads as non-existent
+        let mut accounts3 = fnv::FnvHashMap::default();
+        accounts3.insert(4, models::plasma::account::Account::default());
+        conn.commit_state_update(3, &accounts3).unwrap();
+        assert_eq!(conn.state_for_account_at_block(4, 2).unwrap(), Some(acc(4)));
+        assert_eq!(conn.state_for_account_at_block(4, 3).unwrap(), None);
+        assert_eq!(conn.last_committed_state_for_account(4).unwrap(), None);
+        let (_, state) = conn.load_state_diff(3, 4).unwrap();
+        assert!(state.get(&4).unwrap().is_empty());
+
+        // the exit is inside the range of a historical state, so the account is left out
+        let mut accounts4 = fnv::FnvHashMap::default();
+        accounts4.insert(1, acc(11));
+        conn.commit_state_update(4, &accounts4).unwrap();
+        let (last_block, state) = conn.load_state_at_block(4).unwrap();
+        assert_eq!(last_block, 4);
+        assert_eq!(state.len(), 3);
+        assert_eq!(state.get(&4), None);
+        assert_eq!(state.get(&1).unwrap(), &acc(11));
+        let (_, state) = conn.load_state_at_block(2).unwrap();
+        assert_eq!(state.get(&4).unwrap(), &acc(4));
+    }
+
+    #[test]
+    fn test_load_account_stored_before_multi_token_balances() {
+        let pool = ConnectionPool::new();
+        let conn = pool.access_storage().unwrap();
+        conn.conn().begin_test_transaction().unwrap(); // this will revert db after test
+
+        let mut account = models::plasma::account::Account::default();
+        account.nonce = 3;
+        account.set_balance(ETH_TOKEN_ID, BigDecimal::from(15));
+
+        // the row as it was written when an account had a single balance
+        let mut data = serde_json::to_value(&account).unwrap();
+        let fields = data.as_object_mut().unwrap();
+        fields.remove("balances");
+        fields.insert(
+            "balance".to_owned(),
+            serde_json::to_value(BigDecimal::from(15)).unwrap(),
+        );
+        for table in ["accounts", "account_updates"].iter() {
+            let columns = if *table == "accounts" {
+                "id, last_block"
+            } else {
+                "account_id, block_number"
+            };
+            diesel::sql_query(format!(
+                "INSERT INTO {} ({}, data) VALUES (7, 1, '{}')",
+                table, columns, data
+            ))
+            .execute(conn.conn())
+            .unwrap();
+        }
+
+        let (_, state) = conn.load_verified_state().unwrap();
+        assert_eq!(state.get(&7), Some(&account));
+        let (_, state) = conn.load_committed_state().unwrap();
+        assert_eq!(state.get(&7), Some(&account));
+        assert_eq!(
+            conn.last_committed_state_for_account(7).unwrap(),
+            Some(account)
+        );
+    }
+
+    #[test]
+    fn test_load_ops_stored_before_multi_token_balances() {
+        let pool = ConnectionPool::new();
+        let conn = pool.access_storage().unwrap();
+        conn.conn().begin_test_transaction().unwrap(); // this will revert db after test
+        conn.prepare_nonce_scheduling("0x0", 0).unwrap();
+
+        let transfer = TransferTx {
+            token: 3,
+            ..Default::default()
+        };
+        let deposit = DepositTx {
+            account: 1,
+            token: 3,
+            amount: BigDecimal::from(10),
+            pub_x: Fr::zero(),
+            pub_y: Fr::zero(),
+        };
+        let exit = ExitTx {
+            account: 1,
+            token: 3,
+            amount: BigDecimal::from(10),
+        };
+        let blocks = vec![
+            BlockData::Transfer {
+                transactions: vec![transfer],
+                total_fees: BigDecimal::from(0),
+            },
+            BlockData::Deposit {
+                transactions: vec![deposit],
+                batch_number: 0,
+            },
+            BlockData::Exit {
+                transactions: vec![exit],
+                batch_number: 0,
+            },
+        ];
+        for (block_number, block_data) in (1..).zip(blocks) {
+            conn.execute_operation(&Operation {
+                id: None,
+                action: Action::Commit,
+                block: Block {
+                    block_number,
+                    new_root_hash: Fr::default(),
+                    block_data,
+                },
+                accounts_updated: Some(fnv::FnvHashMap::default()),
+                tx_meta: None,
+            })
+            .unwrap();
+        }
+
+        // the operations as they were written when txs had no token
+        diesel::sql_query(
+            "UPDATE operations SET data = data #- '{block,block_data,transactions,0,token}'",
+        )
+        .execute(conn.conn())
+        .unwrap();
+
+        for block_number in 1..=3 {
+            let op = conn.load_commit_op(block_number).unwrap();
+            let token = match op.block.block_data {
+                BlockData::Transfer { transactions, .. } => transactions[0].token,
+                BlockData::Deposit { transactions, .. } => transactions[0].token,
+                BlockData::Exit { transactions, .. } => transactions[0].token,
+                _ => unreachable!(),
+            };
+            assert_eq!(token, ETH_TOKEN_ID);
+        }
+    }
+
+    #[test]
+    fn test_load_ops_stored_before_chain_id() {
+        let pool = ConnectionPool::new();
+        let conn = pool.access_storage().unwrap();
+        conn.conn().begin_test_transaction().unwrap(); // this will revert db after test
+        conn.prepare_nonce_scheduling("0x0", 0).unwrap();
+
+        let transfer = TransferTx {
+            chain_id: 9,
+            ..Default::default()
+        };
+        let cheque = ChequeTx {
+            from: 1,
+            to: 2,
+            token: ETH_TOKEN_ID,
+            amount: BigDecimal::from(10),
+            serial: 0,
+            good_until_block: 100,
+            chain_id: 9,
+            signature: TxSignature::default(),
+            cached_pub_key: None,
+        };
+        let blocks = vec![
+            BlockData::Transfer {
+                transactions: vec![transfer],
+                total_fees: BigDecimal::from(0),
+            },
+            BlockData::Cheque {
+                transactions: vec![cheque],
+            },
+        ];
+        for (block_number, block_data) in (1..).zip(blocks) {
+            conn.execute_operation(&Operation {
+                id: None,
+                action: Action::Commit,
+                block: Block {
+                    block_number,
+                    new_root_hash: Fr::default(),
+                    block_data,
+                },
+                accounts_updated: Some(fnv::FnvHashMap::default()),
+                tx_meta: None,
+            })
+            .unwrap();
+        }
+
+        // the operations as they were written when txs had no chain id
+        diesel::sql_query(
+            "UPDATE operations SET data = data #- '{block,block_data,transactions,0,chain_id}'",
+        )
+        .execute(conn.conn())
+        .unwrap();
+
+        for block_number in 1..=2 {
+            let op = conn.load_commit_op(block_number).unwrap();
+            let chain_id = match op.block.block_data {
+                BlockData::Transfer { transactions, .. } => transactions[0].chain_id,
+                BlockData::Cheque { transactions } => transactions[0].chain_id,
+                _ => unreachable!(),
+            };
+            assert_eq!(chain_id, 0);
+        }
     }
 
     #[test]
@@ -1171,7 +1779,7 @@ mod test {
         let mut accounts = fnv::FnvHashMap::default();
         let acc = |balance| {
             let mut a = models::plasma::account::Account::default();
-            a.balance = BigDecimal::from(balance);
+            a.set_balance(ETH_TOKEN_ID, BigDecimal::from(balance));
             a
         };
 
@@ -1199,7 +1807,7 @@ mod test {
             conn.last_committed_state_for_account(5)
                 .unwrap()
                 .unwrap()
-                .balance,
+                .get_balance(ETH_TOKEN_ID),
             BigDecimal::from(2)
         );
 
@@ -1225,14 +1833,14 @@ mod test {
             conn.last_verified_state_for_account(7)
                 .unwrap()
                 .unwrap()
-                .balance,
+                .get_balance(ETH_TOKEN_ID),
             BigDecimal::from(3)
         );
         assert_eq!(
             conn.last_committed_state_for_account(7)
                 .unwrap()
                 .unwrap()
-                .balance,
+                .get_balance(ETH_TOKEN_ID),
             BigDecimal::from(3)
         );
 
@@ -1309,6 +1917,7 @@ mod test {
         assert_eq!(0, conn.get_last_verified_block().unwrap());
         assert_eq!(conn.last_committed_state_for_account(9999).unwrap(), None);
         assert_eq!(conn.last_verified_state_for_account(9999).unwrap(), None);
+        assert!(!conn.is_operation_stored(1, ActionType::COMMIT).unwrap());
 
         conn.execute_operation(&Operation {
             id: None,
@@ -1326,6 +1935,8 @@ mod test {
         })
         .unwrap();
         assert_eq!(3, conn.load_last_committed_deposit_batch().unwrap());
+        assert!(conn.is_operation_stored(1, ActionType::COMMIT).unwrap());
+        assert!(!conn.is_operation_stored(1, ActionType::VERIFY).unwrap());
 
         conn.execute_operation(&Operation {
             id: None,
@@ -1353,6 +1964,7 @@ mod test {
 
         let deposit_tx: DepositTx = DepositTx {
             account: 1,
+            token: ETH_TOKEN_ID,
             amount: BigDecimal::from_str_radix(&format!("{}", 10000), 10).unwrap(),
             pub_x: Fr::zero(),
             pub_y: Fr::zero(),
@@ -1361,16 +1973,21 @@ mod test {
         let transfer_tx: TransferTx = TransferTx {
             from: 1,
             to: 2,
+            token: ETH_TOKEN_ID,
             amount: BigDecimal::from_str_radix(&format!("{}", 5000), 10).unwrap(),
             fee: BigDecimal::from_str_radix(&format!("{}", 0), 10).unwrap(),
             nonce: 1,
             good_until_block: 100_000,
+            chain_id: 0,
             signature: TxSignature::default(),
+            multisig_signatures: Vec::new(),
+            recipient_pub_key: None,
             cached_pub_key: None,
         };
 
         let exit_tx: ExitTx = ExitTx {
             account: 1,
+            token: ETH_TOKEN_ID,
             amount: BigDecimal::from_str_radix(&format!("{}", 5000), 10).unwrap(),
         };
 
@@ -1426,6 +2043,67 @@ mod test {
         assert_eq!(txs.len(), 6);
     }
 
+    #[test]
+    fn test_rollback_blocks() {
+        let pool = ConnectionPool::new();
+        let conn = pool.access_storage().unwrap();
+        conn.conn().begin_test_transaction().unwrap(); // this will revert db after test
+
+        let acc = |balance| {
+            let mut a = models::plasma::account::Account::default();
+            a.set_balance(ETH_TOKEN_ID, BigDecimal::from(balance));
+            a
+        };
+
+        let mut commit_ids = vec![];
+        for block_number in 1..=3 {
+            let mut op = dummy_op(Action::Commit, block_number);
+            let mut accounts = fnv::FnvHashMap::default();
+            accounts.insert(1, acc(block_number));
+            op.accounts_updated = Some(accounts);
+            commit_ids.push(conn.execute_operation(&op).unwrap().id.unwrap());
+        }
+        conn.execute_operation(&dummy_op(
+            Action::Verify {
+                proof: Box::new(EncodedProof::default()),
+            },
+            1,
+        ))
+        .unwrap();
+        conn.store_proof(2, &EncodedProof::default()).unwrap();
+
+        // verified blocks must stay
+        assert!(conn.rollback_blocks(0).is_err());
+
+        // and so must the blocks sent to Ethereum
+        conn.save_operation_tx_hash(commit_ids[2], "0x01".to_string())
+            .unwrap();
+        assert_eq!(conn.load_first_sent_block_after(1).unwrap(), Some(3));
+        assert!(conn.rollback_blocks(1).is_err());
+        assert!(conn.rollback_blocks(2).is_err());
+        diesel::update(operations::table.filter(operations::id.eq(commit_ids[2])))
+            .set(operations::tx_hash.eq(None::<String>))
+            .execute(conn.conn())
+            .unwrap();
+        assert_eq!(conn.load_first_sent_block_after(1).unwrap(), None);
+
+        let removed = conn.rollback_blocks(1).expect("rollback failed");
+        assert_eq!(
+            removed
+                .iter()
+                .map(|op| op.block.block_number)
+                .collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(conn.get_last_committed_block().unwrap(), 1);
+        assert_eq!(conn.get_last_verified_block().unwrap(), 1);
+        assert!(conn.load_proof(2).is_err());
+
+        let (last_block, state) = conn.load_committed_state().unwrap();
+        assert_eq!(last_block, 1);
+        assert_eq!(state.get(&1).unwrap(), &acc(1));
+    }
+
     fn dummy_op(action: Action, block_number: BlockNumber) -> Operation {
         Operation {
             id: None,
@@ -80,6 +80,15 @@ table! {
         block_number -> Nullable<Int4>,
         state_root -> Nullable<Text>,
         created_at -> Timestamp,
+        token -> Int4,
+    }
+}
+
+table! {
+    tokens (id) {
+        id -> Int4,
+        address -> Text,
+        symbol -> Text,
     }
 }
 
@@ -92,5 +101,6 @@ allow_tables_to_appear_in_same_query!(
     proofs,
     prover_runs,
     server_config,
+    tokens,
     transactions,
 );